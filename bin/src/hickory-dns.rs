@@ -320,6 +320,10 @@ async fn async_run(args: Cli) -> Result<(), String> {
         catalog.set_nsid(Some(payload));
     }
 
+    // zone handlers with negative trust anchors, which are reloaded on SIGHUP
+    #[cfg(all(unix, feature = "recursor", feature = "__dnssec"))]
+    let mut validating_handlers = Vec::new();
+
     // configure our server based on the config_path
     for zone in config.zones() {
         let zone_name = zone
@@ -327,7 +331,16 @@ async fn async_run(args: Cli) -> Result<(), String> {
             .map_err(|err| format!("failed to read zone name from {config_path:?}: {err}"))?;
 
         match zone.load(&zone_dir).await {
-            Ok(handlers) => catalog.upsert(zone_name.into(), handlers),
+            Ok(handlers) => {
+                #[cfg(all(unix, feature = "recursor", feature = "__dnssec"))]
+                validating_handlers.extend(
+                    handlers
+                        .iter()
+                        .filter(|handler| handler.negative_trust_anchors().is_some())
+                        .map(|handler| (zone_name.clone(), handler.clone())),
+                );
+                catalog.upsert(zone_name.into(), handlers)
+            }
             Err(err) => return Err(format!("could not load zone {zone_name}: {err}")),
         }
 
@@ -480,6 +493,22 @@ async fn async_run(args: Cli) -> Result<(), String> {
         });
     }
 
+    #[cfg(all(unix, feature = "recursor", feature = "__dnssec"))]
+    if !validating_handlers.is_empty() {
+        let mut hangup = tokio::signal::unix::signal(SignalKind::hangup())
+            .map_err(|e| format!("failed to register signal handler: {e}"))?;
+        let config_path = config_path.to_owned();
+        tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                info!("reloading negative trust anchors from {config_path:?}");
+                match Config::read_config(&config_path) {
+                    Ok(config) => config.reload_negative_trust_anchors(&validating_handlers),
+                    Err(err) => error!("failed to read config file from {config_path:?}: {err}"),
+                }
+            }
+        });
+    }
+
     // config complete, starting!
     banner();
 
@@ -56,6 +56,8 @@ use hickory_server::store::forwarder::ForwardZoneHandler;
 use hickory_server::store::recursor::RecursiveConfig;
 #[cfg(feature = "recursor")]
 use hickory_server::store::recursor::RecursiveZoneHandler;
+#[cfg(all(feature = "recursor", feature = "__dnssec"))]
+use hickory_server::store::recursor::reload_negative_trust_anchors;
 #[cfg(feature = "sqlite")]
 use hickory_server::store::sqlite::{SqliteConfig, SqliteZoneHandler};
 use hickory_server::{
@@ -275,6 +277,41 @@ impl Config {
         &self.zones
     }
 
+    /// Replaces the negative trust anchors of the running recursors with those of this
+    /// configuration
+    ///
+    /// `handlers` are the zone handlers of each zone with negative trust anchors, in the order of
+    /// the stores of the zone.
+    #[cfg(all(feature = "recursor", feature = "__dnssec"))]
+    pub fn reload_negative_trust_anchors(&self, handlers: &[(Name, Arc<dyn ZoneHandler>)]) {
+        for zone in &self.zones {
+            let ZoneTypeConfig::External { stores } = &zone.zone_type_config else {
+                continue;
+            };
+            let Ok(zone_name) = zone.zone() else {
+                continue;
+            };
+
+            let configs = stores.iter().filter_map(|store| match store {
+                ExternalStoreConfig::Recursor(config) => {
+                    Some(config.dnssec_policy.negative_trust_anchors())
+                }
+                _ => None,
+            });
+            let anchors = handlers
+                .iter()
+                .filter(|(name, _)| *name == zone_name)
+                .filter_map(|(_, handler)| handler.negative_trust_anchors());
+            for (config, anchors) in configs.zip(anchors) {
+                info!(
+                    "loading {} negative trust anchors for {zone_name}",
+                    config.len()
+                );
+                reload_negative_trust_anchors(anchors, config);
+            }
+        }
+    }
+
     /// the tls certificate to use for accepting tls connections
     pub fn tls_cert(&self) -> Option<&TlsCertConfig> {
         cfg_if! {
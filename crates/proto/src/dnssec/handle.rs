@@ -7,9 +7,19 @@
 
 //! The `DnssecDnsHandle` is used to validate all DNS responses for correct DNSSEC signatures.
 
-use alloc::{borrow::ToOwned, boxed::Box, sync::Arc, vec::Vec};
-use core::{clone::Clone, fmt::Display, pin::Pin};
-use std::collections::{HashMap, HashSet};
+use alloc::{borrow::ToOwned, boxed::Box, string::ToString, sync::Arc, vec::Vec};
+use core::{
+    clone::Clone,
+    fmt::Display,
+    pin::Pin,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::OnceLock,
+    time::UNIX_EPOCH,
+};
 
 use futures_util::{
     future::{self, FutureExt},
@@ -19,11 +29,13 @@ use tracing::{debug, error, trace, warn};
 
 use crate::{
     dnssec::{
-        Proof, ProofError, ProofErrorKind, TrustAnchors, Verifier,
+        DenialKind, NegativeTrustAnchors, Proof, ProofError, ProofErrorKind, TrustAnchorManager,
+        TrustAnchors, ValidationFailure, ValidationStep, Verifier,
         nsec3::verify_nsec3,
         rdata::{DNSKEY, DNSSECRData, DS, NSEC, RRSIG},
+        validation_trace::TraceRecorder,
     },
-    error::{DnsError, NoRecords, ProtoError, ProtoErrorKind},
+    error::{DnsError, NoRecords, ProtoError, ProtoErrorKind, ResourceLimit},
     op::{DnsRequest, DnsRequestOptions, DnsResponse, Edns, Message, OpCode, Query, ResponseCode},
     rr::{Name, RData, Record, RecordType, RecordTypeSet, SerialNumber, resource::RecordRef},
     runtime::{RuntimeProvider, Time},
@@ -41,10 +53,14 @@ use self::rrset::Rrset;
 #[must_use = "queries can only be sent through a DnsHandle"]
 pub struct DnssecDnsHandle<H> {
     handle: H,
-    trust_anchor: Arc<TrustAnchors>,
+    trust_anchor: TrustAnchorSource,
+    negative_trust_anchors: Option<Arc<NegativeTrustAnchors>>,
+    insecure_zones: Arc<[Name]>,
+    trace: TraceRecorder,
     request_depth: usize,
     nsec3_soft_iteration_limit: u16,
     nsec3_hard_iteration_limit: u16,
+    budget: ValidationBudget,
 }
 
 impl<H: DnsHandle> DnssecDnsHandle<H> {
@@ -66,14 +82,34 @@ impl<H: DnsHandle> DnssecDnsHandle<H> {
     /// * `handle` - handle to use for all connections to a remote server.
     /// * `trust_anchor` - custom DNSKEYs that will be trusted, can be used to pin trusted keys.
     pub fn with_trust_anchor(handle: H, trust_anchor: Arc<TrustAnchors>) -> Self {
+        Self::with_source(handle, TrustAnchorSource::Static(trust_anchor))
+    }
+
+    /// Create a new DnssecDnsHandle wrapping the specified handle.
+    ///
+    /// The trusted keys are the current trust anchors of the `manager`, which are updated as the
+    /// manager follows key rollovers, see [`TrustAnchorManager`].
+    ///
+    /// # Arguments
+    /// * `handle` - handle to use for all connections to a remote server.
+    /// * `manager` - maintains the DNSKEYs that will be trusted.
+    pub fn with_trust_anchor_manager(handle: H, manager: Arc<TrustAnchorManager>) -> Self {
+        Self::with_source(handle, TrustAnchorSource::Managed(manager))
+    }
+
+    fn with_source(handle: H, trust_anchor: TrustAnchorSource) -> Self {
         Self {
             handle,
             trust_anchor,
+            negative_trust_anchors: None,
+            insecure_zones: Arc::from([]),
+            trace: TraceRecorder::default(),
             request_depth: 0,
             // These default values are based on
             // [RFC 9276 Appendix A](https://www.rfc-editor.org/rfc/rfc9276.html#appendix-A)
             nsec3_soft_iteration_limit: 100,
             nsec3_hard_iteration_limit: 500,
+            budget: ValidationBudget::new(None, None),
         }
     }
 
@@ -100,11 +136,119 @@ impl<H: DnsHandle> DnssecDnsHandle<H> {
         self
     }
 
+    /// Set limits on the work done to validate a response, against KeyTrap denial of service attacks
+    ///
+    /// The limits apply to each request, including the DS and DNSKEY lookups made to validate its
+    /// response. A request exceeding one of them fails with
+    /// [`ProtoErrorKind::ResourceLimitExceeded`]. Without limits, the number of signatures checked
+    /// is not limited, and DNSKEY or DS records beyond the second with the same key tag are skipped.
+    ///
+    /// # Arguments
+    /// * `max_signature_validations` - the maximum number of signatures checked.
+    /// * `max_key_tag_collisions` - the maximum number of DNSKEY or DS records with the same key
+    ///   tag that are tried.
+    pub fn validation_limits(
+        mut self,
+        max_signature_validations: u16,
+        max_key_tag_collisions: u8,
+    ) -> Self {
+        self.budget = ValidationBudget::new(
+            Some(max_signature_validations),
+            Some(max_key_tag_collisions),
+        );
+        self
+    }
+
+    /// Set the negative trust anchors, see [`NegativeTrustAnchors`]
+    ///
+    /// Records at or below a negative trust anchor are not validated, and are marked as
+    /// `Proof::Insecure`.
+    pub fn negative_trust_anchors(mut self, anchors: Arc<NegativeTrustAnchors>) -> Self {
+        self.negative_trust_anchors = Some(anchors);
+        self
+    }
+
+    /// Set zones which are never validated, like a negative trust anchor which does not expire
+    ///
+    /// Unlike [`Self::negative_trust_anchors`], these zones are fixed when the handle is built.
+    pub fn insecure_zones(mut self, zones: impl IntoIterator<Item = Name>) -> Self {
+        self.insecure_zones = zones.into_iter().collect();
+        self
+    }
+
+    /// Returns a copy of this handle, which sends its requests through the handle returned by `f`
+    ///
+    /// The trust anchors and other settings are the same as those of this handle.
+    pub fn map_handle(&self, f: impl FnOnce(&H) -> H) -> Self {
+        Self {
+            handle: f(&self.handle),
+            trust_anchor: self.trust_anchor.clone(),
+            negative_trust_anchors: self.negative_trust_anchors.clone(),
+            insecure_zones: self.insecure_zones.clone(),
+            trace: self.trace.clone(),
+            request_depth: self.request_depth,
+            nsec3_soft_iteration_limit: self.nsec3_soft_iteration_limit,
+            nsec3_hard_iteration_limit: self.nsec3_hard_iteration_limit,
+            budget: self.budget.clone(),
+        }
+    }
+
+    /// Whether validation is disabled for `name` by a negative trust anchor or an insecure zone
+    fn is_negative_trust_anchor(&self, name: &Name, current_time: u32) -> bool {
+        if self.insecure_zones.iter().any(|zone| zone.zone_of(name)) {
+            return true;
+        }
+
+        let Some(anchors) = &self.negative_trust_anchors else {
+            return false;
+        };
+
+        let now = UNIX_EPOCH + Duration::from_secs(u64::from(current_time));
+        anchors.covers(name, now)
+    }
+
+    /// Validates the response, and attaches the validation trace to it for top-level requests
     async fn verify_response(
         self,
         result: Result<DnsResponse, ProtoError>,
         query: Query,
         options: DnsRequestOptions,
+    ) -> Result<DnsResponse, ProtoError> {
+        let trace = match self.request_depth {
+            1 => self.trace.clone(),
+            _ => TraceRecorder::default(),
+        };
+
+        let budget = self.budget.clone();
+        // boxed, as the nested lookups made to validate the response would overflow the stack
+        let mut result = Box::pin(self.verify_message(result, query, options)).await;
+        if let Some(limit) = budget.exceeded() {
+            result = Err(ProtoError::from(ProtoErrorKind::ResourceLimitExceeded(
+                limit,
+            )));
+        }
+
+        let Some(trace) = trace.snapshot() else {
+            return result;
+        };
+
+        match &mut result {
+            Ok(response) => response.set_validation_trace(trace),
+            Err(err) => {
+                if let ProtoErrorKind::Dns(DnsError::Nsec { response, .. }) = &mut err.kind {
+                    response.set_validation_trace(trace);
+                }
+            }
+        }
+
+        result
+    }
+
+    async fn verify_message(
+        self,
+        result: Result<DnsResponse, ProtoError>,
+        query: Query,
+        options: DnsRequestOptions,
     ) -> Result<DnsResponse, ProtoError> {
         let mut message = match result {
             Ok(response) => response,
@@ -144,7 +288,7 @@ impl<H: DnsHandle> DnssecDnsHandle<H> {
         debug!(
             "validating message_response: {}, with {} trust_anchors",
             message.id(),
-            self.trust_anchor.len(),
+            self.trust_anchor.get().len(),
         );
 
         // use the same current time value for all rrsig + rrset pairs.
@@ -169,6 +313,14 @@ impl<H: DnsHandle> DnssecDnsHandle<H> {
             return Ok(message);
         }
 
+        if self.is_negative_trust_anchor(query.name(), current_time) {
+            debug!("negative trust anchor covers {}", query.name());
+            self.trace.push(ValidationStep::NegativeTrustAnchor {
+                name: query.name().clone(),
+            });
+            return Ok(message);
+        }
+
         if !message.authorities().is_empty()
             && message
                 .authorities()
@@ -219,27 +371,33 @@ impl<H: DnsHandle> DnssecDnsHandle<H> {
         // Both NSEC and NSEC3 records cannot coexist during
         // transition periods, as per RFC 5515 10.4.3 and
         // 10.5.2
-        let nsec_proof = match (!nsec3s.is_empty(), !nsecs.is_empty()) {
-            (true, false) => verify_nsec3(
-                &query,
-                find_soa_name(&message)?,
-                message.response_code(),
-                message.answers(),
-                &nsec3s,
-                self.nsec3_soft_iteration_limit,
-                self.nsec3_hard_iteration_limit,
+        let (denial_kind, nsec_proof) = match (!nsec3s.is_empty(), !nsecs.is_empty()) {
+            (true, false) => (
+                DenialKind::Nsec3,
+                verify_nsec3(
+                    &query,
+                    find_soa_name(&message)?,
+                    message.response_code(),
+                    message.answers(),
+                    &nsec3s,
+                    self.nsec3_soft_iteration_limit,
+                    self.nsec3_hard_iteration_limit,
+                ),
             ),
-            (false, true) => verify_nsec(
-                &query,
-                find_soa_name(&message)?,
-                message.response_code(),
-                nsecs.as_slice(),
+            (false, true) => (
+                DenialKind::Nsec,
+                verify_nsec(
+                    &query,
+                    find_soa_name(&message)?,
+                    message.response_code(),
+                    nsecs.as_slice(),
+                ),
             ),
             (true, true) => {
                 warn!(
                     "response contains both NSEC and NSEC3 records\nQuery:\n{query:?}\nResponse:\n{message:?}"
                 );
-                Proof::Bogus
+                (DenialKind::Both, Proof::Bogus)
             }
             (false, false) => {
                 // Check if the zone is insecure first.
@@ -252,10 +410,17 @@ impl<H: DnsHandle> DnssecDnsHandle<H> {
                 warn!(
                     "response does not contain NSEC or NSEC3 records. Query: {query:?} response: {message:?}"
                 );
-                Proof::Bogus
+                (DenialKind::Missing, Proof::Bogus)
             }
         };
 
+        self.trace.push(ValidationStep::Denial {
+            name: query.name().clone(),
+            record_type: query.query_type(),
+            kind: denial_kind,
+            proof: nsec_proof,
+        });
+
         if !nsec_proof.is_secure() {
             debug!("returning Nsec error for {} {nsec_proof}", query.name());
             // TODO change this to remove the NSECs, like we do for the others?
@@ -347,15 +512,21 @@ impl<H: DnsHandle> DnssecDnsHandle<H> {
                 rrsig_len = rrsigs.len()
             );
 
-            // verify this rrset
-            let proof = self
-                .verify_rrset(&rrset, rrsigs, options, current_time)
-                .await;
+            // verify this rrset, unless validation is disabled for it
+            let proof = if self.is_negative_trust_anchor(&name, current_time) {
+                debug!("negative trust anchor covers {name}");
+                self.trace
+                    .push(ValidationStep::NegativeTrustAnchor { name: name.clone() });
+                Ok((Proof::Insecure, None, None))
+            } else {
+                self.verify_rrset(&rrset, rrsigs, options, current_time)
+                    .await
+            };
 
-            let proof = match proof {
+            let (proof, reason) = match proof {
                 Ok(proof) => {
                     debug!("verified: {name} record_type: {record_type}",);
-                    proof
+                    (proof, None)
                 }
                 Err(err) => {
                     match err.kind() {
@@ -366,10 +537,17 @@ impl<H: DnsHandle> DnssecDnsHandle<H> {
                             debug!("failed to verify: {name} record_type: {record_type}: {kind}")
                         }
                     }
-                    (err.proof, None, None)
+                    ((err.proof, None, None), Some(err.kind().to_string()))
                 }
             };
 
+            self.trace.push(ValidationStep::Rrset {
+                name: name.clone(),
+                record_type,
+                proof: proof.0,
+                reason,
+            });
+
             let (proof, adjusted_ttl, rrsig_idx) = proof;
             for mut record in current_rrset {
                 record.set_proof(proof);
@@ -528,7 +706,7 @@ impl<H: DnsHandle> DnssecDnsHandle<H> {
             }
 
             // need to track each proof on each dnskey to ensure they are all validated
-            match verify_dnskey(&dnskey, &ds_records) {
+            match verify_dnskey(&dnskey, &ds_records, &self.trace, &self.budget) {
                 Ok(pf) => *proof = (pf, None, None),
                 Err(err) => *proof = (err.proof, None, None),
             }
@@ -552,7 +730,16 @@ impl<H: DnsHandle> DnssecDnsHandle<H> {
                         .map(|r| (r, proof))
                 })
                 .find_map(|(dnskey, proof)| {
-                    verify_rrset_with_dnskey(dnskey, *proof, rrsig, rrset, current_time).ok()
+                    verify_rrset_with_dnskey(
+                        dnskey,
+                        *proof,
+                        rrsig,
+                        rrset,
+                        current_time,
+                        &self.trace,
+                        &self.budget,
+                    )
+                    .ok()
                 });
 
             if let Some(rrset_proof) = rrset_proof {
@@ -747,12 +934,20 @@ impl<H: DnsHandle> DnssecDnsHandle<H> {
         let pub_key = dns_key.public_key();
 
         // Checks to see if the key is valid against the registered root certificates
-        if self.trust_anchor.contains(pub_key) {
+        if self.trust_anchor.get().contains(pub_key) {
             debug!(
                 "validated dnskey with trust_anchor: {}, {dns_key}",
                 rr.name(),
             );
 
+            if let Ok(key_tag) = dns_key.calculate_key_tag() {
+                self.trace.push(ValidationStep::TrustAnchor {
+                    name: rr.name().clone(),
+                    key_tag,
+                    algorithm: dns_key.algorithm(),
+                });
+            }
+
             Proof::Secure
         } else {
             Proof::Bogus
@@ -839,14 +1034,21 @@ impl<H: DnsHandle> DnssecDnsHandle<H> {
                 }
 
                 // TODO: Should this sig.signer_name should be confirmed to be in the same zone as the rrsigs and rrset?
+                let trace = self.trace.clone();
+                let budget = self.budget.clone();
                 Some(
                     self.lookup(query.clone(), options)
                         .first_answer()
                         .map(move |result| match result {
-                            Ok(message) => {
-                                Ok(verify_rrsig_with_keys(message, rrsig, rrset, current_time)
-                                    .map(|(proof, adjusted_ttl)| (proof, adjusted_ttl, Some(i))))
-                            }
+                            Ok(message) => Ok(verify_rrsig_with_keys(
+                                message,
+                                rrsig,
+                                rrset,
+                                current_time,
+                                &trace,
+                                &budget,
+                            )
+                            .map(|(proof, adjusted_ttl)| (proof, adjusted_ttl, Some(i)))),
                             Err(proto) => Err(ProofError::new(
                                 Proof::Bogus,
                                 ProofErrorKind::Proto { query, proto },
@@ -886,13 +1088,42 @@ impl<H: DnsHandle> DnssecDnsHandle<H> {
     /// An internal function used to clone the handle, but maintain some information back to the
     ///  original handle, such as the request_depth such that infinite recursion does
     ///  not occur.
-    fn clone_with_context(&self) -> Self {
+    fn clone_with_context(&self, options: &DnsRequestOptions) -> Self {
         Self {
             handle: self.handle.clone(),
-            trust_anchor: Arc::clone(&self.trust_anchor),
+            trust_anchor: self.trust_anchor.clone(),
+            negative_trust_anchors: self.negative_trust_anchors.clone(),
+            insecure_zones: self.insecure_zones.clone(),
+            // top-level requests asking for a trace get their own, shared with the lookups they make
+            trace: match self.request_depth {
+                0 if options.validation_trace => TraceRecorder::start(),
+                0 => TraceRecorder::default(),
+                _ => self.trace.clone(),
+            },
             request_depth: self.request_depth + 1,
             nsec3_soft_iteration_limit: self.nsec3_soft_iteration_limit,
             nsec3_hard_iteration_limit: self.nsec3_hard_iteration_limit,
+            // as is the budget of signature validations
+            budget: match self.request_depth {
+                0 => self.budget.restart(),
+                _ => self.budget.clone(),
+            },
+        }
+    }
+}
+
+/// The trusted keys of a [`DnssecDnsHandle`]
+#[derive(Clone)]
+enum TrustAnchorSource {
+    Static(Arc<TrustAnchors>),
+    Managed(Arc<TrustAnchorManager>),
+}
+
+impl TrustAnchorSource {
+    fn get(&self) -> Arc<TrustAnchors> {
+        match self {
+            Self::Static(trust_anchors) => trust_anchors.clone(),
+            Self::Managed(manager) => manager.trust_anchors(),
         }
     }
 }
@@ -932,7 +1163,7 @@ impl<H: DnsHandle> DnsHandle for DnssecDnsHandle<H> {
             ))));
         };
 
-        let handle = self.clone_with_context();
+        let handle = self.clone_with_context(request.options());
         request
             .extensions_mut()
             .get_or_insert_with(Edns::new)
@@ -940,7 +1171,10 @@ impl<H: DnsHandle> DnsHandle for DnssecDnsHandle<H> {
 
         request.set_authentic_data(true);
         request.set_checking_disabled(false);
-        let options = *request.options();
+        // the client subnet is only sent with the query itself, not with the DS and DNSKEY queries
+        // made to validate the response
+        let mut options = *request.options();
+        options.client_subnet = None;
 
         Box::pin(self.handle.send(request).then(move |result| {
             handle
@@ -955,6 +1189,8 @@ fn verify_rrsig_with_keys(
     rrsig: &RecordRef<'_, RRSIG>,
     rrset: &Rrset<'_>,
     current_time: u32,
+    trace: &TraceRecorder,
+    budget: &ValidationBudget,
 ) -> Option<(Proof, Option<u32>)> {
     let mut tag_count = HashMap::<u16, usize>::new();
 
@@ -983,7 +1219,7 @@ fn verify_rrsig_with_keys(
         match tag_count.get_mut(&tag) {
             Some(n_keys) => {
                 *n_keys += 1;
-                if *n_keys > MAX_KEY_TAG_COLLISIONS {
+                if !budget.try_key_tag_collision(*n_keys) {
                     warn!("too many ({n_keys}) DNSKEYs with key tag {tag}; skipping");
                     return None;
                 }
@@ -991,17 +1227,25 @@ fn verify_rrsig_with_keys(
             None => _ = tag_count.insert(tag, 1),
         }
 
-        Some(dnskey)
+        Some((tag, dnskey))
     });
 
     let mut all_insecure = None;
-    for dnskey in dnskeys {
+    let mut key_tag_found = false;
+    for (tag, dnskey) in dnskeys {
+        key_tag_found |= tag == rrsig.data().input().key_tag;
         match dnskey.proof() {
             Proof::Secure => {
                 all_insecure = Some(false);
-                if let Ok(proof) =
-                    verify_rrset_with_dnskey(dnskey, dnskey.proof(), rrsig, rrset, current_time)
-                {
+                if let Ok(proof) = verify_rrset_with_dnskey(
+                    dnskey,
+                    dnskey.proof(),
+                    rrsig,
+                    rrset,
+                    current_time,
+                    trace,
+                    budget,
+                ) {
                     return Some((proof.0, proof.1));
                 }
             }
@@ -1016,6 +1260,9 @@ fn verify_rrsig_with_keys(
         // inherit Insecure state
         Some((Proof::Insecure, None))
     } else {
+        if !key_tag_found {
+            trace_rrsig(trace, rrsig, rrset, Err(ValidationFailure::WrongKeyTag));
+        }
         None
     }
 }
@@ -1037,6 +1284,8 @@ fn find_soa_name(verified_message: &DnsResponse) -> Result<&Name, ProtoError> {
 fn verify_dnskey(
     rr: &RecordRef<'_, DNSKEY>,
     ds_records: &[Record<DS>],
+    trace: &TraceRecorder,
+    budget: &ValidationBudget,
 ) -> Result<Proof, ProofError> {
     let key_rdata = rr.data();
     let key_tag = key_rdata.calculate_key_tag().map_err(|_| {
@@ -1048,8 +1297,17 @@ fn verify_dnskey(
         )
     })?;
     let key_algorithm = key_rdata.algorithm();
+    let trace_ds = |result| {
+        trace.push(ValidationStep::Ds {
+            name: rr.name().clone(),
+            key_tag,
+            algorithm: key_algorithm,
+            result,
+        })
+    };
 
     if !key_algorithm.is_supported() {
+        trace_ds(Err(ValidationFailure::UnsupportedAlgorithm(key_algorithm)));
         return Err(ProofError::new(
             Proof::Insecure,
             ProofErrorKind::UnsupportedKeyAlgorithm,
@@ -1058,6 +1316,7 @@ fn verify_dnskey(
 
     // DS check if covered by DS keys
     let mut key_authentication_attempts = 0;
+    let mut key_tag_found = false;
     for r in ds_records.iter().filter(|ds| ds.proof().is_secure()) {
         if r.data().algorithm() != key_algorithm {
             trace!(
@@ -1080,11 +1339,14 @@ fn verify_dnskey(
             continue;
         }
 
+        key_tag_found = true;
+
         // Count the number of DS records with the same algorithm and key tag as this DNSKEY.
-        // Ignore remaining DS records if there are too many key tag collisions. Doing so before
+        // Ignore remaining DS records if there are too many key tag collisions, and fail the
+        // request if its limits say so. Doing so before
         // checking hashes or signatures protects us from KeyTrap denial of service attacks.
         key_authentication_attempts += 1;
-        if key_authentication_attempts > MAX_KEY_TAG_COLLISIONS {
+        if !budget.try_key_tag_collision(key_authentication_attempts) {
             warn!(
                 key_tag,
                 attempts = key_authentication_attempts,
@@ -1105,10 +1367,15 @@ fn verify_dnskey(
         );
 
         // If this key is valid, then it is secure
+        trace_ds(Ok(r.data().digest_type()));
         return Ok(Proof::Secure);
     }
 
     trace!("bogus dnskey: {}", rr.name());
+    trace_ds(Err(match key_tag_found {
+        true => ValidationFailure::DigestMismatch,
+        false => ValidationFailure::WrongKeyTag,
+    }));
     Err(ProofError::new(
         Proof::Bogus,
         ProofErrorKind::DnsKeyHasNoDs {
@@ -1118,50 +1385,89 @@ fn verify_dnskey(
 }
 
 /// Verifies the given SIG of the RRSET with the DNSKEY.
+///
+/// The outcome is recorded in the `trace` if the key tag of the DNSKEY is the one of the RRSIG.
 fn verify_rrset_with_dnskey(
     dnskey: RecordRef<'_, DNSKEY>,
     dnskey_proof: Proof,
     rrsig: &RecordRef<'_, RRSIG>,
     rrset: &Rrset<'_>,
     current_time: u32,
+    trace: &TraceRecorder,
+    budget: &ValidationBudget,
 ) -> Result<(Proof, Option<u32>), ProofError> {
+    let result = check_rrset_with_dnskey(dnskey, dnskey_proof, rrsig, rrset, current_time, budget);
+    if dnskey.data().calculate_key_tag().ok() == Some(rrsig.data().input.key_tag) {
+        let result = match &result {
+            Ok(_) => Ok(()),
+            Err((_, failure)) => Err(*failure),
+        };
+        trace_rrsig(trace, rrsig, rrset, result);
+    }
+
+    result.map_err(|(err, _)| err)
+}
+
+fn check_rrset_with_dnskey(
+    dnskey: RecordRef<'_, DNSKEY>,
+    dnskey_proof: Proof,
+    rrsig: &RecordRef<'_, RRSIG>,
+    rrset: &Rrset<'_>,
+    current_time: u32,
+    budget: &ValidationBudget,
+) -> Result<(Proof, Option<u32>), (ProofError, ValidationFailure)> {
     match dnskey_proof {
         Proof::Secure => (),
         proof => {
             debug!("insecure dnskey {} {}", dnskey.name(), dnskey.data());
-            return Err(ProofError::new(
-                proof,
-                ProofErrorKind::InsecureDnsKey {
-                    name: dnskey.name().clone(),
-                    key_tag: rrsig.data().input.key_tag,
-                },
+            return Err((
+                ProofError::new(
+                    proof,
+                    ProofErrorKind::InsecureDnsKey {
+                        name: dnskey.name().clone(),
+                        key_tag: rrsig.data().input.key_tag,
+                    },
+                ),
+                ValidationFailure::InsecureKey(proof),
             ));
         }
     }
 
     if dnskey.data().revoke() {
         debug!("revoked dnskey {} {}", dnskey.name(), dnskey.data());
-        return Err(ProofError::new(
-            Proof::Bogus,
-            ProofErrorKind::DnsKeyRevoked {
-                name: dnskey.name().clone(),
-                key_tag: rrsig.data().input.key_tag,
-            },
+        return Err((
+            ProofError::new(
+                Proof::Bogus,
+                ProofErrorKind::DnsKeyRevoked {
+                    name: dnskey.name().clone(),
+                    key_tag: rrsig.data().input.key_tag,
+                },
+            ),
+            ValidationFailure::RevokedKey,
         ));
     } // TODO: does this need to be validated? RFC 5011
     if !dnskey.data().zone_key() {
-        return Err(ProofError::new(
-            Proof::Bogus,
-            ProofErrorKind::NotZoneDnsKey {
-                name: dnskey.name().clone(),
-                key_tag: rrsig.data().input.key_tag,
-            },
+        return Err((
+            ProofError::new(
+                Proof::Bogus,
+                ProofErrorKind::NotZoneDnsKey {
+                    name: dnskey.name().clone(),
+                    key_tag: rrsig.data().input.key_tag,
+                },
+            ),
+            ValidationFailure::NotZoneKey,
         ));
     }
     if dnskey.data().algorithm() != rrsig.data().input.algorithm {
-        return Err(ProofError::new(
-            Proof::Bogus,
-            ProofErrorKind::AlgorithmMismatch {
+        return Err((
+            ProofError::new(
+                Proof::Bogus,
+                ProofErrorKind::AlgorithmMismatch {
+                    rrsig: rrsig.data().input.algorithm,
+                    dnskey: dnskey.data().algorithm(),
+                },
+            ),
+            ValidationFailure::AlgorithmMismatch {
                 rrsig: rrsig.data().input.algorithm,
                 dnskey: dnskey.data().algorithm(),
             },
@@ -1169,12 +1475,27 @@ fn verify_rrset_with_dnskey(
     }
 
     let validity = RrsigValidity::check(*rrsig, rrset, dnskey, current_time);
-    if !matches!(validity, RrsigValidity::ValidRrsig) {
+    let failure = match validity {
+        RrsigValidity::ValidRrsig => None,
+        RrsigValidity::ExpiredRrsig => Some(ValidationFailure::Expired),
+        RrsigValidity::PrematureRrsig => Some(ValidationFailure::NotYetValid),
+        RrsigValidity::WrongDnskey => Some(ValidationFailure::WrongKeyTag),
+        RrsigValidity::WrongRrsig => Some(ValidationFailure::WrongRrsig),
+    };
+    if let Some(failure) = failure {
         // TODO better error handling when the error payload is not immediately discarded by
         // the caller
-        return Err(ProofError::new(
-            Proof::Bogus,
-            ProofErrorKind::Msg(format!("{validity:?}")),
+        return Err((
+            ProofError::new(Proof::Bogus, ProofErrorKind::Msg(format!("{validity:?}"))),
+            failure,
+        ));
+    }
+
+    if !budget.validate_signature() {
+        let limit = ResourceLimit::SignatureValidations;
+        return Err((
+            ProofError::new(Proof::Bogus, ProofErrorKind::ResourceLimitExceeded(limit)),
+            ValidationFailure::LimitExceeded(limit),
         ));
     }
 
@@ -1207,21 +1528,44 @@ fn verify_rrset_with_dnskey(
                 dnskey.name(),
                 dnskey.data()
             );
-            ProofError::new(
-                Proof::Bogus,
-                ProofErrorKind::DnsKeyVerifyRrsig {
-                    name: dnskey.name().clone(),
-                    key_tag: rrsig.data().input.key_tag,
-                    error: e,
-                },
+            (
+                ProofError::new(
+                    Proof::Bogus,
+                    ProofErrorKind::DnsKeyVerifyRrsig {
+                        name: dnskey.name().clone(),
+                        key_tag: rrsig.data().input.key_tag,
+                        error: e,
+                    },
+                ),
+                ValidationFailure::InvalidSignature,
             )
         })
 }
 
+/// Records the outcome of checking an RRSIG in the trace
+fn trace_rrsig(
+    trace: &TraceRecorder,
+    rrsig: &RecordRef<'_, RRSIG>,
+    rrset: &Rrset<'_>,
+    result: Result<(), ValidationFailure>,
+) {
+    let input = rrsig.data().input();
+    trace.push(ValidationStep::Rrsig {
+        name: rrset.name.clone(),
+        record_type: rrset.record_type,
+        signer_name: input.signer_name.clone(),
+        key_tag: input.key_tag,
+        algorithm: input.algorithm,
+        result,
+    });
+}
+
 #[derive(Clone, Copy, Debug)]
 enum RrsigValidity {
     /// RRSIG has already expired
     ExpiredRrsig,
+    /// RRSIG is not valid yet
+    PrematureRrsig,
     /// RRSIG is valid
     ValidRrsig,
     /// DNSKEY does not match RRSIG
@@ -1264,18 +1608,20 @@ impl RrsigValidity {
 
         // Section 3.1.5 of RFC4034 states that 'all comparisons involving these fields MUST use
         // "Serial number arithmetic", as defined in RFC1982'
-        if !(
-            // "The validator's notion of the current time MUST be less than or equal to the time listed
-            // in the RRSIG RR's Expiration field"
-            current_time <= sig_input.sig_expiration &&
-
-            // "The validator's notion of the current time MUST be greater than or equal to the time
-            // listed in the RRSIG RR's Inception field"
-            current_time >= sig_input.sig_inception
-        ) {
+        // "The validator's notion of the current time MUST be less than or equal to the time listed
+        // in the RRSIG RR's Expiration field"
+        let before_expiration = current_time <= sig_input.sig_expiration;
+        if !before_expiration {
             return Self::ExpiredRrsig;
         }
 
+        // "The validator's notion of the current time MUST be greater than or equal to the time
+        // listed in the RRSIG RR's Inception field"
+        let after_inception = current_time >= sig_input.sig_inception;
+        if !after_inception {
+            return Self::PrematureRrsig;
+        }
+
         if !(
             // "The RRSIG RR's Signer's Name, Algorithm, and Key Tag fields MUST match the owner name,
             // algorithm, and key tag for some DNSKEY RR in the zone's apex DNSKEY RRset"
@@ -1529,12 +1875,87 @@ mod rrset {
     }
 }
 
+/// Signature validations made for a top-level request and the lookups it makes, checked against
+/// the limits set with [`DnssecDnsHandle::validation_limits()`]
+#[derive(Clone)]
+struct ValidationBudget {
+    max_signature_validations: Option<u16>,
+    max_key_tag_collisions: Option<u8>,
+    state: Arc<BudgetState>,
+}
+
+#[derive(Default)]
+struct BudgetState {
+    signature_validations: AtomicUsize,
+    /// The first limit which was exceeded
+    exceeded: OnceLock<ResourceLimit>,
+}
+
+impl ValidationBudget {
+    fn new(max_signature_validations: Option<u16>, max_key_tag_collisions: Option<u8>) -> Self {
+        Self {
+            max_signature_validations,
+            max_key_tag_collisions,
+            state: Arc::default(),
+        }
+    }
+
+    /// Returns a new budget with the same limits
+    fn restart(&self) -> Self {
+        Self::new(self.max_signature_validations, self.max_key_tag_collisions)
+    }
+
+    /// Counts a signature validation, returns false if there were too many already
+    fn validate_signature(&self) -> bool {
+        let Some(max) = self.max_signature_validations else {
+            return true;
+        };
+
+        let count = self
+            .state
+            .signature_validations
+            .fetch_add(1, Ordering::Relaxed)
+            + 1;
+        if count <= usize::from(max) {
+            return true;
+        }
+
+        self.exceed(ResourceLimit::SignatureValidations);
+        false
+    }
+
+    /// Returns false if `count` records with the same key tag are too many to try
+    fn try_key_tag_collision(&self, count: usize) -> bool {
+        let Some(max) = self.max_key_tag_collisions else {
+            return count <= MAX_KEY_TAG_COLLISIONS;
+        };
+
+        if count <= usize::from(max) {
+            return true;
+        }
+
+        self.exceed(ResourceLimit::KeyTagCollisions);
+        false
+    }
+
+    fn exceed(&self, limit: ResourceLimit) {
+        if self.state.exceeded.set(limit).is_ok() {
+            warn!("DNSSEC validation limit exceeded: {limit}");
+        }
+    }
+
+    fn exceeded(&self) -> Option<ResourceLimit> {
+        self.state.exceeded.get().copied()
+    }
+}
+
 /// The maximum number of key tag collisions to accept when:
 ///
 /// 1) Retrieving DNSKEY records for a zone
 /// 2) Retrieving DS records from a parent zone
 ///
-/// Any colliding records encountered beyond this limit will be discarded.
+/// Any colliding records encountered beyond this limit will be discarded, unless a limit was set
+/// with [`DnssecDnsHandle::validation_limits()`].
 const MAX_KEY_TAG_COLLISIONS: usize = 2;
 
 /// The maximum number of RRSIGs to attempt to validate for each RRSET.
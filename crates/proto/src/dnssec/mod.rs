@@ -33,6 +33,9 @@ pub mod crypto;
 
 mod ec_public_key;
 
+mod negative_trust_anchor;
+pub use negative_trust_anchor::NegativeTrustAnchors;
+
 mod nsec3;
 pub use nsec3::Nsec3HashAlgorithm;
 
@@ -59,9 +62,15 @@ pub use tbs::TBS;
 mod trust_anchor;
 pub use trust_anchor::TrustAnchors;
 
+mod trust_anchor_manager;
+pub use trust_anchor_manager::{KeyState, TrustAnchorManager};
+
 mod tsig;
 pub use tsig::{TSigResponseContext, TSigner};
 
+mod validation_trace;
+pub use validation_trace::{DenialKind, ValidationFailure, ValidationStep, ValidationTrace};
+
 mod verifier;
 pub use verifier::Verifier;
 
@@ -0,0 +1,134 @@
+//! Negative trust anchors, see [RFC 7646](https://www.rfc-editor.org/rfc/rfc7646)
+
+use alloc::vec::Vec;
+use std::{collections::HashMap, sync::RwLock, time::SystemTime};
+
+use crate::rr::Name;
+
+/// Domains for which DNSSEC validation is disabled, until an expiration time
+///
+/// A negative trust anchor lets a validating resolver keep serving a domain whose DNSSEC is
+/// broken: responses at or below the name of the anchor are treated as insecure rather than bogus.
+/// Anchors can be added and removed at any time, including while the set is shared with running
+/// [`DnssecDnsHandle`](super::DnssecDnsHandle)s. Responses cached before a change are not
+/// affected by it.
+#[derive(Debug, Default)]
+pub struct NegativeTrustAnchors {
+    anchors: RwLock<HashMap<Name, SystemTime>>,
+}
+
+impl NegativeTrustAnchors {
+    /// Creates an empty set of negative trust anchors
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Disables validation for `name` and its subdomains until `expiration`
+    ///
+    /// Returns the previous expiration of the anchor, if `name` was already an anchor.
+    pub fn insert(&self, name: Name, expiration: SystemTime) -> Option<SystemTime> {
+        self.anchors.write().unwrap().insert(name, expiration)
+    }
+
+    /// Re-enables validation for `name`, returns `true` if it was an anchor
+    pub fn remove(&self, name: &Name) -> bool {
+        self.anchors.write().unwrap().remove(name).is_some()
+    }
+
+    /// Replaces all anchors with `anchors`, at once
+    pub fn replace(&self, anchors: impl IntoIterator<Item = (Name, SystemTime)>) {
+        let anchors = anchors.into_iter().collect();
+        *self.anchors.write().unwrap() = anchors;
+    }
+
+    /// Removes all anchors
+    pub fn clear(&self) {
+        self.anchors.write().unwrap().clear();
+    }
+
+    /// Removes anchors which expired at `now`
+    pub fn remove_expired(&self, now: SystemTime) {
+        self.anchors
+            .write()
+            .unwrap()
+            .retain(|_, expiration| *expiration > now);
+    }
+
+    /// Returns the anchors which have not expired at `now`, with their expiration
+    pub fn anchors(&self, now: SystemTime) -> Vec<(Name, SystemTime)> {
+        self.anchors
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, expiration)| **expiration > now)
+            .map(|(name, expiration)| (name.clone(), *expiration))
+            .collect()
+    }
+
+    /// Whether `name` is at or below an anchor which has not expired at `now`
+    pub fn covers(&self, name: &Name, now: SystemTime) -> bool {
+        self.anchors
+            .read()
+            .unwrap()
+            .iter()
+            .any(|(anchor, expiration)| *expiration > now && anchor.zone_of(name))
+    }
+
+    /// Returns true if there are no anchors, expired or not
+    pub fn is_empty(&self) -> bool {
+        self.anchors.read().unwrap().is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_covers() {
+        let now = SystemTime::now();
+        let anchors = NegativeTrustAnchors::new();
+        let name = Name::from_ascii("example.com.").unwrap();
+        anchors.insert(name.clone(), now + Duration::from_secs(60));
+
+        assert!(anchors.covers(&name, now));
+        assert!(anchors.covers(&Name::from_ascii("www.Example.com.").unwrap(), now));
+        assert!(!anchors.covers(&Name::from_ascii("com.").unwrap(), now));
+        assert!(!anchors.covers(&Name::from_ascii("example.net.").unwrap(), now));
+
+        let later = now + Duration::from_secs(60);
+        assert!(!anchors.covers(&name, later));
+        assert!(anchors.anchors(later).is_empty());
+
+        anchors.remove_expired(later);
+        assert!(anchors.is_empty());
+    }
+
+    #[test]
+    fn test_remove() {
+        let now = SystemTime::now();
+        let anchors = NegativeTrustAnchors::new();
+        let name = Name::from_ascii("example.com.").unwrap();
+        anchors.insert(name.clone(), now + Duration::from_secs(60));
+
+        assert_eq!(anchors.anchors(now).len(), 1);
+        assert!(anchors.remove(&name));
+        assert!(!anchors.remove(&name));
+        assert!(!anchors.covers(&name, now));
+    }
+
+    #[test]
+    fn test_replace() {
+        let now = SystemTime::now();
+        let anchors = NegativeTrustAnchors::new();
+        let old = Name::from_ascii("example.com.").unwrap();
+        let new = Name::from_ascii("example.net.").unwrap();
+        anchors.insert(old.clone(), now + Duration::from_secs(60));
+
+        anchors.replace([(new.clone(), now + Duration::from_secs(60))]);
+        assert!(!anchors.covers(&old, now));
+        assert!(anchors.covers(&new, now));
+    }
+}
@@ -18,7 +18,7 @@ use thiserror::Error;
 
 use super::Algorithm;
 use crate::{
-    error::ProtoError,
+    error::{ProtoError, ResourceLimit},
     op::Query,
     rr::{Name, RecordType},
 };
@@ -289,6 +289,10 @@ pub enum ProofErrorKind {
     /// Unsupported key algorithms
     #[error("unsupported key algorithms")]
     UnsupportedKeyAlgorithm,
+
+    /// A limit on the work done to validate the response was exceeded
+    #[error("{0}")]
+    ResourceLimitExceeded(ResourceLimit),
 }
 
 /// The error type for dnssec errors that get returned in the crate
@@ -0,0 +1,839 @@
+//! Automated updates of the root trust anchors, see [RFC 5011](https://www.rfc-editor.org/rfc/rfc5011)
+
+use alloc::{
+    borrow::ToOwned,
+    string::{String, ToString},
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::{fmt, time::Duration};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Mutex, RwLock},
+};
+
+use tracing::{debug, info, warn};
+
+use super::{
+    Algorithm, PublicKey, PublicKeyBuf, TrustAnchors, Verifier,
+    rdata::{DNSKEY, RRSIG},
+};
+use crate::{
+    error::ProtoError,
+    op::{DnsRequestOptions, Query},
+    rr::{DNSClass, Name, Record, RecordType, SerialNumber, resource::RecordRef},
+    runtime::{RuntimeProvider, Time},
+    xfer::{FirstAnswer, dns_handle::DnsHandle},
+};
+
+/// Maintains the root trust anchors by following rollovers of the root key signing keys
+///
+/// The manager starts from an initial set of trusted keys, which are considered valid. It then
+/// periodically fetches the root DNSKEY RRset and tracks the keys of the RRset as described in
+/// [RFC 5011 section 4](https://www.rfc-editor.org/rfc/rfc5011#section-4):
+///
+/// * a new key signing key that is part of an RRset validated by a trusted key is added after the
+///   add hold-down time, as long as it stays in the RRset;
+/// * a key that is revoked with a valid self-signature is immediately untrusted, and forgotten
+///   after the remove hold-down time;
+/// * a trusted key that disappears from the RRset is still trusted, until it reappears or is
+///   revoked.
+///
+/// An RRset whose signature was made before that of the last accepted RRset is ignored, so that
+/// replaying an old RRset cannot undo a rollover, see
+/// [RFC 5011 section 2.2](https://www.rfc-editor.org/rfc/rfc5011#section-2.2).
+///
+/// The state of the keys can be persisted to a file, so that it survives restarts. Use
+/// [`DnssecDnsHandle::with_trust_anchor_manager`](super::DnssecDnsHandle::with_trust_anchor_manager)
+/// to validate responses with the current trust anchors.
+pub struct TrustAnchorManager {
+    state: Mutex<State>,
+    trust_anchors: RwLock<Arc<TrustAnchors>>,
+    state_file: Option<PathBuf>,
+    add_hold_down: Duration,
+    remove_hold_down: Duration,
+}
+
+impl TrustAnchorManager {
+    /// Creates a new manager, initially trusting all of the given keys
+    pub fn new(initial: &TrustAnchors) -> Self {
+        let keys = (0..initial.len())
+            .filter_map(|idx| initial.get(idx))
+            .map(|key| ManagedKey {
+                key: key.clone(),
+                state: KeyState::Valid,
+                since: 0,
+            })
+            .collect();
+
+        Self::from_keys(keys, None, None)
+    }
+
+    /// Creates a new manager which persists its state to `path`
+    ///
+    /// If the file exists, the state is loaded from it and `initial` is ignored, otherwise the
+    /// manager starts from `initial` and creates the file.
+    pub fn with_state_file(initial: &TrustAnchors, path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let manager = match fs::read_to_string(&path) {
+            Ok(contents) => {
+                let (keys, last_inception) = parse_state(&contents)?;
+                Self::from_keys(keys, last_inception, Some(path))
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                let mut manager = Self::new(initial);
+                manager.state_file = Some(path);
+                manager.persist(&manager.state.lock().unwrap())?;
+                manager
+            }
+            Err(err) => return Err(err),
+        };
+
+        Ok(manager)
+    }
+
+    fn from_keys(
+        keys: Vec<ManagedKey>,
+        last_inception: Option<SerialNumber>,
+        state_file: Option<PathBuf>,
+    ) -> Self {
+        let trust_anchors = active_trust_anchors(&keys);
+        Self {
+            state: Mutex::new(State {
+                keys,
+                last_inception,
+                validity: None,
+            }),
+            trust_anchors: RwLock::new(Arc::new(trust_anchors)),
+            state_file,
+            add_hold_down: DEFAULT_HOLD_DOWN,
+            remove_hold_down: DEFAULT_HOLD_DOWN,
+        }
+    }
+
+    /// Sets the time a new key must be observed before it is trusted, 30 days by default
+    pub fn with_add_hold_down(mut self, add_hold_down: Duration) -> Self {
+        self.add_hold_down = add_hold_down;
+        self
+    }
+
+    /// Sets the time a revoked key is remembered before it is forgotten, 30 days by default
+    pub fn with_remove_hold_down(mut self, remove_hold_down: Duration) -> Self {
+        self.remove_hold_down = remove_hold_down;
+        self
+    }
+
+    /// Returns the keys which are currently trusted
+    pub fn trust_anchors(&self) -> Arc<TrustAnchors> {
+        self.trust_anchors.read().unwrap().clone()
+    }
+
+    /// Returns all tracked keys, with their state
+    pub fn keys(&self) -> Vec<(PublicKeyBuf, KeyState)> {
+        self.state
+            .lock()
+            .unwrap()
+            .keys
+            .iter()
+            .map(|key| (key.key.clone(), key.state))
+            .collect()
+    }
+
+    /// Fetches the root DNSKEY RRset through `handle` and updates the trust anchors
+    ///
+    /// On success, returns the time until the next refresh, per
+    /// [RFC 5011 section 2.3](https://www.rfc-editor.org/rfc/rfc5011#section-2.3).
+    pub async fn refresh<H: DnsHandle>(&self, handle: &H) -> Result<Duration, ProtoError> {
+        let options = DnsRequestOptions {
+            use_edns: true,
+            edns_set_dnssec_ok: true,
+            ..DnsRequestOptions::default()
+        };
+
+        let response = handle
+            .lookup(Query::query(Name::root(), RecordType::DNSKEY), options)
+            .first_answer()
+            .await?;
+
+        let current_time = <H::Runtime as RuntimeProvider>::Timer::current_time();
+        self.update(response.answers(), current_time)?;
+        Ok(self.refresh_interval())
+    }
+
+    /// Periodically refreshes the trust anchors of `manager` until it is dropped
+    ///
+    /// This is meant to be spawned in the background.
+    pub async fn run<H: DnsHandle>(manager: Weak<Self>, handle: H) -> Result<(), ProtoError> {
+        loop {
+            let Some(this) = manager.upgrade() else {
+                return Ok(());
+            };
+
+            let interval = match this.refresh(&handle).await {
+                Ok(interval) => interval,
+                Err(err) => {
+                    warn!("failed to refresh the root trust anchors: {err}");
+                    this.retry_interval()
+                }
+            };
+
+            drop(this);
+            debug!("next refresh of the root trust anchors in {interval:?}");
+            <H::Runtime as RuntimeProvider>::Timer::delay_for(interval).await;
+        }
+    }
+
+    /// Updates the state of the keys from the answers to a root DNSKEY query
+    ///
+    /// `records` must contain the DNSKEY RRset and its RRSIGs, `current_time` is the number of
+    /// seconds since the Unix epoch. This fails if the RRset is not signed by a trusted key, in
+    /// which case the state is left untouched. An RRset signed before the last accepted one is
+    /// ignored.
+    pub fn update(&self, records: &[Record], current_time: u64) -> Result<(), ProtoError> {
+        let root = Name::root();
+        let dnskeys = records
+            .iter()
+            .filter(|record| record.name() == &root)
+            .filter_map(|record| record.try_borrow::<DNSKEY>())
+            .collect::<Vec<_>>();
+        let rrsigs = records
+            .iter()
+            .filter(|record| record.name() == &root)
+            .filter_map(|record| record.try_borrow::<RRSIG>())
+            .filter(|rrsig| rrsig.data().input().type_covered == RecordType::DNSKEY)
+            .collect::<Vec<_>>();
+        let rrset = records
+            .iter()
+            .filter(|record| record.name() == &root && record.record_type() == RecordType::DNSKEY)
+            .collect::<Vec<_>>();
+
+        let signed_by = |dnskey: &DNSKEY| {
+            rrsigs
+                .iter()
+                .find(|rrsig| verify_self_signed(dnskey, rrsig, &rrset, current_time as u32))
+        };
+
+        let trust_anchors = self.trust_anchors();
+        let Some(rrsig) = dnskeys
+            .iter()
+            .filter(|dnskey| {
+                let dnskey = dnskey.data();
+                dnskey.zone_key() && !dnskey.revoke() && trust_anchors.contains(dnskey.public_key())
+            })
+            .find_map(|dnskey| signed_by(dnskey.data()))
+        else {
+            return Err(ProtoError::from(
+                "root DNSKEY RRset is not signed by a trusted key",
+            ));
+        };
+
+        let mut state = self.state.lock().unwrap();
+        let inception = rrsig.data().input().sig_inception;
+        let inception_changed = match state.last_inception {
+            Some(last) if inception < last => {
+                warn!(
+                    "ignoring root DNSKEY RRset signed at {}, before the last accepted one at {}",
+                    inception.get(),
+                    last.get()
+                );
+                return Ok(());
+            }
+            Some(last) => inception != last,
+            None => true,
+        };
+        state.last_inception = Some(inception);
+
+        let validity = rrset
+            .first()
+            .map(|record| rrsig.data().authenticated_ttl(record, current_time as u32));
+        state.validity = validity;
+
+        let mut changed = false;
+        for dnskey in &dnskeys {
+            let dnskey = dnskey.data();
+            if !dnskey.zone_key() {
+                continue;
+            }
+
+            let public_key = dnskey.public_key();
+            let existing = state
+                .keys
+                .iter_mut()
+                .find(|key| same_key(&key.key, public_key));
+
+            if dnskey.revoke() {
+                // the revocation must be signed by the revoked key itself
+                let Some(existing) = existing else {
+                    continue;
+                };
+                if existing.state == KeyState::Revoked || signed_by(dnskey).is_none() {
+                    continue;
+                }
+
+                info!("root key {} has been revoked", key_tag(dnskey));
+                existing.transition(KeyState::Revoked, current_time);
+                changed = true;
+                continue;
+            }
+
+            if !dnskey.secure_entry_point() {
+                continue;
+            }
+
+            match existing {
+                None => {
+                    info!(
+                        "new root key {}, trusted after the add hold-down",
+                        key_tag(dnskey)
+                    );
+                    state.keys.push(ManagedKey {
+                        key: public_key.clone(),
+                        state: KeyState::AddPend,
+                        since: current_time,
+                    });
+                    changed = true;
+                }
+                Some(existing) => match existing.state {
+                    KeyState::AddPend
+                        if current_time
+                            >= existing.since.saturating_add(self.add_hold_down.as_secs()) =>
+                    {
+                        info!("root key {} is now trusted", key_tag(dnskey));
+                        existing.transition(KeyState::Valid, current_time);
+                        changed = true;
+                    }
+                    KeyState::Missing => {
+                        existing.transition(KeyState::Valid, current_time);
+                        changed = true;
+                    }
+                    KeyState::AddPend | KeyState::Valid | KeyState::Revoked => {}
+                },
+            }
+        }
+
+        let remove_hold_down = self.remove_hold_down.as_secs();
+        state.keys.retain_mut(|key| {
+            let present = dnskeys
+                .iter()
+                .any(|dnskey| same_key(&key.key, dnskey.data().public_key()));
+
+            match key.state {
+                // a key which disappears during its add hold-down starts over
+                KeyState::AddPend if !present => {
+                    changed = true;
+                    false
+                }
+                KeyState::Valid if !present => {
+                    key.transition(KeyState::Missing, current_time);
+                    changed = true;
+                    true
+                }
+                KeyState::Revoked if current_time >= key.since.saturating_add(remove_hold_down) => {
+                    changed = true;
+                    false
+                }
+                _ => true,
+            }
+        });
+
+        if !changed && !inception_changed {
+            return Ok(());
+        }
+
+        if changed {
+            let trust_anchors = active_trust_anchors(&state.keys);
+            if trust_anchors.is_empty() {
+                warn!("all root trust anchors have been revoked");
+            }
+
+            *self.trust_anchors.write().unwrap() = Arc::new(trust_anchors);
+        }
+
+        if let Err(err) = self.persist(&state) {
+            warn!("failed to persist the root trust anchor state: {err}");
+        }
+
+        Ok(())
+    }
+
+    /// The active refresh interval, `MAX(1 hr, MIN(15 days, 1/2*OrigTTL, 1/2*RRSigExpirationInterval))`
+    fn refresh_interval(&self) -> Duration {
+        let validity = self.state.lock().unwrap().validity;
+        clamp_interval(validity, 2, Duration::from_secs(15 * 24 * 60 * 60))
+    }
+
+    /// The retry interval, `MAX(1 hr, MIN(1 day, 1/10*OrigTTL, 1/10*RRSigExpirationInterval))`
+    fn retry_interval(&self) -> Duration {
+        let validity = self.state.lock().unwrap().validity;
+        clamp_interval(validity, 10, Duration::from_secs(24 * 60 * 60))
+    }
+
+    fn persist(&self, state: &State) -> io::Result<()> {
+        let Some(path) = &self.state_file else {
+            return Ok(());
+        };
+
+        write_state(path, &state.keys, state.last_inception)
+    }
+}
+
+impl fmt::Debug for TrustAnchorManager {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TrustAnchorManager")
+            .field("keys", &self.keys())
+            .field("state_file", &self.state_file)
+            .finish()
+    }
+}
+
+/// The state of a key tracked by the [`TrustAnchorManager`]
+///
+/// See [RFC 5011 section 4](https://www.rfc-editor.org/rfc/rfc5011#section-4).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum KeyState {
+    /// The key has been seen in a validated RRset, but the add hold-down time has not passed yet
+    AddPend,
+    /// The key is trusted
+    Valid,
+    /// The key is trusted, but was missing from the last validated RRset
+    Missing,
+    /// The key has been revoked, and is no longer trusted
+    Revoked,
+}
+
+impl KeyState {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::AddPend => "addpend",
+            Self::Valid => "valid",
+            Self::Missing => "missing",
+            Self::Revoked => "revoked",
+        }
+    }
+}
+
+impl core::str::FromStr for KeyState {
+    type Err = ProtoError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "addpend" => Self::AddPend,
+            "valid" => Self::Valid,
+            "missing" => Self::Missing,
+            "revoked" => Self::Revoked,
+            _ => return Err(ProtoError::from(format!("unknown key state: {s}"))),
+        })
+    }
+}
+
+struct State {
+    keys: Vec<ManagedKey>,
+    /// Inception of the signature of the last accepted RRset, older RRsets are replays
+    last_inception: Option<SerialNumber>,
+    /// Validity of the last validated RRset, in seconds, used to schedule refreshes
+    validity: Option<u32>,
+}
+
+struct ManagedKey {
+    key: PublicKeyBuf,
+    state: KeyState,
+    /// Time of the last state transition, in seconds since the Unix epoch
+    since: u64,
+}
+
+impl ManagedKey {
+    fn transition(&mut self, state: KeyState, current_time: u64) {
+        self.state = state;
+        self.since = current_time;
+    }
+}
+
+fn active_trust_anchors(keys: &[ManagedKey]) -> TrustAnchors {
+    let mut trust_anchors = TrustAnchors::empty();
+    for key in keys {
+        if matches!(key.state, KeyState::Valid | KeyState::Missing) {
+            trust_anchors.insert(&key.key);
+        }
+    }
+
+    trust_anchors
+}
+
+fn same_key(key: &PublicKeyBuf, other: &PublicKeyBuf) -> bool {
+    key.algorithm() == other.algorithm() && key.public_bytes() == other.public_bytes()
+}
+
+fn key_tag(dnskey: &DNSKEY) -> u16 {
+    dnskey.calculate_key_tag().unwrap_or_default()
+}
+
+/// Whether `rrsig` is a currently valid signature of the DNSKEY `rrset` made with `dnskey`
+fn verify_self_signed(
+    dnskey: &DNSKEY,
+    rrsig: &RecordRef<'_, RRSIG>,
+    rrset: &[&Record],
+    current_time: u32,
+) -> bool {
+    let input = rrsig.data().input();
+    let current_time = SerialNumber(current_time);
+    input.algorithm == dnskey.algorithm()
+        && dnskey.calculate_key_tag().ok() == Some(input.key_tag)
+        && input.signer_name.is_root()
+        && input.sig_inception <= current_time
+        && current_time <= input.sig_expiration
+        && dnskey
+            .verify_rrsig(
+                &Name::root(),
+                DNSClass::IN,
+                rrsig.data(),
+                rrset.iter().copied(),
+            )
+            .is_ok()
+}
+
+fn clamp_interval(validity: Option<u32>, divisor: u32, max: Duration) -> Duration {
+    let interval = match validity {
+        Some(validity) => Duration::from_secs(u64::from(validity / divisor)),
+        None => max,
+    };
+
+    interval.clamp(MIN_INTERVAL, max)
+}
+
+fn parse_state(contents: &str) -> io::Result<(Vec<ManagedKey>, Option<SerialNumber>)> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+
+    let mut keys = Vec::new();
+    let mut last_inception = None;
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with(';') {
+            continue;
+        }
+
+        if let Some(inception) = line.strip_prefix("inception ") {
+            let inception = inception
+                .trim()
+                .parse::<u32>()
+                .map_err(|err| invalid(err.to_string()))?;
+            last_inception = Some(SerialNumber(inception));
+            continue;
+        }
+
+        let mut fields = line.split_ascii_whitespace();
+        let (Some(state), Some(since), Some(algorithm), Some(key), None) = (
+            fields.next(),
+            fields.next(),
+            fields.next(),
+            fields.next(),
+            fields.next(),
+        ) else {
+            return Err(invalid(format!("invalid trust anchor state: {line}")));
+        };
+
+        let state = state
+            .parse::<KeyState>()
+            .map_err(|err| invalid(err.to_string()))?;
+        let since = since
+            .parse::<u64>()
+            .map_err(|err| invalid(err.to_string()))?;
+        let algorithm = algorithm
+            .parse::<u8>()
+            .map_err(|err| invalid(err.to_string()))?;
+        let key = data_encoding::BASE64
+            .decode(key.as_bytes())
+            .map_err(|err| invalid(err.to_string()))?;
+
+        keys.push(ManagedKey {
+            key: PublicKeyBuf::new(key, Algorithm::from_u8(algorithm)),
+            state,
+            since,
+        });
+    }
+
+    Ok((keys, last_inception))
+}
+
+/// Writes the state to a temporary file first, so that a crash never leaves a truncated file
+fn write_state(
+    path: &Path,
+    keys: &[ManagedKey],
+    last_inception: Option<SerialNumber>,
+) -> io::Result<()> {
+    let mut contents = "; root trust anchor state, see RFC 5011\n".to_owned();
+    if let Some(inception) = last_inception {
+        contents.push_str("; signature inception of the last accepted RRset\n");
+        contents.push_str(&format!("inception {}\n", inception.get()));
+    }
+    contents.push_str("; state, time of the last transition, algorithm, public key\n");
+    for key in keys {
+        contents.push_str(&format!(
+            "{} {} {} {}\n",
+            key.state.as_str(),
+            key.since,
+            u8::from(key.key.algorithm()),
+            data_encoding::BASE64.encode(key.key.public_bytes()),
+        ));
+    }
+
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, path)
+}
+
+/// Minimum interval between two queries, for both refreshes and retries
+const MIN_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Default add and remove hold-down times, see
+/// [RFC 5011 section 2.4.1](https://www.rfc-editor.org/rfc/rfc5011#section-2.4.1)
+const DEFAULT_HOLD_DOWN: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+#[cfg(test)]
+mod tests {
+    use alloc::boxed::Box;
+
+    use rustls_pki_types::PrivateKeyDer;
+
+    use super::*;
+    use crate::dnssec::{
+        SigningKey, TBS, crypto::EcdsaSigningKey, rdata::DNSSECRData, rdata::SigInput,
+    };
+    use crate::rr::RData;
+
+    const NOW: u64 = 1_700_000_000;
+    const DAY: u64 = 24 * 60 * 60;
+
+    struct Key {
+        signing: Box<dyn SigningKey>,
+        public: PublicKeyBuf,
+    }
+
+    impl Key {
+        fn generate() -> Self {
+            let algorithm = Algorithm::ECDSAP256SHA256;
+            let pkcs8 = EcdsaSigningKey::generate_pkcs8(algorithm).unwrap();
+            let signing =
+                EcdsaSigningKey::from_key_der(&PrivateKeyDer::from(pkcs8), algorithm).unwrap();
+            let public = signing.to_public_key().unwrap();
+            Self {
+                signing: Box::new(signing),
+                public,
+            }
+        }
+
+        fn dnskey(&self, revoke: bool) -> DNSKEY {
+            DNSKEY::new(true, true, revoke, self.public.clone())
+        }
+    }
+
+    /// Builds the answers of a root DNSKEY query, signed by each of the `signers`
+    fn answers(keys: &[(&Key, bool)], signers: &[(&Key, bool)], now: u64) -> Vec<Record> {
+        let mut records = keys
+            .iter()
+            .map(|(key, revoke)| {
+                Record::from_rdata(
+                    Name::root(),
+                    172_800,
+                    RData::DNSSEC(DNSSECRData::DNSKEY(key.dnskey(*revoke))),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let mut rrsigs = Vec::new();
+        for (signer, revoke) in signers {
+            let input = SigInput {
+                type_covered: RecordType::DNSKEY,
+                algorithm: signer.public.algorithm(),
+                num_labels: 0,
+                original_ttl: 172_800,
+                sig_expiration: SerialNumber((now + 14 * DAY) as u32),
+                sig_inception: SerialNumber((now - DAY) as u32),
+                key_tag: signer.dnskey(*revoke).calculate_key_tag().unwrap(),
+                signer_name: Name::root(),
+            };
+            let tbs = TBS::from_input(&Name::root(), DNSClass::IN, &input, records.iter()).unwrap();
+            let sig = signer.signing.sign(&tbs).unwrap();
+            rrsigs.push(Record::from_rdata(
+                Name::root(),
+                172_800,
+                RData::DNSSEC(DNSSECRData::RRSIG(RRSIG::from_sig(input, sig))),
+            ));
+        }
+
+        records.extend(rrsigs);
+        records
+    }
+
+    fn manager(keys: &[&Key]) -> TrustAnchorManager {
+        let mut initial = TrustAnchors::empty();
+        for key in keys {
+            initial.insert(&key.public);
+        }
+
+        TrustAnchorManager::new(&initial)
+    }
+
+    fn state(manager: &TrustAnchorManager, key: &Key) -> Option<KeyState> {
+        manager
+            .keys()
+            .into_iter()
+            .find(|(public, _)| same_key(public, &key.public))
+            .map(|(_, state)| state)
+    }
+
+    #[test]
+    fn test_rollover() {
+        let old = Key::generate();
+        let new = Key::generate();
+        let manager = manager(&[&old]);
+
+        // the new key is published, but only trusted after the add hold-down
+        let published = answers(&[(&old, false), (&new, false)], &[(&old, false)], NOW);
+        manager.update(&published, NOW).unwrap();
+        assert_eq!(state(&manager, &new), Some(KeyState::AddPend));
+        assert!(!manager.trust_anchors().contains(&new.public));
+
+        let later = NOW + 29 * DAY;
+        let published = answers(&[(&old, false), (&new, false)], &[(&old, false)], later);
+        manager.update(&published, later).unwrap();
+        assert_eq!(state(&manager, &new), Some(KeyState::AddPend));
+
+        let later = NOW + 31 * DAY;
+        let published = answers(&[(&old, false), (&new, false)], &[(&old, false)], later);
+        manager.update(&published, later).unwrap();
+        assert_eq!(state(&manager, &new), Some(KeyState::Valid));
+        assert!(manager.trust_anchors().contains(&new.public));
+
+        // the old key is revoked, signing the RRset with its revoked self
+        let later = NOW + 40 * DAY;
+        let revoked = answers(
+            &[(&old, true), (&new, false)],
+            &[(&old, true), (&new, false)],
+            later,
+        );
+        manager.update(&revoked, later).unwrap();
+        assert_eq!(state(&manager, &old), Some(KeyState::Revoked));
+        assert!(!manager.trust_anchors().contains(&old.public));
+        assert_eq!(manager.trust_anchors().len(), 1);
+
+        // and forgotten after the remove hold-down
+        let later = NOW + 71 * DAY;
+        let removed = answers(&[(&new, false)], &[(&new, false)], later);
+        manager.update(&removed, later).unwrap();
+        assert_eq!(state(&manager, &old), None);
+        assert_eq!(state(&manager, &new), Some(KeyState::Valid));
+    }
+
+    #[test]
+    fn test_untrusted_rrset() {
+        let trusted = Key::generate();
+        let other = Key::generate();
+        let manager = manager(&[&trusted]);
+
+        let published = answers(&[(&other, false)], &[(&other, false)], NOW);
+        assert!(manager.update(&published, NOW).is_err());
+        assert_eq!(state(&manager, &other), None);
+    }
+
+    #[test]
+    fn test_add_pend_key_disappears() {
+        let trusted = Key::generate();
+        let new = Key::generate();
+        let manager = manager(&[&trusted]);
+
+        let published = answers(
+            &[(&trusted, false), (&new, false)],
+            &[(&trusted, false)],
+            NOW,
+        );
+        manager.update(&published, NOW).unwrap();
+        assert_eq!(state(&manager, &new), Some(KeyState::AddPend));
+
+        let later = NOW + DAY;
+        let published = answers(&[(&trusted, false)], &[(&trusted, false)], later);
+        manager.update(&published, later).unwrap();
+        assert_eq!(state(&manager, &new), None);
+    }
+
+    #[test]
+    fn test_missing_key() {
+        let first = Key::generate();
+        let second = Key::generate();
+        let manager = manager(&[&first, &second]);
+
+        let published = answers(&[(&first, false)], &[(&first, false)], NOW);
+        manager.update(&published, NOW).unwrap();
+        assert_eq!(state(&manager, &second), Some(KeyState::Missing));
+        assert!(manager.trust_anchors().contains(&second.public));
+
+        let published = answers(
+            &[(&first, false), (&second, false)],
+            &[(&first, false)],
+            NOW,
+        );
+        manager.update(&published, NOW).unwrap();
+        assert_eq!(state(&manager, &second), Some(KeyState::Valid));
+    }
+
+    #[test]
+    fn test_revocation_requires_self_signature() {
+        let first = Key::generate();
+        let second = Key::generate();
+        let manager = manager(&[&first, &second]);
+
+        let published = answers(&[(&first, false), (&second, true)], &[(&first, false)], NOW);
+        manager.update(&published, NOW).unwrap();
+        assert_ne!(state(&manager, &second), Some(KeyState::Revoked));
+    }
+
+    #[test]
+    fn test_replayed_rrset() {
+        let trusted = Key::generate();
+        let new = Key::generate();
+        let manager = manager(&[&trusted]);
+
+        let before = answers(&[(&trusted, false)], &[(&trusted, false)], NOW - DAY);
+        manager.update(&before, NOW - DAY).unwrap();
+        let published = answers(
+            &[(&trusted, false), (&new, false)],
+            &[(&trusted, false)],
+            NOW,
+        );
+        manager.update(&published, NOW).unwrap();
+        assert_eq!(state(&manager, &new), Some(KeyState::AddPend));
+
+        // the earlier RRset is still validly signed, but does not restart the add hold-down
+        manager.update(&before, NOW + DAY).unwrap();
+        assert_eq!(state(&manager, &new), Some(KeyState::AddPend));
+    }
+
+    #[test]
+    fn test_state_file() {
+        let old = Key::generate();
+        let new = Key::generate();
+        let mut initial = TrustAnchors::empty();
+        initial.insert(&old.public);
+
+        let path =
+            std::env::temp_dir().join(format!("hickory-rfc5011-{}.state", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let manager = TrustAnchorManager::with_state_file(&initial, &path).unwrap();
+        let published = answers(&[(&old, false), (&new, false)], &[(&old, false)], NOW);
+        manager.update(&published, NOW).unwrap();
+        drop(manager);
+
+        let manager = TrustAnchorManager::with_state_file(&TrustAnchors::empty(), &path).unwrap();
+        assert_eq!(state(&manager, &old), Some(KeyState::Valid));
+        assert_eq!(state(&manager, &new), Some(KeyState::AddPend));
+        assert!(manager.trust_anchors().contains(&old.public));
+
+        // the inception of the last accepted RRset is restored too
+        let before = answers(&[(&old, false)], &[(&old, false)], NOW - DAY);
+        manager.update(&before, NOW).unwrap();
+        assert_eq!(state(&manager, &new), Some(KeyState::AddPend));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,374 @@
+//! Diagnostics recorded while validating a response
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+use core::fmt;
+use std::sync::Mutex;
+
+use crate::{
+    dnssec::{Algorithm, DigestType, Proof},
+    error::ResourceLimit,
+    rr::{Name, RecordType},
+};
+
+/// The steps taken by a [`DnssecDnsHandle`](super::DnssecDnsHandle) to validate a response
+///
+/// This covers the RRsets of the response, as well as the DS and DNSKEY RRsets of the chain of
+/// trust fetched to validate them. Steps are recorded in the order in which they completed, so the
+/// DNSKEYs a signature was checked against appear before the RRset they validated.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ValidationTrace {
+    steps: Vec<ValidationStep>,
+}
+
+impl ValidationTrace {
+    /// Returns the recorded steps, in the order in which they completed
+    pub fn steps(&self) -> &[ValidationStep] {
+        &self.steps
+    }
+
+    /// Returns true if no steps were recorded
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Returns the steps which failed
+    pub fn failures(&self) -> impl Iterator<Item = &ValidationStep> + '_ {
+        self.steps.iter().filter(|step| step.is_failure())
+    }
+}
+
+impl FromIterator<ValidationStep> for ValidationTrace {
+    fn from_iter<I: IntoIterator<Item = ValidationStep>>(iter: I) -> Self {
+        Self {
+            steps: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl Extend<ValidationStep> for ValidationTrace {
+    fn extend<I: IntoIterator<Item = ValidationStep>>(&mut self, iter: I) {
+        self.steps.extend(iter);
+    }
+}
+
+impl fmt::Display for ValidationTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for step in &self.steps {
+            writeln!(f, "{step}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A single step of a [`ValidationTrace`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum ValidationStep {
+    /// The outcome of the validation of an RRset
+    Rrset {
+        /// Owner name of the RRset
+        name: Name,
+        /// Type of the RRset
+        record_type: RecordType,
+        /// The proof assigned to the records of the RRset
+        proof: Proof,
+        /// Why the RRset is not secure, if it is not
+        reason: Option<String>,
+    },
+    /// An RRSIG was checked with the DNSKEY matching its key tag
+    Rrsig {
+        /// Owner name of the signed RRset
+        name: Name,
+        /// Type of the signed RRset
+        record_type: RecordType,
+        /// Name of the zone which signed the RRset
+        signer_name: Name,
+        /// Key tag of the signing DNSKEY
+        key_tag: u16,
+        /// Algorithm of the signature
+        algorithm: Algorithm,
+        /// Whether the signature was valid
+        result: Result<(), ValidationFailure>,
+    },
+    /// A DNSKEY is one of the configured trust anchors
+    TrustAnchor {
+        /// Owner name of the DNSKEY
+        name: Name,
+        /// Key tag of the DNSKEY
+        key_tag: u16,
+        /// Algorithm of the DNSKEY
+        algorithm: Algorithm,
+    },
+    /// A DNSKEY was checked against the DS RRset of the parent zone
+    Ds {
+        /// Owner name of the DNSKEY
+        name: Name,
+        /// Key tag of the DNSKEY
+        key_tag: u16,
+        /// Algorithm of the DNSKEY
+        algorithm: Algorithm,
+        /// The digest type of the matching DS record, or why none matched
+        result: Result<DigestType, ValidationFailure>,
+    },
+    /// A negative response was checked for an authenticated denial of existence
+    Denial {
+        /// The queried name
+        name: Name,
+        /// The queried type
+        record_type: RecordType,
+        /// The kind of records the proof was made of
+        kind: DenialKind,
+        /// The outcome of the proof
+        proof: Proof,
+    },
+    /// Validation was skipped because a negative trust anchor covers the name
+    NegativeTrustAnchor {
+        /// The covered name
+        name: Name,
+    },
+}
+
+impl ValidationStep {
+    /// Returns true if this step did not succeed
+    pub fn is_failure(&self) -> bool {
+        match self {
+            Self::Rrset { proof, .. } | Self::Denial { proof, .. } => proof.is_bogus(),
+            Self::Rrsig { result, .. } => result.is_err(),
+            Self::Ds { result, .. } => result.is_err(),
+            Self::TrustAnchor { .. } | Self::NegativeTrustAnchor { .. } => false,
+        }
+    }
+}
+
+impl fmt::Display for ValidationStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Rrset {
+                name,
+                record_type,
+                proof,
+                reason,
+            } => {
+                write!(f, "{name} {record_type}: {proof}")?;
+                if let Some(reason) = reason {
+                    write!(f, ", {reason}")?;
+                }
+                Ok(())
+            }
+            Self::Rrsig {
+                name,
+                record_type,
+                signer_name,
+                key_tag,
+                algorithm,
+                result,
+            } => {
+                write!(
+                    f,
+                    "RRSIG {name} {record_type} by {signer_name} key tag {key_tag} ({algorithm}): "
+                )?;
+                match result {
+                    Ok(()) => write!(f, "valid"),
+                    Err(failure) => write!(f, "invalid, {failure}"),
+                }
+            }
+            Self::TrustAnchor {
+                name,
+                key_tag,
+                algorithm,
+            } => write!(
+                f,
+                "DNSKEY {name} key tag {key_tag} ({algorithm}): trust anchor"
+            ),
+            Self::Ds {
+                name,
+                key_tag,
+                algorithm,
+                result,
+            } => {
+                write!(f, "DNSKEY {name} key tag {key_tag} ({algorithm}): ")?;
+                match result {
+                    Ok(digest_type) => write!(f, "matches DS with digest {digest_type:?}"),
+                    Err(failure) => write!(f, "not authenticated by DS, {failure}"),
+                }
+            }
+            Self::Denial {
+                name,
+                record_type,
+                kind,
+                proof,
+            } => write!(f, "{kind} denial of {name} {record_type}: {proof}"),
+            Self::NegativeTrustAnchor { name } => {
+                write!(f, "{name}: covered by a negative trust anchor")
+            }
+        }
+    }
+}
+
+/// Why a signature or a DNSKEY could not be authenticated
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum ValidationFailure {
+    /// The signature expiration is in the past
+    Expired,
+    /// The signature inception is in the future
+    NotYetValid,
+    /// No key with the key tag of the signature or of the DNSKEY was found
+    WrongKeyTag,
+    /// The owner name, class, type or labels of the signature do not match the RRset
+    WrongRrsig,
+    /// The algorithm is not supported
+    UnsupportedAlgorithm(Algorithm),
+    /// The algorithm of the signature is not the algorithm of the DNSKEY
+    AlgorithmMismatch {
+        /// Algorithm of the signature
+        rrsig: Algorithm,
+        /// Algorithm of the DNSKEY
+        dnskey: Algorithm,
+    },
+    /// DS records with the key tag of the DNSKEY exist, but none has its digest
+    DigestMismatch,
+    /// The cryptographic verification of the signature failed
+    InvalidSignature,
+    /// The DNSKEY has the REVOKE flag set
+    RevokedKey,
+    /// The DNSKEY does not have the Zone Key flag set
+    NotZoneKey,
+    /// The DNSKEY itself was not proven secure
+    InsecureKey(Proof),
+    /// The signature was not checked, as a limit on the work done to validate the response was
+    /// exceeded
+    LimitExceeded(ResourceLimit),
+}
+
+impl fmt::Display for ValidationFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Expired => write!(f, "signature expired"),
+            Self::NotYetValid => write!(f, "signature not yet valid"),
+            Self::WrongKeyTag => write!(f, "no key with a matching key tag"),
+            Self::WrongRrsig => write!(f, "signature does not cover the rrset"),
+            Self::UnsupportedAlgorithm(algorithm) => write!(f, "unsupported algorithm {algorithm}"),
+            Self::AlgorithmMismatch { rrsig, dnskey } => {
+                write!(f, "algorithm mismatch, rrsig: {rrsig} dnskey: {dnskey}")
+            }
+            Self::DigestMismatch => write!(f, "digest mismatch"),
+            Self::InvalidSignature => write!(f, "invalid signature"),
+            Self::RevokedKey => write!(f, "key revoked"),
+            Self::NotZoneKey => write!(f, "not a zone key"),
+            Self::InsecureKey(proof) => write!(f, "key is {proof}"),
+            Self::LimitExceeded(limit) => write!(f, "not checked, {limit}"),
+        }
+    }
+}
+
+/// The records used for an authenticated denial of existence
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum DenialKind {
+    /// NSEC records, see [RFC 4035](https://www.rfc-editor.org/rfc/rfc4035#section-5.4)
+    Nsec,
+    /// NSEC3 records, see [RFC 5155](https://www.rfc-editor.org/rfc/rfc5155#section-8)
+    Nsec3,
+    /// Both NSEC and NSEC3 records were present, which is never valid
+    Both,
+    /// Neither NSEC nor NSEC3 records were present
+    Missing,
+}
+
+impl fmt::Display for DenialKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Nsec => "NSEC",
+            Self::Nsec3 => "NSEC3",
+            Self::Both => "NSEC and NSEC3",
+            Self::Missing => "missing",
+        })
+    }
+}
+
+/// Collects the steps of a validation, shared by the nested lookups it makes
+#[derive(Clone, Default)]
+pub(super) struct TraceRecorder(Option<Arc<Mutex<ValidationTrace>>>);
+
+impl TraceRecorder {
+    /// Starts a new, empty trace
+    pub(super) fn start() -> Self {
+        Self(Some(Arc::default()))
+    }
+
+    pub(super) fn push(&self, step: ValidationStep) {
+        if let Some(trace) = &self.0 {
+            trace.lock().unwrap().steps.push(step);
+        }
+    }
+
+    /// Returns a copy of the steps recorded so far
+    pub(super) fn snapshot(&self) -> Option<Arc<ValidationTrace>> {
+        self.0
+            .as_ref()
+            .map(|trace| Arc::new(trace.lock().unwrap().clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let name = Name::from_ascii("example.com.").unwrap();
+        let trace = [
+            ValidationStep::Ds {
+                name: name.clone(),
+                key_tag: 12345,
+                algorithm: Algorithm::ECDSAP256SHA256,
+                result: Err(ValidationFailure::DigestMismatch),
+            },
+            ValidationStep::Rrsig {
+                name: name.clone(),
+                record_type: RecordType::A,
+                signer_name: name.clone(),
+                key_tag: 54321,
+                algorithm: Algorithm::ECDSAP256SHA256,
+                result: Err(ValidationFailure::Expired),
+            },
+            ValidationStep::Rrset {
+                name: name.clone(),
+                record_type: RecordType::A,
+                proof: Proof::Bogus,
+                reason: None,
+            },
+        ]
+        .into_iter()
+        .collect::<ValidationTrace>();
+
+        assert_eq!(trace.failures().count(), 3);
+        assert_eq!(
+            trace.to_string(),
+            "DNSKEY example.com. key tag 12345 (ECDSAP256SHA256): not authenticated by DS, digest mismatch\n\
+             RRSIG example.com. A by example.com. key tag 54321 (ECDSAP256SHA256): invalid, signature expired\n\
+             example.com. A: Bogus\n"
+        );
+    }
+
+    #[test]
+    fn test_recorder() {
+        let step = ValidationStep::NegativeTrustAnchor {
+            name: Name::from_ascii("example.com.").unwrap(),
+        };
+
+        let disabled = TraceRecorder::default();
+        disabled.push(step.clone());
+        assert!(disabled.snapshot().is_none());
+
+        let recorder = TraceRecorder::start();
+        recorder.clone().push(step.clone());
+        let trace = recorder.snapshot().unwrap();
+        assert_eq!(trace.steps(), &[step]);
+        assert_eq!(trace.failures().count(), 0);
+    }
+}
@@ -284,6 +284,10 @@ pub enum ProtoErrorKind {
         count: usize,
     },
 
+    /// The work done to answer a query exceeded one of the configured limits
+    #[error("resource limit exceeded: {0}")]
+    ResourceLimitExceeded(ResourceLimit),
+
     // foreign
     /// An error got returned from IO
     #[cfg(feature = "std")]
@@ -376,6 +380,28 @@ impl From<io::Error> for ProtoErrorKind {
     }
 }
 
+/// A limit on the work done to answer a single query, protecting against resource exhaustion
+/// attacks such as NXNS and KeyTrap
+///
+/// The description of each limit is the reason given to clients, like the extra text of an
+/// Extended DNS Error.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Error)]
+#[non_exhaustive]
+pub enum ResourceLimit {
+    /// Name server names without glue resolved for a single referral
+    #[error("too many name server names to resolve for a referral")]
+    NameServerNames,
+    /// Queries sent to name servers
+    #[error("too many queries to name servers")]
+    UpstreamQueries,
+    /// DNSSEC signatures validated
+    #[error("too many DNSSEC signatures to validate")]
+    SignatureValidations,
+    /// DNSKEY or DS records with the same key tag tried
+    #[error("too many DNSSEC keys with the same key tag")]
+    KeyTagCollisions,
+}
+
 /// Semantic DNS errors
 #[derive(Clone, Debug, EnumAsInner, Error)]
 #[non_exhaustive]
@@ -12,6 +12,9 @@ use core::ops::{Deref, DerefMut};
 #[cfg(feature = "std")]
 use crate::op::Edns;
 use crate::op::{Message, Query};
+use crate::rr::rdata::opt::ClientSubnet;
+#[cfg(feature = "std")]
+use crate::rr::rdata::opt::EdnsOption;
 
 /// A set of options for expressing options to how requests should be treated
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -26,9 +29,19 @@ pub struct DnsRequestOptions {
     pub max_request_depth: usize,
     /// set recursion desired (or not) for any requests
     pub recursion_desired: bool,
+    /// set checking disabled (or not) for any requests
+    pub checking_disabled: bool,
+    /// When set, adds an EDNS Client Subnet option to the request, which enables EDNS
+    pub client_subnet: Option<ClientSubnet>,
     /// Randomize case of query name, and check that the response matches, for spoofing resistance.
     #[cfg(feature = "std")]
     pub case_randomization: bool,
+    /// Record the steps taken to validate the response, see
+    /// [`DnsResponse::validation_trace`](super::DnsResponse::validation_trace)
+    ///
+    /// This is only done by a [`DnssecDnsHandle`](crate::dnssec::DnssecDnsHandle).
+    #[cfg(feature = "__dnssec")]
+    pub validation_trace: bool,
 }
 
 impl Default for DnsRequestOptions {
@@ -38,8 +51,12 @@ impl Default for DnsRequestOptions {
             use_edns: false,
             edns_set_dnssec_ok: false,
             recursion_desired: true,
+            checking_disabled: false,
+            client_subnet: None,
             #[cfg(feature = "std")]
             case_randomization: false,
+            #[cfg(feature = "__dnssec")]
+            validation_trace: false,
         }
     }
 }
@@ -69,7 +86,8 @@ impl DnsRequest {
 
         message
             .add_query(query)
-            .set_recursion_desired(options.recursion_desired);
+            .set_recursion_desired(options.recursion_desired)
+            .set_checking_disabled(options.checking_disabled);
 
         if options.use_edns {
             message
@@ -79,6 +97,15 @@ impl DnsRequest {
                 .set_dnssec_ok(options.edns_set_dnssec_ok);
         }
 
+        if let Some(subnet) = options.client_subnet {
+            message
+                .extensions_mut()
+                .get_or_insert_with(Edns::new)
+                .set_max_payload(MAX_PAYLOAD_LEN)
+                .options_mut()
+                .insert(EdnsOption::Subnet(subnet));
+        }
+
         Self::new(message, options).with_original_query(original_query)
     }
 
@@ -7,6 +7,8 @@
 
 //! `DnsResponse` wraps a `Message` and any associated connection details
 
+#[cfg(feature = "__dnssec")]
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::{
     convert::TryFrom,
@@ -16,6 +18,8 @@ use core::{
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
+#[cfg(feature = "__dnssec")]
+use crate::dnssec::ValidationTrace;
 use crate::{
     error::ProtoError,
     op::Message,
@@ -32,6 +36,9 @@ use crate::{
 pub struct DnsResponse {
     message: Message,
     buffer: Vec<u8>,
+    #[cfg(feature = "__dnssec")]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    validation_trace: Option<Arc<ValidationTrace>>,
 }
 
 // TODO: when `impl Trait` lands in stable, remove this, and expose FlatMap over answers, et al.
@@ -41,6 +48,8 @@ impl DnsResponse {
         Ok(Self {
             buffer: message.to_vec()?,
             message,
+            #[cfg(feature = "__dnssec")]
+            validation_trace: None,
         })
     }
 
@@ -49,7 +58,12 @@ impl DnsResponse {
     /// Returns an error if the response message cannot be decoded.
     pub fn from_buffer(buffer: Vec<u8>) -> Result<Self, ProtoError> {
         let message = Message::from_vec(&buffer)?;
-        Ok(Self { message, buffer })
+        Ok(Self {
+            message,
+            buffer,
+            #[cfg(feature = "__dnssec")]
+            validation_trace: None,
+        })
     }
 
     /// Retrieves the SOA from the response. This will only exist if it was an authoritative response.
@@ -153,6 +167,20 @@ impl DnsResponse {
         false
     }
 
+    /// The steps taken to validate this response, if it was validated by a
+    /// [`DnssecDnsHandle`](crate::dnssec::DnssecDnsHandle) for a request with
+    /// [`DnsRequestOptions::validation_trace`](super::DnsRequestOptions::validation_trace) set
+    #[cfg(feature = "__dnssec")]
+    pub fn validation_trace(&self) -> Option<&Arc<ValidationTrace>> {
+        self.validation_trace.as_ref()
+    }
+
+    /// Set the validation trace of this response
+    #[cfg(feature = "__dnssec")]
+    pub fn set_validation_trace(&mut self, trace: Arc<ValidationTrace>) {
+        self.validation_trace = Some(trace);
+    }
+
     /// Borrow the inner buffer from the response
     pub fn as_buffer(&self) -> &[u8] {
         &self.buffer
@@ -22,6 +22,7 @@ use tracing::warn;
 
 use crate::{
     error::{ProtoError, ProtoErrorKind, ProtoResult},
+    op::Message,
     rr::{RData, RecordData, RecordDataDecodable, RecordType},
     serialize::binary::{
         BinDecodable, BinDecoder, BinEncodable, BinEncoder, RDataEncoding, Restrict,
@@ -647,6 +648,21 @@ impl ClientSubnet {
         self.scope_prefix = scope_prefix;
     }
 
+    /// Returns the scope prefix of the client subnet option of a response
+    ///
+    /// This is the number of leading bits of the client address the response is valid for, RFC
+    /// 7871 section 7.3.1. Responses without the option have a scope of 0, valid for all clients.
+    pub fn response_scope(response: &Message) -> u8 {
+        match response
+            .extensions()
+            .as_ref()
+            .and_then(|edns| edns.option(EdnsCode::Subnet))
+        {
+            Some(EdnsOption::Subnet(subnet)) => subnet.scope_prefix(),
+            _ => 0,
+        }
+    }
+
     fn addr_len(&self) -> u16 {
         let source_prefix = self.source_prefix as u16;
         source_prefix / 8 + if source_prefix % 8 > 0 { 1 } else { 0 }
@@ -115,6 +115,8 @@ pub enum RecordType {
     TSIG,
     /// [RFC 1035](https://tools.ietf.org/html/rfc1035) Text record
     TXT,
+    /// [RFC 8976](https://tools.ietf.org/html/rfc8976) Message digest for DNS zones
+    ZONEMD,
     /// Unknown Record type, or unsupported
     Unknown(u16),
 
@@ -246,6 +248,7 @@ impl FromStr for RecordType {
             "TLSA" => Ok(Self::TLSA),
             "TXT" => Ok(Self::TXT),
             "TSIG" => Ok(Self::TSIG),
+            "ZONEMD" => Ok(Self::ZONEMD),
             "ANY" | "*" => Ok(Self::ANY),
             _ => Err(DecodeError::UnknownRecordTypeStr(str.to_owned())),
         }
@@ -300,6 +303,7 @@ impl From<u16> for RecordType {
             52 => Self::TLSA,
             250 => Self::TSIG,
             16 => Self::TXT,
+            63 => Self::ZONEMD,
             0 => Self::ZERO,
             // all unknown record types
             _ => Self::Unknown(value),
@@ -376,6 +380,7 @@ impl From<RecordType> for &'static str {
             RecordType::TLSA => "TLSA",
             RecordType::TSIG => "TSIG",
             RecordType::TXT => "TXT",
+            RecordType::ZONEMD => "ZONEMD",
             RecordType::ZERO => "ZERO",
             RecordType::Unknown(_) => "Unknown",
         }
@@ -430,6 +435,7 @@ impl From<RecordType> for u16 {
             RecordType::TLSA => 52,
             RecordType::TSIG => 250,
             RecordType::TXT => 16,
+            RecordType::ZONEMD => 63,
             RecordType::ZERO => 0,
             RecordType::Unknown(code) => code,
         }
@@ -538,6 +544,7 @@ mod tests {
             "SSHFP",
             "TLSA",
             "TXT",
+            "ZONEMD",
             "ANY",
             "AXFR",
         ];
@@ -7,13 +7,15 @@
 
 //! TLS protocol related components for DNS over TLS
 
-use alloc::sync::Arc;
+use alloc::{sync::Arc, vec::Vec};
 
 #[cfg(not(feature = "rustls-platform-verifier"))]
 use rustls::RootCertStore;
 use rustls::{
-    ClientConfig,
-    crypto::{self, CryptoProvider},
+    ClientConfig, DigitallySignedStruct, SignatureScheme,
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{self, CryptoProvider, verify_tls12_signature, verify_tls13_signature},
+    pki_types::{CertificateDer, ServerName, UnixTime},
 };
 #[cfg(feature = "rustls-platform-verifier")]
 use rustls_platform_verifier::BuilderVerifierExt;
@@ -46,6 +48,70 @@ pub fn client_config() -> Result<ClientConfig, rustls::Error> {
     Ok(builder.with_no_client_auth())
 }
 
+/// Make a new [`ClientConfig`] which does not authenticate the server
+///
+/// The certificate presented by the server is accepted whatever its issuer and names, only the
+/// handshake signatures are checked. This protects against passive eavesdroppers but not against
+/// active attackers, and is meant for opportunistic encryption, such as the unauthenticated DNS
+/// over TLS and DNS over QUIC used between recursive resolvers and authoritative servers (RFC 9539).
+pub fn unauthenticated_client_config() -> Result<ClientConfig, rustls::Error> {
+    let provider = Arc::new(default_provider());
+    Ok(ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(UnauthenticatedVerifier(provider)))
+        .with_no_client_auth())
+}
+
+/// Accepts any server certificate, see [`unauthenticated_client_config`]
+#[derive(Debug)]
+struct UnauthenticatedVerifier(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for UnauthenticatedVerifier {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
 /// Instantiate a new [`CryptoProvider`] for use with rustls
 #[cfg(all(feature = "tls-aws-lc-rs", not(feature = "tls-ring")))]
 pub fn default_provider() -> CryptoProvider {
@@ -93,6 +93,10 @@ impl RDataParser for RData {
             RecordType::SVCB => svcb::parse(tokens).map(Self::SVCB)?,
             RecordType::TLSA => Self::TLSA(tlsa::parse(tokens)?),
             RecordType::TXT => Self::TXT(txt::parse(tokens)?),
+            RecordType::ZONEMD => Self::Unknown {
+                code: record_type,
+                rdata: zonemd::parse(tokens)?,
+            },
             RecordType::SIG => return Err(ParseError::from("parsing SIG doesn't make sense")),
             RecordType::DNSKEY => {
                 return Err(ParseError::from("DNSKEY should be dynamically generated"));
@@ -133,6 +137,28 @@ impl RDataParser for RData {
     }
 }
 
+/// Parses the RData of a record of a signed zone, see [`Parser::signed()`]
+///
+/// Unlike [`RData::parse()`], the DNSKEY, NSEC and RRSIG records, which are otherwise generated
+/// when signing a zone, are parsed.
+///
+/// [`Parser::signed()`]: super::Parser::signed
+pub(crate) fn parse_signed<'i, I: Iterator<Item = &'i str>>(
+    record_type: RecordType,
+    tokens: I,
+    origin: Option<&Name>,
+) -> ParseResult<RData> {
+    Ok(match record_type {
+        #[cfg(feature = "__dnssec")]
+        RecordType::DNSKEY => RData::DNSSEC(DNSSECRData::DNSKEY(dnskey::parse(tokens)?)),
+        #[cfg(feature = "__dnssec")]
+        RecordType::NSEC => RData::DNSSEC(DNSSECRData::NSEC(nsec::parse(tokens, origin)?)),
+        #[cfg(feature = "__dnssec")]
+        RecordType::RRSIG => RData::DNSSEC(DNSSECRData::RRSIG(rrsig::parse(tokens, origin)?)),
+        _ => RData::parse(record_type, tokens, origin)?,
+    })
+}
+
 #[cfg(test)]
 mod tests {
     #![allow(clippy::dbg_macro, clippy::print_stdout)]
@@ -32,10 +32,15 @@ pub(crate) mod hinfo;
 pub(crate) mod mx;
 pub(crate) mod name;
 pub(crate) mod naptr;
+#[cfg(feature = "__dnssec")]
+pub(crate) mod nsec;
 pub(crate) mod openpgpkey;
+#[cfg(feature = "__dnssec")]
+pub(crate) mod rrsig;
 pub(crate) mod soa;
 pub(crate) mod srv;
 pub(crate) mod sshfp;
 pub(crate) mod svcb;
 pub(crate) mod tlsa;
 pub(crate) mod txt;
+pub(crate) mod zonemd;
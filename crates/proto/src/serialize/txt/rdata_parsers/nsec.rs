@@ -0,0 +1,87 @@
+//! Parser for NSEC text form
+
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::str::FromStr;
+
+use crate::dnssec::rdata::NSEC;
+use crate::rr::{Name, RecordType};
+use crate::serialize::txt::errors::{ParseError, ParseErrorKind, ParseResult};
+
+/// Parse the RData from a set of Tokens
+///
+/// [RFC 4034, Resource Records for the DNS Security Extensions](https://datatracker.ietf.org/doc/html/rfc4034#section-4.2)
+/// ```text
+/// 4.2.  The NSEC RR Presentation Format
+///
+///    The Next Domain field is represented as a domain name.
+///
+///    The Type Bit Maps field is represented as a sequence of RR type
+///    mnemonics.  When the mnemonic is not known, the TYPE representation
+///    described in [RFC3597], Section 5, MUST be used.
+/// ```
+pub(crate) fn parse<'i, I: Iterator<Item = &'i str>>(
+    mut tokens: I,
+    origin: Option<&Name>,
+) -> ParseResult<NSEC> {
+    let next_domain_name = tokens
+        .next()
+        .ok_or_else(|| ParseError::from(ParseErrorKind::MissingToken("next domain".to_string())))
+        .and_then(|s| Name::parse(s, origin).map_err(ParseError::from))?;
+
+    let types = tokens.map(parse_type).collect::<ParseResult<Vec<_>>>()?;
+    Ok(NSEC::new(next_domain_name, types))
+}
+
+/// Parse a record type mnemonic, or its `TYPE` representation from RFC 3597
+pub(super) fn parse_type(s: &str) -> ParseResult<RecordType> {
+    match s.strip_prefix("TYPE") {
+        Some(code) if !code.is_empty() && code.bytes().all(|b| b.is_ascii_digit()) => {
+            Ok(RecordType::from(u16::from_str(code)?))
+        }
+        _ => Ok(RecordType::from_str(s)?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parsing() {
+        let nsec = parse(
+            "aaa. NS SOA RRSIG NSEC DNSKEY ZONEMD".split_whitespace(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(nsec.next_domain_name(), &Name::from_ascii("aaa.").unwrap());
+        assert_eq!(
+            nsec.type_bit_maps().collect::<Vec<_>>(),
+            [
+                RecordType::NS,
+                RecordType::SOA,
+                RecordType::RRSIG,
+                RecordType::NSEC,
+                RecordType::DNSKEY,
+                RecordType::ZONEMD,
+            ]
+        );
+
+        let nsec = parse(
+            "b TYPE65534".split_whitespace(),
+            Some(&Name::from_ascii("example.").unwrap()),
+        )
+        .unwrap();
+        assert_eq!(
+            nsec.next_domain_name(),
+            &Name::from_ascii("b.example.").unwrap()
+        );
+        assert_eq!(
+            nsec.type_bit_maps().collect::<Vec<_>>(),
+            [RecordType::Unknown(65534)]
+        );
+
+        assert!(parse(core::iter::empty(), None).is_err());
+        assert!(parse("a. BOGUS".split_whitespace(), None).is_err());
+    }
+}
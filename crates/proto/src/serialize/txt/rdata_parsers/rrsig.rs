@@ -0,0 +1,174 @@
+//! Parser for RRSIG text form
+
+use alloc::string::{String, ToString};
+use core::str::FromStr;
+
+use time::{Date, Month, PrimitiveDateTime, Time};
+
+use crate::dnssec::Algorithm;
+use crate::dnssec::rdata::{RRSIG, SigInput};
+use crate::rr::{Name, SerialNumber};
+use crate::serialize::txt::errors::{ParseError, ParseErrorKind, ParseResult};
+
+/// Parse the RData from a set of Tokens
+///
+/// [RFC 4034, Resource Records for the DNS Security Extensions](https://datatracker.ietf.org/doc/html/rfc4034#section-3.2)
+/// ```text
+/// 3.2.  The RRSIG RR Presentation Format
+///
+///    The Type Covered field is represented as an RR type mnemonic.
+///
+///    The Algorithm field value MUST be represented either as an unsigned
+///    decimal integer or as an algorithm mnemonic, as specified in Appendix
+///    A.1.
+///
+///    The Labels field value MUST be represented as an unsigned decimal
+///    integer.
+///
+///    The Original TTL field value MUST be represented as an unsigned
+///    decimal integer.
+///
+///    The Signature Expiration Time and Inception Time field values MUST be
+///    represented either as an unsigned decimal integer indicating seconds
+///    since 1 January 1970 00:00:00 UTC, or in the form YYYYMMDDHHmmSS in
+///    UTC, where:
+///
+///       YYYY is the year (0001-9999, but see Section 3.1.5);
+///       MM is the month number (01-12);
+///       DD is the day of the month (01-31);
+///       HH is the hour, in 24 hour notation (00-23);
+///       mm is the minute (00-59); and
+///       SS is the second (00-59).
+///
+///    The Key Tag field MUST be represented as an unsigned decimal integer.
+///
+///    The Signer's Name field value MUST be represented as a domain name.
+///
+///    The Signature field is represented as a Base64 encoding of the
+///    signature.  Whitespace is allowed within the Base64 text.
+/// ```
+pub(crate) fn parse<'i, I: Iterator<Item = &'i str>>(
+    mut tokens: I,
+    origin: Option<&Name>,
+) -> ParseResult<RRSIG> {
+    let mut next = |field: &str| {
+        tokens
+            .next()
+            .ok_or_else(|| ParseError::from(ParseErrorKind::MissingToken(field.to_string())))
+    };
+
+    let type_covered = super::nsec::parse_type(next("type covered")?)?;
+    let algorithm = Algorithm::from_u8(next("algorithm")?.parse()?);
+    let num_labels = u8::from_str(next("labels")?)?;
+    let original_ttl = u32::from_str(next("original ttl")?)?;
+    let sig_expiration = parse_time(next("signature expiration")?)?;
+    let sig_inception = parse_time(next("signature inception")?)?;
+    let key_tag = u16::from_str(next("key tag")?)?;
+    let signer_name = Name::parse(next("signer's name")?, origin)?;
+
+    let sig_str: String = tokens.collect();
+    if sig_str.is_empty() {
+        return Err(ParseError::from(ParseErrorKind::Message(
+            "signature not present",
+        )));
+    }
+    let sig = data_encoding::BASE64.decode(sig_str.as_bytes())?;
+
+    Ok(RRSIG::from_sig(
+        SigInput {
+            type_covered,
+            algorithm,
+            num_labels,
+            original_ttl,
+            sig_expiration,
+            sig_inception,
+            key_tag,
+            signer_name,
+        },
+        sig,
+    ))
+}
+
+/// Parse a signature time, either in seconds or in the form YYYYMMDDHHmmSS
+fn parse_time(s: &str) -> ParseResult<SerialNumber> {
+    if s.len() != 14 {
+        return Ok(SerialNumber(u32::from_str(s)?));
+    }
+
+    let invalid = || ParseError::from(ParseErrorKind::ParseTime(s.to_string()));
+    let field = |start: usize, end: usize| -> ParseResult<u8> {
+        s.get(start..end)
+            .ok_or_else(invalid)?
+            .parse()
+            .map_err(|_| invalid())
+    };
+
+    let year = s
+        .get(0..4)
+        .and_then(|year| i32::from_str(year).ok())
+        .ok_or_else(invalid)?;
+    let month = Month::try_from(field(4, 6)?).map_err(|_| invalid())?;
+    let date = Date::from_calendar_date(year, month, field(6, 8)?).map_err(|_| invalid())?;
+    let time =
+        Time::from_hms(field(8, 10)?, field(10, 12)?, field(12, 14)?).map_err(|_| invalid())?;
+
+    // serial number arithmetic, the time is taken modulo 2^32
+    let timestamp = PrimitiveDateTime::new(date, time)
+        .assume_utc()
+        .unix_timestamp();
+    Ok(SerialNumber(timestamp as u32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rr::RecordType;
+
+    #[test]
+    fn test_parsing() {
+        let rrsig = parse(
+            "ZONEMD 8 0 86400 20250101170000 20241219160000 26470 . \
+             Zm9vYmFy YmF6"
+                .split_whitespace(),
+            None,
+        )
+        .unwrap();
+
+        let input = rrsig.input();
+        assert_eq!(input.type_covered, RecordType::ZONEMD);
+        assert_eq!(input.algorithm, Algorithm::RSASHA256);
+        assert_eq!(input.num_labels, 0);
+        assert_eq!(input.original_ttl, 86400);
+        assert_eq!(input.sig_expiration, SerialNumber(1_735_750_800));
+        assert_eq!(input.sig_inception, SerialNumber(1_734_624_000));
+        assert_eq!(input.key_tag, 26470);
+        assert_eq!(input.signer_name, Name::root());
+        assert_eq!(rrsig.sig(), b"foobarbaz");
+
+        let rrsig = parse(
+            "A 13 2 3600 1735750800 1734624000 1 example.com. Zm9v".split_whitespace(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(rrsig.input().sig_expiration, SerialNumber(1_735_750_800));
+    }
+
+    #[test]
+    fn test_parsing_errors() {
+        assert!(parse("A 8 0 86400".split_whitespace(), None).is_err());
+        assert!(
+            parse(
+                "A 8 0 86400 20251301000000 20241219160000 1 . Zm9v".split_whitespace(),
+                None
+            )
+            .is_err()
+        );
+        assert!(
+            parse(
+                "A 8 0 86400 20250101170000 20241219160000 1 .".split_whitespace(),
+                None
+            )
+            .is_err()
+        );
+    }
+}
@@ -0,0 +1,73 @@
+//! Parser for ZONEMD text form
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::str::FromStr;
+
+use crate::rr::rdata::NULL;
+use crate::serialize::txt::errors::{ParseError, ParseErrorKind, ParseResult};
+
+/// Parse the RData from a set of Tokens
+///
+/// The record data is kept in its wire format, as there is no dedicated ZONEMD type.
+///
+/// [RFC 8976, Message Digest for DNS Zones](https://datatracker.ietf.org/doc/html/rfc8976#section-2.3)
+/// ```text
+/// 2.3.  ZONEMD Presentation Format
+///
+///    The presentation format of the RDATA portion is as follows:
+///
+///    The Serial field MUST be represented as an unsigned decimal integer.
+///
+///    The Scheme field MUST be represented as an unsigned decimal integer.
+///
+///    The Hash Algorithm field MUST be represented as an unsigned decimal
+///    integer.
+///
+///    The Digest MUST be represented as a sequence of case-insensitive
+///    hexadecimal digits.  Whitespace is allowed within the hexadecimal
+///    text.
+/// ```
+pub(crate) fn parse<'i, I: Iterator<Item = &'i str>>(mut tokens: I) -> ParseResult<NULL> {
+    let mut next = |field: &str| {
+        tokens
+            .next()
+            .ok_or_else(|| ParseError::from(ParseErrorKind::MissingToken(field.to_string())))
+    };
+
+    let serial = u32::from_str(next("serial")?)?;
+    let scheme = u8::from_str(next("scheme")?)?;
+    let hash_algorithm = u8::from_str(next("hash algorithm")?)?;
+
+    let digest_str: String = tokens.collect();
+    if digest_str.is_empty() {
+        return Err(ParseError::from(ParseErrorKind::Message(
+            "digest not present",
+        )));
+    }
+    let digest = data_encoding::HEXLOWER_PERMISSIVE.decode(digest_str.as_bytes())?;
+
+    let mut rdata = Vec::with_capacity(6 + digest.len());
+    rdata.extend_from_slice(&serial.to_be_bytes());
+    rdata.push(scheme);
+    rdata.push(hash_algorithm);
+    rdata.extend_from_slice(&digest);
+    Ok(NULL::with(rdata))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parsing() {
+        let rdata = parse("2025010100 1 1 0A0b 0c".split_whitespace()).unwrap();
+        assert_eq!(
+            rdata.anything(),
+            [0x78, 0xb3, 0x33, 0xb4, 1, 1, 0x0a, 0x0b, 0x0c]
+        );
+
+        assert!(parse("2025010100 1 1".split_whitespace()).is_err());
+        assert!(parse("2025010100 1 1 xyz".split_whitespace()).is_err());
+    }
+}
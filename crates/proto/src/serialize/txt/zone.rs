@@ -21,7 +21,7 @@ use crate::{
     rr::{DNSClass, LowerName, Name, RData, Record, RecordSet, RecordType, RrKey},
     serialize::txt::{
         ParseError, ParseErrorKind, ParseResult,
-        parse_rdata::RDataParser,
+        parse_rdata::{self, RDataParser},
         zone_lex::{Lexer, Token},
     },
 };
@@ -129,6 +129,7 @@ use crate::{
 pub struct Parser<'a> {
     lexers: Vec<(Lexer<'a>, Option<PathBuf>)>,
     origin: Option<Name>,
+    signed: bool,
 }
 
 impl<'a> Parser<'a> {
@@ -147,16 +148,27 @@ impl<'a> Parser<'a> {
         Self {
             lexers: vec![(Lexer::new(input), path)],
             origin,
+            signed: false,
         }
     }
 
+    /// Parses the zone as a signed zone, which is used as is
+    ///
+    /// The DNSKEY, NSEC and RRSIG records are parsed instead of being rejected as records
+    /// generated when signing the zone, and the TTL given for the SOA record is kept, as it is
+    /// covered by signatures. Parsing DNSSEC records requires one of the `dnssec` features.
+    pub fn signed(mut self) -> Self {
+        self.signed = true;
+        self
+    }
+
     /// Parse a file from the Lexer
     ///
     /// # Return
     ///
     /// A pair of the Zone origin name and a map of all Keys to RecordSets
     pub fn parse(mut self) -> ParseResult<(Name, BTreeMap<RrKey, RecordSet>)> {
-        let mut cx = Context::new(self.origin);
+        let mut cx = Context::new(self.origin, self.signed);
         let mut state = State::StartLine;
         let mut stack = self.lexers.len();
 
@@ -166,6 +178,7 @@ impl<'a> Parser<'a> {
                     State::StartLine => {
                         // current_name is not reset on the next line b/c it might be needed from the previous
                         cx.rtype = None;
+                        cx.line_ttl = None;
 
                         match t {
                             // if Dollar, then $INCLUDE or $ORIGIN
@@ -270,6 +283,7 @@ impl<'a> Parser<'a> {
                                 let result: ParseResult<u32> = Self::parse_time(&data);
                                 if let Ok(ttl) = result {
                                     cx.ttl = Some(ttl);
+                                    cx.line_ttl = Some(ttl);
                                     State::TtlClassType // hm, should this go to just ClassType?
                                 } else {
                                     // if can parse DNSClass, then class
@@ -428,10 +442,14 @@ struct Context {
     current_name: Option<Name>,
     rtype: Option<RecordType>,
     ttl: Option<u32>,
+    /// TTL given on the current line
+    line_ttl: Option<u32>,
+    /// Whether this is a signed zone, see [`Parser::signed()`]
+    signed: bool,
 }
 
 impl Context {
-    fn new(origin: Option<Name>) -> Self {
+    fn new(origin: Option<Name>, signed: bool) -> Self {
         Self {
             origin,
             records: BTreeMap::default(),
@@ -439,6 +457,8 @@ impl Context {
             current_name: None,
             rtype: None,
             ttl: None,
+            line_ttl: None,
+            signed,
         }
     }
 
@@ -449,11 +469,11 @@ impl Context {
             .rtype
             .ok_or_else(|| ParseError::from("record type not specified"))?;
 
-        let rdata = RData::parse(
-            rtype,
-            record_parts.iter().map(AsRef::as_ref),
-            self.origin.as_ref(),
-        )?;
+        let tokens = record_parts.iter().map(AsRef::as_ref);
+        let rdata = match self.signed {
+            true => parse_rdata::parse_signed(rtype, tokens, self.origin.as_ref())?,
+            false => RData::parse(rtype, tokens, self.origin.as_ref())?,
+        };
 
         // verify that we have everything we need for the record
         // TODO COW or RC would reduce mem usage, perhaps Name should have an intern()...
@@ -467,16 +487,19 @@ impl Context {
         // slightly annoying, need to grab the TTL, then move rdata into the record,
         //  then check the Type again and have custom add logic.
         let set_ttl = match (rtype, self.ttl, &rdata) {
-            // TTL for the SOA is set internally...
+            // TTL for the SOA is set internally, unless it is given explicitly in a signed zone...
             // expire is for the SOA, minimum is default for records
-            (RecordType::SOA, _, RData::SOA(soa)) => {
-                // TODO, this looks wrong, get_expire() should be get_minimum(), right?
-                let set_ttl = soa.expire() as u32; // the spec seems a little inaccurate with u32 and i32
-                if self.ttl.is_none() {
-                    self.ttl = Some(soa.minimum());
-                } // TODO: should this only set it if it's not set?
-                set_ttl
-            }
+            (RecordType::SOA, _, RData::SOA(soa)) => match self.line_ttl.filter(|_| self.signed) {
+                Some(ttl) => ttl,
+                None => {
+                    // TODO, this looks wrong, get_expire() should be get_minimum(), right?
+                    let set_ttl = soa.expire() as u32; // the spec seems a little inaccurate with u32 and i32
+                    if self.ttl.is_none() {
+                        self.ttl = Some(soa.minimum());
+                    } // TODO: should this only set it if it's not set?
+                    set_ttl
+                }
+            },
             (RecordType::SOA, _, _) => {
                 return ParseResult::Err(ParseError::from(format!(
                     "invalid RData here, expected SOA: {rdata:?}"
@@ -553,4 +576,28 @@ mod tests {
             result
         );
     }
+
+    #[test]
+    fn test_soa_ttl() {
+        let soa_ttl = |zone_data, signed| {
+            let mut parser = Parser::new(zone_data, None, Some(Name::root()));
+            if signed {
+                parser = parser.signed();
+            }
+            let (_, records) = parser.parse().unwrap();
+            let soa = records.values().next().unwrap();
+            soa.records_without_rrsigs().next().unwrap().ttl()
+        };
+
+        let explicit =
+            "@ 86400 IN SOA a.root-servers.net. nstld.verisign-grs.com. 1 1800 900 604800 86400";
+        let implicit =
+            "@ IN SOA a.root-servers.net. nstld.verisign-grs.com. 1 1800 900 604800 86400";
+
+        // the TTL is the expire time of the SOA record, unless it is given in a signed zone
+        assert_eq!(soa_ttl(explicit, false), 604800);
+        assert_eq!(soa_ttl(implicit, false), 604800);
+        assert_eq!(soa_ttl(explicit, true), 86400);
+        assert_eq!(soa_ttl(implicit, true), 604800);
+    }
 }
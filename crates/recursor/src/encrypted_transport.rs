@@ -0,0 +1,462 @@
+//! Opportunistic encryption of the queries sent to authoritative name servers, see
+//! [RFC 9539](https://www.rfc-editor.org/rfc/rfc9539)
+//!
+//! Name servers are probed for DNS over QUIC and DNS over TLS on port 853, without authenticating
+//! them. Queries are sent over the encrypted transport of the servers for which a probe succeeded,
+//! and over UDP and TCP to the other servers, or when the encrypted transport fails.
+
+use std::{
+    future::Future,
+    net::IpAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use lru_cache::LruCache;
+use parking_lot::Mutex;
+use tracing::debug;
+
+use crate::{
+    proto::{
+        DnsHandle, ProtoError, ProtoErrorKind,
+        op::{DnsRequest, DnsResponse},
+        runtime::{RuntimeProvider, Spawn, Time},
+        xfer::FirstAnswer,
+    },
+    resolver::{
+        config::{ConnectionConfig, NameServerConfig, ResolverOpts},
+        name_server::{ConnectionProvider, NameServer, NameServerPool, TlsConfig},
+    },
+};
+
+/// The encrypted transports of the name servers queried by the recursor, by IP address
+pub(crate) struct EncryptedTransports<P: ConnectionProvider> {
+    servers: Mutex<LruCache<IpAddr, TransportState<P>>>,
+    options: Arc<ResolverOpts>,
+    tls: Arc<TlsConfig>,
+    conn_provider: P,
+}
+
+impl<P: ConnectionProvider> EncryptedTransports<P> {
+    pub(crate) fn new(
+        size: usize,
+        options: Arc<ResolverOpts>,
+        tls: Arc<TlsConfig>,
+        conn_provider: P,
+    ) -> Self {
+        Self {
+            servers: Mutex::new(LruCache::new(size)),
+            options,
+            tls,
+            conn_provider,
+        }
+    }
+
+    /// Returns `request` sent over the encrypted transport of the server at `ip`, if it has one
+    ///
+    /// Returns `None` if the server is not known to support an encrypted transport. A server which
+    /// was not probed recently, or whose probe did not complete in time, is probed in the
+    /// background with `request`. The returned future
+    /// resolves to `None` if the server did not respond over its encrypted transport, in which case
+    /// the request should be sent over UDP and TCP instead.
+    pub(crate) fn send(
+        self: &Arc<Self>,
+        ip: IpAddr,
+        request: &DnsRequest,
+    ) -> Option<impl Future<Output = Option<Result<DnsResponse, ProtoError>>> + Send + 'static>
+    {
+        let pool = {
+            let mut servers = self.servers.lock();
+            let now = Instant::now();
+            match servers.get_mut(&ip) {
+                Some(TransportState::Available(pool)) => pool.clone(),
+                Some(
+                    TransportState::Probing { until, .. }
+                    | TransportState::Unavailable { until, .. },
+                ) if *until > now => return None,
+                _ => {
+                    let runtime = self.conn_provider.runtime_provider()?;
+                    let failures = match servers.get_mut(&ip) {
+                        Some(
+                            TransportState::Probing { failures, .. }
+                            | TransportState::Unavailable { failures, .. },
+                        ) => *failures,
+                        _ => 0,
+                    };
+                    let probing = TransportState::Probing {
+                        failures,
+                        until: now + PROBE_DEADLINE,
+                    };
+                    servers.insert(ip, probing);
+                    let probe = self.clone().probe(ip, request.clone(), failures);
+                    runtime.create_handle().spawn_bg(probe);
+                    return None;
+                }
+            }
+        };
+
+        let this = self.clone();
+        let request = request.clone();
+        Some(async move {
+            let result = send::<P>(&pool, request).await;
+            if result.is_none() {
+                debug!(%ip, "encrypted transport failed, falling back to UDP and TCP");
+                this.servers
+                    .lock()
+                    .insert(ip, TransportState::unavailable(1, Instant::now()));
+            }
+            result
+        })
+    }
+
+    /// Sends `request` to the server at `ip` over each of the encrypted transports in turn, and
+    /// remembers the first one over which it responds
+    async fn probe(
+        self: Arc<Self>,
+        ip: IpAddr,
+        request: DnsRequest,
+        failures: u32,
+    ) -> Result<(), ProtoError> {
+        let server_name = Arc::<str>::from(ip.to_string());
+        let connections = [
+            #[cfg(feature = "__quic")]
+            ConnectionConfig::quic(server_name.clone()),
+            ConnectionConfig::tls(server_name),
+        ];
+
+        for connection in connections {
+            let protocol = connection.protocol.to_protocol();
+            let server = NameServer::new(
+                [],
+                NameServerConfig::new(ip, true, vec![connection]),
+                self.options.clone(),
+                self.tls.clone(),
+                self.conn_provider.clone(),
+            );
+            let pool =
+                NameServerPool::from_nameservers(vec![Arc::new(server)], self.options.clone());
+
+            if send::<P>(&pool, request.clone()).await.is_some() {
+                debug!(%ip, %protocol, "name server supports encrypted transport");
+                self.servers
+                    .lock()
+                    .insert(ip, TransportState::Available(pool));
+                return Ok(());
+            }
+        }
+
+        debug!(%ip, "name server does not support encrypted transports");
+        self.servers.lock().insert(
+            ip,
+            TransportState::unavailable(failures.saturating_add(1), Instant::now()),
+        );
+        Ok(())
+    }
+}
+
+/// Sends `request` to the server of `pool`, returning `None` if the server did not respond
+async fn send<P: ConnectionProvider>(
+    pool: &NameServerPool<P>,
+    request: DnsRequest,
+) -> Option<Result<DnsResponse, ProtoError>> {
+    let lookup = pool.send(request).first_answer();
+    match <P::RuntimeProvider as RuntimeProvider>::Timer::timeout(TIMEOUT, lookup).await {
+        Ok(Ok(response)) => Some(Ok(response)),
+        // negative responses and referrals are responses all the same
+        Ok(Err(e)) if matches!(e.kind(), ProtoErrorKind::Dns(_)) => Some(Err(e)),
+        Ok(Err(_)) | Err(_) => None,
+    }
+}
+
+/// Whether a name server supports an encrypted transport
+enum TransportState<P: ConnectionProvider> {
+    /// The server is being probed, again after this deadline if the probe did not complete
+    Probing { failures: u32, until: Instant },
+    /// Queries are sent over an encrypted transport, through this pool of the server alone
+    Available(NameServerPool<P>),
+    /// The server did not respond over the encrypted transports, it is not probed again until then
+    Unavailable { failures: u32, until: Instant },
+}
+
+impl<P: ConnectionProvider> TransportState<P> {
+    /// The time until the next probe doubles after each consecutive failure, up to a maximum
+    fn unavailable(failures: u32, now: Instant) -> Self {
+        let damping = DAMPING
+            .saturating_mul(2u32.saturating_pow(failures.saturating_sub(1)))
+            .min(MAX_DAMPING);
+        Self::Unavailable {
+            failures,
+            until: now + damping,
+        }
+    }
+}
+
+/// Time to wait for a response over an encrypted transport, see RFC 9539 section 4.1
+const TIMEOUT: Duration = Duration::from_secs(4);
+/// Time after which a probe is started again, if it did not complete because its task was dropped
+const PROBE_DEADLINE: Duration = Duration::from_secs(60);
+/// Time during which a server is not probed after a failure, see RFC 9539 section 4.1
+const DAMPING: Duration = Duration::from_secs(24 * 60 * 60);
+/// Upper bound of the time during which a server is not probed, after consecutive failures
+const MAX_DAMPING: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        net::Ipv4Addr,
+        pin::Pin,
+        sync::atomic::{AtomicBool, Ordering},
+    };
+
+    use futures_util::{
+        Stream,
+        future::{self, Ready},
+        stream,
+    };
+    use test_support::subscribe;
+
+    use super::*;
+    use crate::{
+        infra_cache::InfraCache,
+        proto::{
+            op::{DnsRequestOptions, Message, Query},
+            rr::{Name, RData, Record, RecordType, rdata::A},
+            runtime::{TokioRuntimeProvider, TokioTime},
+            xfer::Protocol,
+        },
+        recursor_pool::RecursorPool,
+        resolution_trace::TraceRecorder,
+    };
+
+    #[tokio::test]
+    async fn test_probe() {
+        subscribe();
+        let connections = MockConnections::default();
+        let transports = mock_transports(&connections);
+
+        // the first request starts a probe, and is sent over UDP and TCP meanwhile
+        assert!(transports.send(SERVER_IP, &request()).is_none());
+        wait_for_probe(&transports).await;
+        assert!(matches!(
+            transports.servers.lock().get_mut(&SERVER_IP),
+            Some(TransportState::Available(_))
+        ));
+
+        let response = transports.send(SERVER_IP, &request()).unwrap().await;
+        assert!(response.unwrap().unwrap().contains_answer());
+        assert_eq!(connections.take_log().last(), Some(&Protocol::Tls));
+
+        // the server stops answering over DoT, the request is then sent over UDP and TCP
+        connections.tls.store(false, Ordering::Relaxed);
+        assert!(
+            transports
+                .send(SERVER_IP, &request())
+                .unwrap()
+                .await
+                .is_none()
+        );
+        assert!(matches!(
+            transports.servers.lock().get_mut(&SERVER_IP),
+            Some(TransportState::Unavailable { failures: 1, .. })
+        ));
+        assert!(transports.send(SERVER_IP, &request()).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_probe_deadline() {
+        subscribe();
+        let connections = MockConnections::default();
+        let transports = mock_transports(&connections);
+
+        // a probe whose task was dropped before it completed
+        let deadline = Instant::now() + PROBE_DEADLINE;
+        let probing = |deadline| TransportState::Probing {
+            failures: 2,
+            until: deadline,
+        };
+        transports
+            .servers
+            .lock()
+            .insert(SERVER_IP, probing(deadline));
+        assert!(transports.send(SERVER_IP, &request()).is_none());
+        assert!(matches!(
+            transports.servers.lock().get_mut(&SERVER_IP),
+            Some(TransportState::Probing { until, .. }) if *until == deadline
+        ));
+
+        // the server is probed again after the deadline
+        let deadline = Instant::now();
+        transports
+            .servers
+            .lock()
+            .insert(SERVER_IP, probing(deadline));
+        assert!(transports.send(SERVER_IP, &request()).is_none());
+        assert!(matches!(
+            transports.servers.lock().get_mut(&SERVER_IP),
+            Some(TransportState::Probing { failures: 2, until }) if *until > deadline
+        ));
+        wait_for_probe(&transports).await;
+        assert!(matches!(
+            transports.servers.lock().get_mut(&SERVER_IP),
+            Some(TransportState::Available(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_fallback_to_udp() {
+        subscribe();
+        let connections = MockConnections::default();
+        let transports = mock_transports(&connections);
+        let options = Arc::new(ResolverOpts::default());
+        let ns = NameServerPool::from_config(
+            [NameServerConfig::udp_and_tcp(SERVER_IP)],
+            options,
+            Arc::new(TlsConfig::new().unwrap()),
+            connections.clone(),
+        );
+        let pool = RecursorPool::from(Name::root(), ns, Arc::new(InfraCache::new(16)))
+            .with_encrypted_transports(Some(transports.clone()));
+        let trace = TraceRecorder::default();
+        let lookup = || pool.lookup(query(), false, None, &trace);
+
+        lookup().await.unwrap();
+        wait_for_probe(&transports).await;
+        connections.take_log();
+
+        // the server stops answering over DoT, the same query is answered over UDP
+        connections.tls.store(false, Ordering::Relaxed);
+        let response = lookup().await.unwrap();
+        assert!(response.contains_answer());
+        assert_eq!(connections.take_log(), [Protocol::Tls, Protocol::Udp]);
+    }
+
+    #[test]
+    fn test_damping() {
+        let now = Instant::now();
+        let until =
+            |failures| match TransportState::<TokioRuntimeProvider>::unavailable(failures, now) {
+                TransportState::Unavailable { until, .. } => until - now,
+                _ => unreachable!(),
+            };
+
+        assert_eq!(until(1), DAMPING);
+        assert_eq!(until(2), DAMPING * 2);
+        assert_eq!(until(3), DAMPING * 4);
+        assert_eq!(until(4), MAX_DAMPING);
+        assert_eq!(until(u32::MAX), MAX_DAMPING);
+    }
+
+    fn mock_transports(connections: &MockConnections) -> Arc<EncryptedTransports<MockConnections>> {
+        Arc::new(EncryptedTransports::new(
+            16,
+            Arc::new(ResolverOpts::default()),
+            Arc::new(TlsConfig::unauthenticated().unwrap()),
+            connections.clone(),
+        ))
+    }
+
+    async fn wait_for_probe(transports: &EncryptedTransports<MockConnections>) {
+        while matches!(
+            transports.servers.lock().get_mut(&SERVER_IP),
+            Some(TransportState::Probing { .. })
+        ) {
+            TokioTime::delay_for(Duration::from_millis(10)).await;
+        }
+    }
+
+    fn query() -> Query {
+        Query::query(Name::from_ascii("www.example.com.").unwrap(), RecordType::A)
+    }
+
+    fn request() -> DnsRequest {
+        DnsRequest::from_query(query(), DnsRequestOptions::default())
+    }
+
+    /// Connections which answer over UDP, TCP and, while `tls` is set, DoT, and log the protocol
+    /// of each request
+    #[derive(Clone)]
+    struct MockConnections {
+        tls: Arc<AtomicBool>,
+        log: Arc<Mutex<Vec<Protocol>>>,
+        runtime: TokioRuntimeProvider,
+    }
+
+    impl MockConnections {
+        fn take_log(&self) -> Vec<Protocol> {
+            std::mem::take(&mut *self.log.lock())
+        }
+    }
+
+    impl Default for MockConnections {
+        fn default() -> Self {
+            Self {
+                tls: Arc::new(AtomicBool::new(true)),
+                log: Arc::default(),
+                runtime: TokioRuntimeProvider::default(),
+            }
+        }
+    }
+
+    impl ConnectionProvider for MockConnections {
+        type Conn = MockConnection;
+        type FutureConn = Ready<Result<MockConnection, ProtoError>>;
+        type RuntimeProvider = TokioRuntimeProvider;
+
+        fn new_connection(
+            &self,
+            _ip: IpAddr,
+            config: &ConnectionConfig,
+            _options: &ResolverOpts,
+            _tls: &TlsConfig,
+        ) -> Result<Self::FutureConn, io::Error> {
+            Ok(future::ready(Ok(MockConnection {
+                protocol: config.protocol.to_protocol(),
+                connections: self.clone(),
+            })))
+        }
+
+        fn runtime_provider(&self) -> Option<Self::RuntimeProvider> {
+            Some(self.runtime.clone())
+        }
+    }
+
+    #[derive(Clone)]
+    struct MockConnection {
+        protocol: Protocol,
+        connections: MockConnections,
+    }
+
+    impl DnsHandle for MockConnection {
+        type Response = Pin<Box<dyn Stream<Item = Result<DnsResponse, ProtoError>> + Send + Unpin>>;
+        type Runtime = TokioRuntimeProvider;
+
+        fn send(&self, request: DnsRequest) -> Self::Response {
+            self.connections.log.lock().push(self.protocol);
+            let answers = match self.protocol {
+                Protocol::Udp | Protocol::Tcp => true,
+                Protocol::Tls => self.connections.tls.load(Ordering::Relaxed),
+                _ => false,
+            };
+            if !answers {
+                let error = io::Error::from(io::ErrorKind::ConnectionRefused);
+                return Box::pin(stream::once(future::ready(Err(error.into()))));
+            }
+
+            let mut response = Message::response(request.id(), request.op_code());
+            for query in request.queries() {
+                response.add_query(query.clone());
+                response.add_answer(Record::from_rdata(
+                    query.name().clone(),
+                    300,
+                    RData::A(A(Ipv4Addr::new(192, 0, 2, 1))),
+                ));
+            }
+            Box::pin(stream::once(future::ready(DnsResponse::from_message(
+                response,
+            ))))
+        }
+    }
+
+    const SERVER_IP: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 1, 1));
+}
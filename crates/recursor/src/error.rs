@@ -17,7 +17,7 @@ use thiserror::Error;
 use tracing::warn;
 
 use crate::proto::{
-    DnsError, ForwardNSData, ProtoErrorKind,
+    DnsError, ForwardNSData, ProtoErrorKind, ResourceLimit,
     op::ResponseCode,
     rr::{Name, Record, RecordType, rdata::SOA},
     {NoRecords, ProtoError},
@@ -124,6 +124,17 @@ impl Error {
         matches!(proto_error.kind(), ProtoErrorKind::Timeout)
     }
 
+    /// Returns the limit which was exceeded, if the query failed because it required too much work
+    pub fn resource_limit(&self) -> Option<ResourceLimit> {
+        match &self.kind {
+            ErrorKind::Proto(proto) => match proto.kind() {
+                ProtoErrorKind::ResourceLimitExceeded(limit) => Some(*limit),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
     /// Returns the SOA record, if the error contains one
     pub fn into_soa(self) -> Option<Box<Record<SOA>>> {
         match self.kind {
@@ -259,6 +270,7 @@ impl From<Error> for ProtoError {
     fn from(e: Error) -> Self {
         match e.kind {
             ErrorKind::Negative(fwd) => DnsError::NoRecordsFound(fwd.into()).into(),
+            ErrorKind::Proto(proto) if e.resource_limit().is_some() => proto,
             _ => ProtoError::from(e.to_string()),
         }
     }
@@ -0,0 +1,245 @@
+//! Round-trip times and failures of the name servers queried by the recursor
+//!
+//! This is shared by the name server pools of all zones, so that what is learned about a server
+//! while resolving names in one zone applies to the other zones it serves, as in the
+//! infrastructure caches of BIND and Unbound.
+
+use std::{net::IpAddr, time::Duration, time::Instant};
+
+use lru_cache::LruCache;
+use parking_lot::Mutex;
+use tracing::debug;
+
+/// Statistics of the name servers queried by the recursor, by IP address
+pub(crate) struct InfraCache {
+    servers: Mutex<LruCache<IpAddr, ServerStats>>,
+}
+
+impl InfraCache {
+    pub(crate) fn new(size: usize) -> Self {
+        Self {
+            servers: Mutex::new(LruCache::new(size)),
+        }
+    }
+
+    /// Sorts `servers` in the order in which they should be queried
+    ///
+    /// Servers are sorted by smoothed round-trip time, with servers which are held down last. Servers
+    /// which were never queried come first, so that their round-trip time is measured. With a small
+    /// probability, another server which is not held down is moved to the front, so that changes
+    /// in the round-trip times of slower servers are noticed.
+    ///
+    /// Returns the number of servers which are not held down.
+    pub(crate) fn sort<T>(
+        &self,
+        servers: &mut [T],
+        ip: impl Fn(&T) -> IpAddr,
+        now: Instant,
+    ) -> usize {
+        let mut cache = self.servers.lock();
+        servers.sort_by_cached_key(|server| match cache.get_mut(&ip(server)) {
+            Some(stats) => (stats.is_held_down(now), stats.rank()),
+            None => (false, Duration::ZERO),
+        });
+
+        let available = servers
+            .iter()
+            .take_while(|server| {
+                cache
+                    .get_mut(&ip(server))
+                    .map_or(true, |stats| !stats.is_held_down(now))
+            })
+            .count();
+        if available > 1 && rand::random::<f64>() < EXPLORATION_PROBABILITY {
+            let explored = rand::random_range(1..available);
+            debug!(ip = %ip(&servers[explored]), "exploring slower name server");
+            servers[..=explored].rotate_right(1);
+        }
+
+        available
+    }
+
+    /// Returns how long to wait for a response from the server at `ip` over UDP
+    pub(crate) fn timeout(&self, ip: IpAddr) -> Duration {
+        self.servers
+            .lock()
+            .get_mut(&ip)
+            .map_or(INITIAL_TIMEOUT, |stats| stats.timeout)
+    }
+
+    /// Records that the server at `ip` responded after `rtt`
+    pub(crate) fn record_rtt(&self, ip: IpAddr, rtt: Duration) {
+        let mut cache = self.servers.lock();
+        match cache.get_mut(&ip) {
+            Some(stats) => stats.record_rtt(rtt),
+            None => {
+                cache.insert(ip, ServerStats::new(rtt));
+            }
+        }
+    }
+
+    /// Records that the server at `ip` did not respond
+    pub(crate) fn record_failure(&self, ip: IpAddr, now: Instant) {
+        let mut cache = self.servers.lock();
+        match cache.get_mut(&ip) {
+            Some(stats) => stats.record_failure(now),
+            None => {
+                let mut stats = ServerStats::new(INITIAL_TIMEOUT);
+                stats.record_failure(now);
+                cache.insert(ip, stats);
+            }
+        }
+    }
+}
+
+/// Round-trip time and failures of a single name server
+#[derive(Clone, Copy, Debug)]
+struct ServerStats {
+    /// Smoothed round-trip time
+    srtt: Duration,
+    /// Smoothed variation of the round-trip time
+    rttvar: Duration,
+    /// Time to wait for a response, which doubles after each timeout
+    timeout: Duration,
+    /// Number of consecutive queries which timed out or failed
+    failures: u32,
+    /// The server is not queried until then, unless all the servers of a zone are held down
+    held_down_until: Option<Instant>,
+}
+
+impl ServerStats {
+    fn new(rtt: Duration) -> Self {
+        let mut stats = Self {
+            srtt: rtt,
+            rttvar: rtt / 2,
+            timeout: INITIAL_TIMEOUT,
+            failures: 0,
+            held_down_until: None,
+        };
+        stats.update_timeout();
+        stats
+    }
+
+    /// Updates the round-trip time like the retransmission timer of TCP, see RFC 6298 section 2
+    fn record_rtt(&mut self, rtt: Duration) {
+        let deviation = match self.srtt > rtt {
+            true => self.srtt - rtt,
+            false => rtt - self.srtt,
+        };
+        self.rttvar = (self.rttvar * 3 + deviation) / 4;
+        self.srtt = (self.srtt * 7 + rtt) / 8;
+        self.failures = 0;
+        self.held_down_until = None;
+        self.update_timeout();
+    }
+
+    fn record_failure(&mut self, now: Instant) {
+        self.failures = self.failures.saturating_add(1);
+        self.timeout = (self.timeout * 2).min(MAX_TIMEOUT);
+        if self.failures >= HOLD_DOWN_FAILURES {
+            debug!(
+                failures = self.failures,
+                "holding down unresponsive name server"
+            );
+            self.held_down_until = Some(now + HOLD_DOWN_TIME);
+        }
+    }
+
+    /// Servers which did not respond to the last query are ranked by how long they were waited for
+    fn rank(&self) -> Duration {
+        match self.failures {
+            0 => self.srtt,
+            _ => self.srtt.max(self.timeout),
+        }
+    }
+
+    fn update_timeout(&mut self) {
+        self.timeout = (self.srtt + self.rttvar * 4).clamp(MIN_TIMEOUT, MAX_TIMEOUT);
+    }
+
+    fn is_held_down(&self, now: Instant) -> bool {
+        self.held_down_until.is_some_and(|until| until > now)
+    }
+}
+
+/// Probability of querying another server than the fastest one
+const EXPLORATION_PROBABILITY: f64 = 0.05;
+/// Time to wait for a response from a server which was never queried
+const INITIAL_TIMEOUT: Duration = Duration::from_millis(400);
+/// Lower bound of the time to wait for a response
+const MIN_TIMEOUT: Duration = Duration::from_millis(100);
+/// Upper bound of the time to wait for a response, after consecutive timeouts
+const MAX_TIMEOUT: Duration = Duration::from_secs(5);
+/// Number of consecutive failures after which a server is held down
+const HOLD_DOWN_FAILURES: u32 = 3;
+/// Time during which a server is not queried once it is held down
+const HOLD_DOWN_TIME: Duration = Duration::from_secs(60);
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    #[test]
+    fn test_timeout() {
+        let cache = InfraCache::new(16);
+        let ip = IpAddr::from(Ipv4Addr::new(192, 0, 2, 1));
+        let now = Instant::now();
+        assert_eq!(cache.timeout(ip), INITIAL_TIMEOUT);
+
+        for _ in 0..16 {
+            cache.record_rtt(ip, Duration::from_millis(40));
+        }
+        let timeout = cache.timeout(ip);
+        assert!(timeout >= MIN_TIMEOUT && timeout < INITIAL_TIMEOUT);
+
+        // the timeout doubles after each failure, up to the maximum
+        cache.record_failure(ip, now);
+        assert_eq!(cache.timeout(ip), timeout * 2);
+        for _ in 0..16 {
+            cache.record_failure(ip, now);
+        }
+        assert_eq!(cache.timeout(ip), MAX_TIMEOUT);
+
+        // and is computed from the round-trip time again after a response
+        cache.record_rtt(ip, Duration::from_millis(40));
+        assert!(cache.timeout(ip) < MAX_TIMEOUT);
+    }
+
+    #[test]
+    fn test_sort() {
+        let cache = InfraCache::new(16);
+        let [slow, fast, unknown, down] = [1, 2, 3, 4].map(|i| IpAddr::from([192, 0, 2, i]));
+        let now = Instant::now();
+
+        cache.record_rtt(slow, Duration::from_millis(200));
+        cache.record_rtt(fast, Duration::from_millis(20));
+        for _ in 0..HOLD_DOWN_FAILURES {
+            cache.record_failure(down, now);
+        }
+
+        // the exploration of slower servers only moves servers which are not held down
+        let mut explored = false;
+        for _ in 0..1_000 {
+            let mut servers = [down, slow, fast, unknown];
+            assert_eq!(cache.sort(&mut servers, |ip| *ip, now), 3);
+            assert_eq!(servers[3], down);
+            if servers[..3] != [unknown, fast, slow] {
+                explored = true;
+            }
+        }
+        assert!(explored);
+
+        // held down servers are queried again after the hold down time
+        let later = now + HOLD_DOWN_TIME + Duration::from_secs(1);
+        for _ in 0..1_000 {
+            let mut servers = [down, slow];
+            assert_eq!(cache.sort(&mut servers, |ip| *ip, later), 2);
+            if servers[0] == down {
+                return;
+            }
+        }
+        panic!("server not queried after the hold down time");
+    }
+}
@@ -26,13 +26,23 @@
 #![recursion_limit = "2048"]
 #![cfg_attr(docsrs, feature(doc_cfg, doc_auto_cfg))]
 
+#[cfg(feature = "__tls")]
+mod encrypted_transport;
 mod error;
+mod infra_cache;
 #[cfg(all(test, feature = "metrics"))]
 mod metrics_tests;
+#[cfg(test)]
+mod mock_provider;
 mod recursor;
 mod recursor_dns_handle;
 pub(crate) mod recursor_pool;
+mod resolution_trace;
+mod root_priming;
+#[cfg(feature = "__dnssec")]
+mod root_zone;
 
+use std::net::IpAddr;
 #[cfg(feature = "__dnssec")]
 use std::sync::Arc;
 
@@ -41,13 +51,16 @@ pub use hickory_proto as proto;
 pub use hickory_resolver as resolver;
 pub use hickory_resolver::config::NameServerConfig;
 #[cfg(feature = "__dnssec")]
-use proto::dnssec::TrustAnchors;
+use proto::dnssec::{NegativeTrustAnchors, TrustAnchorManager, TrustAnchors};
 use proto::{
     op::{Message, Query},
     rr::Record,
 };
 pub use recursor::{Recursor, RecursorBuilder};
+pub use resolution_trace::{ResolutionStep, ResolutionTrace};
 use resolver::Name;
+#[cfg(feature = "__dnssec")]
+pub use root_zone::RootZoneSource;
 use tracing::warn;
 
 /// `Recursor`'s DNSSEC policy
@@ -74,9 +87,29 @@ pub enum DnssecPolicy {
         /// NSEC3 hard iteration limit.  Responses with NSEC3 responses having an iteration count
         /// exceeding this value will return Proof::Bogus
         nsec3_hard_iteration_limit: Option<u16>,
+        /// domains for which validation is disabled, these can be changed while the `Recursor`
+        /// is running
+        negative_trust_anchors: Option<Arc<NegativeTrustAnchors>>,
+    },
+
+    /// DNSSEC validation is enabled and will use the keys of the `trust_anchor_manager`, which
+    /// follows rollovers of the root keys as described in RFC 5011
+    ///
+    /// The manager is refreshed in the background for as long as the `Recursor` is alive.
+    #[cfg(feature = "__dnssec")]
+    ValidateWithInitialKey {
+        /// maintains the trusted keys, starting from a set of initial keys
+        trust_anchor_manager: Arc<TrustAnchorManager>,
+        /// NSEC3 soft iteration limit.  Responses with NSEC3 records having an iteration count
+        /// exceeding this value, but less than the hard limit, will return Proof::Insecure
+        nsec3_soft_iteration_limit: Option<u16>,
+        /// NSEC3 hard iteration limit.  Responses with NSEC3 responses having an iteration count
+        /// exceeding this value will return Proof::Bogus
+        nsec3_hard_iteration_limit: Option<u16>,
+        /// domains for which validation is disabled, these can be changed while the `Recursor`
+        /// is running
+        negative_trust_anchors: Option<Arc<NegativeTrustAnchors>>,
     },
-    // TODO RFC5011
-    // ValidateWithInitialKey { ..  },}
 }
 
 impl DnssecPolicy {
@@ -85,6 +118,111 @@ impl DnssecPolicy {
     }
 }
 
+/// How query names are minimised when looking for the name servers of a zone, see RFC 9156
+///
+/// The name servers of each zone are asked for the next label of the query name only, so that the
+/// full query name is only sent to the name servers of the zone it belongs to. In the minimising
+/// modes, NXDOMAIN for an ancestor name ends the resolution (RFC 8020).
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum QnameMinimization {
+    /// Send the full query name when a name server answers a minimised query with an error, as
+    /// some name servers are broken for minimised queries
+    #[default]
+    Relaxed,
+    /// Never send the full query name to the name servers of an ancestor zone, errors for a
+    /// minimised query end the resolution
+    Strict,
+    /// Send the full query name to the name servers of every zone
+    Off,
+}
+
+/// Limits on the work done to answer a single client query, against resource exhaustion attacks
+/// such as NXNS and KeyTrap
+///
+/// A query exceeding one of the limits fails with
+/// [`ProtoErrorKind::ResourceLimitExceeded`](crate::proto::ProtoErrorKind::ResourceLimitExceeded)
+/// giving the limit which was exceeded. The hickory-server recursive zone handler answers it
+/// with SERVFAIL, and an Extended DNS Error (RFC 8914) of INFO-CODE 0 (Other) whose
+/// EXTRA-TEXT describes the limit for clients using EDNS. See
+/// [`RecursorBuilder::resource_limits()`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize, serde::Serialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct ResourceLimits {
+    /// Maximum number of name server names without glue resolved for a referral; the query fails
+    /// if none of the names resolved has an address
+    pub max_ns_names_per_referral: u8,
+    /// Maximum number of queries sent to name servers, including those made to find name servers
+    /// and to validate the response
+    pub max_upstream_queries: u16,
+    /// Maximum number of DNSSEC signatures checked, when validation is enabled
+    pub max_signature_validations: u16,
+    /// Maximum number of DNSKEY or DS records with the same key tag tried, when validation is
+    /// enabled
+    pub max_key_tag_collisions: u8,
+}
+
+impl Default for ResourceLimits {
+    fn default() -> Self {
+        Self {
+            max_ns_names_per_referral: 5,
+            max_upstream_queries: 128,
+            max_signature_validations: 64,
+            max_key_tag_collisions: 2,
+        }
+    }
+}
+
+/// Name servers used for the names in a zone, instead of those found by following delegations from
+/// the roots
+///
+/// See [`RecursorBuilder::stub_zone()`] and [`RecursorBuilder::forward_zone()`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Deserialize, serde::Serialize),
+    serde(deny_unknown_fields)
+)]
+pub struct ZoneServers {
+    /// Name of the zone
+    pub zone: Name,
+    /// Addresses of the name servers, which are queried on port 53
+    pub servers: Vec<IpAddr>,
+    /// How responses for names in the zone are validated
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub validation: ZoneValidation,
+}
+
+impl ZoneServers {
+    /// Creates a zone served by `servers`, validated like any other zone
+    pub fn new(zone: Name, servers: Vec<IpAddr>) -> Self {
+        Self {
+            zone,
+            servers,
+            validation: ZoneValidation::default(),
+        }
+    }
+}
+
+/// How responses for names in a stub or forward zone are validated, see [`ZoneServers`]
+///
+/// This only applies when the [`DnssecPolicy`] enables validation.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum ZoneValidation {
+    /// Validate responses with the chain of trust from the trust anchor, which requires the zone to
+    /// be delegated from the public DNS tree, or to be provably insecure
+    #[default]
+    Validate,
+    /// Do not validate responses, they are treated as insecure like names covered by a negative
+    /// trust anchor; use this for internal zones which are not delegated from the public DNS tree
+    Insecure,
+}
+
 // as per section 3.2.1 of RFC4035
 fn maybe_strip_dnssec_records(
     query_has_dnssec_ok: bool,
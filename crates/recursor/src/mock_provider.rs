@@ -0,0 +1,292 @@
+//! A runtime provider answering queries from a [`MockHandler`] instead of the network
+
+use std::{
+    cmp,
+    collections::VecDeque,
+    future::{Future, ready},
+    io,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use bytes::Buf;
+use futures_util::{AsyncRead, AsyncWrite};
+use hickory_proto::{
+    op::Message,
+    runtime::{RuntimeProvider, Time, TokioHandle, TokioTime},
+    serialize::binary::BinDecodable,
+    tcp::DnsTcpStream,
+    udp::DnsUdpSocket,
+};
+use tracing::error;
+
+/// Request handling functionality that can be plugged into [`MockProvider`].
+pub(crate) trait MockHandler {
+    /// Takes in a request message and produces a response message.
+    fn handle(&self, destination: IpAddr, request: Message) -> Message;
+}
+
+impl<F: Fn(IpAddr, Message) -> Message> MockHandler for F {
+    fn handle(&self, destination: IpAddr, request: Message) -> Message {
+        self(destination, request)
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct MockProvider {
+    handler: Arc<dyn MockHandler + Send + Sync>,
+    tokio_handle: TokioHandle,
+    tcp_delay: Duration,
+}
+
+impl MockProvider {
+    pub(crate) fn new(handler: impl MockHandler + Send + Sync + 'static) -> Self {
+        Self {
+            handler: Arc::new(handler),
+            tokio_handle: TokioHandle::default(),
+            tcp_delay: Duration::ZERO,
+        }
+    }
+
+    /// Delays TCP connections by `delay`, like a distant or busy server
+    pub(crate) fn with_tcp_delay(mut self, delay: Duration) -> Self {
+        self.tcp_delay = delay;
+        self
+    }
+}
+
+impl RuntimeProvider for MockProvider {
+    type Handle = TokioHandle;
+
+    type Timer = TokioTime;
+
+    type Udp = MockUdpSocket;
+
+    type Tcp = MockTcpStream;
+
+    fn create_handle(&self) -> Self::Handle {
+        self.tokio_handle.clone()
+    }
+
+    fn connect_tcp(
+        &self,
+        server_addr: SocketAddr,
+        _bind_addr: Option<SocketAddr>,
+        _timeout: Option<Duration>,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Self::Tcp>> + Send>> {
+        let stream = MockTcpStream::new(self.handler.clone(), server_addr.ip());
+        let delay = self.tcp_delay;
+        Box::pin(async move {
+            TokioTime::delay_for(delay).await;
+            Ok(stream)
+        })
+    }
+
+    fn bind_udp(
+        &self,
+        _local_addr: SocketAddr,
+        _server_addr: SocketAddr,
+    ) -> Pin<Box<dyn Future<Output = io::Result<Self::Udp>> + Send>> {
+        Box::pin(ready(Ok(MockUdpSocket::new(self.handler.clone()))))
+    }
+}
+
+pub(crate) struct MockUdpSocket {
+    inner: Mutex<MockUdpSocketInner>,
+    handler: Arc<dyn MockHandler + Send + Sync + 'static>,
+}
+
+struct MockUdpSocketInner {
+    /// Response messages ready to be returned to the client.
+    incoming_datagrams: VecDeque<(Message, SocketAddr)>,
+    /// Waker from the last call to [`Self::poll_recv_from()`], if it returned `Pending`.
+    waker: Option<Waker>,
+}
+
+impl MockUdpSocket {
+    fn new(handler: Arc<dyn MockHandler + Send + Sync + 'static>) -> Self {
+        Self {
+            inner: Mutex::new(MockUdpSocketInner {
+                incoming_datagrams: VecDeque::new(),
+                waker: None,
+            }),
+            handler,
+        }
+    }
+}
+
+impl DnsUdpSocket for MockUdpSocket {
+    type Time = TokioTime;
+
+    fn poll_recv_from(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<(usize, SocketAddr)>> {
+        let mut guard = self.inner.lock().unwrap();
+        let Some((message, socket_addr)) = guard.incoming_datagrams.pop_front() else {
+            guard.waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        };
+
+        let encoded = match message.to_vec() {
+            Ok(vec) => vec,
+            Err(error) => {
+                error!(%error, "encoding response message failed");
+                return Poll::Ready(Err(io::Error::other(error)));
+            }
+        };
+        buf[..encoded.len()].copy_from_slice(&encoded);
+        Poll::Ready(Ok((encoded.len(), socket_addr)))
+    }
+
+    fn poll_send_to(
+        &self,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+        target: SocketAddr,
+    ) -> Poll<io::Result<usize>> {
+        let request = match Message::from_bytes(buf) {
+            Ok(message) => message,
+            Err(error) => {
+                error!(%error, "decoding request message failed");
+                return Poll::Ready(Err(io::Error::other(error)));
+            }
+        };
+        let response = self.handler.handle(target.ip(), request);
+
+        let mut guard = self.inner.lock().unwrap();
+        guard.incoming_datagrams.push_back((response, target));
+
+        if let Some(waker) = guard.waker.take() {
+            waker.wake();
+        }
+
+        Poll::Ready(Ok(buf.len()))
+    }
+}
+
+pub(crate) struct MockTcpStream {
+    inner: Mutex<MockTcpStreamInner>,
+    destination: IpAddr,
+    handler: Arc<dyn MockHandler + Send + Sync + 'static>,
+}
+
+struct MockTcpStreamInner {
+    /// Buffered stream data, from the client to the mocked server.
+    ///
+    /// This is produced via [`AsyncRead::poll_write()`], and consumed whenever a full message has
+    /// been buffered.
+    outgoing_buffer: VecDeque<u8>,
+    /// Buffered stream data, from the mocked server back to the client.
+    ///
+    /// This is consumed via [`AsyncRead::poll_read()`].
+    incoming_buffer: VecDeque<u8>,
+    /// Waker from the last call to [`AsyncRead::poll_read()`], if it returned `Pending`.
+    waker: Option<Waker>,
+}
+
+impl MockTcpStream {
+    fn new(handler: Arc<dyn MockHandler + Send + Sync + 'static>, destination: IpAddr) -> Self {
+        Self {
+            inner: Mutex::new(MockTcpStreamInner {
+                outgoing_buffer: VecDeque::new(),
+                incoming_buffer: VecDeque::new(),
+                waker: None,
+            }),
+            destination,
+            handler,
+        }
+    }
+}
+
+impl DnsTcpStream for MockTcpStream {
+    type Time = TokioTime;
+}
+
+impl AsyncRead for MockTcpStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut guard = self.inner.lock().unwrap();
+        let len = guard.incoming_buffer.len();
+        if len == 0 {
+            guard.waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        let clamped_len = cmp::min(len, buf.len());
+        guard.incoming_buffer.copy_to_slice(&mut buf[..clamped_len]);
+        Poll::Ready(Ok(clamped_len))
+    }
+}
+
+impl AsyncWrite for MockTcpStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let mut guard = self.inner.lock().unwrap();
+        guard.outgoing_buffer.extend(buf);
+
+        let mut any_writes = false;
+        while guard.outgoing_buffer.len() >= 2 {
+            let request_length_prefix = u16::from_be_bytes([
+                *guard.outgoing_buffer.front().unwrap(),
+                *guard.outgoing_buffer.get(1).unwrap(),
+            ]);
+            if guard.outgoing_buffer.len() < 2 + request_length_prefix as usize {
+                break;
+            }
+
+            guard.outgoing_buffer.advance(2);
+            let mut message_buf = vec![0u8; request_length_prefix as usize];
+            guard.outgoing_buffer.copy_to_slice(&mut message_buf);
+
+            let request = match Message::from_bytes(&message_buf) {
+                Ok(message) => message,
+                Err(error) => {
+                    error!(%error, "decoding request message failed");
+                    return Poll::Ready(Err(io::Error::other(error)));
+                }
+            };
+            let response = self.handler.handle(self.destination, request);
+
+            let encoded = match response.to_vec() {
+                Ok(vec) => vec,
+                Err(error) => {
+                    error!(%error, "encoding response message failed");
+                    return Poll::Ready(Err(io::Error::other(error)));
+                }
+            };
+
+            guard
+                .incoming_buffer
+                .extend(u16::to_be_bytes(u16::try_from(encoded.len()).unwrap()));
+            guard.incoming_buffer.extend(&encoded);
+
+            any_writes = true;
+        }
+
+        if any_writes {
+            if let Some(waker) = guard.waker.take() {
+                waker.wake();
+            }
+        }
+
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
@@ -13,29 +13,37 @@ use std::{
 };
 
 use ipnet::IpNet;
+#[cfg(feature = "metrics")]
+use metrics::{Counter, Unit, counter, describe_counter};
+use tracing::debug;
 
+#[cfg(feature = "metrics")]
+use crate::proto::ResourceLimit;
 #[cfg(all(feature = "__dnssec", feature = "metrics"))]
 use crate::recursor_dns_handle::RecursorCacheMetrics;
 use crate::{
-    DnssecPolicy, Error,
+    DnssecPolicy, Error, QnameMinimization, ResourceLimits, ZoneServers,
     proto::{
         op::{Message, Query},
+        rr::rdata::opt::ClientSubnet,
         runtime::TokioRuntimeProvider,
     },
     recursor_dns_handle::RecursorDnsHandle,
+    resolution_trace::{ResolutionTrace, TraceRecorder},
     resolver::{
         TtlConfig,
+        config::ClientSubnetConfig,
         name_server::{ConnectionProvider, TlsConfig},
     },
 };
 #[cfg(feature = "__dnssec")]
 use crate::{
-    ErrorKind,
+    ErrorKind, ResolutionStep, RootZoneSource,
     proto::{
-        DnsError, NoRecords, ProtoError,
-        dnssec::DnssecDnsHandle,
+        DnsError, NoRecords, ProtoError, ProtoErrorKind,
+        dnssec::{DnssecDnsHandle, ValidationTrace},
         op::{DnsRequestOptions, ResponseCode},
-        rr::RecordType,
+        rr::{Name, RecordType},
         xfer::{DnsHandle as _, FirstAnswer as _},
     },
     resolver::ResponseCache,
@@ -58,6 +66,19 @@ pub struct RecursorBuilder<P: ConnectionProvider> {
     pub(super) avoid_local_udp_ports: HashSet<u16>,
     pub(super) ttl_config: TtlConfig,
     pub(super) case_randomization: bool,
+    pub(super) client_subnet: Option<ClientSubnetConfig>,
+    pub(super) qname_minimization: QnameMinimization,
+    /// This controls how many queries are made to find the zone of a query name, see RFC 9156
+    /// section 2.3. Names with more labels are minimised by more than one label at a time.
+    pub(super) max_minimization_steps: u8,
+    pub(super) resource_limits: ResourceLimits,
+    pub(super) root_priming: bool,
+    #[cfg(feature = "__tls")]
+    pub(super) opportunistic_encryption: bool,
+    #[cfg(feature = "__dnssec")]
+    pub(super) root_zone: Option<RootZoneSource>,
+    pub(super) stub_zones: Vec<ZoneServers>,
+    pub(super) forward_zones: Vec<ZoneServers>,
     pub(super) conn_provider: P,
 }
 
@@ -153,6 +174,97 @@ impl<P: ConnectionProvider> RecursorBuilder<P> {
         self
     }
 
+    /// Send the subnet of the client to authoritative name servers, see [`ClientSubnetConfig`].
+    ///
+    /// The address of the client is passed to [`Recursor::resolve_for_client()`].
+    pub fn client_subnet(mut self, config: ClientSubnetConfig) -> Self {
+        self.client_subnet = Some(config);
+        self
+    }
+
+    /// Sets how query names are minimised, see [`QnameMinimization`]
+    ///
+    /// Minimisation is relaxed by default, [`QnameMinimization::Off`] disables it.
+    pub fn qname_minimization(mut self, qname_minimization: QnameMinimization) -> Self {
+        self.qname_minimization = qname_minimization;
+        self
+    }
+
+    /// Sets the maximum number of queries made to find the zone of a query name
+    ///
+    /// The first four labels are added one at a time, and the remaining labels are then spread
+    /// over the remaining queries, as recommended by RFC 9156. This has no effect with
+    /// [`QnameMinimization::Off`], which is how minimisation is disabled.
+    pub fn max_minimization_steps(mut self, steps: u8) -> Self {
+        self.max_minimization_steps = steps;
+        self
+    }
+
+    /// Sets limits on the work done to answer a single client query, see [`ResourceLimits`]
+    pub fn resource_limits(mut self, limits: ResourceLimits) -> Self {
+        self.resource_limits = limits;
+        self
+    }
+
+    /// Query the root name servers for the servers of the root zone, see RFC 8109
+    ///
+    /// Enabled by default. The name servers passed to [`Self::build`] are then only used as hints:
+    /// a priming query for the NS records of the root zone is sent to them in the background when
+    /// the root name servers are first queried, and again when these records expire. The root name servers and
+    /// addresses of the response replace the hints, and the differences between them are logged.
+    /// The priming task is spawned on the runtime of the connection provider.
+    pub fn root_priming(mut self, enabled: bool) -> Self {
+        self.root_priming = enabled;
+        self
+    }
+
+    /// Send queries to authoritative name servers over DNS over QUIC or DNS over TLS when they
+    /// support it, see RFC 9539
+    ///
+    /// Each name server is probed for these transports on port 853 the first time it is queried,
+    /// and the result is remembered. Servers are not authenticated, which protects queries from
+    /// passive eavesdroppers only. Queries are sent over UDP and TCP to the servers which do not
+    /// support an encrypted transport, and to those for which it fails; these are probed again
+    /// after a day, and after longer periods following repeated failures.
+    #[cfg(feature = "__tls")]
+    pub fn opportunistic_encryption(mut self, enabled: bool) -> Self {
+        self.opportunistic_encryption = enabled;
+        self
+    }
+
+    /// Answer queries to the root name servers from a local copy of the root zone, see RFC 8806
+    ///
+    /// The zone is loaded from `source` in the background, and refreshed as specified by its SOA
+    /// record. It is only used after its ZONEMD record has been verified, with a signature chaining
+    /// to the trust anchor of the DNSSEC policy, or to the built-in trust anchor when validation is
+    /// disabled. The root name servers are queried while no current copy is available.
+    #[cfg(feature = "__dnssec")]
+    pub fn root_zone(mut self, source: RootZoneSource) -> Self {
+        self.root_zone = Some(source);
+        self
+    }
+
+    /// Query the given name servers as the authoritative name servers of a zone
+    ///
+    /// Names in the zone are resolved starting from these name servers rather than from the roots,
+    /// following any delegations to its subzones. This is useful for internal zones which are not
+    /// delegated from the public DNS tree. The servers are queried even if their addresses are in
+    /// the networks denied with [`Self::deny_servers`], but the addresses they refer to are not.
+    pub fn stub_zone(mut self, zone: ZoneServers) -> Self {
+        self.stub_zones.push(zone);
+        self
+    }
+
+    /// Forward queries for the names in a zone to the given recursive resolvers
+    ///
+    /// Queries are sent to these resolvers with the RD bit set, and their responses are used as
+    /// they are, without following delegations. As for [`Self::stub_zone`], the resolvers are
+    /// queried even if their addresses are in the networks denied with [`Self::deny_servers`].
+    pub fn forward_zone(mut self, zone: ZoneServers) -> Self {
+        self.forward_zones.push(zone);
+        self
+    }
+
     /// Construct a new recursor using the list of root zone name server addresses
     ///
     /// # Panics
@@ -168,6 +280,9 @@ impl<P: ConnectionProvider> RecursorBuilder<P> {
 /// This is the well known root nodes, referred to as hints in RFCs. See the IANA [Root Servers](https://www.iana.org/domains/root/servers) list.
 pub struct Recursor<P: ConnectionProvider> {
     pub(super) mode: RecursorMode<P>,
+    client_subnet: Option<ClientSubnetConfig>,
+    #[cfg(feature = "metrics")]
+    resource_limit_metrics: ResourceLimitMetrics,
 }
 
 impl Recursor<TokioRuntimeProvider> {
@@ -194,6 +309,17 @@ impl<P: ConnectionProvider> Recursor<P> {
             avoid_local_udp_ports: HashSet::new(),
             ttl_config: TtlConfig::default(),
             case_randomization: false,
+            client_subnet: None,
+            qname_minimization: QnameMinimization::default(),
+            max_minimization_steps: 10,
+            resource_limits: ResourceLimits::default(),
+            root_priming: true,
+            #[cfg(feature = "__tls")]
+            opportunistic_encryption: false,
+            #[cfg(feature = "__dnssec")]
+            root_zone: None,
+            stub_zones: vec![],
+            forward_zones: vec![],
             conn_provider,
         }
     }
@@ -206,6 +332,9 @@ impl<P: ConnectionProvider> Recursor<P> {
 
     fn build(roots: &[IpAddr], builder: RecursorBuilder<P>) -> Result<Self, Error> {
         Ok(Self {
+            client_subnet: builder.client_subnet.clone(),
+            #[cfg(feature = "metrics")]
+            resource_limit_metrics: ResourceLimitMetrics::new(),
             mode: RecursorDnsHandle::build_recursor_mode(
                 roots,
                 Arc::new(TlsConfig::new()?),
@@ -380,32 +509,208 @@ impl<P: ConnectionProvider> Recursor<P> {
         query: Query,
         request_time: Instant,
         query_has_dnssec_ok: bool,
+    ) -> Result<Message, Error> {
+        self.resolve_inner(
+            query,
+            request_time,
+            query_has_dnssec_ok,
+            None,
+            TraceRecorder::default(),
+        )
+        .await
+    }
+
+    /// Resolve a query for a client at the address `client`, like [`Self::resolve`]
+    ///
+    /// If client subnets are configured with [`RecursorBuilder::client_subnet()`], the prefix of
+    /// `client` is sent to the authoritative name servers of the configured domains, and responses
+    /// are cached for the network given by their scope.
+    pub async fn resolve_for_client(
+        &self,
+        query: Query,
+        request_time: Instant,
+        query_has_dnssec_ok: bool,
+        client: IpAddr,
+    ) -> Result<Message, Error> {
+        self.resolve_inner(
+            query,
+            request_time,
+            query_has_dnssec_ok,
+            Some(client),
+            TraceRecorder::default(),
+        )
+        .await
+    }
+
+    /// Resolve a query, like [`Self::resolve`], and return the steps taken to resolve it
+    ///
+    /// The trace lists the zone cuts visited with their name servers, the queries sent to each name
+    /// server with its response time and response code, and the responses found in the cache. This
+    /// includes the lookups made to validate the response, if DNSSEC validation is enabled. Queries
+    /// which were already in flight for a concurrent resolution that is not traced are not listed.
+    pub async fn resolve_with_trace(
+        &self,
+        query: Query,
+        request_time: Instant,
+        query_has_dnssec_ok: bool,
+    ) -> (Result<Message, Error>, ResolutionTrace) {
+        let trace = TraceRecorder::start();
+        let result = self
+            .resolve_inner(
+                query,
+                request_time,
+                query_has_dnssec_ok,
+                None,
+                trace.clone(),
+            )
+            .await;
+        (result, trace.snapshot())
+    }
+
+    async fn resolve_inner(
+        &self,
+        query: Query,
+        request_time: Instant,
+        query_has_dnssec_ok: bool,
+        client: Option<IpAddr>,
+        trace: TraceRecorder,
     ) -> Result<Message, Error> {
         if !query.name().is_fqdn() {
             return Err(Error::from("query's domain name must be fully qualified"));
         }
 
-        match &self.mode {
+        let client_subnet = self.client_subnet(client);
+        let result = match &self.mode {
             RecursorMode::NonValidating { handle } => {
                 handle
+                    .for_query(trace)
                     .resolve(
                         query,
                         request_time,
                         query_has_dnssec_ok,
                         0,
                         Arc::new(AtomicU8::new(0)),
+                        client_subnet,
                     )
                     .await
             }
 
             #[cfg(feature = "__dnssec")]
+            RecursorMode::Validating { .. } => {
+                self.validating_resolve(
+                    query,
+                    request_time,
+                    query_has_dnssec_ok,
+                    client_subnet,
+                    trace,
+                    false,
+                )
+                .await
+                .0
+            }
+        };
+
+        self.count_resource_limit(&result);
+        result
+    }
+
+    /// Counts queries which failed because they exceeded a resource limit
+    fn count_resource_limit(&self, result: &Result<Message, Error>) {
+        let Some(limit) = result.as_ref().err().and_then(Error::resource_limit) else {
+            return;
+        };
+
+        debug!("resource limit exceeded: {limit}");
+        #[cfg(feature = "metrics")]
+        self.resource_limit_metrics.increment(limit);
+    }
+
+    /// Returns the client subnet sent for a client at `client`, if any
+    fn client_subnet(&self, client: Option<IpAddr>) -> Option<ClientSubnet> {
+        self.client_subnet
+            .as_ref()
+            .and_then(|config| config.client_subnet(client))
+    }
+
+    /// Whether the client subnet is sent in queries for `name`
+    #[cfg(feature = "__dnssec")]
+    fn is_client_subnet_domain(&self, name: &Name) -> bool {
+        self.client_subnet
+            .as_ref()
+            .is_some_and(|config| config.domains.iter().any(|domain| domain.zone_of(name)))
+    }
+
+    /// Resolve a query, like [`Self::resolve`], and return the steps taken to validate the response
+    ///
+    /// The trace is `None` if DNSSEC validation is disabled, or if the response was answered from
+    /// the cache of validated responses. For bogus negative responses, the trace is also included
+    /// in the response of the [`DnsError::Nsec`] error.
+    #[cfg(feature = "__dnssec")]
+    pub async fn resolve_with_validation_trace(
+        &self,
+        query: Query,
+        request_time: Instant,
+        query_has_dnssec_ok: bool,
+    ) -> (Result<Message, Error>, Option<Arc<ValidationTrace>>) {
+        if !query.name().is_fqdn() {
+            return (
+                Err(Error::from("query's domain name must be fully qualified")),
+                None,
+            );
+        }
+
+        let (result, trace) = self
+            .validating_resolve(
+                query,
+                request_time,
+                query_has_dnssec_ok,
+                self.client_subnet(None),
+                TraceRecorder::default(),
+                true,
+            )
+            .await;
+        self.count_resource_limit(&result);
+        (result, trace)
+    }
+
+    #[cfg(feature = "__dnssec")]
+    async fn validating_resolve(
+        &self,
+        query: Query,
+        request_time: Instant,
+        query_has_dnssec_ok: bool,
+        client_subnet: Option<ClientSubnet>,
+        trace: TraceRecorder,
+        validation_trace: bool,
+    ) -> (Result<Message, Error>, Option<Arc<ValidationTrace>>) {
+        match &self.mode {
+            RecursorMode::NonValidating { handle } => {
+                let result = handle
+                    .for_query(trace)
+                    .resolve(
+                        query,
+                        request_time,
+                        query_has_dnssec_ok,
+                        0,
+                        Arc::new(AtomicU8::new(0)),
+                        client_subnet,
+                    )
+                    .await;
+                (result, None)
+            }
             RecursorMode::Validating {
                 handle,
                 validated_response_cache,
                 #[cfg(feature = "metrics")]
                 cache_metrics,
             } => {
-                if let Some(Ok(response)) = validated_response_cache.get(&query, request_time) {
+                let cached = match &client_subnet {
+                    Some(subnet) => {
+                        validated_response_cache.get_for_subnet(&query, subnet, request_time)
+                    }
+                    None => validated_response_cache.get(&query, request_time),
+                };
+                if let Some(Ok(response)) = cached {
                     // Increment metrics on cache hits only. We will check the cache a second time
                     // inside resolve(), thus we only track cache misses there.
                     #[cfg(feature = "metrics")]
@@ -418,11 +723,12 @@ impl<P: ConnectionProvider> Recursor<P> {
                     // if the cached response is a referral, or if any record is indeterminate, fall
                     // through and perform DNSSEC validation
                     if response.authoritative() && none_indeterminate {
-                        return Ok(super::maybe_strip_dnssec_records(
-                            query_has_dnssec_ok,
-                            response,
-                            query,
-                        ));
+                        trace.push(ResolutionStep::CacheHit {
+                            query: query.clone(),
+                        });
+                        let response =
+                            super::maybe_strip_dnssec_records(query_has_dnssec_ok, response, query);
+                        return (Ok(response), None);
                     }
                 }
 
@@ -430,18 +736,36 @@ impl<P: ConnectionProvider> Recursor<P> {
                 // a validating recursor must be security aware
                 options.use_edns = true;
                 options.edns_set_dnssec_ok = true;
+                options.client_subnet = client_subnet;
+                options.validation_trace = validation_trace;
 
-                let response = handle.lookup(query.clone(), options).first_answer().await?;
+                let handle = handle.map_handle(|handle| handle.for_query(trace));
+                let response = match handle.lookup(query.clone(), options).first_answer().await {
+                    Ok(response) => response,
+                    Err(err) => {
+                        let trace = match err.kind() {
+                            ProtoErrorKind::Dns(DnsError::Nsec { response, .. }) => {
+                                response.validation_trace().cloned()
+                            }
+                            _ => None,
+                        };
+                        return (Err(err.into()), trace);
+                    }
+                };
+                let trace = response.validation_trace().cloned();
 
                 // Return NXDomain and NoData responses in error form
                 // These need to bypass the cache lookup (and casting to a Lookup object in general)
                 // to preserve SOA and DNSSEC records, and to keep those records in the authorities
                 // section of the response.
-                if response.response_code() == ResponseCode::NXDomain {
+                let result = if response.response_code() == ResponseCode::NXDomain {
                     let Err(dns_error) = DnsError::from_response(response) else {
-                        return Err(Error::from(
-                            "unable to build ProtoError from response {response:?}",
-                        ));
+                        return (
+                            Err(Error::from(
+                                "unable to build ProtoError from response {response:?}",
+                            )),
+                            trace,
+                        );
                     };
 
                     Err(Error {
@@ -471,23 +795,80 @@ impl<P: ConnectionProvider> Recursor<P> {
 
                     Err(Error::from(ProtoError::from(no_records)))
                 } else {
+                    // the response is cached for the network given by its scope, RFC 7871 section
+                    // 7.3.1, if the client subnet was sent for the query
+                    let scope = client_subnet
+                        .filter(|_| self.is_client_subnet_domain(query.name()))
+                        .map(|mut subnet| {
+                            subnet.set_scope_prefix(ClientSubnet::response_scope(&response));
+                            subnet
+                        });
                     let message = response.into_message();
-                    validated_response_cache.insert(
-                        query.clone(),
-                        Ok(message.clone()),
-                        request_time,
-                    );
+                    match scope {
+                        Some(scope) => validated_response_cache.insert_for_subnet(
+                            query.clone(),
+                            &scope,
+                            Ok(message.clone()),
+                            request_time,
+                        ),
+                        None => validated_response_cache.insert(
+                            query.clone(),
+                            Ok(message.clone()),
+                            request_time,
+                        ),
+                    }
                     Ok(super::maybe_strip_dnssec_records(
                         query_has_dnssec_ok,
                         message,
                         query,
                     ))
-                }
+                };
+
+                (result, trace)
             }
         }
     }
 }
 
+#[cfg(feature = "metrics")]
+struct ResourceLimitMetrics {
+    ns_names: Counter,
+    upstream_queries: Counter,
+    signature_validations: Counter,
+    key_tag_collisions: Counter,
+}
+
+#[cfg(feature = "metrics")]
+impl ResourceLimitMetrics {
+    fn new() -> Self {
+        let name = "hickory_recursor_resource_limit_exceeded_total";
+        let counter = |limit: &'static str| counter!(name, "limit" => limit);
+        let metrics = Self {
+            ns_names: counter("ns_names"),
+            upstream_queries: counter("upstream_queries"),
+            signature_validations: counter("signature_validations"),
+            key_tag_collisions: counter("key_tag_collisions"),
+        };
+        describe_counter!(
+            name,
+            Unit::Count,
+            "Number of recursive requests that failed because they exceeded a resource limit."
+        );
+        metrics
+    }
+
+    fn increment(&self, limit: ResourceLimit) {
+        let counter = match limit {
+            ResourceLimit::NameServerNames => &self.ns_names,
+            ResourceLimit::UpstreamQueries => &self.upstream_queries,
+            ResourceLimit::SignatureValidations => &self.signature_validations,
+            ResourceLimit::KeyTagCollisions => &self.key_tag_collisions,
+            _ => return,
+        };
+        counter.increment(1);
+    }
+}
+
 pub(super) enum RecursorMode<P: ConnectionProvider> {
     NonValidating {
         handle: RecursorDnsHandle<P>,
@@ -544,18 +925,27 @@ mod for_dnssec {
             };
 
             let this = self.clone();
+            let client_subnet = request.options().client_subnet;
             stream::once(async move {
                 // request the DNSSEC records; we'll strip them if not needed on the caller side
                 let do_bit = true;
 
-                let future =
-                    this.resolve(query, Instant::now(), do_bit, 0, Arc::new(AtomicU8::new(0)));
+                let future = this.resolve(
+                    query,
+                    Instant::now(),
+                    do_bit,
+                    0,
+                    Arc::new(AtomicU8::new(0)),
+                    client_subnet,
+                );
                 let response = match future.await {
                     Ok(response) => response,
                     Err(e) => {
                         return Err(match e.kind() {
                             // Translate back into a ProtoError::NoRecordsFound
                             ErrorKind::Negative(_fwd) => e.into(),
+                            // Keep the limit which was exceeded
+                            _ if e.resource_limit().is_some() => e.into(),
                             _ => ProtoError::from(e.to_string()),
                         });
                     }
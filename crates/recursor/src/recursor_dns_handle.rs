@@ -1,9 +1,11 @@
 use std::{
+    cmp::Reverse,
     collections::{HashMap, HashSet},
+    future::Future,
     net::IpAddr,
     sync::{
         Arc,
-        atomic::{AtomicU8, Ordering},
+        atomic::{AtomicU8, AtomicUsize, Ordering},
     },
     time::Instant,
 };
@@ -15,36 +17,57 @@ use ipnet::{IpNet, Ipv4Net, Ipv6Net};
 use lru_cache::LruCache;
 #[cfg(feature = "metrics")]
 use metrics::{Counter, Unit, counter, describe_counter};
-use parking_lot::Mutex;
+use parking_lot::{Mutex, RwLock};
 use prefix_trie::PrefixSet;
 use tracing::{debug, info, trace, warn};
 
-#[cfg(feature = "__dnssec")]
-use crate::proto::dnssec::{DnssecDnsHandle, TrustAnchors};
+#[cfg(feature = "__tls")]
+use crate::encrypted_transport::EncryptedTransports;
 use crate::{
-    DnssecPolicy, Error, ErrorKind, RecursorBuilder,
+    DnssecPolicy, Error, ErrorKind, QnameMinimization, RecursorBuilder, ResourceLimits,
+    infra_cache::InfraCache,
     proto::{
+        ProtoError, ProtoErrorKind, ResourceLimit,
         op::{Message, Query},
         rr::{
             RData,
             RData::CNAME,
             Record, RecordType,
-            rdata::{A, AAAA, NS},
+            rdata::{A, AAAA, NS, opt::ClientSubnet},
         },
+        runtime::{RuntimeProvider, Spawn},
     },
     recursor::RecursorMode,
     recursor_pool::RecursorPool,
+    resolution_trace::{ResolutionStep, TraceRecorder},
     resolver::{
         Name, ResponseCache,
         config::{NameServerConfig, ResolverOpts},
         name_server::{ConnectionProvider, NameServerPool},
     },
+    root_priming::RootPriming,
+};
+#[cfg(feature = "__dnssec")]
+use crate::{
+    ZoneValidation,
+    proto::dnssec::{DnssecDnsHandle, TrustAnchorManager, TrustAnchors},
+    root_zone::RootZoneMirror,
 };
 
 #[derive(Clone)]
 pub(crate) struct RecursorDnsHandle<P: ConnectionProvider> {
-    roots: RecursorPool<P>,
+    /// Pool of the root name servers, which is replaced by root priming
+    roots: Arc<RwLock<RecursorPool<P>>>,
+    /// Root priming, which is started when the root name servers are first queried
+    root_priming: Arc<Mutex<Option<RootPriming<P>>>>,
+    /// Pools of the configured stub and forward zones, with the longest zone names first
+    configured_zones: Arc<[RecursorPool<P>]>,
     name_server_cache: Arc<Mutex<LruCache<Name, RecursorPool<P>>>>,
+    /// Round-trip times and failures of the name servers, shared by the pools of all zones
+    infra_cache: Arc<InfraCache>,
+    /// Encrypted transports of the name servers, if opportunistic encryption is enabled
+    #[cfg(feature = "__tls")]
+    encrypted_transports: Option<Arc<EncryptedTransports<P>>>,
     response_cache: ResponseCache,
     #[cfg(feature = "metrics")]
     cache_metrics: RecursorCacheMetrics,
@@ -57,8 +80,18 @@ pub(crate) struct RecursorDnsHandle<P: ConnectionProvider> {
     allow_server_v6: PrefixSet<Ipv6Net>,
     avoid_local_udp_ports: Arc<HashSet<u16>>,
     case_randomization: bool,
+    qname_minimization: QnameMinimization,
+    max_minimization_steps: u8,
+    /// Domains for which the client subnet is sent to authoritative name servers
+    client_subnet_domains: Arc<[Name]>,
     tls: Arc<TlsConfig>,
     conn_provider: P,
+    /// Records the steps of the resolution, if it is traced
+    trace: TraceRecorder,
+    resource_limits: ResourceLimits,
+    /// Number of queries sent to name servers for the client query, which is not tracked for the
+    /// lookups of background tasks
+    upstream_queries: Option<Arc<AtomicUsize>>,
 }
 
 impl<P: ConnectionProvider> RecursorDnsHandle<P> {
@@ -85,6 +118,17 @@ impl<P: ConnectionProvider> RecursorDnsHandle<P> {
             avoid_local_udp_ports,
             ttl_config,
             case_randomization,
+            client_subnet,
+            qname_minimization,
+            max_minimization_steps,
+            resource_limits,
+            root_priming,
+            #[cfg(feature = "__tls")]
+            opportunistic_encryption,
+            #[cfg(feature = "__dnssec")]
+            root_zone,
+            stub_zones,
+            forward_zones,
             conn_provider,
         } = builder;
 
@@ -94,15 +138,111 @@ impl<P: ConnectionProvider> RecursorDnsHandle<P> {
             "Using cache sizes {}/{}",
             ns_cache_size, response_cache_size
         );
-        let opts = recursor_opts(avoid_local_udp_ports.clone(), case_randomization);
-        let roots = NameServerPool::from_config(
-            servers,
-            Arc::new(opts),
-            tls.clone(),
-            conn_provider.clone(),
-        );
+        let opts = Arc::new(recursor_opts(
+            avoid_local_udp_ports.clone(),
+            case_randomization,
+        ));
+        let roots =
+            NameServerPool::from_config(servers, opts.clone(), tls.clone(), conn_provider.clone());
+
+        // zones usually have a few name servers, which are often shared with other zones
+        let infra_cache = Arc::new(InfraCache::new(ns_cache_size.saturating_mul(4)));
+        #[cfg(feature = "__tls")]
+        let encrypted_transports = match opportunistic_encryption {
+            true => Some(Arc::new(EncryptedTransports::new(
+                ns_cache_size.saturating_mul(4),
+                opts.clone(),
+                Arc::new(TlsConfig::unauthenticated()?),
+                conn_provider.clone(),
+            ))),
+            false => None,
+        };
+        let roots = RecursorPool::from(Name::root(), roots, infra_cache.clone());
+        #[cfg(feature = "__tls")]
+        let roots = roots.with_encrypted_transports(encrypted_transports.clone());
+        // priming queries are sent to the root hints, as the local root zone has no glue records
+        let hints = roots.clone();
+        #[cfg(feature = "__dnssec")]
+        let roots = match root_zone {
+            Some(source) => {
+                let mirror = Arc::new(RootZoneMirror::new(source, &dnssec_policy));
+                let refresh = RootZoneMirror::run(
+                    Arc::downgrade(&mirror),
+                    conn_provider.clone(),
+                    tls.clone(),
+                );
+                spawn_bg(&conn_provider, refresh)?;
+                roots.with_root_zone(mirror)
+            }
+            None => roots,
+        };
+
+        let roots = Arc::new(RwLock::new(roots));
+        let root_priming = root_priming.then(|| {
+            RootPriming::new(
+                &roots,
+                hints,
+                dnssec_policy.is_security_aware(),
+                opts,
+                tls.clone(),
+                conn_provider.clone(),
+            )
+        });
+
+        let mut configured_zones = Vec::<RecursorPool<P>>::new();
+        #[cfg(feature = "__dnssec")]
+        let mut insecure_zones = Vec::new();
+        let zones = stub_zones
+            .iter()
+            .map(|zone| (zone, false))
+            .chain(forward_zones.iter().map(|zone| (zone, true)));
+        for (config, forwarding) in zones {
+            let mut zone = config.zone.clone();
+            zone.set_fqdn(true);
+            if configured_zones.iter().any(|pool| pool.zone() == &zone) {
+                return Err(Error::from(format!(
+                    "zone {zone} is configured more than once"
+                )));
+            }
+            if config.servers.is_empty() {
+                return Err(Error::from(format!(
+                    "no name servers configured for {zone}"
+                )));
+            }
+
+            #[cfg(feature = "__dnssec")]
+            if config.validation == ZoneValidation::Insecure {
+                insecure_zones.push(zone.clone());
+            }
+
+            let mut opts = recursor_opts(avoid_local_udp_ports.clone(), case_randomization);
+            opts.recursion_desired = forwarding;
+            let ns = NameServerPool::from_config(
+                config
+                    .servers
+                    .iter()
+                    .copied()
+                    .map(NameServerConfig::udp_and_tcp)
+                    .collect::<Vec<_>>(),
+                Arc::new(opts),
+                tls.clone(),
+                conn_provider.clone(),
+            );
+
+            info!(forwarding, "using configured name servers for {zone}");
+            let pool = RecursorPool::from(zone, ns, infra_cache.clone());
+            configured_zones.push(if forwarding {
+                pool.forwarding()
+            } else {
+                // the name servers of stub zones are authoritative, like those found by following
+                // referrals
+                #[cfg(feature = "__tls")]
+                let pool = pool.with_encrypted_transports(encrypted_transports.clone());
+                pool
+            });
+        }
+        configured_zones.sort_by_key(|pool| Reverse(pool.zone().num_labels()));
 
-        let roots = RecursorPool::from(Name::root(), roots);
         let name_server_cache = Arc::new(Mutex::new(LruCache::new(ns_cache_size)));
         let response_cache = ResponseCache::new(response_cache_size, ttl_config.clone());
 
@@ -138,7 +278,12 @@ impl<P: ConnectionProvider> RecursorDnsHandle<P> {
 
         let handle = Self {
             roots,
+            root_priming: Arc::new(Mutex::new(root_priming)),
+            configured_zones: Arc::from(configured_zones),
             name_server_cache,
+            infra_cache,
+            #[cfg(feature = "__tls")]
+            encrypted_transports,
             response_cache,
             #[cfg(feature = "metrics")]
             cache_metrics: RecursorCacheMetrics::new(),
@@ -151,8 +296,17 @@ impl<P: ConnectionProvider> RecursorDnsHandle<P> {
             allow_server_v6,
             avoid_local_udp_ports,
             case_randomization,
+            qname_minimization,
+            max_minimization_steps,
+            client_subnet_domains: match client_subnet {
+                Some(config) => Arc::from(config.domains),
+                None => Arc::from([]),
+            },
             tls,
             conn_provider,
+            trace: TraceRecorder::default(),
+            resource_limits,
+            upstream_queries: None,
         };
 
         Ok(match dnssec_policy {
@@ -166,6 +320,7 @@ impl<P: ConnectionProvider> RecursorDnsHandle<P> {
                 trust_anchor,
                 nsec3_soft_iteration_limit,
                 nsec3_hard_iteration_limit,
+                negative_trust_anchors,
             } => {
                 let validated_response_cache = ResponseCache::new(response_cache_size, ttl_config);
                 let trust_anchor = match trust_anchor {
@@ -184,12 +339,62 @@ impl<P: ConnectionProvider> RecursorDnsHandle<P> {
                         .nsec3_iteration_limits(
                             nsec3_soft_iteration_limit,
                             nsec3_hard_iteration_limit,
-                        ),
+                        )
+                        .validation_limits(
+                            resource_limits.max_signature_validations,
+                            resource_limits.max_key_tag_collisions,
+                        )
+                        .negative_trust_anchors(negative_trust_anchors.unwrap_or_default())
+                        .insecure_zones(insecure_zones),
+                }
+            }
+
+            #[cfg(feature = "__dnssec")]
+            DnssecPolicy::ValidateWithInitialKey {
+                trust_anchor_manager,
+                nsec3_soft_iteration_limit,
+                nsec3_hard_iteration_limit,
+                negative_trust_anchors,
+            } => {
+                if trust_anchor_manager.trust_anchors().is_empty() {
+                    return Err(Error::from("trust anchor must not be empty"));
+                }
+
+                let refresh =
+                    TrustAnchorManager::run(Arc::downgrade(&trust_anchor_manager), handle.clone());
+                spawn_bg(&handle.conn_provider, refresh)?;
+
+                let validated_response_cache = ResponseCache::new(response_cache_size, ttl_config);
+                RecursorMode::Validating {
+                    validated_response_cache,
+                    #[cfg(feature = "metrics")]
+                    cache_metrics: handle.cache_metrics().clone(),
+                    handle: DnssecDnsHandle::with_trust_anchor_manager(
+                        handle,
+                        trust_anchor_manager,
+                    )
+                    .nsec3_iteration_limits(nsec3_soft_iteration_limit, nsec3_hard_iteration_limit)
+                    .validation_limits(
+                        resource_limits.max_signature_validations,
+                        resource_limits.max_key_tag_collisions,
+                    )
+                    .negative_trust_anchors(negative_trust_anchors.unwrap_or_default())
+                    .insecure_zones(insecure_zones),
                 }
             }
         })
     }
 
+    /// Returns a copy of this handle to resolve a single client query, which records the steps of
+    /// the resolution in `trace`, and counts the queries sent against the resource limits
+    pub(crate) fn for_query(&self, trace: TraceRecorder) -> Self {
+        Self {
+            trace,
+            upstream_queries: Some(Arc::default()),
+            ..self.clone()
+        }
+    }
+
     pub(crate) async fn resolve(
         &self,
         query: Query,
@@ -197,12 +402,16 @@ impl<P: ConnectionProvider> RecursorDnsHandle<P> {
         query_has_dnssec_ok: bool,
         depth: u8,
         cname_limit: Arc<AtomicU8>,
+        client_subnet: Option<ClientSubnet>,
     ) -> Result<Message, Error> {
-        if let Some(result) = self.response_cache.get(&query, request_time) {
+        if let Some(result) = self.cache_get(&query, client_subnet.as_ref(), request_time) {
             let response = result?;
             if response.authoritative() {
                 #[cfg(feature = "metrics")]
                 self.cache_metrics.cache_hit_counter.increment(1);
+                self.trace.push(ResolutionStep::CacheHit {
+                    query: query.clone(),
+                });
 
                 let response = self
                     .resolve_cnames(
@@ -212,6 +421,7 @@ impl<P: ConnectionProvider> RecursorDnsHandle<P> {
                         query_has_dnssec_ok,
                         depth,
                         cname_limit,
+                        client_subnet,
                     )
                     .await?;
 
@@ -271,10 +481,29 @@ impl<P: ConnectionProvider> RecursorDnsHandle<P> {
 
         debug!("found zone {} for {query}", ns.zone());
 
-        let cached_response = self.filtered_cache_lookup(&query, request_time);
+        // responses of forwarders are not authoritative
+        let cached_response = if ns.is_forwarding() {
+            self.cache_get(&query, client_subnet.as_ref(), request_time)
+                .map(|result| result.map_err(Error::from))
+        } else {
+            self.filtered_cache_lookup(&query, client_subnet.as_ref(), request_time)
+        };
         let response = match cached_response {
-            Some(result) => result?,
-            None => self.lookup(query.clone(), ns, request_time).await?,
+            Some(result) => {
+                self.trace.push(ResolutionStep::CacheHit {
+                    query: query.clone(),
+                });
+                result?
+            }
+            None => {
+                let client_subnet = client_subnet.filter(|_| {
+                    self.client_subnet_domains
+                        .iter()
+                        .any(|domain| domain.zone_of(query.name()))
+                });
+                self.lookup(query.clone(), ns, request_time, client_subnet)
+                    .await?
+            }
         };
 
         let response = self
@@ -285,6 +514,7 @@ impl<P: ConnectionProvider> RecursorDnsHandle<P> {
                 query_has_dnssec_ok,
                 depth,
                 cname_limit,
+                client_subnet,
             )
             .await?;
 
@@ -298,6 +528,7 @@ impl<P: ConnectionProvider> RecursorDnsHandle<P> {
     }
 
     /// Handle CNAME expansion for the current query
+    #[allow(clippy::too_many_arguments)]
     #[async_recursion]
     async fn resolve_cnames(
         &self,
@@ -307,6 +538,7 @@ impl<P: ConnectionProvider> RecursorDnsHandle<P> {
         query_has_dnssec_ok: bool,
         mut depth: u8,
         cname_limit: Arc<AtomicU8>,
+        client_subnet: Option<ClientSubnet>,
     ) -> Result<Message, Error> {
         let query_type = query.query_type();
         let query_name = query.name().clone();
@@ -358,6 +590,7 @@ impl<P: ConnectionProvider> RecursorDnsHandle<P> {
                     query_has_dnssec_ok,
                     depth,
                     cname_limit.clone(),
+                    client_subnet,
                 )
                 .await
             {
@@ -394,8 +627,13 @@ impl<P: ConnectionProvider> RecursorDnsHandle<P> {
     }
 
     /// Retrieve a response from the cache, filtering out non-authoritative responses.
-    fn filtered_cache_lookup(&self, query: &Query, now: Instant) -> Option<Result<Message, Error>> {
-        let response = match self.response_cache.get(query, now) {
+    fn filtered_cache_lookup(
+        &self,
+        query: &Query,
+        client_subnet: Option<&ClientSubnet>,
+        now: Instant,
+    ) -> Option<Result<Message, Error>> {
+        let response = match self.cache_get(query, client_subnet, now) {
             Some(Ok(response)) => response,
             Some(Err(e)) => return Some(Err(e.into())),
             None => return None,
@@ -409,21 +647,54 @@ impl<P: ConnectionProvider> RecursorDnsHandle<P> {
         Some(Ok(response))
     }
 
+    /// Retrieve a response from the cache, for a client in `client_subnet` if set
+    fn cache_get(
+        &self,
+        query: &Query,
+        client_subnet: Option<&ClientSubnet>,
+        now: Instant,
+    ) -> Option<Result<Message, ProtoError>> {
+        match client_subnet {
+            Some(subnet) => self.response_cache.get_for_subnet(query, subnet, now),
+            None => self.response_cache.get(query, now),
+        }
+    }
+
     async fn lookup(
         &self,
         query: Query,
         ns: RecursorPool<P>,
         now: Instant,
+        client_subnet: Option<ClientSubnet>,
     ) -> Result<Message, Error> {
-        let response_future = ns.lookup(query.clone(), self.security_aware);
+        self.count_upstream_queries(1)?;
+        let response_future = ns.lookup(
+            query.clone(),
+            self.security_aware,
+            client_subnet,
+            &self.trace,
+        );
 
         // TODO: we are only expecting one response
         // TODO: should we change DnsHandle to always be a single response? And build a totally custom handler for other situations?
         // TODO: check if data is "authentic"
         match response_future.await {
             Ok(r) => {
+                // the response is cached for the network given by its scope, RFC 7871 section 7.3.1
+                let scope = client_subnet.map(|mut subnet| {
+                    subnet.set_scope_prefix(ClientSubnet::response_scope(&r));
+                    subnet
+                });
                 let message = r.into_message();
-                self.response_cache.insert(query, Ok(message.clone()), now);
+                match scope {
+                    Some(scope) => self.response_cache.insert_for_subnet(
+                        query,
+                        &scope,
+                        Ok(message.clone()),
+                        now,
+                    ),
+                    None => self.response_cache.insert(query, Ok(message.clone()), now),
+                }
                 Ok(message)
             }
             Err(e) => {
@@ -433,147 +704,338 @@ impl<P: ConnectionProvider> RecursorDnsHandle<P> {
         }
     }
 
-    #[async_recursion]
+    /// Find the name servers of `zone`, querying its minimised ancestors from the top
     async fn ns_pool_for_zone(
         &self,
         zone: Name,
         request_time: Instant,
-        mut depth: u8,
+        depth: u8,
     ) -> Result<(u8, RecursorPool<P>), Error> {
-        // TODO: need to check TTLs here.
-        if let Some(ns) = self.name_server_cache.lock().get_mut(&zone) {
-            debug!("returning cached pool for {zone}");
-            return Ok((depth, ns.clone()));
+        let mut ancestors = match self.qname_minimization {
+            QnameMinimization::Off => Vec::new(),
+            QnameMinimization::Relaxed | QnameMinimization::Strict => {
+                minimised_ancestors(&zone, self.max_minimization_steps)
+            }
         };
+        if let Some(pool) = self.configured_pool(&zone) {
+            if pool.is_forwarding() {
+                self.trace_configured_zone(pool);
+                return Ok((depth, pool.clone()));
+            }
 
-        trace!("ns_pool_for_zone: depth {depth} for {zone}");
+            // the names above a stub zone are not queried, its name servers are used instead
+            ancestors.retain(|name| pool.zone().zone_of(name));
+        }
 
-        depth += 1;
-        Error::recursion_exceeded(self.ns_recursion_limit, depth, &zone)?;
+        let (depth, ns, _) = self
+            .ns_pool_for_names(&zone, &ancestors, request_time, depth, true)
+            .await?;
+        Ok((depth, ns))
+    }
 
-        let parent_zone = zone.base_name();
+    /// Find the name servers of `zone`, querying the name servers of its last ancestor
+    ///
+    /// `full_name` is set for the name being resolved. The returned flag is cleared once a name
+    /// server failed to answer a minimised query, in which case the remaining ancestors are
+    /// skipped and the full name is sent instead.
+    #[async_recursion]
+    async fn ns_pool_for_names(
+        &self,
+        zone: &Name,
+        ancestors: &[Name],
+        request_time: Instant,
+        mut depth: u8,
+        full_name: bool,
+    ) -> Result<(u8, RecursorPool<P>, bool), Error> {
+        if let Some(pool) = self
+            .configured_pool(zone)
+            .filter(|pool| pool.zone() == zone)
+        {
+            debug!("using configured name servers for {zone}");
+            self.trace_configured_zone(pool);
+            return Ok((depth, pool.clone(), true));
+        }
 
-        let nameserver_pool = if parent_zone.is_root() {
-            debug!("using roots for {zone} nameservers");
-            self.roots.clone()
-        } else {
-            // Discard depth returned from recursive call.
-            self.ns_pool_for_zone(parent_zone, request_time, depth)
-                .await?
-                .1
+        // TODO: need to check TTLs here.
+        if let Some(ns) = self.name_server_cache.lock().get_mut(zone) {
+            debug!("returning cached pool for {zone}");
+            if self.trace.is_enabled() {
+                self.trace.push(ResolutionStep::CachedZoneCut {
+                    zone: zone.clone(),
+                    servers: ns.servers(),
+                });
+            }
+            return Ok((depth, ns.clone(), true));
         };
 
-        let query = Query::query(zone.clone(), RecordType::NS);
+        trace!("ns_pool_for_zone: depth {depth} for {zone}");
 
-        // Query for nameserver records via the pool for the parent zone.
-        let lookup_res = match self.response_cache.get(&query, request_time) {
-            Some(Ok(response)) => {
-                debug!(?response, "cached data");
-                Ok(response)
-            }
-            Some(Err(e)) => Err(e.into()),
-            None => {
-                self.lookup(query, nameserver_pool.clone(), request_time)
-                    .await
+        depth += 1;
+        Error::recursion_exceeded(self.ns_recursion_limit, depth, zone)?;
+
+        let (nameserver_pool, minimize) = match ancestors.split_last() {
+            Some((parent, ancestors)) => {
+                // Discard depth returned from recursive call.
+                let (_, pool, minimize) = self
+                    .ns_pool_for_names(parent, ancestors, request_time, depth, false)
+                    .await?;
+                (pool, minimize)
             }
-        };
-        let response = match lookup_res {
-            Ok(response) => response,
-            // Short-circuit on NXDOMAIN, per RFC 8020.
-            Err(e) if e.is_nx_domain() => return Err(e),
-            // Short-circuit on timeouts. Requesting a longer name from the same pool would likely
-            // encounter them again.
-            Err(e) if e.is_timeout() => return Err(e),
-            // The name `zone` is not a zone cut. Return the same pool of name servers again, but do
-            // not cache it. If this was recursively called by `ns_pool_for_zone()`, the outer call
-            // will try again with one more label added to the iterative query name.
-            Err(_) => return Ok((depth, nameserver_pool)),
+            None => match self.configured_pool(zone) {
+                Some(pool) => {
+                    debug!(
+                        "using configured name servers of {} for {zone}",
+                        pool.zone()
+                    );
+                    self.trace_configured_zone(pool);
+                    (pool.clone(), true)
+                }
+                None => {
+                    debug!("using roots for {zone} nameservers");
+                    (self.roots(), true)
+                }
+            },
         };
 
-        let any_ns = response
-            .all_sections()
-            .any(|record| record.record_type() == RecordType::NS);
-        if !any_ns {
-            // Not a zone cut, but there is a CNAME or other record at this name. Return the
-            // same pool of name servers as above in the error case, to try again with a
-            // longer name.
-            return Ok((depth, nameserver_pool));
+        if !minimize && !full_name {
+            return Ok((depth, nameserver_pool, false));
         }
 
-        // TODO: grab TTL and use for cache
-        // get all the NS records and glue
-        let mut config_group = Vec::new();
-        let mut need_ips_for_names = Vec::new();
-        let mut glue_ips = HashMap::new();
-
-        self.add_glue_to_map(&mut glue_ips, response.all_sections());
+        let strict = self.qname_minimization == QnameMinimization::Strict;
+        let mut nameserver_pool = nameserver_pool;
+        let mut referred = false;
+        loop {
+            let query = Query::query(zone.clone(), RecordType::NS);
+
+            // Query for nameserver records via the pool for the parent zone. After a referral to
+            // a zone between the parent zone and `zone`, the cached response is that of the parent.
+            let cached = match referred {
+                true => None,
+                false => self.response_cache.get(&query, request_time),
+            };
+            let lookup_res = match cached {
+                Some(result) => {
+                    debug!(?result, "cached data");
+                    self.trace.push(ResolutionStep::CacheHit {
+                        query: query.clone(),
+                    });
+                    result.map_err(Error::from)
+                }
+                None => {
+                    self.lookup(query, nameserver_pool.clone(), request_time, None)
+                        .await
+                }
+            };
+            let response = match lookup_res {
+                Ok(response) => response,
+                // Short-circuit on NXDOMAIN, per RFC 8020.
+                Err(e) if e.is_nx_domain() => return Err(e),
+                // Short-circuit on timeouts. Requesting a longer name from the same pool would
+                // likely encounter them again.
+                Err(e) if e.is_timeout() => return Err(e),
+                // A referral to a zone between the pool and `zone`, after minimisation was
+                // abandoned or when some ancestors were skipped.
+                Err(Error {
+                    kind: ErrorKind::ForwardNS(referrals),
+                    ..
+                }) => {
+                    let mut response = Message::query();
+                    for referral in referrals.iter() {
+                        response.add_authorities([referral.ns.clone()]);
+                        response.add_additionals(referral.glue.iter().cloned());
+                    }
+                    response
+                }
+                // The name `zone` is not a zone cut. Return the same pool of name servers again,
+                // but do not cache it. If this was recursively called by `ns_pool_for_names()`, the
+                // outer call will try again with the next iterative query name.
+                Err(e) if e.is_no_records_found() => {
+                    return Ok((depth, nameserver_pool, minimize));
+                }
+                Err(e) if strict && !full_name => return Err(e),
+                Err(e) => {
+                    if !full_name {
+                        debug!("minimised query {zone} failed, sending the full name: {e}");
+                    }
+                    return Ok((depth, nameserver_pool, false));
+                }
+            };
 
-        for zns in response.all_sections() {
-            let Some(ns_data) = zns.data().as_ns() else {
-                continue;
+            // The owner of the NS records is `zone`, or a zone between the pool and `zone` for a
+            // referral to an intermediate zone.
+            let parent = nameserver_pool.zone().clone();
+            let Some(cut) = response
+                .all_sections()
+                .filter(|record| record.record_type() == RecordType::NS)
+                .map(|record| record.name())
+                .find(|name| **name != parent && name.zone_of(zone))
+                .cloned()
+            else {
+                // Not a zone cut, but there is a CNAME or other record at this name. Return the
+                // same pool of name servers as above in the error case, to try again with a
+                // longer name.
+                return Ok((depth, nameserver_pool, minimize));
             };
 
-            if !super::is_subzone(&zone.base_name(), zns.name()) {
-                warn!(
-                    "dropping out of bailiwick record for {:?} with parent {:?}",
-                    zns.name(),
-                    zone.base_name(),
-                );
-                continue;
-            }
+            // TODO: grab TTL and use for cache
+            // get all the NS records and glue
+            let mut config_group = Vec::new();
+            let mut need_ips_for_names = Vec::new();
+            let mut glue_ips = HashMap::new();
+
+            self.add_glue_to_map(&mut glue_ips, response.all_sections());
+
+            for zns in response.all_sections() {
+                let Some(ns_data) = zns.data().as_ns() else {
+                    continue;
+                };
+
+                if !super::is_subzone(&parent, zns.name()) {
+                    warn!(
+                        "dropping out of bailiwick record for {:?} with parent {:?}",
+                        zns.name(),
+                        parent,
+                    );
+                    continue;
+                }
+
+                if *zns.name() != cut {
+                    continue;
+                }
 
-            for record_type in [RecordType::A, RecordType::AAAA] {
-                if let Some(Ok(response)) = self
-                    .response_cache
-                    .get(&Query::query(ns_data.0.clone(), record_type), request_time)
-                {
-                    self.add_glue_to_map(&mut glue_ips, response.all_sections());
+                for record_type in [RecordType::A, RecordType::AAAA] {
+                    if let Some(Ok(response)) = self
+                        .response_cache
+                        .get(&Query::query(ns_data.0.clone(), record_type), request_time)
+                    {
+                        self.add_glue_to_map(&mut glue_ips, response.all_sections());
+                    }
+                }
+
+                match glue_ips.get(&ns_data.0) {
+                    Some(glue) if !glue.is_empty() => {
+                        config_group
+                            .extend(glue.iter().copied().map(NameServerConfig::udp_and_tcp));
+                    }
+                    _ => {
+                        debug!("glue not found for {ns_data}");
+                        need_ips_for_names.push(ns_data.to_owned());
+                    }
                 }
             }
 
-            match glue_ips.get(&ns_data.0) {
-                Some(glue) if !glue.is_empty() => {
-                    config_group.extend(glue.iter().copied().map(NameServerConfig::udp_and_tcp));
+            // If we have no glue, collect missing nameserver IP addresses.
+            // For non-child name servers, get a new pool by calling ns_pool_for_zone recursively.
+            // For child child name servers, we can use the existing pool, but we *must* use lookup
+            // to avoid infinite recursion.
+            if config_group.is_empty() && !need_ips_for_names.is_empty() {
+                debug!("need glue for {cut}");
+
+                // Only resolve a few of the names, so that a referral to many names without glue
+                // does not make us send many queries (NXNS attack).
+                let max_names = usize::from(self.resource_limits.max_ns_names_per_referral);
+                let truncated = need_ips_for_names.len() > max_names;
+                if truncated {
+                    debug!(
+                        "resolving {max_names} of the {} name servers of {cut}",
+                        need_ips_for_names.len()
+                    );
+                    need_ips_for_names.truncate(max_names);
                 }
-                _ => {
-                    debug!("glue not found for {ns_data}");
-                    need_ips_for_names.push(ns_data.to_owned());
+
+                depth = self
+                    .append_ips_from_lookup(
+                        &cut,
+                        depth,
+                        request_time,
+                        nameserver_pool.clone(),
+                        need_ips_for_names.iter(),
+                        &mut config_group,
+                    )
+                    .await?;
+
+                if truncated && config_group.is_empty() {
+                    warn!("no address found for the name servers of {cut} within the limit");
+                    return Err(resource_limit_exceeded(ResourceLimit::NameServerNames));
                 }
             }
-        }
 
-        // If we have no glue, collect missing nameserver IP addresses.
-        // For non-child name servers, get a new pool by calling ns_pool_for_zone recursively.
-        // For child child name servers, we can use the existing pool, but we *must* use lookup
-        // to avoid infinite recursion.
-        if config_group.is_empty() && !need_ips_for_names.is_empty() {
-            debug!("need glue for {zone}");
+            if self.trace.is_enabled() {
+                let name_servers = response
+                    .all_sections()
+                    .filter(|record| *record.name() == cut)
+                    .filter_map(|record| record.data().as_ns())
+                    .map(|ns| ns.0.clone())
+                    .collect::<Vec<_>>();
+                self.trace.push(ResolutionStep::ZoneCut {
+                    zone: cut.clone(),
+                    glue: response
+                        .additionals()
+                        .iter()
+                        .filter(|record| name_servers.contains(record.name()))
+                        .filter(|record| record.data().ip_addr().is_some())
+                        .cloned()
+                        .collect(),
+                    name_servers,
+                    servers: config_group.iter().map(|config| config.ip).collect(),
+                });
+            }
 
-            depth = self
-                .append_ips_from_lookup(
-                    &zone,
-                    depth,
-                    request_time,
-                    nameserver_pool,
-                    need_ips_for_names.iter(),
-                    &mut config_group,
-                )
-                .await?;
+            // now construct a namesever pool based off the NS and glue records
+            let ns = NameServerPool::from_config(
+                config_group,
+                Arc::new(self.recursor_opts()),
+                self.tls.clone(),
+                self.conn_provider.clone(),
+            );
+            let ns = RecursorPool::from(cut.clone(), ns, self.infra_cache.clone());
+            #[cfg(feature = "__tls")]
+            let ns = ns.with_encrypted_transports(self.encrypted_transports.clone());
+
+            // store in cache for future usage
+            debug!("found nameservers for {cut}");
+            self.name_server_cache
+                .lock()
+                .insert(cut.clone(), ns.clone());
+            if cut == *zone {
+                return Ok((depth, ns, true));
+            }
+
+            // ask the name servers of the intermediate zone for `zone`
+            debug!("referral to {cut} for {zone}");
+            depth += 1;
+            Error::recursion_exceeded(self.ns_recursion_limit, depth, zone)?;
+            nameserver_pool = ns;
+            referred = true;
         }
+    }
 
-        // now construct a namesever pool based off the NS and glue records
-        let ns = NameServerPool::from_config(
-            config_group,
-            Arc::new(self.recursor_opts()),
-            self.tls.clone(),
-            self.conn_provider.clone(),
-        );
-        let ns = RecursorPool::from(zone.clone(), ns);
+    /// Returns the pool of the root name servers, starting root priming on its first use
+    fn roots(&self) -> RecursorPool<P> {
+        if let Some(priming) = self.root_priming.lock().take() {
+            if let Err(err) = spawn_bg(&self.conn_provider, priming.run()) {
+                warn!("failed to start root priming: {err}");
+            }
+        }
 
-        // store in cache for future usage
-        debug!("found nameservers for {zone}");
-        self.name_server_cache.lock().insert(zone, ns.clone());
-        Ok((depth, ns))
+        self.roots.read().clone()
+    }
+
+    fn trace_configured_zone(&self, pool: &RecursorPool<P>) {
+        if self.trace.is_enabled() {
+            self.trace.push(ResolutionStep::ConfiguredZone {
+                zone: pool.zone().clone(),
+                servers: pool.servers(),
+                forwarding: pool.is_forwarding(),
+            });
+        }
+    }
+
+    /// Returns the pool of the closest stub or forward zone containing `name`, if any
+    fn configured_pool(&self, name: &Name) -> Option<&RecursorPool<P>> {
+        self.configured_zones
+            .iter()
+            .find(|pool| pool.zone().zone_of(name))
     }
 
     /// Helper function to add IP addresses from any A or AAAA records to a map indexed by record
@@ -649,12 +1111,17 @@ impl<P: ConnectionProvider> RecursorDnsHandle<P> {
             pool_queries.push((nameserver_pool, record_name));
         }
 
+        self.count_upstream_queries(2 * pool_queries.len())?;
         let mut futures = FuturesUnordered::new();
 
         for (pool, query) in pool_queries.iter() {
             for rec_type in [RecordType::A, RecordType::AAAA] {
-                futures
-                    .push(pool.lookup(Query::query(query.clone(), rec_type), self.security_aware));
+                futures.push(pool.lookup(
+                    Query::query(query.clone(), rec_type),
+                    self.security_aware,
+                    None,
+                    &self.trace,
+                ));
             }
         }
 
@@ -685,6 +1152,21 @@ impl<P: ConnectionProvider> RecursorDnsHandle<P> {
         Ok(depth)
     }
 
+    /// Counts `count` queries to be sent to name servers, failing if the client query made too many
+    fn count_upstream_queries(&self, count: usize) -> Result<(), Error> {
+        let Some(upstream_queries) = &self.upstream_queries else {
+            return Ok(());
+        };
+
+        let total = upstream_queries.fetch_add(count, Ordering::Relaxed) + count;
+        if total <= usize::from(self.resource_limits.max_upstream_queries) {
+            return Ok(());
+        }
+
+        warn!("upstream query limit exceeded after {total} queries");
+        Err(resource_limit_exceeded(ResourceLimit::UpstreamQueries))
+    }
+
     fn recursor_opts(&self) -> ResolverOpts {
         recursor_opts(self.avoid_local_udp_ports.clone(), self.case_randomization)
     }
@@ -710,6 +1192,57 @@ fn recursor_opts(
     options
 }
 
+/// Returns the names to query for name servers before `name`, from the top
+///
+/// The first [`MINIMIZE_ONE_LABEL`] names add one label each, then the remaining labels are spread
+/// so that no more than `max_steps` queries are made, as described in RFC 9156 section 2.3.
+fn minimised_ancestors(name: &Name, max_steps: u8) -> Vec<Name> {
+    let labels = name.iter().len();
+    let mut ancestors = Vec::new();
+    let mut len = 0;
+    for step in 1.. {
+        let remaining = labels - len;
+        len += if step >= usize::from(max_steps) {
+            remaining
+        } else if step <= MINIMIZE_ONE_LABEL {
+            1
+        } else {
+            (remaining / (usize::from(max_steps) - step + 1)).max(1)
+        };
+
+        if len >= labels {
+            break;
+        }
+        ancestors.push(name.trim_to(len));
+    }
+
+    ancestors
+}
+
+/// Spawns a background task of the recursor on the runtime of the connection provider
+fn spawn_bg<P: ConnectionProvider>(
+    conn_provider: &P,
+    task: impl Future<Output = Result<(), ProtoError>> + Send + 'static,
+) -> Result<(), Error> {
+    let Some(runtime) = conn_provider.runtime_provider() else {
+        return Err(Error::from(
+            "the connection provider has no runtime provider to run background tasks",
+        ));
+    };
+
+    runtime.create_handle().spawn_bg(task);
+    Ok(())
+}
+
+fn resource_limit_exceeded(limit: ResourceLimit) -> Error {
+    Error::from(ProtoError::from(ProtoErrorKind::ResourceLimitExceeded(
+        limit,
+    )))
+}
+
+/// Number of queries adding a single label to the query name, see RFC 9156 section 2.3
+const MINIMIZE_ONE_LABEL: usize = 4;
+
 #[cfg(feature = "metrics")]
 #[derive(Clone)]
 pub(super) struct RecursorCacheMetrics {
@@ -746,11 +1279,28 @@ const MAX_CNAME_LOOKUPS: u8 = 64;
 #[cfg(test)]
 mod tests {
     use std::net::{IpAddr, Ipv4Addr};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
 
     use ipnet::IpNet;
+    use test_support::subscribe;
 
-    use crate::Recursor;
+    use super::minimised_ancestors;
+    use crate::mock_provider::{MockHandler, MockProvider};
+    use crate::proto::op::{Message, OpCode, Query, ResponseCode};
+    use crate::proto::rr::rdata::{A, NS, SOA};
+    use crate::proto::rr::{RData, Record, RecordType};
     use crate::recursor::RecursorMode;
+    use crate::resolver::Name;
+    use crate::{QnameMinimization, Recursor, ResolutionStep, ZoneServers};
+
+    const ROOT_IP: Ipv4Addr = Ipv4Addr::new(10, 0, 1, 1);
+    const TLD_IP: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 1);
+    const LEAF_IP: Ipv4Addr = Ipv4Addr::new(10, 0, 3, 1);
+    const A_RR_IP: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+    const STUB_IP: Ipv4Addr = Ipv4Addr::new(10, 0, 4, 1);
+    const FORWARDER_IP: Ipv4Addr = Ipv4Addr::new(10, 0, 5, 1);
 
     #[test]
     fn test_nameserver_filter() {
@@ -769,6 +1319,7 @@ mod tests {
         #[cfg_attr(not(feature = "__dnssec"), allow(irrefutable_let_patterns))]
         let Recursor {
             mode: RecursorMode::NonValidating { handle },
+            ..
         } = builder.build(&[IpAddr::from([192, 0, 2, 1])]).unwrap()
         else {
             panic!("unexpected DNSSEC validation mode");
@@ -788,4 +1339,519 @@ mod tests {
             assert!(!handle.matches_nameserver_filter(IpAddr::from(addr)));
         }
     }
+
+    #[test]
+    fn test_configured_zones() {
+        let name = |name: &str| Name::from_ascii(name).unwrap();
+        let servers = vec![IpAddr::from([10, 0, 0, 53])];
+
+        let builder = Recursor::builder()
+            .stub_zone(ZoneServers::new(name("corp.internal"), servers.clone()))
+            .forward_zone(ZoneServers::new(
+                name("lab.corp.internal."),
+                servers.clone(),
+            ));
+
+        #[cfg_attr(not(feature = "__dnssec"), allow(irrefutable_let_patterns))]
+        let Recursor {
+            mode: RecursorMode::NonValidating { handle },
+            ..
+        } = builder
+            .clone()
+            .build(&[IpAddr::from([192, 0, 2, 1])])
+            .unwrap()
+        else {
+            panic!("unexpected DNSSEC validation mode");
+        };
+
+        let pool = handle.configured_pool(&name("www.corp.internal.")).unwrap();
+        assert_eq!(pool.zone(), &name("corp.internal."));
+        assert!(!pool.is_forwarding());
+
+        let pool = handle
+            .configured_pool(&name("host.lab.corp.internal."))
+            .unwrap();
+        assert_eq!(pool.zone(), &name("lab.corp.internal."));
+        assert!(pool.is_forwarding());
+
+        assert!(handle.configured_pool(&name("internal.")).is_none());
+        assert!(handle.configured_pool(&name("example.com.")).is_none());
+
+        let duplicate = builder.forward_zone(ZoneServers::new(name("corp.internal."), servers));
+        assert!(duplicate.build(&[IpAddr::from([192, 0, 2, 1])]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_configured_zone_routing() {
+        subscribe();
+        let name = |name: &str| Name::from_ascii(name).unwrap();
+        let log = QueryLog::default();
+        let recursor =
+            Recursor::builder_with_provider(MockProvider::new(log.handler(mock_configured_zones)))
+                .clear_deny_servers() // We use addresses in the default deny filter.
+                .root_priming(false) // Priming queries would be logged.
+                .stub_zone(ZoneServers::new(
+                    name("corp.internal."),
+                    vec![STUB_IP.into()],
+                ))
+                .forward_zone(ZoneServers::new(
+                    name("lab.corp.internal."),
+                    vec![FORWARDER_IP.into()],
+                ))
+                .build(&[ROOT_IP.into()])
+                .unwrap();
+
+        let query = Query::query(name("www.corp.internal."), RecordType::A);
+        let response = recursor.resolve(query, Instant::now(), false).await;
+        assert_eq!(response.unwrap().answers().len(), 1);
+        assert!(
+            log.queries(STUB_IP)
+                .contains(&"www.corp.internal. A".to_owned())
+        );
+        assert!(log.queries(FORWARDER_IP).is_empty());
+
+        let query = Query::query(name("host.lab.corp.internal."), RecordType::A);
+        let response = recursor.resolve(query, Instant::now(), false).await;
+        assert_eq!(response.unwrap().answers().len(), 1);
+        assert_eq!(log.queries(FORWARDER_IP), ["host.lab.corp.internal. A"]);
+
+        // the name servers of stub zones are authoritative, forwarders resolve names themselves
+        assert!(log.recursion_desired(STUB_IP).iter().all(|rd| !rd));
+        assert_eq!(log.recursion_desired(FORWARDER_IP), [true]);
+        assert!(log.queries(ROOT_IP).is_empty());
+    }
+
+    #[cfg(feature = "__dnssec")]
+    #[tokio::test]
+    async fn test_insecure_zones() {
+        use crate::proto::dnssec::NegativeTrustAnchors;
+        use crate::{DnssecPolicy, ZoneValidation};
+
+        let anchors = Arc::new(NegativeTrustAnchors::new());
+        let mut zone = ZoneServers::new(
+            Name::from_ascii("corp.internal.").unwrap(),
+            vec![STUB_IP.into()],
+        );
+        zone.validation = ZoneValidation::Insecure;
+
+        let handler = |_: IpAddr, request: Message| servfail(&request);
+        let recursor = Recursor::builder_with_provider(MockProvider::new(handler))
+            .dnssec_policy(DnssecPolicy::ValidateWithStaticKey {
+                trust_anchor: None,
+                nsec3_soft_iteration_limit: None,
+                nsec3_hard_iteration_limit: None,
+                negative_trust_anchors: Some(anchors.clone()),
+            })
+            .stub_zone(zone)
+            .build(&[ROOT_IP.into()]);
+
+        // the insecure zones are not listed with the anchors managed by the caller
+        assert!(recursor.is_ok());
+        assert!(anchors.is_empty());
+    }
+
+    #[test]
+    fn test_minimised_ancestors() {
+        let ancestors = |name: &str, max_steps| {
+            minimised_ancestors(&Name::from_ascii(name).unwrap(), max_steps)
+                .iter()
+                .map(Name::to_ascii)
+                .collect::<Vec<_>>()
+        };
+
+        assert!(ancestors(".", 10).is_empty());
+        assert!(ancestors("com.", 10).is_empty());
+        assert_eq!(ancestors("www.example.com.", 10), ["com.", "example.com."]);
+        assert!(ancestors("www.example.com.", 0).is_empty());
+        assert_eq!(ancestors("www.example.com.", 2), ["com."]);
+
+        // one label at a time for the first four queries, then the remaining labels are spread
+        // over the remaining queries
+        let name = "l.k.j.i.h.g.f.e.d.c.b.a.";
+        let ancestors = ancestors(name, 10);
+        assert_eq!(ancestors.len(), 9);
+        assert_eq!(ancestors[..4], ["a.", "b.a.", "c.b.a.", "d.c.b.a."]);
+        assert_eq!(ancestors[7], "h.g.f.e.d.c.b.a.");
+        assert_eq!(ancestors[8], "j.i.h.g.f.e.d.c.b.a.");
+    }
+
+    #[tokio::test]
+    async fn test_qname_minimization_empty_non_terminal() {
+        subscribe();
+        for mode in [QnameMinimization::Relaxed, QnameMinimization::Strict] {
+            let log = QueryLog::default();
+            let recursor = mock_recursor(mode, log.handler(mock_network));
+
+            let response = recursor.resolve(www_query(), Instant::now(), false).await;
+            assert_eq!(response.unwrap().answers().len(), 1);
+
+            // the name servers of the ancestor zones only see the next label of the name
+            assert_eq!(log.queries(ROOT_IP), ["testing. NS"]);
+            assert_eq!(log.queries(TLD_IP), ["example.testing. NS"]);
+            assert_eq!(
+                log.queries(LEAF_IP),
+                [
+                    "ent.example.testing. NS",
+                    "www.ent.example.testing. NS",
+                    "www.ent.example.testing. A",
+                ]
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_qname_minimization_off() {
+        subscribe();
+        let log = QueryLog::default();
+        let recursor = mock_recursor(QnameMinimization::Off, log.handler(mock_network));
+
+        let response = recursor.resolve(www_query(), Instant::now(), false).await;
+        assert_eq!(response.unwrap().answers().len(), 1);
+
+        assert_eq!(log.queries(ROOT_IP), ["www.ent.example.testing. NS"]);
+        assert_eq!(log.queries(TLD_IP), ["www.ent.example.testing. NS"]);
+        assert_eq!(
+            log.queries(LEAF_IP),
+            ["www.ent.example.testing. NS", "www.ent.example.testing. A"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_qname_minimization_broken_server() {
+        subscribe();
+        // the name servers of `example.testing.` fail the minimised query for the empty
+        // non-terminal
+        let broken = |destination: IpAddr, request: &Message| match query_string(request).as_str() {
+            "ent.example.testing. NS" => servfail(request),
+            _ => mock_network(destination, request),
+        };
+
+        let log = QueryLog::default();
+        let recursor = mock_recursor(QnameMinimization::Relaxed, log.handler(broken));
+        let response = recursor.resolve(www_query(), Instant::now(), false).await;
+        assert_eq!(response.unwrap().answers().len(), 1);
+        assert!(
+            log.queries(LEAF_IP)
+                .contains(&"www.ent.example.testing. NS".to_owned())
+        );
+
+        let log = QueryLog::default();
+        let recursor = mock_recursor(QnameMinimization::Strict, log.handler(broken));
+        let error = recursor
+            .resolve(www_query(), Instant::now(), false)
+            .await
+            .unwrap_err();
+        assert!(!error.is_nx_domain());
+        assert!(
+            log.queries(LEAF_IP)
+                .iter()
+                .all(|query| query.starts_with("ent.example.testing."))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_qname_minimization_nx_domain() {
+        subscribe();
+        // NXDOMAIN for an empty non-terminal is taken as is, per RFC 8020
+        let nx_domain =
+            |destination: IpAddr, request: &Message| match query_string(request).as_str() {
+                "ent.example.testing. NS" => {
+                    let mut response = mock_network(destination, request);
+                    response.set_response_code(ResponseCode::NXDomain);
+                    response
+                }
+                _ => mock_network(destination, request),
+            };
+
+        for mode in [QnameMinimization::Relaxed, QnameMinimization::Strict] {
+            let log = QueryLog::default();
+            let recursor = mock_recursor(mode, log.handler(nx_domain));
+            let error = recursor
+                .resolve(www_query(), Instant::now(), false)
+                .await
+                .unwrap_err();
+            assert!(error.is_nx_domain());
+            assert_eq!(log.queries(LEAF_IP), ["ent.example.testing. NS"]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_qname_minimization_referral_after_fallback() {
+        subscribe();
+        // the root name servers fail minimised queries, the full name is then sent to all servers
+        let broken = |destination: IpAddr, request: &Message| match query_string(request).as_str() {
+            "testing. NS" => servfail(request),
+            _ => mock_network(destination, request),
+        };
+
+        let log = QueryLog::default();
+        let recursor = mock_recursor(QnameMinimization::Relaxed, log.handler(broken));
+        let response = recursor.resolve(www_query(), Instant::now(), false).await;
+        assert_eq!(response.unwrap().answers().len(), 1);
+        assert!(
+            log.queries(ROOT_IP)
+                .contains(&"www.ent.example.testing. NS".to_owned())
+        );
+        assert_eq!(log.queries(TLD_IP), ["www.ent.example.testing. NS"]);
+
+        // the referrals were cached for the zones they delegate
+        log.clear();
+        let query = Query::query(
+            Name::from_ascii("mail.example.testing.").unwrap(),
+            RecordType::A,
+        );
+        let _ = recursor.resolve(query, Instant::now(), false).await;
+        assert!(log.queries(ROOT_IP).is_empty());
+        assert!(log.queries(TLD_IP).is_empty());
+        assert!(!log.queries(LEAF_IP).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_resolve_with_trace() {
+        subscribe();
+        let recursor = mock_recursor(
+            QnameMinimization::Relaxed,
+            QueryLog::default().handler(mock_network),
+        );
+        let (response, trace) = recursor
+            .resolve_with_trace(www_query(), Instant::now(), false)
+            .await;
+        assert_eq!(response.unwrap().answers().len(), 1);
+
+        let queries = trace
+            .steps()
+            .iter()
+            .filter_map(|step| match step {
+                ResolutionStep::Query {
+                    query,
+                    server,
+                    result,
+                    ..
+                } => Some((
+                    *server,
+                    format!("{} {}", query.name(), query.query_type()),
+                    result.clone(),
+                )),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let www = "www.ent.example.testing.";
+        assert_eq!(
+            queries,
+            [
+                (
+                    ROOT_IP.into(),
+                    "testing. NS".to_owned(),
+                    Ok(ResponseCode::NoError)
+                ),
+                (
+                    TLD_IP.into(),
+                    "example.testing. NS".to_owned(),
+                    Ok(ResponseCode::NoError)
+                ),
+                (
+                    LEAF_IP.into(),
+                    "ent.example.testing. NS".to_owned(),
+                    Ok(ResponseCode::NoError)
+                ),
+                (
+                    LEAF_IP.into(),
+                    format!("{www} NS"),
+                    Ok(ResponseCode::NoError)
+                ),
+                (
+                    LEAF_IP.into(),
+                    format!("{www} A"),
+                    Ok(ResponseCode::NoError)
+                ),
+            ]
+        );
+
+        let (_, trace) = recursor
+            .resolve_with_trace(www_query(), Instant::now(), false)
+            .await;
+        assert_eq!(
+            trace.steps(),
+            [ResolutionStep::CacheHit { query: www_query() }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_slow_tcp_retry() {
+        subscribe();
+        // the first response is truncated, and the retry over TCP takes longer than the time given
+        // to the server to respond over UDP
+        let truncated = AtomicBool::new(false);
+        let handler = move |destination: IpAddr, request: Message| {
+            let mut response = mock_network(destination, &request);
+            if !response.answers().is_empty() && !truncated.swap(true, Ordering::Relaxed) {
+                response.take_answers();
+                response.set_truncated(true);
+            }
+            response
+        };
+
+        let provider = MockProvider::new(handler).with_tcp_delay(Duration::from_millis(600));
+        let recursor = Recursor::builder_with_provider(provider)
+            .clear_deny_servers() // We use addresses in the default deny filter.
+            .root_priming(false) // The priming response would be truncated instead.
+            .build(&[ROOT_IP.into()])
+            .unwrap();
+        let response = recursor.resolve(www_query(), Instant::now(), false).await;
+        assert_eq!(response.unwrap().answers().len(), 1);
+    }
+
+    fn www_query() -> Query {
+        Query::query(
+            Name::from_ascii("www.ent.example.testing.").unwrap(),
+            RecordType::A,
+        )
+    }
+
+    fn mock_recursor(
+        mode: QnameMinimization,
+        handler: impl MockHandler + Send + Sync + 'static,
+    ) -> Recursor<MockProvider> {
+        Recursor::builder_with_provider(MockProvider::new(handler))
+            .clear_deny_servers() // We use addresses in the default deny filter.
+            .root_priming(false) // Priming queries would be logged.
+            .qname_minimization(mode)
+            .build(&[ROOT_IP.into()])
+            .unwrap()
+    }
+
+    /// Queries received by each server of a mocked network
+    #[derive(Clone, Default)]
+    struct QueryLog(Arc<Mutex<Vec<(IpAddr, Message)>>>);
+
+    impl QueryLog {
+        fn handler(
+            &self,
+            network: impl Fn(IpAddr, &Message) -> Message + Send + Sync + 'static,
+        ) -> impl MockHandler + Send + Sync + 'static {
+            let log = self.clone();
+            move |destination: IpAddr, request: Message| {
+                let response = network(destination, &request);
+                log.0.lock().unwrap().push((destination, request));
+                response
+            }
+        }
+
+        fn queries(&self, server: Ipv4Addr) -> Vec<String> {
+            self.0
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(destination, _)| *destination == IpAddr::from(server))
+                .map(|(_, request)| query_string(request))
+                .collect()
+        }
+
+        /// Whether the requests sent to `server` had the RD bit set
+        fn recursion_desired(&self, server: Ipv4Addr) -> Vec<bool> {
+            self.0
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(destination, _)| *destination == IpAddr::from(server))
+                .map(|(_, request)| request.recursion_desired())
+                .collect()
+        }
+
+        fn clear(&self) {
+            self.0.lock().unwrap().clear();
+        }
+    }
+
+    /// Answers as the name servers of the root zone, of `testing.` and of `example.testing.`
+    ///
+    /// The only record of `example.testing.` is `www.ent.example.testing. A`, so
+    /// `ent.example.testing.` is an empty non-terminal.
+    fn mock_network(destination: IpAddr, request: &Message) -> Message {
+        let name = |name: &str| Name::from_ascii(name).unwrap();
+        let query = &request.queries()[0];
+        let (zone, delegation) = match destination {
+            IpAddr::V4(ip) if ip == ROOT_IP => (Name::root(), Some((name("testing."), TLD_IP))),
+            IpAddr::V4(ip) if ip == TLD_IP => {
+                (name("testing."), Some((name("example.testing."), LEAF_IP)))
+            }
+            IpAddr::V4(ip) if ip == LEAF_IP => (name("example.testing."), None),
+            _ => return servfail(request),
+        };
+
+        let mut response = Message::response(request.id(), request.op_code());
+        response.add_query(query.clone());
+        if let Some((child, ip)) = delegation {
+            if child.zone_of(query.name()) {
+                let server = name("ns").append_domain(&child).unwrap();
+                response.add_authority(Record::from_rdata(
+                    child,
+                    3600,
+                    RData::NS(NS(server.clone())),
+                ));
+                response.add_additional(Record::from_rdata(server, 3600, RData::A(A(ip))));
+                return response;
+            }
+        }
+
+        response.set_authoritative(true);
+        let www = name("www.ent.example.testing.");
+        if *query.name() == www && query.query_type() == RecordType::A {
+            response.add_answer(Record::from_rdata(www, 3600, RData::A(A(A_RR_IP))));
+            return response;
+        }
+
+        if !query.name().zone_of(&www) || zone.is_root() {
+            response.set_response_code(ResponseCode::NXDomain);
+        }
+        let soa = SOA::new(
+            name("ns").append_domain(&zone).unwrap(),
+            Name::root(),
+            1,
+            3600,
+            60,
+            86400,
+            3600,
+        );
+        response.add_authority(Record::from_rdata(zone, 3600, RData::SOA(soa)));
+        response
+    }
+
+    /// Answers as the name servers of `corp.internal.` and the forwarder of `lab.corp.internal.`,
+    /// with an A record for every name
+    fn mock_configured_zones(destination: IpAddr, request: &Message) -> Message {
+        let zone = match destination {
+            IpAddr::V4(ip) if ip == STUB_IP => Name::from_ascii("corp.internal.").unwrap(),
+            IpAddr::V4(ip) if ip == FORWARDER_IP => Name::from_ascii("lab.corp.internal.").unwrap(),
+            _ => return servfail(request),
+        };
+
+        let query = &request.queries()[0];
+        let mut response = Message::response(request.id(), request.op_code());
+        response.add_query(query.clone());
+        response.set_authoritative(true);
+        if query.query_type() == RecordType::A {
+            response.add_answer(Record::from_rdata(
+                query.name().clone(),
+                3600,
+                RData::A(A(A_RR_IP)),
+            ));
+            return response;
+        }
+
+        let soa = SOA::new(zone.clone(), Name::root(), 1, 3600, 60, 86400, 3600);
+        response.add_authority(Record::from_rdata(zone, 3600, RData::SOA(soa)));
+        response
+    }
+
+    fn query_string(request: &Message) -> String {
+        let query = &request.queries()[0];
+        format!("{} {}", query.name(), query.query_type())
+    }
+
+    fn servfail(request: &Message) -> Message {
+        let mut response = Message::error_msg(request.id(), OpCode::Query, ResponseCode::ServFail);
+        response.add_query(request.queries()[0].clone());
+        response
+    }
 }
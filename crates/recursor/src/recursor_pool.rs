@@ -5,40 +5,51 @@
 // https://opensource.org/licenses/MIT>, at your option. This file may not be
 // copied, modified, or distributed except according to those terms.
 
+#[cfg(not(feature = "__tls"))]
+use std::marker::PhantomData;
 use std::{
     collections::HashMap,
+    net::IpAddr,
     pin::Pin,
     sync::Arc,
     task::{Context, Poll},
+    time::{Duration, Instant},
 };
 
 use futures_util::{
-    Future, FutureExt, StreamExt,
+    Future, FutureExt,
     future::{BoxFuture, Shared},
 };
-use hickory_resolver::name_server::NameServerPool;
+use hickory_resolver::name_server::{NameServer, NameServerPool, PoolHooks};
 #[cfg(feature = "metrics")]
 use metrics::{Counter, Unit, counter, describe_counter};
 use parking_lot::Mutex;
 use tracing::info;
 
+#[cfg(feature = "__tls")]
+use crate::encrypted_transport::EncryptedTransports;
+use crate::infra_cache::InfraCache;
 use crate::proto::{
-    DnsHandle, ProtoError,
-    op::{DnsRequestOptions, DnsResponse, Query},
+    ProtoError, ProtoErrorKind,
+    op::{DnsRequest, DnsRequestOptions, DnsResponse, Query},
+    rr::rdata::opt::ClientSubnet,
 };
+use crate::resolution_trace::{ResolutionStep, TraceRecorder};
 use crate::resolver::{Name, name_server::ConnectionProvider};
+#[cfg(feature = "__dnssec")]
+use crate::root_zone::RootZoneMirror;
+
+/// The result of a lookup, with the queries sent to the name servers for it if it was traced
+type LookupOutcome = (Result<DnsResponse, ProtoError>, Vec<ResolutionStep>);
 
 #[derive(Clone)]
-pub(crate) struct SharedLookup(Shared<BoxFuture<'static, Option<Result<DnsResponse, ProtoError>>>>);
+pub(crate) struct SharedLookup(Shared<BoxFuture<'static, LookupOutcome>>);
 
 impl Future for SharedLookup {
-    type Output = Result<DnsResponse, ProtoError>;
+    type Output = LookupOutcome;
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        self.0.poll_unpin(cx).map(|o| match o {
-            Some(r) => r,
-            None => Err("no response from nameserver".into()),
-        })
+        self.0.poll_unpin(cx)
     }
 }
 
@@ -46,13 +57,25 @@ impl Future for SharedLookup {
 pub(crate) struct RecursorPool<P: ConnectionProvider> {
     zone: Name,
     ns: NameServerPool<P>,
-    active_requests: Arc<Mutex<HashMap<Query, SharedLookup>>>,
+    /// Round-trip times of the name servers, shared by the pools of all zones
+    infra_cache: Arc<InfraCache>,
+    /// Encrypted transports of the name servers, shared by the pools of all zones
+    #[cfg(feature = "__tls")]
+    encrypted_transports: Option<Arc<EncryptedTransports<P>>>,
+    /// Requests in flight, by query and client subnet
+    #[allow(clippy::type_complexity)]
+    active_requests: Arc<Mutex<HashMap<(Query, Option<ClientSubnet>), SharedLookup>>>,
     #[cfg(feature = "metrics")]
     outgoing_query_counter: Counter,
+    /// Whether the name servers are recursive resolvers that queries are forwarded to
+    forwarding: bool,
+    /// Local copy of the zone, used instead of its name servers
+    #[cfg(feature = "__dnssec")]
+    root_zone: Option<Arc<RootZoneMirror>>,
 }
 
 impl<P: ConnectionProvider> RecursorPool<P> {
-    pub(crate) fn from(zone: Name, ns: NameServerPool<P>) -> Self {
+    pub(crate) fn from(zone: Name, ns: NameServerPool<P>, infra_cache: Arc<InfraCache>) -> Self {
         #[cfg(feature = "metrics")]
         let outgoing_query_counter = counter!("hickory_recursor_outgoing_queries_total");
         #[cfg(feature = "metrics")]
@@ -65,23 +88,83 @@ impl<P: ConnectionProvider> RecursorPool<P> {
         Self {
             zone,
             ns,
+            infra_cache,
+            #[cfg(feature = "__tls")]
+            encrypted_transports: None,
             active_requests: Arc::new(Mutex::new(HashMap::default())),
             #[cfg(feature = "metrics")]
             outgoing_query_counter,
+            forwarding: false,
+            #[cfg(feature = "__dnssec")]
+            root_zone: None,
         }
     }
 
+    /// Sends queries with the RD bit set, for name servers which are recursive resolvers
+    pub(crate) fn forwarding(mut self) -> Self {
+        self.forwarding = true;
+        self
+    }
+
+    /// Whether the name servers are recursive resolvers, which answer queries for any name in the
+    /// zone without referrals
+    pub(crate) fn is_forwarding(&self) -> bool {
+        self.forwarding
+    }
+
+    /// Sends queries over the encrypted transports of the name servers when they support one, see
+    /// RFC 9539
+    #[cfg(feature = "__tls")]
+    pub(crate) fn with_encrypted_transports(
+        mut self,
+        encrypted_transports: Option<Arc<EncryptedTransports<P>>>,
+    ) -> Self {
+        self.encrypted_transports = encrypted_transports;
+        self
+    }
+
+    /// Answers queries from `root_zone` while a current copy of the zone is available
+    #[cfg(feature = "__dnssec")]
+    pub(crate) fn with_root_zone(mut self, root_zone: Arc<RootZoneMirror>) -> Self {
+        self.root_zone = Some(root_zone);
+        self
+    }
+
+    /// Returns a copy of this pool which sends queries to the name servers of `ns`
+    pub(crate) fn with_name_servers(&self, ns: NameServerPool<P>) -> Self {
+        Self { ns, ..self.clone() }
+    }
+
     pub(crate) fn zone(&self) -> &Name {
         &self.zone
     }
 
+    /// Returns the addresses of the name servers
+    pub(crate) fn servers(&self) -> Vec<IpAddr> {
+        self.ns
+            .servers()
+            .iter()
+            .map(|server| server.config().ip)
+            .collect()
+    }
+
     pub(crate) async fn lookup(
         &self,
         query: Query,
         security_aware: bool,
+        client_subnet: Option<ClientSubnet>,
+        trace: &TraceRecorder,
     ) -> Result<DnsResponse, ProtoError> {
-        let ns = self.ns.clone();
+        #[cfg(feature = "__dnssec")]
+        if let Some(response) = self
+            .root_zone
+            .as_ref()
+            .and_then(|root_zone| root_zone.lookup(&query, security_aware))
+        {
+            return response;
+        }
 
+        let ns = self.ns.clone();
         let query_cpy = query.clone();
         let case_randomization = self.ns.options().case_randomization;
 
@@ -89,7 +172,7 @@ impl<P: ConnectionProvider> RecursorPool<P> {
         let lookup = self
             .active_requests
             .lock()
-            .entry(query.clone())
+            .entry((query.clone(), client_subnet))
             .or_insert_with(move || {
                 info!("querying {} for {}", self.zone, query_cpy);
 
@@ -97,19 +180,34 @@ impl<P: ConnectionProvider> RecursorPool<P> {
                 options.use_edns = security_aware;
                 options.edns_set_dnssec_ok = security_aware;
                 options.case_randomization = case_randomization;
+                options.client_subnet = client_subnet;
 
                 // Set RD=0 in queries made by the recursive resolver. See the last figure in
                 // section 2.2 of RFC 1035, for example. Failure to do so may allow for loops
-                // between recursive resolvers following referrals to each other.
-                options.recursion_desired = false;
+                // between recursive resolvers following referrals to each other. Queries
+                // forwarded to a configured resolver are the exception, it is expected to recurse.
+                options.recursion_desired = self.forwarding;
+
+                let hooks = LookupHooks {
+                    zone: self.zone.clone(),
+                    query: query_cpy.clone(),
+                    infra_cache: self.infra_cache.clone(),
+                    #[cfg(feature = "__tls")]
+                    encrypted_transports: self.encrypted_transports.clone(),
+                    steps: trace.is_enabled().then(Mutex::default),
+                    #[cfg(not(feature = "__tls"))]
+                    provider: PhantomData,
+                };
 
                 // convert the lookup into a shared future
-                let lookup = ns
-                    .lookup(query_cpy, options)
-                    .into_future()
-                    .map(|(next, _)| next)
-                    .boxed()
-                    .shared();
+                let lookup = async move {
+                    let request = DnsRequest::from_query(query_cpy, options);
+                    let result = ns.send_with_hooks(request, &hooks).await;
+                    let steps = hooks.steps.map(Mutex::into_inner).unwrap_or_default();
+                    (result, steps)
+                }
+                .boxed()
+                .shared();
 
                 #[cfg(feature = "metrics")]
                 self.outgoing_query_counter.increment(1);
@@ -118,11 +216,81 @@ impl<P: ConnectionProvider> RecursorPool<P> {
             })
             .clone();
 
-        let result = lookup.await;
+        let (result, steps) = lookup.await;
 
         // remove the concurrent request marker
-        self.active_requests.lock().remove(&query);
+        self.active_requests.lock().remove(&(query, client_subnet));
 
+        for step in steps {
+            trace.push(step);
+        }
         result
     }
 }
+
+/// Orders the name servers of a lookup by what `infra_cache` knows of them, records how they
+/// responded, and sends queries over their encrypted transports when they have one
+struct LookupHooks<P: ConnectionProvider> {
+    zone: Name,
+    query: Query,
+    infra_cache: Arc<InfraCache>,
+    #[cfg(feature = "__tls")]
+    encrypted_transports: Option<Arc<EncryptedTransports<P>>>,
+    /// The queries sent to the name servers, if the lookup is traced
+    steps: Option<Mutex<Vec<ResolutionStep>>>,
+    #[cfg(not(feature = "__tls"))]
+    provider: PhantomData<P>,
+}
+
+impl<P: ConnectionProvider> PoolHooks<P> for LookupHooks<P> {
+    fn order(&self, servers: &mut Vec<Arc<NameServer<P>>>) {
+        let available = self
+            .infra_cache
+            .sort(servers, |server| server.config().ip, Instant::now());
+        // skip the servers which are held down, unless all of them are
+        if available > 0 {
+            servers.truncate(available);
+        }
+    }
+
+    fn udp_timeout(&self, server: &NameServer<P>) -> Option<Duration> {
+        Some(self.infra_cache.timeout(server.config().ip))
+    }
+
+    #[cfg(feature = "__tls")]
+    fn send(
+        &self,
+        server: &NameServer<P>,
+        request: &DnsRequest,
+    ) -> Option<BoxFuture<'static, Option<Result<DnsResponse, ProtoError>>>> {
+        let transports = self.encrypted_transports.as_ref()?;
+        Some(transports.send(server.config().ip, request)?.boxed())
+    }
+
+    fn response(
+        &self,
+        server: &NameServer<P>,
+        rtt: Duration,
+        result: &Result<DnsResponse, ProtoError>,
+    ) {
+        let ip = server.config().ip;
+        match result.as_ref().map_err(ProtoError::kind) {
+            // Negative responses, referrals and error response codes are responses all the same.
+            Ok(_) | Err(ProtoErrorKind::Dns(_)) => self.infra_cache.record_rtt(ip, rtt),
+            Err(
+                ProtoErrorKind::Timeout | ProtoErrorKind::Io(_) | ProtoErrorKind::NoConnections,
+            ) => self.infra_cache.record_failure(ip, Instant::now()),
+            Err(_) => {}
+        }
+
+        if let Some(steps) = &self.steps {
+            steps.lock().push(ResolutionStep::query(
+                self.zone.clone(),
+                self.query.clone(),
+                ip,
+                rtt,
+                result,
+            ));
+        }
+    }
+}
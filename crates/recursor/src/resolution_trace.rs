@@ -0,0 +1,315 @@
+//! Diagnostics recorded while resolving a query
+
+use std::{fmt, net::IpAddr, sync::Arc, time::Duration};
+
+use parking_lot::Mutex;
+
+use crate::{
+    proto::{
+        DnsError, ProtoError, ProtoErrorKind,
+        op::{DnsResponse, Query, ResponseCode},
+        rr::Record,
+    },
+    resolver::Name,
+};
+
+/// The steps taken by a [`Recursor`](crate::Recursor) to resolve a query, like `dig +trace`
+///
+/// This covers the lookups made for the query itself, as well as those made to find the addresses
+/// of name servers, to follow CNAMEs, and to fetch the DS and DNSKEY records used for DNSSEC
+/// validation. Steps are recorded in the order in which they completed.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ResolutionTrace {
+    steps: Vec<ResolutionStep>,
+}
+
+impl ResolutionTrace {
+    /// Returns the recorded steps, in the order in which they completed
+    pub fn steps(&self) -> &[ResolutionStep] {
+        &self.steps
+    }
+
+    /// Returns true if no steps were recorded
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+}
+
+impl FromIterator<ResolutionStep> for ResolutionTrace {
+    fn from_iter<I: IntoIterator<Item = ResolutionStep>>(iter: I) -> Self {
+        Self {
+            steps: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl fmt::Display for ResolutionTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for step in &self.steps {
+            writeln!(f, "{step}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A single step of a [`ResolutionTrace`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum ResolutionStep {
+    /// A response was found in the cache
+    CacheHit {
+        /// The query answered from the cache
+        query: Query,
+    },
+    /// A referral to the name servers of a zone was followed
+    ZoneCut {
+        /// Name of the zone
+        zone: Name,
+        /// Names of the name servers, from the NS records of the referral
+        name_servers: Vec<Name>,
+        /// Addresses of the name servers included in the referral
+        glue: Vec<Record>,
+        /// Addresses of the name servers which will be queried, from the glue, the cache or
+        /// separate lookups
+        servers: Vec<IpAddr>,
+    },
+    /// The name servers of a zone were found in the name server cache
+    CachedZoneCut {
+        /// Name of the zone
+        zone: Name,
+        /// Addresses of the name servers
+        servers: Vec<IpAddr>,
+    },
+    /// The name servers of a stub or forward zone were used
+    ConfiguredZone {
+        /// Name of the zone
+        zone: Name,
+        /// Addresses of the name servers
+        servers: Vec<IpAddr>,
+        /// Whether the name servers are recursive resolvers that queries are forwarded to
+        forwarding: bool,
+    },
+    /// A query was sent to a name server
+    Query {
+        /// Zone served by the name server
+        zone: Name,
+        /// The query
+        query: Query,
+        /// Address of the name server
+        server: IpAddr,
+        /// Time until the response was received, or until the query failed
+        rtt: Duration,
+        /// The response code of the response, or why no response was received
+        result: Result<ResponseCode, String>,
+    },
+}
+
+impl ResolutionStep {
+    /// Returns true if this step is a query which did not get a response
+    pub fn is_failure(&self) -> bool {
+        matches!(self, Self::Query { result: Err(_), .. })
+    }
+
+    pub(crate) fn query(
+        zone: Name,
+        query: Query,
+        server: IpAddr,
+        rtt: Duration,
+        result: &Result<DnsResponse, ProtoError>,
+    ) -> Self {
+        Self::Query {
+            zone,
+            query,
+            server,
+            rtt,
+            result: match result {
+                Ok(response) => Ok(response.response_code()),
+                Err(error) => match error.kind() {
+                    // negative responses and referrals are turned into errors by the name server
+                    ProtoErrorKind::Dns(DnsError::NoRecordsFound(no_records)) => {
+                        Ok(no_records.response_code)
+                    }
+                    ProtoErrorKind::Dns(DnsError::ResponseCode(response_code)) => {
+                        Ok(*response_code)
+                    }
+                    _ => Err(error.to_string()),
+                },
+            },
+        }
+    }
+}
+
+impl fmt::Display for ResolutionStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::CacheHit { query } => write!(
+                f,
+                "{} {}: cached response",
+                query.name(),
+                query.query_type()
+            ),
+            Self::ZoneCut {
+                zone,
+                name_servers,
+                glue,
+                servers,
+            } => {
+                write!(f, "zone cut {zone}: NS")?;
+                write_list(f, name_servers)?;
+                if !glue.is_empty() {
+                    write!(f, ", glue")?;
+                    for record in glue {
+                        write!(f, " {} {}", record.name(), record.data())?;
+                    }
+                }
+                write!(f, ", servers")?;
+                write_list(f, servers)
+            }
+            Self::CachedZoneCut { zone, servers } => {
+                write!(f, "zone cut {zone}: cached, servers")?;
+                write_list(f, servers)
+            }
+            Self::ConfiguredZone {
+                zone,
+                servers,
+                forwarding,
+            } => {
+                let kind = match forwarding {
+                    true => "forward",
+                    false => "stub",
+                };
+                write!(f, "zone cut {zone}: {kind} zone, servers")?;
+                write_list(f, servers)
+            }
+            Self::Query {
+                zone,
+                query,
+                server,
+                rtt,
+                result,
+            } => {
+                write!(
+                    f,
+                    "{} {} to {server} ({zone}): ",
+                    query.name(),
+                    query.query_type(),
+                )?;
+                match result {
+                    Ok(response_code) => write!(f, "{response_code} in {} ms", rtt.as_millis()),
+                    Err(error) => write!(f, "failed after {} ms, {error}", rtt.as_millis()),
+                }
+            }
+        }
+    }
+}
+
+fn write_list<T: fmt::Display>(f: &mut fmt::Formatter<'_>, items: &[T]) -> fmt::Result {
+    if items.is_empty() {
+        return write!(f, " none");
+    }
+
+    for (i, item) in items.iter().enumerate() {
+        match i {
+            0 => write!(f, " {item}")?,
+            _ => write!(f, ", {item}")?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Collects the steps of a resolution, shared by the nested lookups it makes
+#[derive(Clone, Default)]
+pub(crate) struct TraceRecorder(Option<Arc<Mutex<ResolutionTrace>>>);
+
+impl TraceRecorder {
+    /// Starts a new, empty trace
+    pub(crate) fn start() -> Self {
+        Self(Some(Arc::default()))
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.0.is_some()
+    }
+
+    pub(crate) fn push(&self, step: ResolutionStep) {
+        if let Some(trace) = &self.0 {
+            trace.lock().steps.push(step);
+        }
+    }
+
+    /// Returns a copy of the steps recorded so far
+    pub(crate) fn snapshot(&self) -> ResolutionTrace {
+        match &self.0 {
+            Some(trace) => trace.lock().clone(),
+            None => ResolutionTrace::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::proto::rr::{RData, RecordType, rdata::A};
+
+    #[test]
+    fn test_display() {
+        let zone = Name::from_ascii("example.com.").unwrap();
+        let ns = Name::from_ascii("ns1.example.com.").unwrap();
+        let server = IpAddr::from(Ipv4Addr::new(192, 0, 2, 1));
+        let query = Query::query(Name::from_ascii("www.example.com.").unwrap(), RecordType::A);
+
+        let trace = [
+            ResolutionStep::ZoneCut {
+                zone: zone.clone(),
+                name_servers: vec![ns.clone()],
+                glue: vec![Record::from_rdata(ns, 3600, RData::A(A::new(192, 0, 2, 1)))],
+                servers: vec![server],
+            },
+            ResolutionStep::Query {
+                zone: zone.clone(),
+                query: query.clone(),
+                server,
+                rtt: Duration::from_millis(12),
+                result: Ok(ResponseCode::NoError),
+            },
+            ResolutionStep::Query {
+                zone,
+                query: query.clone(),
+                server,
+                rtt: Duration::from_millis(5000),
+                result: Err("request timed out".to_string()),
+            },
+            ResolutionStep::CacheHit { query },
+        ]
+        .into_iter()
+        .collect::<ResolutionTrace>();
+
+        assert_eq!(trace.steps().iter().filter(|s| s.is_failure()).count(), 1);
+        assert_eq!(
+            trace.to_string(),
+            "zone cut example.com.: NS ns1.example.com., glue ns1.example.com. 192.0.2.1, servers 192.0.2.1\n\
+             www.example.com. A to 192.0.2.1 (example.com.): No Error in 12 ms\n\
+             www.example.com. A to 192.0.2.1 (example.com.): failed after 5000 ms, request timed out\n\
+             www.example.com. A: cached response\n"
+        );
+    }
+
+    #[test]
+    fn test_recorder() {
+        let step = ResolutionStep::CacheHit {
+            query: Query::query(Name::root(), RecordType::NS),
+        };
+
+        let disabled = TraceRecorder::default();
+        disabled.push(step.clone());
+        assert!(disabled.snapshot().is_empty());
+
+        let recorder = TraceRecorder::start();
+        recorder.clone().push(step.clone());
+        assert_eq!(recorder.snapshot().steps(), &[step]);
+    }
+}
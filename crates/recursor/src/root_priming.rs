@@ -0,0 +1,302 @@
+//! Priming of the root name servers, see [RFC 8109](https://www.rfc-editor.org/rfc/rfc8109)
+//!
+//! The root hints only give the recursor a starting point. The NS records of the root zone, and the
+//! addresses of the root name servers, are queried from the servers listed in the hints when the
+//! recursor first needs them, and again whenever the NS records expire, and they replace the
+//! servers of the hints.
+
+use std::{
+    collections::BTreeSet,
+    net::IpAddr,
+    sync::{Arc, Weak},
+    time::Duration,
+};
+
+use parking_lot::RwLock;
+use tracing::{debug, info, warn};
+
+use crate::{
+    proto::{
+        ProtoError,
+        op::{Message, Query},
+        rr::{Name, RecordType},
+        runtime::{RuntimeProvider, Time},
+    },
+    recursor_pool::RecursorPool,
+    resolution_trace::TraceRecorder,
+    resolver::{
+        config::{NameServerConfig, ResolverOpts},
+        name_server::{ConnectionProvider, NameServerPool, TlsConfig},
+    },
+};
+
+/// Periodically replaces the pool of the root name servers with the servers of the priming response
+pub(crate) struct RootPriming<P: ConnectionProvider> {
+    /// The pool of the root name servers used by the recursor
+    roots: Weak<RwLock<RecursorPool<P>>>,
+    /// The pool of the servers listed in the root hints, which priming queries are sent to
+    hints: RecursorPool<P>,
+    security_aware: bool,
+    options: Arc<ResolverOpts>,
+    tls: Arc<TlsConfig>,
+    conn_provider: P,
+}
+
+impl<P: ConnectionProvider> RootPriming<P> {
+    /// Priming queries are sent to the servers of `hints`, and the servers of `roots` are replaced
+    /// with those of the responses
+    pub(crate) fn new(
+        roots: &Arc<RwLock<RecursorPool<P>>>,
+        hints: RecursorPool<P>,
+        security_aware: bool,
+        options: Arc<ResolverOpts>,
+        tls: Arc<TlsConfig>,
+        conn_provider: P,
+    ) -> Self {
+        Self {
+            roots: Arc::downgrade(roots),
+            hints,
+            security_aware,
+            options,
+            tls,
+            conn_provider,
+        }
+    }
+
+    /// Primes the root name servers immediately, then again when the NS records of the root zone
+    /// expire, until the recursor is dropped
+    ///
+    /// This is meant to be spawned in the background.
+    pub(crate) async fn run(self) -> Result<(), ProtoError> {
+        let hints = self.hints.servers().into_iter().collect::<BTreeSet<_>>();
+        let mut primed = hints.clone();
+        loop {
+            if self.roots.strong_count() == 0 {
+                return Ok(());
+            }
+
+            let interval = match self.prime().await {
+                Ok(root_servers) => {
+                    let servers = root_servers.addresses();
+                    if servers != primed {
+                        log_discrepancies(&hints, &root_servers);
+                        self.replace_roots(&servers);
+                        primed = servers;
+                    }
+                    root_servers.ttl.clamp(MIN_INTERVAL, MAX_INTERVAL)
+                }
+                Err(err) => {
+                    warn!("failed to prime the root name servers: {err}");
+                    RETRY_INTERVAL
+                }
+            };
+
+            debug!("priming the root name servers again in {interval:?}");
+            <P::RuntimeProvider as RuntimeProvider>::Timer::delay_for(interval).await;
+        }
+    }
+
+    /// Sends the priming query, `./NS`, to the servers of the root hints
+    async fn prime(&self) -> Result<RootServers, ProtoError> {
+        let query = Query::query(Name::root(), RecordType::NS);
+        let response = self
+            .hints
+            .lookup(query, self.security_aware, None, &TraceRecorder::default())
+            .await?;
+
+        RootServers::from_response(&response)
+            .ok_or_else(|| ProtoError::from("no addresses of root name servers in the response"))
+    }
+
+    fn replace_roots(&self, servers: &BTreeSet<IpAddr>) {
+        let Some(roots) = self.roots.upgrade() else {
+            return;
+        };
+
+        info!("using {} primed root name server addresses", servers.len());
+        let ns = NameServerPool::from_config(
+            servers.iter().copied().map(NameServerConfig::udp_and_tcp),
+            self.options.clone(),
+            self.tls.clone(),
+            self.conn_provider.clone(),
+        );
+
+        let mut roots = roots.write();
+        *roots = roots.with_name_servers(ns);
+    }
+}
+
+/// Logs the root name servers which are missing from the root hints, and the addresses of the
+/// hints which are not those of a root name server
+fn log_discrepancies(hints: &BTreeSet<IpAddr>, root_servers: &RootServers) {
+    for (name, ip) in &root_servers.servers {
+        if !hints.contains(ip) {
+            warn!("root name server {name} at {ip} is missing from the root hints");
+        }
+    }
+
+    let servers = root_servers.addresses();
+    for ip in hints.difference(&servers) {
+        warn!("root hint {ip} is not the address of a root name server");
+    }
+}
+
+/// The root name servers, from a priming response
+#[derive(Debug, Eq, PartialEq)]
+struct RootServers {
+    /// Names and addresses of the root name servers
+    servers: Vec<(Name, IpAddr)>,
+    /// Time to live of the NS records
+    ttl: Duration,
+}
+
+impl RootServers {
+    /// Reads the NS records of the root zone from the answers of `response`, and the addresses
+    /// of the name servers from its additional records
+    ///
+    /// Returns `None` if the response does not give the address of any root name server.
+    fn from_response(response: &Message) -> Option<Self> {
+        let ns_records = response
+            .answers()
+            .iter()
+            .filter(|record| record.name().is_root())
+            .filter_map(|record| Some((record.data().as_ns()?, record.ttl())))
+            .collect::<Vec<_>>();
+
+        let servers = response
+            .additionals()
+            .iter()
+            .filter(|record| ns_records.iter().any(|(ns, _)| &ns.0 == record.name()))
+            .filter_map(|record| Some((record.name().clone(), record.data().ip_addr()?)))
+            .collect::<Vec<_>>();
+        if servers.is_empty() {
+            return None;
+        }
+
+        let ttl = ns_records.iter().map(|(_, ttl)| *ttl).min()?;
+        Some(Self {
+            servers,
+            ttl: Duration::from_secs(u64::from(ttl)),
+        })
+    }
+
+    fn addresses(&self) -> BTreeSet<IpAddr> {
+        self.servers.iter().map(|(_, ip)| *ip).collect()
+    }
+}
+
+/// Lower bound of the time between priming queries
+const MIN_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Upper bound of the time between priming queries, below the TTL of the root NS records
+const MAX_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+/// Time until the next priming query after a failure
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use test_support::subscribe;
+
+    use super::*;
+    use crate::{
+        infra_cache::InfraCache,
+        mock_provider::MockProvider,
+        proto::{
+            op::{OpCode, ResponseCode},
+            rr::{
+                RData, Record,
+                rdata::{A, AAAA, NS},
+            },
+        },
+    };
+
+    #[tokio::test]
+    async fn test_priming_replaces_roots() {
+        subscribe();
+        const HINT_IP: Ipv4Addr = Ipv4Addr::new(10, 0, 1, 1);
+        const ROOT_IP: Ipv4Addr = Ipv4Addr::new(10, 0, 1, 2);
+
+        let handler = |destination: IpAddr, request: Message| {
+            let query = &request.queries()[0];
+            if destination != IpAddr::from(HINT_IP) || query.query_type() != RecordType::NS {
+                return Message::error_msg(request.id(), request.op_code(), ResponseCode::ServFail);
+            }
+
+            let root_server = Name::from_ascii("a.root-servers.net.").unwrap();
+            let mut response = Message::response(request.id(), OpCode::Query);
+            response.add_query(query.clone());
+            response.set_authoritative(true);
+            response.add_answer(Record::from_rdata(
+                Name::root(),
+                518400,
+                RData::NS(NS(root_server.clone())),
+            ));
+            response.add_additional(Record::from_rdata(
+                root_server,
+                518400,
+                RData::A(A(ROOT_IP)),
+            ));
+            response
+        };
+
+        let options = Arc::new(ResolverOpts::default());
+        let tls = Arc::new(TlsConfig::new().unwrap());
+        let provider = MockProvider::new(handler);
+        let ns = NameServerPool::from_config(
+            [NameServerConfig::udp_and_tcp(HINT_IP.into())],
+            options.clone(),
+            tls.clone(),
+            provider.clone(),
+        );
+        let hints = RecursorPool::from(Name::root(), ns, Arc::new(InfraCache::new(16)));
+        let roots = Arc::new(RwLock::new(hints.clone()));
+
+        let priming = RootPriming::new(&roots, hints, false, options, tls, provider);
+        tokio::spawn(priming.run());
+        for _ in 0..100 {
+            if roots.read().servers() != [IpAddr::from(HINT_IP)] {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let roots = roots.read();
+        assert_eq!(roots.servers(), [IpAddr::from(ROOT_IP)]);
+        assert!(roots.zone().is_root());
+    }
+
+    #[test]
+    fn test_root_servers() {
+        let name = |name: &str| Name::from_ascii(name).unwrap();
+        let a = name("a.root-servers.net.");
+        let b = name("b.root-servers.net.");
+        let a_v4 = Ipv4Addr::new(198, 41, 0, 4);
+        let a_v6 = Ipv6Addr::new(0x2001, 0x503, 0xba3e, 0, 0, 0, 0x2, 0x30);
+
+        let mut response = Message::query();
+        response.add_answers([
+            Record::from_rdata(Name::root(), 518400, RData::NS(NS(a.clone()))),
+            Record::from_rdata(Name::root(), 86400, RData::NS(NS(b.clone()))),
+        ]);
+        response.add_additionals([
+            Record::from_rdata(a.clone(), 518400, RData::A(A(a_v4))),
+            Record::from_rdata(a.clone(), 518400, RData::AAAA(AAAA(a_v6))),
+            // not the name of a root name server
+            Record::from_rdata(name("ns.example."), 518400, RData::A(A::new(192, 0, 2, 1))),
+        ]);
+
+        assert_eq!(
+            RootServers::from_response(&response),
+            Some(RootServers {
+                servers: vec![(a.clone(), a_v4.into()), (a, a_v6.into())],
+                ttl: Duration::from_secs(86400),
+            })
+        );
+
+        // NS records without glue
+        let mut response = Message::query();
+        response.add_answer(Record::from_rdata(Name::root(), 518400, RData::NS(NS(b))));
+        assert_eq!(RootServers::from_response(&response), None);
+    }
+}
@@ -0,0 +1,1012 @@
+//! Local copy of the root zone, see [RFC 8806](https://www.rfc-editor.org/rfc/rfc8806)
+
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{Arc, Weak},
+    time::{Duration, Instant},
+};
+
+use futures_util::StreamExt;
+use parking_lot::RwLock;
+use tracing::{debug, info, warn};
+
+use crate::{
+    DnssecPolicy, Error,
+    proto::{
+        DnsError, ProtoError,
+        dnssec::{
+            DigestType, TrustAnchorManager, TrustAnchors, Verifier,
+            crypto::Digest,
+            rdata::{DNSKEY, RRSIG},
+        },
+        op::{DnsRequestOptions, DnsResponse, Message, OpCode, Query, ResponseCode},
+        rr::{DNSClass, Name, RData, Record, RecordType, SerialNumber, resource::RecordRef},
+        runtime::{RuntimeProvider, Time},
+        serialize::{
+            binary::{BinEncodable, BinEncoder, NameEncoding},
+            txt::Parser,
+        },
+        xfer::{DnsHandle, FirstAnswer},
+    },
+    resolver::{
+        config::{ConnectionConfig, ResolverOpts},
+        name_server::{ConnectionProvider, TlsConfig},
+    },
+};
+
+/// Where the local copy of the root zone is loaded from, see [`RecursorBuilder::root_zone()`]
+///
+/// [`RecursorBuilder::root_zone()`]: crate::RecursorBuilder::root_zone
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum RootZoneSource {
+    /// A zone file, which is read again whenever the zone is refreshed
+    File(PathBuf),
+    /// A server allowing zone transfers (AXFR) of the root zone over TCP, like the ones listed in
+    /// [RFC 8806 appendix A](https://www.rfc-editor.org/rfc/rfc8806#appendix-A), or a local
+    /// server serving a copy of the zone
+    Transfer(SocketAddr),
+}
+
+/// Answers queries to the root name servers from a validated copy of the root zone
+///
+/// The zone is only used once it has been loaded and verified, and until the expire time of its
+/// SOA record passes without a successful refresh. Until then, the root name servers are queried.
+pub(crate) struct RootZoneMirror {
+    source: RootZoneSource,
+    trust_anchors: RootTrustAnchors,
+    /// The current copy of the zone, and the time at which it expires
+    zone: RwLock<Option<(Arc<RootZone>, Instant)>>,
+}
+
+impl RootZoneMirror {
+    /// Creates a mirror of the zone from `source`, validated with the trust anchors of `policy`
+    ///
+    /// The zone is validated with the built-in trust anchor if `policy` does not validate responses.
+    pub(crate) fn new(source: RootZoneSource, policy: &DnssecPolicy) -> Self {
+        let trust_anchors = match policy {
+            DnssecPolicy::ValidateWithStaticKey {
+                trust_anchor: Some(trust_anchor),
+                ..
+            } => RootTrustAnchors::Static(trust_anchor.clone()),
+            DnssecPolicy::ValidateWithInitialKey {
+                trust_anchor_manager,
+                ..
+            } => RootTrustAnchors::Managed(trust_anchor_manager.clone()),
+            _ => RootTrustAnchors::Static(Arc::new(TrustAnchors::default())),
+        };
+
+        Self {
+            source,
+            trust_anchors,
+            zone: RwLock::new(None),
+        }
+    }
+
+    /// Answers `query` from the zone, if a current copy of the zone is available
+    ///
+    /// Like responses from name servers, negative responses are returned as errors.
+    pub(crate) fn lookup(
+        &self,
+        query: &Query,
+        dnssec_ok: bool,
+    ) -> Option<Result<DnsResponse, ProtoError>> {
+        let zone = match &*self.zone.read() {
+            Some((zone, expires)) if Instant::now() < *expires => zone.clone(),
+            _ => return None,
+        };
+
+        let message = zone.lookup(query, dnssec_ok)?;
+        debug!("answering {query} from the local root zone");
+        Some(
+            DnsResponse::from_message(message)
+                .and_then(|response| DnsError::from_response(response).map_err(ProtoError::from)),
+        )
+    }
+
+    /// Periodically refreshes the zone of `mirror` until it is dropped
+    ///
+    /// The zone is loaded immediately, then refreshed at the refresh interval of its SOA record,
+    /// or at the retry interval after a failure. This is meant to be spawned in the background.
+    pub(crate) async fn run<P: ConnectionProvider>(
+        mirror: Weak<Self>,
+        conn_provider: P,
+        tls: Arc<TlsConfig>,
+    ) -> Result<(), ProtoError> {
+        loop {
+            let Some(this) = mirror.upgrade() else {
+                return Ok(());
+            };
+
+            let interval = match this.refresh(&conn_provider, &tls).await {
+                Ok(interval) => interval,
+                Err(err) => {
+                    warn!("failed to refresh the local root zone: {err}");
+                    match &*this.zone.read() {
+                        Some((zone, _)) => zone.retry,
+                        None => INITIAL_RETRY_INTERVAL,
+                    }
+                }
+            };
+
+            drop(this);
+            debug!("next refresh of the local root zone in {interval:?}");
+            <P::RuntimeProvider as RuntimeProvider>::Timer::delay_for(interval).await;
+        }
+    }
+
+    /// Loads the zone from its source, returning the time until the next refresh
+    async fn refresh<P: ConnectionProvider>(
+        &self,
+        conn_provider: &P,
+        tls: &TlsConfig,
+    ) -> Result<Duration, Error> {
+        let current = self.zone.read().as_ref().map(|(zone, _)| zone.clone());
+        let current_time = <P::RuntimeProvider as RuntimeProvider>::Timer::current_time() as u32;
+
+        let records = match &self.source {
+            RootZoneSource::File(path) => {
+                let text = tokio::fs::read_to_string(path).await?;
+                let (_, records) = Parser::new(text, Some(path.clone()), Some(Name::root()))
+                    .signed()
+                    .parse()
+                    .map_err(|err| Error::from(format!("{}: {err}", path.display())))?;
+                records
+                    .into_values()
+                    .flat_map(|rrset| rrset.records_without_rrsigs().cloned().collect::<Vec<_>>())
+                    .collect()
+            }
+            RootZoneSource::Transfer(addr) => {
+                let conn = connect(*addr, conn_provider, tls).await?;
+                let serial = soa_serial(&conn).await?;
+                if let Some(zone) = current.filter(|zone| zone.serial == serial) {
+                    debug!("local root zone is up to date, serial {serial}");
+                    *self.zone.write() = Some((zone.clone(), Instant::now() + zone.expire));
+                    return Ok(zone.refresh);
+                }
+
+                transfer(&conn).await?
+            }
+        };
+
+        let zone = RootZone::new(records, &self.trust_anchors.get(), current_time)?;
+        info!("loaded local root zone, serial {}", zone.serial);
+
+        let (refresh, expires) = (zone.refresh, Instant::now() + zone.expire);
+        *self.zone.write() = Some((Arc::new(zone), expires));
+        Ok(refresh)
+    }
+}
+
+/// Trust anchors used to validate the root zone
+enum RootTrustAnchors {
+    Static(Arc<TrustAnchors>),
+    Managed(Arc<TrustAnchorManager>),
+}
+
+impl RootTrustAnchors {
+    fn get(&self) -> Arc<TrustAnchors> {
+        match self {
+            Self::Static(trust_anchors) => trust_anchors.clone(),
+            Self::Managed(manager) => manager.trust_anchors(),
+        }
+    }
+}
+
+/// Opens a TCP connection to `addr` for zone transfers
+async fn connect<P: ConnectionProvider>(
+    addr: SocketAddr,
+    conn_provider: &P,
+    tls: &TlsConfig,
+) -> Result<P::Conn, Error> {
+    let mut config = ConnectionConfig::tcp();
+    config.port = addr.port();
+    let mut options = ResolverOpts::default();
+    options.timeout = TRANSFER_TIMEOUT;
+
+    Ok(conn_provider
+        .new_connection(addr.ip(), &config, &options, tls)?
+        .await?)
+}
+
+/// Queries the serial of the root zone through `conn`
+async fn soa_serial(conn: &impl DnsHandle) -> Result<u32, Error> {
+    let response = conn
+        .lookup(
+            Query::query(Name::root(), RecordType::SOA),
+            request_options(),
+        )
+        .first_answer()
+        .await?;
+
+    response
+        .answers()
+        .iter()
+        .find_map(|record| record.data().as_soa())
+        .map(|soa| soa.serial())
+        .ok_or_else(|| Error::from("no SOA record in response"))
+}
+
+/// Transfers the root zone through `conn`, see RFC 5936
+///
+/// The transfer ends with the second occurrence of the SOA record, which is not returned.
+async fn transfer(conn: &impl DnsHandle) -> Result<Vec<Record>, Error> {
+    let mut responses = conn.lookup(
+        Query::query(Name::root(), RecordType::AXFR),
+        request_options(),
+    );
+
+    let mut records = Vec::new();
+    let mut soa_count = 0;
+    while let Some(response) = responses.next().await {
+        let response = response?;
+        if response.response_code() != ResponseCode::NoError {
+            return Err(Error::from(format!(
+                "zone transfer failed: {}",
+                response.response_code()
+            )));
+        }
+
+        for record in response.into_message().take_answers() {
+            if record.record_type() == RecordType::SOA && record.name().is_root() {
+                soa_count += 1;
+                if soa_count == 2 {
+                    return Ok(records);
+                }
+            }
+
+            if soa_count == 0 {
+                return Err(Error::from(
+                    "zone transfer does not start with the SOA record",
+                ));
+            }
+            records.push(record);
+        }
+    }
+
+    Err(Error::from("zone transfer ended early"))
+}
+
+fn request_options() -> DnsRequestOptions {
+    let mut options = DnsRequestOptions::default();
+    options.recursion_desired = false;
+    options
+}
+
+/// A verified copy of the root zone
+struct RootZone {
+    /// Records by owner name, in canonical order
+    records: BTreeMap<Name, Vec<Record>>,
+    serial: u32,
+    refresh: Duration,
+    retry: Duration,
+    expire: Duration,
+}
+
+impl RootZone {
+    /// Builds the zone from its `records`, and verifies it
+    ///
+    /// The zone must contain a ZONEMD record matching its content, see
+    /// [RFC 8976](https://www.rfc-editor.org/rfc/rfc8976), which must be signed by a DNSKEY of the
+    /// zone. The DNSKEY RRset must in turn be signed by one of the `trust_anchors`. `current_time`
+    /// is the number of seconds since the Unix epoch.
+    fn new(
+        records: Vec<Record>,
+        trust_anchors: &TrustAnchors,
+        current_time: u32,
+    ) -> Result<Self, Error> {
+        let mut by_name = BTreeMap::<Name, Vec<Record>>::new();
+        for record in records {
+            by_name
+                .entry(record.name().clone())
+                .or_default()
+                .push(record);
+        }
+
+        let root = Name::root();
+        let apex = by_name
+            .get(&root)
+            .ok_or_else(|| Error::from("no records at the root of the zone"))?;
+        let soa = apex
+            .iter()
+            .find_map(|record| record.data().as_soa())
+            .ok_or_else(|| Error::from("no SOA record in the zone"))?;
+
+        let dnskeys = verified_dnskeys(apex, trust_anchors, current_time)?;
+        verify_zonemd(&by_name, soa.serial(), &dnskeys, current_time)?;
+
+        let seconds = |value: i32| Duration::from_secs(u64::from(value.max(0) as u32));
+        Ok(Self {
+            serial: soa.serial(),
+            refresh: seconds(soa.refresh()).max(MIN_REFRESH_INTERVAL),
+            retry: seconds(soa.retry()).max(MIN_REFRESH_INTERVAL),
+            expire: seconds(soa.expire()),
+            records: by_name,
+        })
+    }
+
+    /// Answers `query` like a root name server, or returns `None` if the zone cannot answer it
+    fn lookup(&self, query: &Query, dnssec_ok: bool) -> Option<Message> {
+        if query.query_class() != DNSClass::IN {
+            return None;
+        }
+
+        let mut message = Message::response(0, OpCode::Query);
+        message.add_query(query.clone());
+
+        let name = query.name();
+        let query_type = query.query_type();
+        let root = Name::root();
+        let apex = self.records.get(&root)?;
+
+        if name.is_root() {
+            message.set_authoritative(true);
+            let answers = rrset(apex, query_type, dnssec_ok);
+            if answers.is_empty() {
+                self.add_nodata(&mut message, apex, dnssec_ok);
+            } else {
+                message.add_answers(answers);
+            }
+            return Some(message);
+        }
+
+        let tld = name.trim_to(1);
+        let Some(records) = self.records.get(&tld) else {
+            // RFC 4035 section 3.1.3.2, proof that the name and the wildcard do not exist
+            message.set_authoritative(true);
+            message.set_response_code(ResponseCode::NXDomain);
+            message.add_authorities(rrset(apex, RecordType::SOA, dnssec_ok));
+            if dnssec_ok {
+                let wildcard = Name::from_ascii("*.").ok()?;
+                let mut nsecs = self.covering_nsec(&tld);
+                for nsec in self.covering_nsec(&wildcard) {
+                    if !nsecs.contains(&nsec) {
+                        nsecs.push(nsec);
+                    }
+                }
+                message.add_authorities(nsecs);
+            }
+            return Some(message);
+        };
+
+        if !records
+            .iter()
+            .any(|record| record.record_type() == RecordType::NS)
+        {
+            // all names below the root are delegated in the root zone
+            return None;
+        }
+
+        // the DS RRset is part of the root zone, not of the delegated zone
+        if name == &tld && query_type == RecordType::DS {
+            message.set_authoritative(true);
+            let answers = rrset(records, RecordType::DS, dnssec_ok);
+            if answers.is_empty() {
+                self.add_nodata(&mut message, records, dnssec_ok);
+            } else {
+                message.add_answers(answers);
+            }
+            return Some(message);
+        }
+
+        // referral to the name servers of the top-level domain
+        message.add_authorities(rrset(records, RecordType::NS, false));
+        if dnssec_ok {
+            let ds = rrset(records, RecordType::DS, true);
+            if ds.is_empty() {
+                // proof that the delegation is insecure
+                message.add_authorities(rrset(records, RecordType::NSEC, true));
+            } else {
+                message.add_authorities(ds);
+            }
+        }
+
+        for ns in records.iter().filter_map(|record| record.data().as_ns()) {
+            let Some(glue) = self.records.get(&ns.0) else {
+                continue;
+            };
+            message.add_additionals(
+                glue.iter()
+                    .filter(|record| matches!(record.data(), RData::A(_) | RData::AAAA(_)))
+                    .cloned(),
+            );
+        }
+
+        Some(message)
+    }
+
+    /// Adds the SOA record and the NSEC record of a name without records of the queried type
+    fn add_nodata(&self, message: &mut Message, records: &[Record], dnssec_ok: bool) {
+        if let Some(apex) = self.records.get(&Name::root()) {
+            message.add_authorities(rrset(apex, RecordType::SOA, dnssec_ok));
+        }
+        if dnssec_ok {
+            message.add_authorities(rrset(records, RecordType::NSEC, true));
+        }
+    }
+
+    /// Returns the NSEC RRset covering `name`, with its signatures
+    ///
+    /// Glue records are skipped, as there are no NSEC records for them.
+    fn covering_nsec(&self, name: &Name) -> Vec<Record> {
+        self.records
+            .range(..name.clone())
+            .rev()
+            .map(|(_, records)| rrset(records, RecordType::NSEC, true))
+            .find(|nsec| !nsec.is_empty())
+            .unwrap_or_default()
+    }
+}
+
+/// Returns the records of `record_type` from `records`, with their signatures if `dnssec_ok`
+fn rrset(records: &[Record], record_type: RecordType, dnssec_ok: bool) -> Vec<Record> {
+    records
+        .iter()
+        .filter(|record| {
+            record.record_type() == record_type
+                || (dnssec_ok && covered_type(record) == Some(record_type))
+        })
+        .cloned()
+        .collect()
+}
+
+/// Returns the type covered by `record`, if it is an RRSIG record
+fn covered_type(record: &Record) -> Option<RecordType> {
+    record
+        .try_borrow::<RRSIG>()
+        .map(|rrsig| rrsig.data().input().type_covered)
+}
+
+/// Returns the zone keys of the apex DNSKEY RRset, if it is signed by one of the `trust_anchors`
+fn verified_dnskeys(
+    apex: &[Record],
+    trust_anchors: &TrustAnchors,
+    current_time: u32,
+) -> Result<Vec<DNSKEY>, Error> {
+    let dnskeys = apex
+        .iter()
+        .filter_map(|record| record.try_borrow::<DNSKEY>())
+        .map(|dnskey| dnskey.data().clone())
+        .filter(|dnskey| dnskey.zone_key() && !dnskey.revoke())
+        .collect::<Vec<_>>();
+
+    let trusted = dnskeys
+        .iter()
+        .filter(|dnskey| trust_anchors.contains(dnskey.public_key()))
+        .collect::<Vec<_>>();
+    if !signed_by(apex, RecordType::DNSKEY, &trusted, current_time) {
+        return Err(Error::from(
+            "root DNSKEY RRset is not signed by a trusted key",
+        ));
+    }
+
+    Ok(dnskeys)
+}
+
+/// Whether the RRset of `record_type` in `apex` has a current signature from one of `dnskeys`
+fn signed_by(
+    apex: &[Record],
+    record_type: RecordType,
+    dnskeys: &[&DNSKEY],
+    current_time: u32,
+) -> bool {
+    let current_time = SerialNumber::from(current_time);
+    let rrsigs = apex
+        .iter()
+        .filter_map(|record| record.try_borrow::<RRSIG>())
+        .filter(|rrsig| rrsig.data().input().type_covered == record_type);
+
+    let verify = |dnskey: &DNSKEY, rrsig: &RecordRef<'_, RRSIG>| {
+        let input = rrsig.data().input();
+        input.algorithm == dnskey.algorithm()
+            && dnskey.calculate_key_tag().ok() == Some(input.key_tag)
+            && input.signer_name.is_root()
+            && input.sig_inception <= current_time
+            && current_time <= input.sig_expiration
+            && dnskey
+                .verify_rrsig(&Name::root(), DNSClass::IN, rrsig.data(), apex.iter())
+                .is_ok()
+    };
+
+    rrsigs
+        .into_iter()
+        .any(|rrsig| dnskeys.iter().any(|dnskey| verify(dnskey, &rrsig)))
+}
+
+/// Verifies the ZONEMD RRset of the zone, see RFC 8976 section 4
+///
+/// Only the SIMPLE scheme with SHA-384 is supported, which is the one used for the root zone.
+fn verify_zonemd(
+    zone: &BTreeMap<Name, Vec<Record>>,
+    serial: u32,
+    dnskeys: &[DNSKEY],
+    current_time: u32,
+) -> Result<(), Error> {
+    let apex = zone
+        .get(&Name::root())
+        .ok_or_else(|| Error::from("no records at the root of the zone"))?;
+
+    let dnskeys = dnskeys.iter().collect::<Vec<_>>();
+    if !signed_by(apex, RecordType::ZONEMD, &dnskeys, current_time) {
+        return Err(Error::from("ZONEMD RRset is not signed by a zone key"));
+    }
+
+    let expected = apex
+        .iter()
+        .filter_map(|record| match record.data() {
+            RData::Unknown { code, rdata } if *code == RecordType::ZONEMD => Some(rdata.anything()),
+            _ => None,
+        })
+        .filter(|rdata| rdata.len() > 6)
+        .filter_map(|rdata| {
+            let (header, digest) = rdata.split_at(6);
+            let zonemd_serial = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+            let supported = header[4] == ZONEMD_SCHEME_SIMPLE && header[5] == ZONEMD_HASH_SHA384;
+            (zonemd_serial == serial && supported).then_some(digest)
+        })
+        .collect::<Vec<_>>();
+    if expected.is_empty() {
+        return Err(Error::from(format!(
+            "no supported ZONEMD record for serial {serial}"
+        )));
+    }
+
+    let digest = zone_digest(&Name::root(), zone)?;
+    if !expected.contains(&digest.as_ref()) {
+        return Err(Error::from("ZONEMD digest does not match the zone"));
+    }
+
+    Ok(())
+}
+
+/// Computes the digest of the zone at `apex` with the SIMPLE scheme, see RFC 8976 section 3.3
+///
+/// The apex ZONEMD RRset and its signatures are excluded, and the records are digested in
+/// canonical form and order, without duplicates.
+fn zone_digest(apex: &Name, zone: &BTreeMap<Name, Vec<Record>>) -> Result<Digest, ProtoError> {
+    let mut wire = Vec::new();
+    for (name, records) in zone {
+        let mut rrs = Vec::with_capacity(records.len());
+        for record in records {
+            if name == apex
+                && (record.record_type() == RecordType::ZONEMD
+                    || covered_type(record) == Some(RecordType::ZONEMD))
+            {
+                continue;
+            }
+
+            rrs.push(canonical_rr(record)?);
+        }
+
+        rrs.sort();
+        rrs.dedup();
+        wire.extend(rrs.into_iter().map(|rr| rr.wire));
+    }
+
+    Ok(Digest::from_iter(
+        wire.iter().map(Vec::as_slice),
+        DigestType::SHA384,
+    )?)
+}
+
+/// A record in canonical form, ordered by type and RDATA
+#[derive(Eq, Ord, PartialEq, PartialOrd)]
+struct CanonicalRr {
+    record_type: u16,
+    rdata: Vec<u8>,
+    wire: Vec<u8>,
+}
+
+/// Returns the canonical form of `record`
+fn canonical_rr(record: &Record) -> Result<CanonicalRr, ProtoError> {
+    let mut rdata = Vec::new();
+    let mut encoder = BinEncoder::new(&mut rdata);
+    encoder.set_canonical_form(true);
+    encoder.set_name_encoding(NameEncoding::Uncompressed);
+    record.data().emit(&mut encoder)?;
+
+    let mut rr = Vec::with_capacity(rdata.len() + 32);
+    let mut encoder = BinEncoder::new(&mut rr);
+    encoder.set_canonical_form(true);
+    {
+        let mut encoder = encoder.with_name_encoding(NameEncoding::UncompressedLowercase);
+        record.name().emit(&mut encoder)?;
+    }
+    record.record_type().emit(&mut encoder)?;
+    record.dns_class().emit(&mut encoder)?;
+    encoder.emit_u32(record.ttl())?;
+    let length = u16::try_from(rdata.len())
+        .map_err(|_| ProtoError::from("RDATA length exceeds u16::MAX"))?;
+    encoder.emit_u16(length)?;
+    encoder.emit_vec(&rdata)?;
+
+    Ok(CanonicalRr {
+        record_type: u16::from(record.record_type()),
+        rdata,
+        wire: rr,
+    })
+}
+
+/// The SIMPLE ZONEMD scheme, RFC 8976 section 2.2.2
+const ZONEMD_SCHEME_SIMPLE: u8 = 1;
+/// The SHA-384 ZONEMD hash algorithm, RFC 8976 section 2.2.3
+const ZONEMD_HASH_SHA384: u8 = 1;
+/// Time to wait for a zone transfer of the root zone
+const TRANSFER_TIMEOUT: Duration = Duration::from_secs(60);
+/// Time before retrying to load the zone if it was never loaded
+const INITIAL_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+/// Lower bound on the refresh and retry intervals from the SOA record
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+    use std::time::{SystemTime, UNIX_EPOCH};
+    use std::{env, fs, process};
+
+    use super::*;
+    use crate::ErrorKind;
+    use crate::proto::{
+        ProtoErrorKind,
+        dnssec::{
+            Algorithm, SigningKey, TBS,
+            crypto::EcdsaSigningKey,
+            rdata::{DNSSECRData, DS, NSEC, SigInput},
+        },
+        rr::rdata::{A, NS, NULL, SOA},
+        runtime::TokioRuntimeProvider,
+    };
+
+    const NOW: u32 = 1_750_000_000;
+
+    #[test]
+    fn test_verify_zone() {
+        let (records, trust_anchors) = signed_zone();
+        let zone = RootZone::new(records.clone(), &trust_anchors, NOW).unwrap();
+        assert_eq!(zone.serial, 2025010100);
+        assert_eq!(zone.refresh, Duration::from_secs(1800));
+
+        // expired signatures
+        assert!(RootZone::new(records.clone(), &trust_anchors, NOW + 2 * 86400).is_err());
+        // untrusted key
+        assert!(RootZone::new(records.clone(), &TrustAnchors::empty(), NOW).is_err());
+
+        // modified glue
+        let mut tampered = records.clone();
+        for record in &mut tampered {
+            if let RData::A(ip) = record.data_mut() {
+                *ip = A::new(192, 0, 2, 2);
+            }
+        }
+        let Err(err) = RootZone::new(tampered, &trust_anchors, NOW) else {
+            panic!("tampered zone was verified");
+        };
+        assert!(err.to_string().contains("digest"), "{err}");
+
+        // missing record
+        let mut missing = records;
+        missing.retain(|record| record.record_type() != RecordType::DS);
+        assert!(RootZone::new(missing, &trust_anchors, NOW).is_err());
+    }
+
+    /// The simple example zone of RFC 8976 appendix A.1
+    #[test]
+    fn test_zone_digest() {
+        let text = "
+example.      86400  IN  SOA     ns1 admin 2018031900 (
+                                 1800 900 604800 86400 )
+              86400  IN  NS      ns1
+              86400  IN  NS      ns2
+              86400  IN  ZONEMD  2018031900 1 1 (
+                                 c68090d90a7aed71
+                                 6bc459f9340e3d7c
+                                 1370d4d24b7e2fc3
+                                 a1ddc0b9a87153b9
+                                 a9713b3c9ae5cc27
+                                 777f98b8e730044c )
+ns1           3600   IN  A       203.0.113.63
+ns2           3600   IN  AAAA    2001:db8::63
+";
+        let apex = Name::from_ascii("example.").unwrap();
+        let (_, rrsets) = Parser::new(text, None, Some(apex.clone()))
+            .signed()
+            .parse()
+            .unwrap();
+        let mut zone = BTreeMap::<Name, Vec<Record>>::new();
+        for record in rrsets
+            .values()
+            .flat_map(|rrset| rrset.records_without_rrsigs())
+        {
+            zone.entry(record.name().clone())
+                .or_default()
+                .push(record.clone());
+        }
+
+        let digest = zone_digest(&apex, &zone).unwrap();
+        let expected = "c68090d90a7aed716bc459f9340e3d7c1370d4d24b7e2fc3\
+                        a1ddc0b9a87153b9a9713b3c9ae5cc27777f98b8e730044c";
+        let digest = digest
+            .as_ref()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<String>();
+        assert_eq!(digest, expected);
+    }
+
+    #[tokio::test]
+    async fn test_load_file() {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as u32;
+        let (records, trust_anchors) = signed_zone_at(now);
+        let mut text = String::new();
+        for record in &records {
+            match record.data() {
+                RData::Unknown { rdata, .. } => {
+                    let rdata = rdata.anything();
+                    let (header, digest) = rdata.split_at(6);
+                    let serial = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+                    let digest = digest
+                        .iter()
+                        .map(|byte| format!("{byte:02x}"))
+                        .collect::<String>();
+                    text.push_str(&format!(
+                        ". {} IN ZONEMD {serial} {} {} {digest}\n",
+                        record.ttl(),
+                        header[4],
+                        header[5],
+                    ));
+                }
+                _ => text.push_str(&format!("{record}\n")),
+            }
+        }
+
+        let path = env::temp_dir().join(format!("root-zone-{}.zone", process::id()));
+        // The RRSIG parser only accepts algorithm numbers.
+        let text = text.replace(" ECDSAP256SHA256 ", " 13 ");
+        fs::write(&path, text).unwrap();
+
+        let mirror = RootZoneMirror {
+            source: RootZoneSource::File(path.clone()),
+            trust_anchors: RootTrustAnchors::Static(Arc::new(trust_anchors)),
+            zone: RwLock::new(None),
+        };
+        let result = mirror
+            .refresh(&TokioRuntimeProvider::default(), &TlsConfig::new().unwrap())
+            .await;
+        fs::remove_file(&path).unwrap();
+        assert_eq!(result.unwrap(), Duration::from_secs(1800));
+
+        let query = Query::query(Name::from_ascii("com.").unwrap(), RecordType::DS);
+        let response = mirror.lookup(&query, false).unwrap().unwrap();
+        assert!(response.authoritative());
+        assert_eq!(response.answers().len(), 1);
+    }
+
+    #[test]
+    fn test_lookup() {
+        let (records, trust_anchors) = signed_zone();
+        let zone = RootZone::new(records, &trust_anchors, NOW).unwrap();
+
+        let mirror = RootZoneMirror::new(
+            RootZoneSource::File(PathBuf::new()),
+            &DnssecPolicy::SecurityUnaware,
+        );
+        let query = Query::query(Name::from_ascii("com.").unwrap(), RecordType::NS);
+        assert!(mirror.lookup(&query, true).is_none());
+        *mirror.zone.write() = Some((Arc::new(zone), Instant::now() + Duration::from_secs(60)));
+
+        // referral, with the DS RRset
+        let response = mirror.lookup(&query, true).unwrap().unwrap();
+        assert!(!response.authoritative());
+        assert!(response.answers().is_empty());
+        let types = |records: &[Record]| {
+            records
+                .iter()
+                .map(|record| covered_type(record).unwrap_or(record.record_type()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            types(response.authorities()),
+            [RecordType::NS, RecordType::DS, RecordType::DS]
+        );
+        assert_eq!(types(response.additionals()), [RecordType::A]);
+
+        let query = Query::query(Name::from_ascii("www.org.").unwrap(), RecordType::A);
+        let err = mirror.lookup(&query, false).unwrap().unwrap_err();
+        assert!(matches!(Error::from(err).kind(), ErrorKind::ForwardNS(_)));
+
+        // authoritative answer for the DS RRset
+        let query = Query::query(Name::from_ascii("com.").unwrap(), RecordType::DS);
+        let response = mirror.lookup(&query, false).unwrap().unwrap();
+        assert!(response.authoritative());
+        assert_eq!(types(response.answers()), [RecordType::DS]);
+
+        // name error, with the NSEC records covering the name and the wildcard
+        let query = Query::query(Name::from_ascii("www.example.").unwrap(), RecordType::A);
+        let err = mirror.lookup(&query, true).unwrap().unwrap_err();
+        let ProtoErrorKind::Dns(DnsError::NoRecordsFound(no_records)) = err.kind() else {
+            panic!("unexpected error {err}");
+        };
+        assert_eq!(no_records.response_code, ResponseCode::NXDomain);
+        let nsecs = no_records
+            .authorities
+            .iter()
+            .flat_map(|records| records.iter())
+            .filter_map(|record| record.try_borrow::<NSEC>())
+            .map(|nsec| nsec.name().to_ascii())
+            .collect::<Vec<_>>();
+        assert_eq!(nsecs, ["com.", "."]);
+
+        // the zone is no longer used once expired
+        if let Some((_, expires)) = &mut *mirror.zone.write() {
+            *expires = Instant::now();
+        }
+        assert!(mirror.lookup(&query, true).is_none());
+    }
+
+    /// Returns a signed root zone with two delegations, and the trust anchor of its key
+    fn signed_zone() -> (Vec<Record>, TrustAnchors) {
+        signed_zone_at(NOW)
+    }
+
+    /// Returns the zone of [`signed_zone()`], with signatures valid at `now`
+    fn signed_zone_at(now: u32) -> (Vec<Record>, TrustAnchors) {
+        let algorithm = Algorithm::ECDSAP256SHA256;
+        let pkcs8 = EcdsaSigningKey::generate_pkcs8(algorithm).unwrap();
+        let key = EcdsaSigningKey::from_pkcs8(&pkcs8, algorithm).unwrap();
+        let public_key = key.to_public_key().unwrap();
+        let dnskey = DNSKEY::new(true, true, false, public_key.clone());
+
+        let mut trust_anchors = TrustAnchors::empty();
+        trust_anchors.insert(&public_key);
+
+        let root = Name::root();
+        let com = Name::from_ascii("com.").unwrap();
+        let org = Name::from_ascii("org.").unwrap();
+        let ns1 = Name::from_ascii("ns1.com.").unwrap();
+        let dnssec = |rdata| RData::DNSSEC(rdata);
+        let mut records = vec![
+            Record::from_rdata(
+                root.clone(),
+                86400,
+                RData::SOA(SOA::new(
+                    ns1.clone(),
+                    Name::from_ascii("hostmaster.com.").unwrap(),
+                    2025010100,
+                    1800,
+                    900,
+                    604800,
+                    86400,
+                )),
+            ),
+            Record::from_rdata(root.clone(), 518400, RData::NS(NS(ns1.clone()))),
+            Record::from_rdata(root.clone(), 172800, dnssec(DNSSECRData::DNSKEY(dnskey))),
+            Record::from_rdata(
+                root.clone(),
+                86400,
+                dnssec(DNSSECRData::NSEC(NSEC::new(
+                    com.clone(),
+                    [
+                        RecordType::NS,
+                        RecordType::SOA,
+                        RecordType::RRSIG,
+                        RecordType::NSEC,
+                        RecordType::DNSKEY,
+                        RecordType::ZONEMD,
+                    ],
+                ))),
+            ),
+            Record::from_rdata(com.clone(), 172800, RData::NS(NS(ns1.clone()))),
+            Record::from_rdata(
+                com.clone(),
+                86400,
+                dnssec(DNSSECRData::DS(DS::new(
+                    1,
+                    algorithm,
+                    DigestType::SHA256,
+                    vec![0; 32],
+                ))),
+            ),
+            Record::from_rdata(
+                com.clone(),
+                86400,
+                dnssec(DNSSECRData::NSEC(NSEC::new(
+                    org.clone(),
+                    [
+                        RecordType::NS,
+                        RecordType::DS,
+                        RecordType::RRSIG,
+                        RecordType::NSEC,
+                    ],
+                ))),
+            ),
+            Record::from_rdata(
+                ns1.clone(),
+                172800,
+                RData::A(A::from(Ipv4Addr::new(192, 0, 2, 1))),
+            ),
+            Record::from_rdata(org.clone(), 172800, RData::NS(NS(ns1))),
+            Record::from_rdata(
+                org.clone(),
+                86400,
+                dnssec(DNSSECRData::NSEC(NSEC::new(
+                    root.clone(),
+                    [RecordType::NS, RecordType::RRSIG, RecordType::NSEC],
+                ))),
+            ),
+        ];
+
+        let signed = [
+            (&root, RecordType::SOA),
+            (&root, RecordType::NS),
+            (&root, RecordType::DNSKEY),
+            (&root, RecordType::NSEC),
+            (&com, RecordType::DS),
+            (&com, RecordType::NSEC),
+            (&org, RecordType::NSEC),
+        ];
+        for (name, record_type) in signed {
+            let rrsig = sign(&key, &records, name, record_type, now);
+            records.push(rrsig);
+        }
+
+        let mut zone = BTreeMap::<Name, Vec<Record>>::new();
+        for record in &records {
+            zone.entry(record.name().clone())
+                .or_default()
+                .push(record.clone());
+        }
+        let digest = zone_digest(&root, &zone).unwrap();
+
+        let mut zonemd = 2025010100_u32.to_be_bytes().to_vec();
+        zonemd.extend([ZONEMD_SCHEME_SIMPLE, ZONEMD_HASH_SHA384]);
+        zonemd.extend_from_slice(digest.as_ref());
+        records.push(Record::from_rdata(
+            root.clone(),
+            86400,
+            RData::Unknown {
+                code: RecordType::ZONEMD,
+                rdata: NULL::with(zonemd),
+            },
+        ));
+        let rrsig = sign(&key, &records, &root, RecordType::ZONEMD, now);
+        records.push(rrsig);
+
+        (records, trust_anchors)
+    }
+
+    fn sign(
+        key: &EcdsaSigningKey,
+        records: &[Record],
+        name: &Name,
+        record_type: RecordType,
+        now: u32,
+    ) -> Record {
+        let dnskey = DNSKEY::new(true, true, false, key.to_public_key().unwrap());
+        let rrset = records
+            .iter()
+            .filter(|record| record.name() == name && record.record_type() == record_type);
+        let input = SigInput {
+            type_covered: record_type,
+            algorithm: key.algorithm(),
+            num_labels: name.num_labels(),
+            original_ttl: rrset.clone().next().unwrap().ttl(),
+            sig_expiration: SerialNumber::from(now + 86400),
+            sig_inception: SerialNumber::from(now - 3600),
+            key_tag: dnskey.calculate_key_tag().unwrap(),
+            signer_name: Name::root(),
+        };
+
+        let tbs = TBS::from_input(name, DNSClass::IN, &input, rrset).unwrap();
+        let sig = key.sign(&tbs).unwrap();
+        Record::from_rdata(
+            name.clone(),
+            86400,
+            RData::DNSSEC(DNSSECRData::RRSIG(RRSIG::from_sig(input, sig))),
+        )
+    }
+}
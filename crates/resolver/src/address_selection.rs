@@ -0,0 +1,402 @@
+//! Destination address selection, see [RFC 6724](https://www.rfc-editor.org/rfc/rfc6724)
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+
+/// Source addresses of recently sorted destinations, shared by all resolvers of the process
+static SOURCE_ADDRS: Lazy<SourceAddrCache> = Lazy::new(SourceAddrCache::default);
+
+/// Sorts destination addresses in order of preference, based on the source address the host
+/// would use to reach each of them
+///
+/// Finding a source address makes blocking system calls, so they are cached for each destination
+/// network, see [`SourceAddrCache`].
+pub(crate) fn sort_destinations(addrs: &mut [IpAddr]) {
+    let now = Instant::now();
+    sort_destinations_with(addrs, |addr| SOURCE_ADDRS.get(addr, now, source_addr));
+}
+
+/// Sorts `addrs` with the rules of section 6 of RFC 6724, `source` returns the source address
+/// used to reach a destination, if it is reachable
+///
+/// Rules 3, 4 and 7 depend on the state of the local interfaces, which is not available here, and
+/// are skipped. The sort is stable, which implements rule 10.
+fn sort_destinations_with(addrs: &mut [IpAddr], source: impl Fn(IpAddr) -> Option<IpAddr>) {
+    let mut destinations = addrs
+        .iter()
+        .map(|&addr| Destination::new(addr, source(addr)))
+        .collect::<Vec<_>>();
+    destinations.sort_by(Destination::cmp_preference);
+
+    for (addr, destination) in addrs.iter_mut().zip(destinations) {
+        *addr = destination.addr;
+    }
+}
+
+/// Returns the source address the host would use to reach `destination`
+///
+/// Connecting a UDP socket does not send any packet, but makes the OS select a route.
+fn source_addr(destination: IpAddr) -> Option<IpAddr> {
+    let bind_addr = match destination {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+
+    let socket = UdpSocket::bind(SocketAddr::new(bind_addr, 0)).ok()?;
+    socket.connect(SocketAddr::new(destination, 9)).ok()?;
+    socket.local_addr().ok().map(|addr| addr.ip())
+}
+
+/// Caches the source address used to reach each destination network
+///
+/// Destinations are grouped by /24 for IPv4, and /64 for IPv6, which are routed the same way in
+/// practice. Entries expire after [`SOURCE_ADDR_TTL`], so that changes to the interfaces and
+/// routes of the host are picked up.
+#[derive(Default)]
+struct SourceAddrCache(Mutex<HashMap<IpAddr, (Option<IpAddr>, Instant)>>);
+
+impl SourceAddrCache {
+    /// Returns the source address for `destination`, calling `lookup` if it isn't cached
+    fn get(
+        &self,
+        destination: IpAddr,
+        now: Instant,
+        lookup: impl Fn(IpAddr) -> Option<IpAddr>,
+    ) -> Option<IpAddr> {
+        let network = network(destination);
+        if let Some((source, expires)) = self.0.lock().get(&network) {
+            if *expires > now {
+                return *source;
+            }
+        }
+
+        // the lock isn't held during the lookup, concurrent misses for a network both look it up
+        let source = lookup(destination);
+        let mut entries = self.0.lock();
+        if entries.len() >= MAX_SOURCE_ADDRS {
+            entries.retain(|_, (_, expires)| *expires > now);
+        }
+        if entries.len() >= MAX_SOURCE_ADDRS {
+            entries.clear();
+        }
+
+        entries.insert(network, (source, now + SOURCE_ADDR_TTL));
+        source
+    }
+}
+
+/// Returns the network of `addr` used as key of the [`SourceAddrCache`]
+fn network(addr: IpAddr) -> IpAddr {
+    match addr {
+        IpAddr::V4(addr) => IpAddr::V4(Ipv4Addr::from(u32::from(addr) & !0xff)),
+        IpAddr::V6(addr) => IpAddr::V6(Ipv6Addr::from(u128::from(addr) & !u128::from(u64::MAX))),
+    }
+}
+
+/// How long a source address is cached
+const SOURCE_ADDR_TTL: Duration = Duration::from_secs(60);
+
+/// The number of destination networks whose source address is cached
+const MAX_SOURCE_ADDRS: usize = 1024;
+
+struct Destination {
+    addr: IpAddr,
+    attrs: Attributes,
+    source: Option<(IpAddr, Attributes)>,
+}
+
+impl Destination {
+    fn new(addr: IpAddr, source: Option<IpAddr>) -> Self {
+        Self {
+            addr,
+            attrs: Attributes::new(addr),
+            source: source.map(|source| (source, Attributes::new(source))),
+        }
+    }
+
+    /// Orders the preferred destination first
+    fn cmp_preference(&self, other: &Self) -> Ordering {
+        let (sa, sb) = match (&self.source, &other.source) {
+            (Some(sa), Some(sb)) => (sa, sb),
+            // Rule 1: Avoid unusable destinations.
+            (Some(_), None) => return Ordering::Less,
+            (None, Some(_)) => return Ordering::Greater,
+            (None, None) => return self.cmp_destination(other),
+        };
+
+        // Rule 2: Prefer matching scope.
+        match (
+            self.attrs.scope == sa.1.scope,
+            other.attrs.scope == sb.1.scope,
+        ) {
+            (true, false) => return Ordering::Less,
+            (false, true) => return Ordering::Greater,
+            _ => {}
+        }
+
+        // Rule 5: Prefer matching label.
+        match (
+            self.attrs.label == sa.1.label,
+            other.attrs.label == sb.1.label,
+        ) {
+            (true, false) => return Ordering::Less,
+            (false, true) => return Ordering::Greater,
+            _ => {}
+        }
+
+        let ordering = self.cmp_destination(other);
+        if ordering.is_ne() {
+            return ordering;
+        }
+
+        // Rule 9: Use longest matching prefix.
+        if self.addr.is_ipv4() == other.addr.is_ipv4() {
+            return common_prefix_len(sb.0, other.addr).cmp(&common_prefix_len(sa.0, self.addr));
+        }
+
+        // Rule 10: Otherwise, leave the order unchanged.
+        Ordering::Equal
+    }
+
+    /// Compares the rules which only depend on the destinations
+    fn cmp_destination(&self, other: &Self) -> Ordering {
+        // Rule 6: Prefer higher precedence.
+        // Rule 8: Prefer smaller scope.
+        other
+            .attrs
+            .precedence
+            .cmp(&self.attrs.precedence)
+            .then(self.attrs.scope.cmp(&other.attrs.scope))
+    }
+}
+
+/// The properties of an address used by the sorting rules
+#[derive(Clone, Copy)]
+struct Attributes {
+    scope: u8,
+    precedence: u8,
+    label: u8,
+}
+
+impl Attributes {
+    fn new(addr: IpAddr) -> Self {
+        // IPv4 addresses are represented as IPv4-mapped IPv6 addresses
+        let addr = match addr {
+            IpAddr::V4(addr) => addr.to_ipv6_mapped(),
+            IpAddr::V6(addr) => addr,
+        };
+
+        let (precedence, label) = policy(addr);
+        Self {
+            scope: scope(addr),
+            precedence,
+            label,
+        }
+    }
+}
+
+const SCOPE_LINK_LOCAL: u8 = 0x2;
+const SCOPE_SITE_LOCAL: u8 = 0x5;
+const SCOPE_GLOBAL: u8 = 0xe;
+
+/// Returns the scope of an address, see section 3.1
+fn scope(addr: Ipv6Addr) -> u8 {
+    let segments = addr.segments();
+    if addr.is_multicast() {
+        return (segments[0] & 0xf) as u8;
+    }
+
+    if let Some(ipv4) = addr.to_ipv4_mapped() {
+        return match ipv4.is_loopback() || ipv4.is_link_local() {
+            true => SCOPE_LINK_LOCAL,
+            false => SCOPE_GLOBAL,
+        };
+    }
+
+    match segments[0] & 0xffc0 {
+        _ if addr.is_loopback() => SCOPE_LINK_LOCAL,
+        0xfe80 => SCOPE_LINK_LOCAL,
+        0xfec0 => SCOPE_SITE_LOCAL,
+        _ => SCOPE_GLOBAL,
+    }
+}
+
+/// Returns the precedence and label of an address, from the default policy table of section 2.1
+fn policy(addr: Ipv6Addr) -> (u8, u8) {
+    const POLICY_TABLE: [(Ipv6Addr, u32, u8, u8); 9] = [
+        (Ipv6Addr::LOCALHOST, 128, 50, 0),
+        (Ipv6Addr::new(0, 0, 0, 0, 0, 0xffff, 0, 0), 96, 35, 4),
+        (Ipv6Addr::UNSPECIFIED, 96, 1, 3),
+        (Ipv6Addr::new(0x2001, 0, 0, 0, 0, 0, 0, 0), 32, 5, 5),
+        (Ipv6Addr::new(0x2002, 0, 0, 0, 0, 0, 0, 0), 16, 30, 2),
+        (Ipv6Addr::new(0x3ffe, 0, 0, 0, 0, 0, 0, 0), 16, 1, 12),
+        (Ipv6Addr::new(0xfec0, 0, 0, 0, 0, 0, 0, 0), 10, 1, 11),
+        (Ipv6Addr::new(0xfc00, 0, 0, 0, 0, 0, 0, 0), 7, 3, 13),
+        (Ipv6Addr::UNSPECIFIED, 0, 40, 1),
+    ];
+
+    // the table is ordered by decreasing prefix length, the first match is the longest
+    let addr = u128::from(addr);
+    POLICY_TABLE
+        .iter()
+        .find(|(prefix, len, ..)| {
+            let mask = u128::MAX.checked_shl(128 - len).unwrap_or(0);
+            addr & mask == u128::from(*prefix)
+        })
+        .map(|&(_, _, precedence, label)| (precedence, label))
+        .unwrap_or((40, 1))
+}
+
+/// Returns the length of the common prefix of two addresses of the same family
+///
+/// For IPv6, only the 64 bits of the network prefix are compared, as the interface identifier does
+/// not say anything about the proximity of the addresses.
+fn common_prefix_len(source: IpAddr, destination: IpAddr) -> u32 {
+    match (source, destination) {
+        (IpAddr::V4(source), IpAddr::V4(destination)) => {
+            (u32::from(source) ^ u32::from(destination)).leading_zeros()
+        }
+        (IpAddr::V6(source), IpAddr::V6(destination)) => (u128::from(source)
+            ^ u128::from(destination))
+        .leading_zeros()
+        .min(64),
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sort(addrs: &[&str], sources: &[(&str, &str)]) -> Vec<String> {
+        let mut addrs = addrs
+            .iter()
+            .map(|addr| addr.parse().unwrap())
+            .collect::<Vec<IpAddr>>();
+        let source = |addr: IpAddr| {
+            sources
+                .iter()
+                .find(|(destination, _)| destination.parse::<IpAddr>().unwrap() == addr)
+                .map(|(_, source)| source.parse().unwrap())
+        };
+
+        sort_destinations_with(&mut addrs, source);
+        addrs.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_source_addr_cache() {
+        let cache = SourceAddrCache::default();
+        let lookups = std::cell::Cell::new(0);
+        let lookup = |addr: IpAddr| {
+            lookups.set(lookups.get() + 1);
+            Some(match addr {
+                IpAddr::V4(_) => "192.0.2.1".parse().unwrap(),
+                IpAddr::V6(_) => "2001:db8::1".parse().unwrap(),
+            })
+        };
+
+        let now = Instant::now();
+        let source = cache.get("198.51.100.1".parse().unwrap(), now, lookup);
+        assert_eq!(source, Some("192.0.2.1".parse().unwrap()));
+        assert_eq!(lookups.get(), 1);
+
+        // the same network is cached
+        cache.get("198.51.100.200".parse().unwrap(), now, lookup);
+        cache.get("2001:db8:1:2::1".parse().unwrap(), now, lookup);
+        cache.get("2001:db8:1:2::2".parse().unwrap(), now, lookup);
+        assert_eq!(lookups.get(), 2);
+
+        // another network, or an expired entry, is looked up again
+        cache.get("198.51.101.1".parse().unwrap(), now, lookup);
+        assert_eq!(lookups.get(), 3);
+        cache.get(
+            "198.51.100.1".parse().unwrap(),
+            now + SOURCE_ADDR_TTL,
+            lookup,
+        );
+        assert_eq!(lookups.get(), 4);
+    }
+
+    // the examples of section 10.2 of RFC 6724
+
+    #[test]
+    fn test_prefer_matching_scope() {
+        let sorted = sort(
+            &["2001:db8:1::1", "198.51.100.121"],
+            &[
+                ("2001:db8:1::1", "2001:db8:1::2"),
+                ("198.51.100.121", "169.254.13.78"),
+            ],
+        );
+        assert_eq!(sorted, ["2001:db8:1::1", "198.51.100.121"]);
+
+        let sorted = sort(
+            &["2001:db8:1::1", "198.51.100.121"],
+            &[
+                ("2001:db8:1::1", "fe80::1"),
+                ("198.51.100.121", "198.51.100.117"),
+            ],
+        );
+        assert_eq!(sorted, ["198.51.100.121", "2001:db8:1::1"]);
+    }
+
+    #[test]
+    fn test_prefer_higher_precedence() {
+        let sorted = sort(
+            &["198.51.100.121", "2001:db8:1::1"],
+            &[
+                ("2001:db8:1::1", "2001:db8:1::2"),
+                ("198.51.100.121", "198.51.100.117"),
+            ],
+        );
+        assert_eq!(sorted, ["2001:db8:1::1", "198.51.100.121"]);
+    }
+
+    #[test]
+    fn test_prefer_matching_label() {
+        let sorted = sort(
+            &["2002:c633:6401::1", "2001:db8:1::1"],
+            &[
+                ("2002:c633:6401::1", "2002:c633:6401::2"),
+                ("2001:db8:1::1", "2002:c633:6401::2"),
+            ],
+        );
+        assert_eq!(sorted, ["2002:c633:6401::1", "2001:db8:1::1"]);
+    }
+
+    #[test]
+    fn test_prefer_smaller_scope() {
+        let sorted = sort(
+            &["2001:db8:1::1", "fe80::1"],
+            &[("2001:db8:1::1", "2001:db8:1::2"), ("fe80::1", "fe80::2")],
+        );
+        assert_eq!(sorted, ["fe80::1", "2001:db8:1::1"]);
+    }
+
+    #[test]
+    fn test_avoid_unusable_destinations() {
+        let sorted = sort(
+            &["2001:db8:1::1", "198.51.100.121"],
+            &[("198.51.100.121", "198.51.100.117")],
+        );
+        assert_eq!(sorted, ["198.51.100.121", "2001:db8:1::1"]);
+    }
+
+    #[test]
+    fn test_longest_matching_prefix() {
+        let sorted = sort(
+            &["2001:db8:2::1", "2001:db8:1::1"],
+            &[
+                ("2001:db8:2::1", "2001:db8:1::2"),
+                ("2001:db8:1::1", "2001:db8:1::2"),
+            ],
+        );
+        assert_eq!(sorted, ["2001:db8:1::1", "2001:db8:2::1"]);
+    }
+}
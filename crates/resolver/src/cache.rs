@@ -2,12 +2,17 @@
 
 use std::{
     collections::HashMap,
+    net::IpAddr,
     ops::RangeInclusive,
-    sync::Arc,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
     time::{Duration, Instant},
 };
 
-use moka::{Expiry, sync::Cache};
+use ipnet::IpNet;
+use moka::{Expiry, notification::RemovalCause, sync::Cache};
 #[cfg(feature = "serde")]
 use serde::Deserialize;
 
@@ -15,14 +20,20 @@ use crate::config;
 use crate::proto::{
     DnsError, NoRecords, ProtoError, ProtoErrorKind,
     op::{Message, Query},
-    rr::RecordType,
+    rr::{Name, RecordType, rdata::opt::ClientSubnet},
 };
 
 /// A cache for DNS responses.
+///
+/// Responses to queries with an EDNS Client Subnet option can be cached for the network given by
+/// the scope of the response, see [`Self::insert_for_subnet()`].
 #[derive(Clone, Debug)]
 pub struct ResponseCache {
-    cache: Cache<Query, Entry>,
+    cache: Cache<CacheKey, Entry>,
     ttl_config: Arc<TtlConfig>,
+    counters: Arc<Counters>,
+    /// Whether any response was cached for a client subnet
+    has_subnets: Arc<AtomicBool>,
 }
 
 impl ResponseCache {
@@ -33,17 +44,58 @@ impl ResponseCache {
     /// * `capacity` - size in number of cached responses
     /// * `ttl_config` - minimum and maximum TTLs for cached records
     pub fn new(capacity: u64, ttl_config: TtlConfig) -> Self {
+        let counters = Arc::new(Counters::default());
+        let eviction_counters = counters.clone();
         Self {
             cache: Cache::builder()
                 .max_capacity(capacity)
                 .expire_after(EntryExpiry)
+                .eviction_listener(move |_key, _value, cause: RemovalCause| {
+                    if cause.was_evicted() {
+                        eviction_counters.evictions.fetch_add(1, Ordering::Relaxed);
+                    }
+                })
                 .build(),
             ttl_config: Arc::new(ttl_config),
+            counters,
+            has_subnets: Arc::default(),
         }
     }
 
     /// Insert a response into the cache.
     pub fn insert(&self, query: Query, result: Result<Message, ProtoError>, now: Instant) {
+        self.insert_key(
+            CacheKey {
+                query,
+                subnet: None,
+            },
+            result,
+            now,
+        );
+    }
+
+    /// Insert the response to a query sent with the client subnet `subnet`.
+    ///
+    /// The response is cached for the network made of the first `scope_prefix` bits of the address
+    /// of `subnet`, or its `source_prefix` bits if that is smaller. With a prefix of 0, the
+    /// response is cached for all clients, as with [`Self::insert()`].
+    pub fn insert_for_subnet(
+        &self,
+        query: Query,
+        subnet: &ClientSubnet,
+        result: Result<Message, ProtoError>,
+        now: Instant,
+    ) {
+        let prefix_len = subnet.scope_prefix().min(subnet.source_prefix());
+        let subnet = network(subnet.addr(), prefix_len);
+        if subnet.is_some() {
+            self.has_subnets.store(true, Ordering::Relaxed);
+        }
+        self.insert_key(CacheKey { query, subnet }, result, now);
+    }
+
+    fn insert_key(&self, key: CacheKey, result: Result<Message, ProtoError>, now: Instant) {
+        let query = &key.query;
         let ttl = match &result {
             Ok(message) => {
                 let (positive_min_ttl, positive_max_ttl) = self
@@ -74,7 +126,7 @@ impl ResponseCache {
         };
         let valid_until = now + ttl;
         self.cache.insert(
-            query,
+            key,
             Entry {
                 result: Arc::new(result),
                 original_time: now,
@@ -84,17 +136,180 @@ impl ResponseCache {
     }
 
     /// Try to retrieve a cached response with the given query.
+    ///
+    /// Only responses cached for all clients are returned.
     pub fn get(&self, query: &Query, now: Instant) -> Option<Result<Message, ProtoError>> {
-        let entry = self.cache.get(query)?;
-        if !entry.is_current(now) {
-            return None;
+        self.get_key(&CacheKey::from(query.clone()), now)
+    }
+
+    /// Try to retrieve a cached response with the given query, for a client in `subnet`.
+    ///
+    /// The response cached for the most specific network containing the first `source_prefix`
+    /// bits of the address of `subnet` is returned, falling back to a response cached for all
+    /// clients.
+    pub fn get_for_subnet(
+        &self,
+        query: &Query,
+        subnet: &ClientSubnet,
+        now: Instant,
+    ) -> Option<Result<Message, ProtoError>> {
+        let mut key = CacheKey::from(query.clone());
+        if self.has_subnets.load(Ordering::Relaxed) {
+            for prefix_len in (1..=subnet.source_prefix()).rev() {
+                key.subnet = network(subnet.addr(), prefix_len);
+                if let Some(entry) = self.cache.get(&key).filter(|entry| entry.is_current(now)) {
+                    self.counters.hits.fetch_add(1, Ordering::Relaxed);
+                    return Some(entry.updated_ttl(now));
+                }
+            }
         }
+
+        key.subnet = None;
+        self.get_key(&key, now)
+    }
+
+    fn get_key(&self, key: &CacheKey, now: Instant) -> Option<Result<Message, ProtoError>> {
+        let Some(entry) = self.cache.get(key).filter(|entry| entry.is_current(now)) else {
+            self.counters.misses.fetch_add(1, Ordering::Relaxed);
+            return None;
+        };
+
+        self.counters.hits.fetch_add(1, Ordering::Relaxed);
         Some(entry.updated_ttl(now))
     }
 
-    pub(crate) fn clear(&self) {
+    /// Returns a snapshot of all current entries in the cache.
+    ///
+    /// TTLs of the returned responses, and the remaining TTL of each entry, are adjusted to `now`.
+    /// Entries that have already expired, but have not yet been evicted, are skipped.
+    pub fn entries(&self, now: Instant) -> Vec<CacheEntry> {
+        self.cache
+            .iter()
+            .filter(|(_, entry)| entry.is_current(now))
+            .map(|(key, entry)| CacheEntry {
+                query: key.query.clone(),
+                client_subnet: key.subnet,
+                ttl: entry.ttl(now),
+                result: entry.updated_ttl(now),
+            })
+            .collect()
+    }
+
+    /// Remove the cached responses for exactly this query, if any, for all client subnets.
+    ///
+    /// Returns `true` if an entry was removed.
+    pub fn remove(&self, query: &Query) -> bool {
+        let mut removed = self.cache.remove(&CacheKey::from(query.clone())).is_some();
+        if self.has_subnets.load(Ordering::Relaxed) {
+            let keys = self
+                .cache
+                .iter()
+                .filter(|(key, _)| key.subnet.is_some() && key.query == *query)
+                .map(|(key, _)| key)
+                .collect::<Vec<_>>();
+            for key in keys {
+                removed |= self.cache.remove(&*key).is_some();
+            }
+        }
+        removed
+    }
+
+    /// Remove all cached responses for `name` and any of its subdomains, regardless of query type.
+    ///
+    /// Returns the number of entries removed.
+    pub fn remove_subtree(&self, name: &Name) -> usize {
+        let keys = self
+            .cache
+            .iter()
+            .filter(|(key, _)| name.zone_of(key.query.name()))
+            .map(|(key, _)| key)
+            .collect::<Vec<_>>();
+
+        keys.into_iter()
+            .filter(|key| self.cache.remove(&**key).is_some())
+            .count()
+    }
+
+    /// Remove all entries from the cache.
+    pub fn clear(&self) {
         self.cache.invalidate_all();
     }
+
+    /// Returns the hit, miss and eviction counts of this cache since it was created.
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.counters.hits.load(Ordering::Relaxed),
+            misses: self.counters.misses.load(Ordering::Relaxed),
+            evictions: self.counters.evictions.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A snapshot of an entry in the [`ResponseCache`], as returned by [`ResponseCache::entries`].
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct CacheEntry {
+    /// The query this entry is cached under.
+    pub query: Query,
+    /// The client network this entry is cached for, or `None` if it applies to all clients.
+    pub client_subnet: Option<ClientSubnet>,
+    /// The remaining time until this entry expires.
+    pub ttl: Duration,
+    /// The cached response (or error), with record TTLs reduced by the time spent in the cache.
+    pub result: Result<Message, ProtoError>,
+}
+
+/// Usage counters for a [`ResponseCache`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct CacheStats {
+    /// Number of lookups answered from the cache.
+    pub hits: u64,
+    /// Number of lookups for which no current entry was cached.
+    pub misses: u64,
+    /// Number of entries dropped because they expired or the cache was full.
+    ///
+    /// Explicit removals, such as [`ResponseCache::remove`] or [`ResponseCache::clear`], are not
+    /// counted.
+    pub evictions: u64,
+}
+
+/// The key of a cached response
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct CacheKey {
+    query: Query,
+    /// The client network the response applies to, truncated to its prefix
+    subnet: Option<ClientSubnet>,
+}
+
+impl From<Query> for CacheKey {
+    fn from(query: Query) -> Self {
+        Self {
+            query,
+            subnet: None,
+        }
+    }
+}
+
+/// Returns the network of the first `prefix_len` bits of `address`, or `None` for a prefix of 0
+fn network(address: IpAddr, prefix_len: u8) -> Option<ClientSubnet> {
+    let max_len = match address {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    };
+    match prefix_len {
+        0 => None,
+        _ => IpNet::new(address, prefix_len.min(max_len))
+            .ok()
+            .map(|net| ClientSubnet::from(net.trunc())),
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
 }
 
 /// An entry in the response cache.
@@ -155,10 +370,10 @@ impl Entry {
 
 struct EntryExpiry;
 
-impl Expiry<Query, Entry> for EntryExpiry {
+impl Expiry<CacheKey, Entry> for EntryExpiry {
     fn expire_after_create(
         &self,
-        _key: &Query,
+        _key: &CacheKey,
         value: &Entry,
         created_at: Instant,
     ) -> Option<Duration> {
@@ -167,7 +382,7 @@ impl Expiry<Query, Entry> for EntryExpiry {
 
     fn expire_after_update(
         &self,
-        _key: &Query,
+        _key: &CacheKey,
         value: &Entry,
         updated_at: Instant,
         _duration_until_expiry: Option<Duration>,
@@ -413,7 +628,11 @@ mod tests {
         let cache = ResponseCache::new(1, ttls);
 
         cache.insert(query.clone(), Ok(message), now);
-        let valid_until = cache.cache.get(&query).unwrap().valid_until;
+        let valid_until = cache
+            .cache
+            .get(&CacheKey::from(query.clone()))
+            .unwrap()
+            .valid_until;
         // The returned lookup should use the cache's minimum TTL, since the
         // query's TTL was below the minimum.
         assert_eq!(valid_until, now + Duration::from_secs(2));
@@ -427,7 +646,11 @@ mod tests {
         ));
 
         cache.insert(query.clone(), Ok(message), now);
-        let valid_until = cache.cache.get(&query).unwrap().valid_until;
+        let valid_until = cache
+            .cache
+            .get(&CacheKey::from(query.clone()))
+            .unwrap()
+            .valid_until;
         // The returned lookup should use the record's TTL, since it's
         // greater than the cache's minimum.
         assert_eq!(valid_until, now + Duration::from_secs(3));
@@ -451,7 +674,11 @@ mod tests {
         let mut no_records = NoRecords::new(query.clone(), ResponseCode::NoError);
         no_records.negative_ttl = Some(1);
         cache.insert(query.clone(), Err(no_records.into()), now);
-        let valid_until = cache.cache.get(&query).unwrap().valid_until;
+        let valid_until = cache
+            .cache
+            .get(&CacheKey::from(query.clone()))
+            .unwrap()
+            .valid_until;
         // The error's `valid_until` field should have been limited to 2 seconds.
         assert_eq!(valid_until, now + Duration::from_secs(2));
 
@@ -459,7 +686,11 @@ mod tests {
         let mut no_records = NoRecords::new(query.clone(), ResponseCode::NoError);
         no_records.negative_ttl = Some(3);
         cache.insert(query.clone(), Err(no_records.into()), now);
-        let valid_until = cache.cache.get(&query).unwrap().valid_until;
+        let valid_until = cache
+            .cache
+            .get(&CacheKey::from(query.clone()))
+            .unwrap()
+            .valid_until;
         // The error's `valid_until` field should not have been limited, as it was over the minimum
         // TTL.
         assert_eq!(valid_until, now + Duration::from_secs(3));
@@ -487,7 +718,11 @@ mod tests {
         let cache = ResponseCache::new(1, ttls);
 
         cache.insert(query.clone(), Ok(message), now);
-        let valid_until = cache.cache.get(&query).unwrap().valid_until;
+        let valid_until = cache
+            .cache
+            .get(&CacheKey::from(query.clone()))
+            .unwrap()
+            .valid_until;
         // The returned lookup should use the cache's minimum TTL, since the
         // query's TTL was above the maximum.
         assert_eq!(valid_until, now + Duration::from_secs(60));
@@ -501,7 +736,11 @@ mod tests {
         ));
 
         cache.insert(query.clone(), Ok(message), now);
-        let valid_until = cache.cache.get(&query).unwrap().valid_until;
+        let valid_until = cache
+            .cache
+            .get(&CacheKey::from(query.clone()))
+            .unwrap()
+            .valid_until;
         // The returned lookup should use the record's TTL, since it's
         // below than the cache's maximum.
         assert_eq!(valid_until, now + Duration::from_secs(59));
@@ -525,7 +764,11 @@ mod tests {
         let mut no_records = NoRecords::new(query.clone(), ResponseCode::NoError);
         no_records.negative_ttl = Some(62);
         cache.insert(query.clone(), Err(no_records.into()), now);
-        let valid_until = cache.cache.get(&query).unwrap().valid_until;
+        let valid_until = cache
+            .cache
+            .get(&CacheKey::from(query.clone()))
+            .unwrap()
+            .valid_until;
         // The error's `valid_until` field should have been limited to 60 seconds.
         assert_eq!(valid_until, now + Duration::from_secs(60));
 
@@ -533,7 +776,11 @@ mod tests {
         let mut no_records = NoRecords::new(query.clone(), ResponseCode::NoError);
         no_records.negative_ttl = Some(59);
         cache.insert(query.clone(), Err(no_records.into()), now);
-        let valid_until = cache.cache.get(&query).unwrap().valid_until;
+        let valid_until = cache
+            .cache
+            .get(&CacheKey::from(query.clone()))
+            .unwrap()
+            .valid_until;
         // The error's `valid_until` field should not have been limited, as it was under the maximum
         // TTL.
         assert_eq!(valid_until, now + Duration::from_secs(59));
@@ -644,7 +891,11 @@ mod tests {
         // This should use the cache's default minimum TTL, since the record's TTL was below the
         // minimum.
         assert_eq!(
-            cache.cache.get(&query_a).unwrap().valid_until,
+            cache
+                .cache
+                .get(&CacheKey::from(query_a.clone()))
+                .unwrap()
+                .valid_until,
             now + Duration::from_secs(2)
         );
 
@@ -652,7 +903,11 @@ mod tests {
         // This should use the minimum for TTL records, since the record's TTL was below the
         // minimum.
         assert_eq!(
-            cache.cache.get(&query_txt).unwrap().valid_until,
+            cache
+                .cache
+                .get(&CacheKey::from(query_txt.clone()))
+                .unwrap()
+                .valid_until,
             now + Duration::from_secs(5)
         );
 
@@ -666,18 +921,179 @@ mod tests {
         cache.insert(query_a.clone(), Ok(message_a), now);
         // This should use the record's TTL, since it's greater than the default minimum TTL.
         assert_eq!(
-            cache.cache.get(&query_a).unwrap().valid_until,
+            cache
+                .cache
+                .get(&CacheKey::from(query_a.clone()))
+                .unwrap()
+                .valid_until,
             now + Duration::from_secs(7)
         );
 
         cache.insert(query_txt.clone(), Ok(message_txt), now);
         // This should use the record's TTL, since it's greater than the minimum TTL for TXT records.
         assert_eq!(
-            cache.cache.get(&query_txt).unwrap().valid_until,
+            cache
+                .cache
+                .get(&CacheKey::from(query_txt.clone()))
+                .unwrap()
+                .valid_until,
             now + Duration::from_secs(7)
         );
     }
 
+    #[test]
+    fn test_entries() {
+        let now = Instant::now();
+
+        let name = Name::from_str("www.example.com.").unwrap();
+        let query = Query::query(name.clone(), RecordType::A);
+        let mut message = Message::response(0, OpCode::Query);
+        message.add_answer(Record::from_rdata(name, 10, RData::A(A::new(127, 0, 0, 1))));
+        let cache = ResponseCache::new(2, TtlConfig::default());
+        cache.insert(query.clone(), Ok(message), now);
+
+        let entries = cache.entries(now + Duration::from_secs(3));
+        assert_eq!(entries.len(), 1);
+        let entry = &entries[0];
+        assert_eq!(entry.query, query);
+        assert_eq!(entry.ttl, Duration::from_secs(7));
+        let message = entry.result.as_ref().unwrap();
+        assert_eq!(message.answers()[0].ttl(), 7);
+
+        // Expired entries are not listed.
+        assert!(cache.entries(now + Duration::from_secs(11)).is_empty());
+    }
+
+    #[test]
+    fn test_remove() {
+        let now = Instant::now();
+        let cache = ResponseCache::new(4, TtlConfig::default());
+        let a = cached_query(&cache, "www.example.com.", RecordType::A, now);
+        let aaaa = cached_query(&cache, "www.example.com.", RecordType::AAAA, now);
+
+        assert!(cache.remove(&a));
+        assert!(!cache.remove(&a));
+        assert!(cache.get(&a, now).is_none());
+        assert!(cache.get(&aaaa, now).is_some());
+    }
+
+    #[test]
+    fn test_remove_subtree() {
+        let now = Instant::now();
+        let cache = ResponseCache::new(8, TtlConfig::default());
+        let apex = cached_query(&cache, "example.com.", RecordType::A, now);
+        let www = cached_query(&cache, "www.example.com.", RecordType::A, now);
+        let deep = cached_query(&cache, "a.b.Example.COM.", RecordType::TXT, now);
+        let other = cached_query(&cache, "example.org.", RecordType::A, now);
+        let suffix = cached_query(&cache, "notexample.com.", RecordType::A, now);
+
+        let removed = cache.remove_subtree(&Name::from_str("example.com.").unwrap());
+        assert_eq!(removed, 3);
+        for query in [&apex, &www, &deep] {
+            assert!(cache.get(query, now).is_none(), "{query} still cached");
+        }
+        for query in [&other, &suffix] {
+            assert!(cache.get(query, now).is_some(), "{query} was removed");
+        }
+    }
+
+    #[test]
+    fn test_client_subnet_scope() {
+        let now = Instant::now();
+        let name = Name::from_str("www.example.com.").unwrap();
+        let query = Query::query(name.clone(), RecordType::A);
+        let cache = ResponseCache::new(8, TtlConfig::default());
+        let response = |octet| {
+            let mut message = Message::response(0, OpCode::Query);
+            let rdata = RData::A(A::new(192, 0, 2, octet));
+            message.add_answer(Record::from_rdata(name.clone(), 60, rdata));
+            Ok(message)
+        };
+        let subnet = |s: &str, scope_prefix| {
+            let mut subnet = ClientSubnet::from_str(s).unwrap();
+            subnet.set_scope_prefix(scope_prefix);
+            subnet
+        };
+        let cached = |s| {
+            let message = cache.get_for_subnet(&query, &subnet(s, 0), now)?.unwrap();
+            Some(message.answers()[0].data().clone())
+        };
+
+        // scoped to the /16, which is shorter than the source prefix
+        cache.insert_for_subnet(
+            query.clone(),
+            &subnet("198.51.100.0/24", 16),
+            response(1),
+            now,
+        );
+        // the scope is limited to the source prefix
+        cache.insert_for_subnet(
+            query.clone(),
+            &subnet("203.0.113.0/24", 32),
+            response(2),
+            now,
+        );
+
+        assert_eq!(
+            cached("198.51.7.0/24"),
+            Some(RData::A(A::new(192, 0, 2, 1)))
+        );
+        assert_eq!(
+            cached("203.0.113.0/24"),
+            Some(RData::A(A::new(192, 0, 2, 2)))
+        );
+        assert_eq!(cached("192.0.2.0/24"), None);
+        assert!(cache.get(&query, now).is_none());
+
+        // a scope of 0 applies to all clients
+        cache.insert_for_subnet(query.clone(), &subnet("192.0.2.0/24", 0), response(3), now);
+        assert_eq!(cached("192.0.2.0/24"), Some(RData::A(A::new(192, 0, 2, 3))));
+        assert!(cache.get(&query, now).is_some());
+
+        assert_eq!(cache.entries(now).len(), 3);
+        assert!(cache.remove(&query));
+        assert!(cache.entries(now).is_empty());
+    }
+
+    #[test]
+    fn test_stats() {
+        let now = Instant::now();
+        let cache = ResponseCache::new(1, TtlConfig::default());
+        let query = cached_query(&cache, "www.example.com.", RecordType::A, now);
+
+        assert!(cache.get(&query, now).is_some());
+        assert!(cache.get(&query, now + Duration::from_secs(60)).is_none());
+        let missing = Query::query(Name::from_str("example.org.").unwrap(), RecordType::A);
+        assert!(cache.get(&missing, now).is_none());
+
+        // Exceeding the capacity evicts an entry.
+        cached_query(&cache, "www.example.net.", RecordType::A, now);
+        cache.cache.run_pending_tasks();
+
+        let stats = cache.stats();
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.misses, 2);
+        assert_eq!(stats.evictions, 1);
+    }
+
+    fn cached_query(
+        cache: &ResponseCache,
+        name: &str,
+        record_type: RecordType,
+        now: Instant,
+    ) -> Query {
+        let name = Name::from_str(name).unwrap();
+        let query = Query::query(name.clone(), record_type);
+        let rdata = match record_type {
+            RecordType::TXT => RData::TXT(TXT::new(vec!["data".to_string()])),
+            _ => RData::A(A::new(127, 0, 0, 1)),
+        };
+        let mut message = Message::response(0, OpCode::Query);
+        message.add_answer(Record::from_rdata(name, 10, rdata));
+        cache.insert(query.clone(), Ok(message), now);
+        query
+    }
+
     #[cfg(feature = "serde")]
     #[test]
     fn ttl_config_deserialize_errors() {
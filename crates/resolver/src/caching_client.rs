@@ -7,15 +7,22 @@
 
 //! Caching related functionality for the Resolver.
 
+#[cfg(feature = "metrics")]
+use std::fmt;
 use std::{
     borrow::Cow,
+    sync::Arc,
     time::{Duration, Instant},
 };
 
 use futures_util::future::BoxFuture;
 use hickory_proto::DnsError;
+#[cfg(feature = "metrics")]
+use metrics::{Counter, Unit, counter, describe_counter};
 use once_cell::sync::Lazy;
 
+#[cfg(feature = "__dnssec")]
+use crate::lookup::merge_validation_traces;
 use crate::{
     cache::{MAX_TTL, ResponseCache, TtlConfig},
     lookup::Lookup,
@@ -28,6 +35,7 @@ use crate::{
                 DEFAULT, IN_ADDR_ARPA_127, INVALID, IP6_ARPA_1, LOCAL,
                 LOCALHOST as LOCALHOST_usage, ONION, ResolverUsage,
             },
+            rdata::opt::ClientSubnet,
             rdata::{A, AAAA, CNAME, PTR},
             resource::RecordRef,
         },
@@ -69,6 +77,12 @@ where
     cache: ResponseCache,
     client: C,
     preserve_intermediates: bool,
+    read_cache: bool,
+    write_cache: bool,
+    /// Domains for which the client subnet of the request options is sent
+    client_subnet_domains: Arc<[Name]>,
+    #[cfg(feature = "metrics")]
+    metrics: CacheMetrics,
 }
 
 impl<C> CachingClient<C>
@@ -93,9 +107,43 @@ where
             cache,
             client,
             preserve_intermediates,
+            read_cache: true,
+            write_cache: true,
+            client_subnet_domains: Arc::from([]),
+            #[cfg(feature = "metrics")]
+            metrics: CacheMetrics::new(),
         }
     }
 
+    /// Returns a client sharing the cache of this one, which sends its requests to `client`
+    pub(crate) fn with_client<D: DnsHandle>(&self, client: D) -> CachingClient<D> {
+        CachingClient {
+            cache: self.cache.clone(),
+            client,
+            preserve_intermediates: self.preserve_intermediates,
+            read_cache: self.read_cache,
+            write_cache: self.write_cache,
+            client_subnet_domains: self.client_subnet_domains.clone(),
+            #[cfg(feature = "metrics")]
+            metrics: self.metrics.clone(),
+        }
+    }
+
+    /// Sets whether lookups are answered from the cache, and whether responses are stored in it
+    pub(crate) fn with_cache_access(mut self, read: bool, write: bool) -> Self {
+        self.read_cache = read;
+        self.write_cache = write;
+        self
+    }
+
+    /// Sets the domains for which the client subnet of the request options is sent
+    ///
+    /// For other names, the client subnet is removed from the request.
+    pub(crate) fn with_client_subnet_domains(mut self, domains: &[Name]) -> Self {
+        self.client_subnet_domains = Arc::from(domains);
+        self
+    }
+
     /// Perform a lookup against this caching client, looking first in the cache for a result
     pub fn lookup(
         &self,
@@ -160,16 +208,43 @@ where
 
         let is_dnssec = client.client.is_verifying_dnssec();
 
-        if let Some(cached_lookup) = client.lookup_from_cache(&query) {
+        if let Some(cached_lookup) = client.lookup_from_cache(&query, options.client_subnet) {
             return cached_lookup;
         };
 
+        // the options are passed on unchanged to the lookups of CNAME targets, which may be in
+        // one of the client subnet domains
+        let mut request_options = options;
+        if !client
+            .client_subnet_domains
+            .iter()
+            .any(|domain| domain.zone_of(query.name()))
+        {
+            request_options.client_subnet = None;
+        }
+
         let response_message = client
             .client
-            .lookup(query.clone(), options)
+            .lookup(query.clone(), request_options)
             .first_answer()
             .await;
 
+        // the scope of the response is the network it is valid for, RFC 7871 section 7.3.1
+        let scope = request_options.client_subnet.map(|mut subnet| {
+            let scope_prefix = match &response_message {
+                Ok(response) => ClientSubnet::response_scope(response),
+                Err(_) => 0,
+            };
+            subnet.set_scope_prefix(scope_prefix);
+            subnet
+        });
+
+        #[cfg(feature = "__dnssec")]
+        let validation_trace = match &response_message {
+            Ok(response) => response.validation_trace().cloned(),
+            Err(_) => None,
+        };
+
         // TODO: technically this might be duplicating work, as name_server already performs this evaluation.
         //  we may want to create a new type, if evaluated... but this is most generic to support any impl in LookupState...
         let response_message = if let Ok(response) = response_message {
@@ -208,20 +283,55 @@ where
         };
 
         // after the request, evaluate if we have additional queries to perform
-        match records {
+        // the scope of the rest of a CNAME chain is unknown, so chains are only cached for the
+        // subnet of the client
+        let chain_scope = options.client_subnet.map(|mut subnet| {
+            subnet.set_scope_prefix(subnet.source_prefix());
+            subnet
+        });
+        let lookup = match records {
             Ok(Records::CnameChain { next: future }) => match future.await {
-                Ok(lookup) => client.cname(lookup, query),
-                Err(e) => client.cache(query, Err(e)),
+                Ok(lookup) => client.cname(lookup, query, chain_scope)?,
+                Err(e) => return client.cache(query, Err(e), chain_scope),
             },
-            Ok(Records::Exists(rdata)) => client.cache(query, Ok(rdata)),
-            Err(e) => client.cache(query, Err(e)),
-        }
+            Ok(Records::Exists(rdata)) => client.cache(query, Ok(rdata), scope)?,
+            Err(e) => return client.cache(query, Err(e), scope),
+        };
+
+        // the trace of this response comes before the one of the rest of the CNAME chain
+        #[cfg(feature = "__dnssec")]
+        let lookup = {
+            let trace =
+                merge_validation_traces(validation_trace.as_ref(), lookup.validation_trace());
+            lookup.with_validation_trace(trace)
+        };
+
+        Ok(lookup)
     }
 
-    /// Check if this query is already cached
-    fn lookup_from_cache(&self, query: &Query) -> Option<Result<Lookup, ProtoError>> {
+    /// Check if this query is already cached, for a client in `subnet`
+    fn lookup_from_cache(
+        &self,
+        query: &Query,
+        subnet: Option<ClientSubnet>,
+    ) -> Option<Result<Lookup, ProtoError>> {
+        if !self.read_cache {
+            return None;
+        }
+
         let now = Instant::now();
-        let message_res = self.cache.get(query, now)?;
+        let cached = match &subnet {
+            Some(subnet) => self.cache.get_for_subnet(query, subnet, now),
+            None => self.cache.get(query, now),
+        };
+        let Some(message_res) = cached else {
+            #[cfg(feature = "metrics")]
+            self.metrics.cache_miss_counter.increment(1);
+            return None;
+        };
+
+        #[cfg(feature = "metrics")]
+        self.metrics.cache_hit_counter.increment(1);
         let message = match message_res {
             Ok(message) => message,
             Err(err) => return Some(Err(err)),
@@ -379,10 +489,17 @@ where
     }
 
     #[allow(clippy::unnecessary_wraps)]
-    fn cname(&self, lookup: Lookup, query: Query) -> Result<Lookup, ProtoError> {
-        let mut message = Message::response(0, OpCode::Query);
-        message.add_answers(lookup.records().iter().cloned());
-        self.cache.insert(query, Ok(message), Instant::now());
+    fn cname(
+        &self,
+        lookup: Lookup,
+        query: Query,
+        scope: Option<ClientSubnet>,
+    ) -> Result<Lookup, ProtoError> {
+        if self.write_cache {
+            let mut message = Message::response(0, OpCode::Query);
+            message.add_answers(lookup.records().iter().cloned());
+            self.insert(query, Ok(message), scope, Instant::now());
+        }
         Ok(lookup)
     }
 
@@ -390,11 +507,14 @@ where
         &self,
         query: Query,
         records: Result<Vec<Record>, ProtoError>,
+        scope: Option<ClientSubnet>,
     ) -> Result<Lookup, ProtoError> {
         let rdata = match records {
             Ok(rdata) => rdata,
             Err(err) => {
-                self.cache.insert(query, Err(err.clone()), Instant::now());
+                if self.write_cache {
+                    self.insert(query, Err(err.clone()), scope, Instant::now());
+                }
                 return Err(err);
             }
         };
@@ -402,17 +522,38 @@ where
         let now = Instant::now();
         let lookup = records_to_lookup(query.clone(), &rdata, now);
 
-        let mut message = Message::response(0, OpCode::Query);
-        message.add_answers(rdata);
-        self.cache.insert(query, Ok(message), now);
+        if self.write_cache {
+            let mut message = Message::response(0, OpCode::Query);
+            message.add_answers(rdata);
+            self.insert(query, Ok(message), scope, now);
+        }
 
         Ok(lookup)
     }
 
+    /// Insert a response into the cache, for the client network `scope` if set
+    fn insert(
+        &self,
+        query: Query,
+        result: Result<Message, ProtoError>,
+        scope: Option<ClientSubnet>,
+        now: Instant,
+    ) {
+        match scope {
+            Some(scope) => self.cache.insert_for_subnet(query, &scope, result, now),
+            None => self.cache.insert(query, result, now),
+        }
+    }
+
     /// Flushes/Removes all entries from the cache
     pub fn clear_cache(&self) {
         self.cache.clear();
     }
+
+    /// Returns the response cache, for inspection or selective removal of entries
+    pub fn response_cache(&self) -> &ResponseCache {
+        &self.cache
+    }
 }
 
 enum Records {
@@ -424,6 +565,43 @@ enum Records {
     },
 }
 
+/// Counters of the cache hits and misses of a [`CachingClient`]
+#[cfg(feature = "metrics")]
+#[derive(Clone)]
+struct CacheMetrics {
+    cache_hit_counter: Counter,
+    cache_miss_counter: Counter,
+}
+
+#[cfg(feature = "metrics")]
+impl CacheMetrics {
+    fn new() -> Self {
+        let cache_hit_counter = counter!("hickory_resolver_cache_hit_total");
+        describe_counter!(
+            "hickory_resolver_cache_hit_total",
+            Unit::Count,
+            "Number of queries answered from the cache."
+        );
+        let cache_miss_counter = counter!("hickory_resolver_cache_miss_total");
+        describe_counter!(
+            "hickory_resolver_cache_miss_total",
+            Unit::Count,
+            "Number of queries that could not be answered from the cache."
+        );
+        Self {
+            cache_hit_counter,
+            cache_miss_counter,
+        }
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl fmt::Debug for CacheMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CacheMetrics").finish_non_exhaustive()
+    }
+}
+
 /// Helper function to construct a [`Lookup`] from a list of records.
 fn records_to_lookup(query: Query, records: &[Record], now: Instant) -> Lookup {
     let ttl = records.iter().map(Record::ttl).min().unwrap_or(MAX_TTL);
@@ -549,6 +727,51 @@ mod tests {
         );
     }
 
+    fn cached_a(cache: &ResponseCache, query: &Query) -> Vec<RData> {
+        let message = cache.get(query, Instant::now()).unwrap().unwrap();
+        message.answers().iter().map(|r| r.data().clone()).collect()
+    }
+
+    #[test]
+    fn test_cache_refresh_and_bypass() {
+        subscribe();
+        let cache = ResponseCache::new(1, TtlConfig::default());
+        let query = Query::query(Name::root(), RecordType::A);
+        let mut message = Message::response(0, OpCode::Query);
+        message.add_answer(Record::from_rdata(
+            Name::root(),
+            u32::MAX,
+            RData::A(A::new(127, 0, 0, 2)),
+        ));
+        cache.insert(query.clone(), Ok(message), Instant::now());
+
+        // bypassing the cache neither reads nor updates it
+        let client = CachingClient::with_cache(cache.clone(), mock(vec![v4_message()]), false)
+            .with_cache_access(false, false);
+        let ips = block_on(client.lookup(query.clone(), DnsRequestOptions::default())).unwrap();
+        assert_eq!(
+            ips.iter().cloned().collect::<Vec<_>>(),
+            vec![RData::A(A::new(127, 0, 0, 1))]
+        );
+        assert_eq!(
+            cached_a(&cache, &query),
+            vec![RData::A(A::new(127, 0, 0, 2))]
+        );
+
+        // refreshing the cache replaces the entry
+        let client = CachingClient::with_cache(cache.clone(), mock(vec![v4_message()]), false)
+            .with_cache_access(false, true);
+        let ips = block_on(client.lookup(query.clone(), DnsRequestOptions::default())).unwrap();
+        assert_eq!(
+            ips.iter().cloned().collect::<Vec<_>>(),
+            vec![RData::A(A::new(127, 0, 0, 1))]
+        );
+        assert_eq!(
+            cached_a(&cache, &query),
+            vec![RData::A(A::new(127, 0, 0, 1))]
+        );
+    }
+
     #[allow(clippy::unnecessary_wraps)]
     pub(crate) fn cname_message() -> Result<DnsResponse, ProtoError> {
         let mut message = Message::query();
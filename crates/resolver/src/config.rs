@@ -14,12 +14,14 @@ use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 
+use ipnet::IpNet;
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
 #[cfg(any(feature = "__https", feature = "__h3"))]
 use crate::proto::http::DEFAULT_DNS_QUERY_PATH;
 use crate::proto::rr::Name;
+use crate::proto::rr::rdata::opt::ClientSubnet;
 use crate::proto::xfer::Protocol;
 
 /// Configuration for the upstream nameservers to use for resolution
@@ -35,6 +37,11 @@ pub struct ResolverConfig {
     pub search: Vec<Name>,
     /// Name servers to use for resolution
     pub name_servers: Vec<NameServerConfig>,
+    /// Conditional forwarding rules, sending queries for specific domains to other name servers
+    ///
+    /// Queries for names that are not covered by any rule are sent to `name_servers`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub forwarding_rules: Vec<ForwardingRule>,
 }
 
 impl ResolverConfig {
@@ -47,6 +54,7 @@ impl ResolverConfig {
             domain: None,
             search: vec![],
             name_servers: config.udp_and_tcp().collect(),
+            forwarding_rules: vec![],
         }
     }
 
@@ -60,6 +68,7 @@ impl ResolverConfig {
             domain: None,
             search: vec![],
             name_servers: config.tls().collect(),
+            forwarding_rules: vec![],
         }
     }
 
@@ -73,6 +82,7 @@ impl ResolverConfig {
             domain: None,
             search: vec![],
             name_servers: config.https().collect(),
+            forwarding_rules: vec![],
         }
     }
 
@@ -86,6 +96,7 @@ impl ResolverConfig {
             domain: None,
             search: vec![],
             name_servers: config.quic().collect(),
+            forwarding_rules: vec![],
         }
     }
 
@@ -99,6 +110,7 @@ impl ResolverConfig {
             domain: None,
             search: vec![],
             name_servers: config.h3().collect(),
+            forwarding_rules: vec![],
         }
     }
 
@@ -118,10 +130,13 @@ impl ResolverConfig {
             domain,
             search,
             name_servers,
+            forwarding_rules: vec![],
         }
     }
 
     /// Take the `domain`, `search`, and `name_servers` from the config.
+    ///
+    /// Any forwarding rules are discarded.
     pub fn into_parts(self) -> (Option<Name>, Vec<Name>, Vec<NameServerConfig>) {
         (self.domain, self.search, self.name_servers)
     }
@@ -161,6 +176,45 @@ impl ResolverConfig {
     pub fn name_servers(&self) -> &[NameServerConfig] {
         &self.name_servers
     }
+
+    /// Add a conditional forwarding rule
+    ///
+    /// Queries for `domain`, and any name below it, will be sent to `name_servers` instead of the
+    /// default name servers. If several rules match a name, the one with the longest domain wins.
+    pub fn add_forwarding_rule(&mut self, domain: Name, name_servers: Vec<NameServerConfig>) {
+        self.forwarding_rules
+            .push(ForwardingRule::new(domain, name_servers));
+    }
+
+    /// Returns a reference to the conditional forwarding rules
+    pub fn forwarding_rules(&self) -> &[ForwardingRule] {
+        &self.forwarding_rules
+    }
+}
+
+/// A conditional forwarding rule, mapping a domain suffix to a group of name servers
+#[derive(Clone, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(deny_unknown_fields)
+)]
+#[non_exhaustive]
+pub struct ForwardingRule {
+    /// The domain covered by this rule, including all names below it
+    pub domain: Name,
+    /// Name servers to use for names covered by this rule
+    pub name_servers: Vec<NameServerConfig>,
+}
+
+impl ForwardingRule {
+    /// Create a new forwarding rule for `domain`
+    pub fn new(domain: Name, name_servers: Vec<NameServerConfig>) -> Self {
+        Self {
+            domain,
+            name_servers,
+        }
+    }
 }
 
 /// Configuration for the NameServer
@@ -467,8 +521,27 @@ pub struct ResolverOpts {
     /// Use DNSSEC to validate the request
     #[cfg(feature = "__dnssec")]
     pub validate: bool,
+    /// Record the steps taken to validate the responses, see [`Lookup::validation_trace`]
+    ///
+    /// This has a cost for each lookup, and is meant for troubleshooting.
+    ///
+    /// [`Lookup::validation_trace`]: crate::lookup::Lookup::validation_trace
+    #[cfg(feature = "__dnssec")]
+    pub validation_trace: bool,
     /// The strategy for the Resolver to use when looking up host IP addresses
     pub ip_strategy: LookupIpStrategy,
+    /// Sort the addresses returned by `lookup_ip` in order of preference.
+    ///
+    /// The addresses are sorted with the destination address selection rules of
+    /// [RFC 6724](https://www.rfc-editor.org/rfc/rfc6724), based on the source address the host
+    /// would use to reach each of them. Disabled by default, which keeps the order of the
+    /// responses.
+    ///
+    /// Finding the source address for a destination costs a blocking UDP socket `bind` and
+    /// `connect`, which don't send any packet, on the thread running the lookup. The source
+    /// address is cached for a minute for each destination /24 (IPv4) or /64 (IPv6) network, so
+    /// this cost is only paid by the first lookups of a network.
+    pub sort_addresses: bool,
     /// Cache size is in number of responses (some responses can be large)
     #[cfg_attr(feature = "serde", serde(default = "default_cache_size"))]
     pub cache_size: u64,
@@ -541,6 +614,26 @@ pub struct ResolverOpts {
     ///
     /// If this is provided, `validate` will automatically be set to `true`, enabling DNSSEC validation.
     pub trust_anchor: Option<PathBuf>,
+    /// Domains for which DNSSEC validation is disabled, see [`NegativeTrustAnchorConfig`].
+    ///
+    /// The anchors can be changed at runtime with [`Resolver::negative_trust_anchors()`].
+    ///
+    /// [`Resolver::negative_trust_anchors()`]: crate::Resolver::negative_trust_anchors
+    #[cfg(feature = "__dnssec")]
+    pub negative_trust_anchors: Vec<NegativeTrustAnchorConfig>,
+    /// Take unresponsive name servers out of rotation.
+    ///
+    /// If this is set, a name server is skipped after a number of consecutive connection failures,
+    /// and re-admitted once a background probe gets a response from it. Disabled by default.
+    ///
+    /// The probes are spawned on the runtime of the connection provider, so building a resolver
+    /// with a connection provider without a runtime provider fails if this is set.
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+    /// Send the subnet of the client in queries, see [`ClientSubnetConfig`]. Disabled by default.
+    pub client_subnet: Option<ClientSubnetConfig>,
+    /// Discover encrypted resolvers designated by plain text name servers, see [`DdrPolicy`].
+    #[cfg(feature = "__tls")]
+    pub ddr: DdrPolicy,
 }
 
 impl Default for ResolverOpts {
@@ -555,7 +648,10 @@ impl Default for ResolverOpts {
             edns0: false,
             #[cfg(feature = "__dnssec")]
             validate: false,
+            #[cfg(feature = "__dnssec")]
+            validation_trace: false,
             ip_strategy: LookupIpStrategy::default(),
+            sort_addresses: false,
             cache_size: default_cache_size(),
             use_hosts_file: ResolveHosts::default(),
             positive_min_ttl: None,
@@ -574,6 +670,12 @@ impl Default for ResolverOpts {
             os_port_selection: false,
             case_randomization: false,
             trust_anchor: None,
+            #[cfg(feature = "__dnssec")]
+            negative_trust_anchors: Vec::new(),
+            circuit_breaker: None,
+            client_subnet: None,
+            #[cfg(feature = "__tls")]
+            ddr: DdrPolicy::default(),
         }
     }
 }
@@ -640,6 +742,189 @@ pub enum ServerOrderingStrategy {
     RoundRobin,
 }
 
+/// A negative trust anchor ([RFC 7646](https://www.rfc-editor.org/rfc/rfc7646))
+///
+/// Responses for `name` and its subdomains are treated as insecure rather than bogus, which keeps
+/// a domain with broken DNSSEC resolvable without disabling validation altogether.
+#[cfg(feature = "__dnssec")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(deny_unknown_fields)
+)]
+#[non_exhaustive]
+pub struct NegativeTrustAnchorConfig {
+    /// Domain for which validation is disabled
+    pub name: Name,
+    /// Time after which validation is enabled again, counted from the construction of the
+    /// resolver. RFC 7646 recommends no more than a week.
+    #[cfg_attr(feature = "serde", serde(with = "duration"))]
+    pub lifetime: Duration,
+}
+
+#[cfg(feature = "__dnssec")]
+impl NegativeTrustAnchorConfig {
+    /// Creates a negative trust anchor for `name`, expiring after `lifetime`
+    pub fn new(name: Name, lifetime: Duration) -> Self {
+        Self { name, lifetime }
+    }
+}
+
+/// Configuration of the per name server circuit breaker
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+#[non_exhaustive]
+pub struct CircuitBreakerConfig {
+    /// Number of consecutive connection failures (timeouts, I/O errors) after which the name
+    /// server is taken out of rotation. Defaults to 5.
+    pub failure_threshold: u32,
+    /// Time between probes sent to a name server that is out of rotation. Defaults to 5 seconds.
+    #[cfg_attr(feature = "serde", serde(with = "duration"))]
+    pub probe_interval: Duration,
+    /// What to do when every name server in a pool is out of rotation.
+    pub fallback: AllServersDown,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            probe_interval: Duration::from_secs(5),
+            fallback: AllServersDown::default(),
+        }
+    }
+}
+
+/// Behavior of a name server pool when the circuit of every name server is open
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[non_exhaustive]
+pub enum AllServersDown {
+    /// Ignore the circuit breakers and try all name servers anyway. This is the default.
+    #[default]
+    TryAll,
+    /// Fail the request immediately, without sending it to any name server.
+    Fail,
+}
+
+/// EDNS Client Subnet configuration ([RFC 7871](https://www.rfc-editor.org/rfc/rfc7871))
+///
+/// Queries for names in one of the `domains` carry a prefix of the client's address, or of the
+/// `fixed_address`, so that authoritative servers can tailor their answers to the location of the
+/// client. Responses are cached for the network given by the scope of the response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+#[non_exhaustive]
+pub struct ClientSubnetConfig {
+    /// Address sent in all queries instead of the address of the client, truncated to the prefix
+    /// length of its family.
+    pub fixed_address: Option<IpAddr>,
+    /// Number of leading bits of IPv4 addresses which are sent. Defaults to 24.
+    pub ipv4_prefix_len: u8,
+    /// Number of leading bits of IPv6 addresses which are sent. Defaults to 56.
+    pub ipv6_prefix_len: u8,
+    /// The client subnet is only sent in queries for these domains and their subdomains. Add the
+    /// root domain to send it in all queries.
+    pub domains: Vec<Name>,
+}
+
+impl ClientSubnetConfig {
+    /// Sends the client subnet in queries for `domains` and their subdomains
+    pub fn new(domains: Vec<Name>) -> Self {
+        Self {
+            domains,
+            ..Self::default()
+        }
+    }
+
+    /// Returns the client subnet sent for a client at `client`
+    ///
+    /// This is the fixed address if there is one, or else the address of the client, truncated to
+    /// the configured prefix length. Loopback, private and link-local client addresses are not
+    /// sent.
+    pub fn client_subnet(&self, client: Option<IpAddr>) -> Option<ClientSubnet> {
+        let address = match self.fixed_address {
+            Some(address) => address,
+            None => client.filter(|address| is_public(*address))?,
+        };
+
+        let prefix_len = match address {
+            IpAddr::V4(_) => self.ipv4_prefix_len.min(32),
+            IpAddr::V6(_) => self.ipv6_prefix_len.min(128),
+        };
+        let network = IpNet::new(address, prefix_len).ok()?.trunc();
+        Some(ClientSubnet::from(network))
+    }
+
+    /// Whether the client subnet is sent in queries for `name`
+    pub fn applies_to(&self, name: &Name) -> bool {
+        self.domains.iter().any(|domain| domain.zone_of(name))
+    }
+}
+
+impl Default for ClientSubnetConfig {
+    fn default() -> Self {
+        Self {
+            fixed_address: None,
+            ipv4_prefix_len: 24,
+            ipv6_prefix_len: 56,
+            domains: Vec::new(),
+        }
+    }
+}
+
+/// Whether `address` may be sent as a client subnet
+fn is_public(address: IpAddr) -> bool {
+    match address {
+        IpAddr::V4(ip) => {
+            let shared = ip.octets()[0] == 100 && ip.octets()[1] & 0xc0 == 64;
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || shared)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let unique_local = ip.segments()[0] & 0xfe00 == 0xfc00;
+                let link_local = ip.segments()[0] & 0xffc0 == 0xfe80;
+                !(ip.is_loopback() || ip.is_unspecified() || unique_local || link_local)
+            }
+        },
+    }
+}
+
+/// Policy for the Discovery of Designated Resolvers ([RFC 9462](https://www.rfc-editor.org/rfc/rfc9462))
+///
+/// When enabled, the resolver queries each name server that is only configured for UDP and TCP for
+/// `_dns.resolver.arpa. SVCB`. If the server designates an encrypted resolver (DoT, DoH, DoQ or
+/// DoH3) reachable at the same IP address, and the certificate of that resolver covers the IP
+/// address, all further requests to the name server are sent to the designated resolver.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg(feature = "__tls")]
+#[non_exhaustive]
+pub enum DdrPolicy {
+    /// Do not attempt discovery. This is the default.
+    #[default]
+    Disabled,
+    /// Use the designated resolver if discovery succeeds, otherwise fall back to plain text.
+    Opportunistic,
+    /// Use the designated resolver, and fail requests to name servers without one.
+    Required,
+}
+
 /// Whether the system hosts file should be respected by the resolver.
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
@@ -655,6 +940,80 @@ pub enum ResolveHosts {
     Auto,
 }
 
+/// Options for a single lookup, see [`Resolver::lookup_with_options()`]
+///
+/// The default options use the settings of the resolver.
+///
+/// [`Resolver::lookup_with_options()`]: crate::Resolver::lookup_with_options
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct LookupOptions {
+    /// Time after which the whole lookup fails, including all attempts and CNAME chasing.
+    ///
+    /// If not set, each request is only bounded by [`ResolverOpts::timeout`].
+    pub timeout: Option<Duration>,
+    /// How the response cache is used for this lookup.
+    pub cache: CacheMode,
+    /// Set the CD (checking disabled) bit, asking upstream resolvers not to validate DNSSEC.
+    ///
+    /// Responses to such queries are not stored in the cache, as they may be bogus.
+    pub checking_disabled: bool,
+    /// Set the DO (DNSSEC OK) bit, asking for the DNSSEC records of the response. This enables
+    /// EDNS for the lookup.
+    pub dnssec_ok: bool,
+    /// Only send the queries over this protocol.
+    ///
+    /// Name servers without a connection for the protocol are skipped, the lookup fails if there
+    /// are none left. The response cache is neither read nor written by such lookups.
+    pub protocol: Option<Protocol>,
+    /// Send the queries to this group of name servers, instead of the one selected by the
+    /// forwarding rules for the query name.
+    ///
+    /// The response cache is neither read nor written by such lookups, as its responses may come
+    /// from other name servers.
+    pub upstream: Option<Upstream>,
+    /// Address of the client this lookup is made for.
+    ///
+    /// If [`ResolverOpts::client_subnet`] is configured without a fixed address, its prefix is
+    /// sent in the queries.
+    pub client_address: Option<IpAddr>,
+}
+
+/// How a lookup uses the response cache
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CacheMode {
+    /// Answer from the cache if possible, and store the response. This is the default.
+    #[default]
+    Enabled,
+    /// Always query the name servers, and store the response in the cache.
+    Refresh,
+    /// Always query the name servers, and do not store the response.
+    Bypass,
+}
+
+impl CacheMode {
+    /// Whether cached responses are used
+    pub(crate) fn reads(self) -> bool {
+        self == Self::Enabled
+    }
+
+    /// Whether responses are stored in the cache
+    pub(crate) fn writes(self) -> bool {
+        self != Self::Bypass
+    }
+}
+
+/// A group of name servers of a resolver
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Upstream {
+    /// The name servers of [`ResolverConfig::name_servers()`]
+    Default,
+    /// The name servers of the forwarding rule for this domain
+    ForwardingRule(Name),
+}
+
 /// Google Public DNS configuration.
 ///
 /// Please see Google's [privacy statement](https://developers.google.com/speed/public-dns/privacy)
@@ -870,6 +1229,7 @@ mod tests {
         #[cfg(feature = "__dnssec")]
         assert_eq!(code.validate, json.validate);
         assert_eq!(code.ip_strategy, json.ip_strategy);
+        assert_eq!(code.sort_addresses, json.sort_addresses);
         assert_eq!(code.cache_size, json.cache_size);
         assert_eq!(code.use_hosts_file, json.use_hosts_file);
         assert_eq!(code.positive_min_ttl, json.positive_min_ttl);
@@ -885,5 +1245,23 @@ mod tests {
         assert_eq!(code.os_port_selection, json.os_port_selection);
         assert_eq!(code.case_randomization, json.case_randomization);
         assert_eq!(code.trust_anchor, json.trust_anchor);
+        assert_eq!(code.circuit_breaker, json.circuit_breaker);
+        #[cfg(feature = "__tls")]
+        assert_eq!(code.ddr, json.ddr);
+    }
+
+    #[test]
+    fn circuit_breaker_defaults() {
+        let json = serde_json::from_str::<ResolverOpts>(r#"{"circuit_breaker": {}}"#).unwrap();
+        assert_eq!(json.circuit_breaker, Some(CircuitBreakerConfig::default()));
+
+        let json = serde_json::from_str::<ResolverOpts>(
+            r#"{"circuit_breaker": {"probe_interval": 1, "fallback": "Fail"}}"#,
+        )
+        .unwrap();
+        let config = json.circuit_breaker.unwrap();
+        assert_eq!(config.failure_threshold, 5);
+        assert_eq!(config.probe_interval, Duration::from_secs(1));
+        assert_eq!(config.fallback, AllServersDown::Fail);
     }
 }
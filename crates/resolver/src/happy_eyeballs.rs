@@ -0,0 +1,243 @@
+//! Connection establishment with Happy Eyeballs, see [RFC 8305](https://www.rfc-editor.org/rfc/rfc8305)
+
+use std::collections::VecDeque;
+use std::future::{Future, poll_fn};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::task::Poll;
+use std::time::Duration;
+
+use futures_util::future::{self, BoxFuture, Either, FutureExt};
+use futures_util::stream::{FuturesUnordered, StreamExt};
+use tracing::debug;
+
+use crate::Resolver;
+use crate::address_selection;
+use crate::config::LookupIpStrategy;
+use crate::name_server::ConnectionProvider;
+use crate::proto::ProtoError;
+use crate::proto::rr::{Name, RecordType};
+use crate::proto::runtime::{RuntimeProvider, Time};
+
+/// Time to wait for the AAAA response after the A response, see section 3
+const RESOLUTION_DELAY: Duration = Duration::from_millis(50);
+
+/// Time to wait for a connection attempt before starting the next one, see section 5
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+type Tcp<P> = <<P as ConnectionProvider>::RuntimeProvider as RuntimeProvider>::Tcp;
+
+type Query<'a> = BoxFuture<'a, Result<Vec<IpAddr>, ProtoError>>;
+
+/// Connects to `port` on the first reachable address of `name`, see [`Resolver::connect_tcp`]
+pub(crate) async fn connect_tcp<P: ConnectionProvider>(
+    resolver: &Resolver<P>,
+    runtime: &P::RuntimeProvider,
+    name: Name,
+    port: u16,
+    strategy: LookupIpStrategy,
+    sort: bool,
+) -> io::Result<Tcp<P>> {
+    let query = |record_type| -> Query<'_> {
+        let name = name.clone();
+        async move {
+            let lookup = resolver.lookup(name, record_type).await?;
+            Ok(lookup.iter().filter_map(|rdata| rdata.ip_addr()).collect())
+        }
+        .boxed()
+    };
+
+    // "Implementations SHOULD NOT wait for both families of answers to return before attempting
+    // connection establishment."
+    let mut race = Race::new(sort);
+    let mut pending = match strategy {
+        LookupIpStrategy::Ipv4Only => {
+            race.resolved(query(RecordType::A).await);
+            None
+        }
+        LookupIpStrategy::Ipv6Only => {
+            race.resolved(query(RecordType::AAAA).await);
+            None
+        }
+        _ => match future::select(query(RecordType::AAAA), query(RecordType::A)).await {
+            // "Whenever a positive AAAA response is received, the client SHOULD begin the
+            // connection attempts immediately."
+            Either::Left((aaaa, a)) => {
+                race.resolved(aaaa);
+                Some(a)
+            }
+            // "If a positive A response is received first due to reordering, the client SHOULD
+            // wait a short time for the AAAA response to ensure that preference is given to IPv6"
+            Either::Right((a, aaaa)) => {
+                race.resolved(a);
+                let delay = delay::<P>(RESOLUTION_DELAY);
+                match future::select(aaaa, delay).await {
+                    Either::Left((aaaa, _)) => {
+                        race.resolved(aaaa);
+                        None
+                    }
+                    Either::Right((_, aaaa)) => Some(aaaa),
+                }
+            }
+        },
+    };
+
+    let mut attempts = FuturesUnordered::new();
+    let mut next_attempt = None;
+    loop {
+        if next_attempt.is_none() || attempts.is_empty() {
+            if let Some(addr) = race.next_addr() {
+                let addr = SocketAddr::new(addr, port);
+                debug!("connecting to {addr}");
+                attempts.push(
+                    runtime
+                        .connect_tcp(addr, None, None)
+                        .map(move |res| (addr, res)),
+                );
+                next_attempt = Some(delay::<P>(CONNECTION_ATTEMPT_DELAY));
+            } else if attempts.is_empty() && pending.is_none() {
+                return Err(race.into_error(name));
+            }
+        }
+
+        let event = poll_fn(|cx| {
+            if let Poll::Ready(Some((addr, res))) = attempts.poll_next_unpin(cx) {
+                return Poll::Ready(Event::Attempt(addr, res));
+            }
+
+            if let Some(query) = &mut pending {
+                if let Poll::Ready(res) = query.as_mut().poll(cx) {
+                    return Poll::Ready(Event::Resolved(res));
+                }
+            }
+
+            if let Some(delay) = &mut next_attempt {
+                if delay.as_mut().poll(cx).is_ready() {
+                    return Poll::Ready(Event::Delay);
+                }
+            }
+
+            Poll::Pending
+        })
+        .await;
+
+        match event {
+            Event::Attempt(_, Ok(stream)) => return Ok(stream),
+            Event::Attempt(addr, Err(e)) => {
+                // "the client SHOULD start the next connection attempt as soon as the previous
+                // attempt fails"
+                debug!("failed to connect to {addr}: {e}");
+                race.last_error = Some(e);
+                next_attempt = None;
+            }
+            Event::Resolved(res) => {
+                pending = None;
+                race.resolved(res);
+            }
+            Event::Delay => next_attempt = None,
+        }
+    }
+}
+
+enum Event<S> {
+    Attempt(SocketAddr, io::Result<S>),
+    Resolved(Result<Vec<IpAddr>, ProtoError>),
+    Delay,
+}
+
+/// The addresses not yet attempted, in order
+struct Race {
+    addrs: VecDeque<IpAddr>,
+    sort: bool,
+    last_error: Option<io::Error>,
+}
+
+impl Race {
+    fn new(sort: bool) -> Self {
+        Self {
+            addrs: VecDeque::new(),
+            sort,
+            last_error: None,
+        }
+    }
+
+    /// Adds the result of a query to the addresses to attempt
+    fn resolved(&mut self, res: Result<Vec<IpAddr>, ProtoError>) {
+        let addrs = match res {
+            Ok(addrs) => addrs,
+            Err(e) => {
+                debug!("lookup failed: {e}");
+                self.last_error = Some(e.into());
+                return;
+            }
+        };
+
+        let mut addrs = self.addrs.drain(..).chain(addrs).collect::<Vec<_>>();
+        match self.sort {
+            true => address_selection::sort_destinations(&mut addrs),
+            // without destination address selection, prefer IPv6 as section 4 does
+            false => addrs.sort_by_key(IpAddr::is_ipv4),
+        }
+        self.addrs = interleave(addrs).into();
+    }
+
+    fn next_addr(&mut self) -> Option<IpAddr> {
+        self.addrs.pop_front()
+    }
+
+    fn into_error(self, name: Name) -> io::Error {
+        self.last_error.unwrap_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no addresses found for {name}"),
+            )
+        })
+    }
+}
+
+/// Alternates the address families, starting with the family of the first address, see section 4
+fn interleave(addrs: Vec<IpAddr>) -> Vec<IpAddr> {
+    let Some(first) = addrs.first() else {
+        return addrs;
+    };
+
+    let first_is_ipv4 = first.is_ipv4();
+    let (mut preferred, mut other): (VecDeque<_>, VecDeque<_>) = addrs
+        .into_iter()
+        .partition(|addr| addr.is_ipv4() == first_is_ipv4);
+
+    let mut interleaved = Vec::with_capacity(preferred.len() + other.len());
+    loop {
+        match (preferred.pop_front(), other.pop_front()) {
+            (None, None) => return interleaved,
+            (first, second) => interleaved.extend(first.into_iter().chain(second)),
+        }
+    }
+}
+
+fn delay<P: ConnectionProvider>(duration: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+    <P::RuntimeProvider as RuntimeProvider>::Timer::delay_for(duration)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interleave() {
+        let addrs = ["2001:db8::1", "2001:db8::2", "192.0.2.1", "2001:db8::3"]
+            .iter()
+            .map(|addr| addr.parse().unwrap())
+            .collect::<Vec<IpAddr>>();
+
+        let interleaved = interleave(addrs)
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            interleaved,
+            ["2001:db8::1", "192.0.2.1", "2001:db8::2", "2001:db8::3"]
+        );
+    }
+}
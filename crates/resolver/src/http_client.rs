@@ -0,0 +1,193 @@
+//! Name resolution for HTTP clients
+//!
+//! [`HttpResolver`] plugs a [`Resolver`] into the connector of `hyper-util` (with the `hyper-util`
+//! feature) or into a `reqwest` client (with the `reqwest` feature).
+//!
+//! ```rust,no_run
+//! # #[cfg(feature = "reqwest")]
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use std::sync::Arc;
+//!
+//! use hickory_resolver::Resolver;
+//! use hickory_resolver::config::{GOOGLE, ResolverConfig};
+//! use hickory_resolver::http_client::HttpResolver;
+//! use hickory_resolver::proto::runtime::TokioRuntimeProvider;
+//!
+//! let resolver = Resolver::builder_with_config(
+//!     ResolverConfig::udp_and_tcp(&GOOGLE),
+//!     TokioRuntimeProvider::default(),
+//! )
+//! .build()?;
+//! let client = reqwest::Client::builder()
+//!     .dns_resolver(Arc::new(HttpResolver::from(resolver)))
+//!     .build()?;
+//! # let _ = client;
+//! # Ok(())
+//! # }
+//! # #[cfg(not(feature = "reqwest"))]
+//! # fn main() {}
+//! ```
+
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+#[cfg(feature = "hyper-util")]
+use std::task::{Context, Poll};
+use std::vec;
+
+use crate::Resolver;
+use crate::name_server::ConnectionProvider;
+use crate::proto::ProtoError;
+
+/// A resolver for HTTP clients, which looks up names with [`Resolver::lookup_ip`]
+///
+/// The addresses are returned in the order of the lookup, so the
+/// [`LookupIpStrategy`](crate::config::LookupIpStrategy) and the
+/// [`sort_addresses`](crate::config::ResolverOpts::sort_addresses) option of the resolver apply.
+/// Lookup failures are reported as [`io::Error`]s, with the [`io::ErrorKind::NotFound`] kind if the
+/// name has no addresses, and [`io::ErrorKind::TimedOut`] if the lookup timed out.
+#[derive(Clone)]
+pub struct HttpResolver<P: ConnectionProvider> {
+    resolver: Resolver<P>,
+}
+
+impl<P: ConnectionProvider> HttpResolver<P> {
+    /// Returns the resolver used for the lookups
+    pub fn resolver(&self) -> &Resolver<P> {
+        &self.resolver
+    }
+
+    /// Looks up the addresses of `name`
+    fn lookup_addrs(
+        &self,
+        name: &str,
+    ) -> Pin<Box<dyn Future<Output = io::Result<SocketAddrs>> + Send>> {
+        let resolver = self.resolver.clone();
+        let name = name.to_owned();
+        Box::pin(async move {
+            let lookup = resolver.lookup_ip(name).await.map_err(into_io_error)?;
+            Ok(SocketAddrs(lookup.iter().collect::<Vec<_>>().into_iter()))
+        })
+    }
+}
+
+impl<P: ConnectionProvider> From<Resolver<P>> for HttpResolver<P> {
+    fn from(resolver: Resolver<P>) -> Self {
+        Self { resolver }
+    }
+}
+
+/// The addresses of a name, with a port of 0 which is replaced by the port of the request
+pub struct SocketAddrs(vec::IntoIter<IpAddr>);
+
+impl Iterator for SocketAddrs {
+    type Item = SocketAddr;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|ip| SocketAddr::new(ip, 0))
+    }
+}
+
+fn into_io_error(e: ProtoError) -> io::Error {
+    match e.is_no_records_found() {
+        true => io::Error::new(io::ErrorKind::NotFound, e),
+        false => e.into(),
+    }
+}
+
+#[cfg(feature = "hyper-util")]
+impl<P: ConnectionProvider> tower_service::Service<hyper_util::client::legacy::connect::dns::Name>
+    for HttpResolver<P>
+{
+    type Response = SocketAddrs;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = io::Result<SocketAddrs>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, name: hyper_util::client::legacy::connect::dns::Name) -> Self::Future {
+        self.lookup_addrs(name.as_str())
+    }
+}
+
+#[cfg(feature = "reqwest")]
+impl<P: ConnectionProvider> reqwest::dns::Resolve for HttpResolver<P> {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        let addrs = self.lookup_addrs(name.as_str());
+        Box::pin(async move {
+            let addrs: reqwest::dns::Addrs = Box::new(addrs.await?);
+            Ok(addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{GOOGLE, LookupIpStrategy, ResolverConfig};
+    use crate::proto::runtime::TokioRuntimeProvider;
+
+    fn resolver(ip_strategy: LookupIpStrategy) -> HttpResolver<TokioRuntimeProvider> {
+        let mut builder = Resolver::builder_with_config(
+            ResolverConfig::udp_and_tcp(&GOOGLE),
+            TokioRuntimeProvider::default(),
+        );
+        builder.options_mut().ip_strategy = ip_strategy;
+        HttpResolver::from(builder.build().unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_resolve_localhost() {
+        let addrs = resolver(LookupIpStrategy::Ipv4Only)
+            .lookup_addrs("localhost")
+            .await
+            .unwrap()
+            .collect::<Vec<_>>();
+        assert_eq!(addrs, [SocketAddr::from(([127, 0, 0, 1], 0))]);
+    }
+
+    #[cfg(feature = "hyper-util")]
+    #[tokio::test]
+    async fn test_hyper_util_service() {
+        use std::str::FromStr;
+
+        use hyper_util::client::legacy::connect::dns::Name;
+        use tower_service::Service;
+
+        let addrs = resolver(LookupIpStrategy::Ipv6Only)
+            .call(Name::from_str("localhost").unwrap())
+            .await
+            .unwrap()
+            .collect::<Vec<_>>();
+        assert_eq!(addrs, [SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 1], 0))]);
+    }
+
+    #[cfg(feature = "reqwest")]
+    #[tokio::test]
+    async fn test_reqwest_resolve() {
+        use std::str::FromStr;
+
+        use reqwest::dns::{Name, Resolve};
+
+        let addrs = Resolve::resolve(
+            &resolver(LookupIpStrategy::Ipv4Only),
+            Name::from_str("localhost").unwrap(),
+        )
+        .await
+        .unwrap()
+        .collect::<Vec<_>>();
+        assert_eq!(addrs, [SocketAddr::from(([127, 0, 0, 1], 0))]);
+    }
+
+    #[test]
+    fn test_not_found() {
+        use crate::proto::NoRecords;
+        use crate::proto::op::{Query, ResponseCode};
+
+        let e = into_io_error(NoRecords::new(Query::default(), ResponseCode::NXDomain).into());
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+    }
+}
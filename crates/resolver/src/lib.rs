@@ -195,21 +195,30 @@ pub use hickory_proto as proto;
 // reexports from proto
 pub use proto::rr::{IntoName, Name};
 
+mod address_selection;
 pub mod caching_client;
 pub mod config;
+mod happy_eyeballs;
 mod hosts;
 pub use hosts::Hosts;
+#[cfg(any(feature = "hyper-util", feature = "reqwest"))]
+pub mod http_client;
 pub mod lookup;
 pub mod lookup_ip;
+#[cfg(all(test, feature = "metrics", feature = "tokio"))]
+mod metrics_tests;
 // TODO: consider #[doc(hidden)]
 pub mod name_server;
 mod resolver;
 pub use resolver::LookupFuture;
+pub mod service_endpoint;
+#[cfg(feature = "tokio")]
+pub mod sync;
 #[cfg(feature = "tokio")]
 pub use resolver::TokioResolver;
 pub use resolver::{Resolver, ResolverBuilder};
 mod cache;
-pub use cache::{MAX_TTL, ResponseCache, TtlBounds, TtlConfig};
+pub use cache::{CacheEntry, CacheStats, MAX_TTL, ResponseCache, TtlBounds, TtlConfig};
 pub mod system_conf;
 #[cfg(test)]
 mod tests;
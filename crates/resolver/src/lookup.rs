@@ -10,24 +10,29 @@
 use std::{
     cmp::min,
     marker::PhantomData,
+    net::{IpAddr, SocketAddr},
     slice::Iter,
     sync::Arc,
     time::{Duration, Instant},
+    vec,
 };
 
 use hickory_proto::rr::RecordData;
 
 use crate::{
+    Resolver,
     cache::MAX_TTL,
     lookup_ip::LookupIpIter,
+    name_server::ConnectionProvider,
     proto::{
+        ProtoError,
         op::Query,
-        rr::{RData, Record, rdata},
+        rr::{Name, RData, Record, rdata},
     },
 };
 
 #[cfg(feature = "__dnssec")]
-use crate::proto::dnssec::Proven;
+use crate::proto::dnssec::{Proof, Proven, ValidationTrace};
 
 /// Result of a DNS query when querying for any record type supported by the Hickory DNS Proto library.
 ///
@@ -37,6 +42,8 @@ pub struct Lookup {
     query: Query,
     records: Arc<[Record]>,
     valid_until: Instant,
+    #[cfg(feature = "__dnssec")]
+    validation_trace: Option<Arc<ValidationTrace>>,
 }
 
 impl Lookup {
@@ -49,11 +56,7 @@ impl Lookup {
     /// Return new instance with given records and the maximum TTL.
     pub fn new_with_max_ttl(query: Query, records: Arc<[Record]>) -> Self {
         let valid_until = Instant::now() + Duration::from_secs(u64::from(MAX_TTL));
-        Self {
-            query,
-            records,
-            valid_until,
-        }
+        Self::new_with_deadline(query, records, valid_until)
     }
 
     /// Return a new instance with the given records and deadline.
@@ -62,9 +65,18 @@ impl Lookup {
             query,
             records,
             valid_until,
+            #[cfg(feature = "__dnssec")]
+            validation_trace: None,
         }
     }
 
+    /// Sets the steps taken to validate the records of this lookup
+    #[cfg(feature = "__dnssec")]
+    pub(crate) fn with_validation_trace(mut self, trace: Option<Arc<ValidationTrace>>) -> Self {
+        self.validation_trace = trace;
+        self
+    }
+
     /// Returns a reference to the `Query` that was used to produce this result.
     pub fn query(&self) -> &Query {
         &self.query
@@ -96,6 +108,16 @@ impl Lookup {
         DnssecLookupRecordIter(self.records.iter())
     }
 
+    /// Returns the steps taken to validate the records, when DNSSEC validation and
+    /// [`ResolverOpts::validation_trace`](crate::config::ResolverOpts::validation_trace) are enabled
+    ///
+    /// This is only available for lookups which were sent to the name servers, lookups answered
+    /// from the cache or from the hosts file do not have a trace.
+    #[cfg(feature = "__dnssec")]
+    pub fn validation_trace(&self) -> Option<&Arc<ValidationTrace>> {
+        self.validation_trace.as_ref()
+    }
+
     /// Returns the `Instant` at which this `Lookup` is no longer valid.
     pub fn valid_until(&self) -> Instant {
         self.valid_until
@@ -124,7 +146,26 @@ impl Lookup {
 
         // Choose the sooner deadline of the two lookups.
         let valid_until = min(self.valid_until(), other.valid_until());
-        Self::new_with_deadline(self.query.clone(), Arc::from(records), valid_until)
+        #[cfg_attr(not(feature = "__dnssec"), allow(unused_mut))]
+        let mut lookup =
+            Self::new_with_deadline(self.query.clone(), Arc::from(records), valid_until);
+
+        #[cfg(feature = "__dnssec")]
+        {
+            lookup.validation_trace = merge_validation_traces(
+                self.validation_trace.as_ref(),
+                other.validation_trace.as_ref(),
+            );
+        }
+
+        lookup
+    }
+
+    /// Returns a copy of this lookup with `records` instead of its records
+    pub(crate) fn with_records(&self, records: Arc<[Record]>) -> Self {
+        let mut lookup = self.clone();
+        lookup.records = records;
+        lookup
     }
 
     /// Add new records to this lookup, without creating a new Lookup
@@ -136,6 +177,25 @@ impl Lookup {
     }
 }
 
+/// Concatenates the steps of two validation traces
+#[cfg(feature = "__dnssec")]
+pub(crate) fn merge_validation_traces(
+    first: Option<&Arc<ValidationTrace>>,
+    second: Option<&Arc<ValidationTrace>>,
+) -> Option<Arc<ValidationTrace>> {
+    match (first, second) {
+        (Some(first), Some(second)) => Some(Arc::new(
+            first
+                .steps()
+                .iter()
+                .chain(second.steps())
+                .cloned()
+                .collect(),
+        )),
+        (first, second) => first.or(second).cloned(),
+    }
+}
+
 /// Borrowed view of set of [`RData`]s returned from a Lookup
 pub struct LookupIter<'a>(Iter<'a, Record>);
 
@@ -206,6 +266,22 @@ impl SrvLookup {
         LookupIpIter(self.0.iter())
     }
 
+    /// Returns the targets of the SRV records, in the order in which they should be tried
+    ///
+    /// As described in [RFC 2782](https://www.rfc-editor.org/rfc/rfc2782), the records are
+    /// ordered by ascending priority, and records with the same priority are ordered randomly, with
+    /// a probability of being first proportional to their weight. Each call returns a new random
+    /// order. If the only record has a target of `.`, the service is not available and there are no
+    /// targets.
+    pub fn targets(&self) -> SrvTargets<'_> {
+        let order = rfc2782_order(self.iter().collect(), |total| rand::random_range(0..=total));
+
+        SrvTargets {
+            lookup: &self.0,
+            order: order.into_iter(),
+        }
+    }
+
     /// Return a reference to the inner lookup
     ///
     /// This can be useful for getting all records from the request
@@ -214,6 +290,130 @@ impl SrvLookup {
     }
 }
 
+/// Orders SRV records per RFC 2782, `random(total)` returns a number in `0..=total`
+fn rfc2782_order(
+    mut srvs: Vec<&rdata::SRV>,
+    mut random: impl FnMut(u32) -> u32,
+) -> Vec<&rdata::SRV> {
+    // "A Target of "." means that the service is decidedly not available at this domain."
+    if let [srv] = srvs.as_slice() {
+        if srv.target().is_root() {
+            return Vec::new();
+        }
+    }
+
+    // "arrange all SRV RRs (that have not been ordered yet) in any order, except that all those
+    // with weight 0 are placed at the beginning of the list."
+    srvs.sort_by_key(|srv| (srv.priority(), srv.weight() != 0));
+
+    let mut ordered = Vec::with_capacity(srvs.len());
+    let mut remaining = srvs.as_slice();
+    while let Some(first) = remaining.first() {
+        let len = remaining
+            .iter()
+            .take_while(|srv| srv.priority() == first.priority())
+            .count();
+        let (group, rest) = remaining.split_at(len);
+        remaining = rest;
+
+        // "Compute the sum of the weights of those RRs, and with each RR associate the running sum
+        // in the selected order. Then choose a uniform random number between 0 and the sum
+        // computed (inclusive), and select the RR whose running sum value is the first in the
+        // selected order which is greater than or equal to the random number selected."
+        let mut group = group.to_vec();
+        while !group.is_empty() {
+            let total = group.iter().map(|srv| u32::from(srv.weight())).sum();
+            let selected = random(total);
+            let mut running_sum = 0;
+            let index = group
+                .iter()
+                .position(|srv| {
+                    running_sum += u32::from(srv.weight());
+                    running_sum >= selected
+                })
+                .unwrap_or(0);
+
+            ordered.push(group.remove(index));
+        }
+    }
+
+    ordered
+}
+
+/// The targets of an SRV lookup, in the order in which they should be tried
+///
+/// See [`SrvLookup::targets`].
+pub struct SrvTargets<'a> {
+    lookup: &'a Lookup,
+    order: vec::IntoIter<&'a rdata::SRV>,
+}
+
+impl<'a> Iterator for SrvTargets<'a> {
+    type Item = SrvTarget<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let srv = self.order.next()?;
+        Some(SrvTarget {
+            srv,
+            lookup: self.lookup,
+        })
+    }
+}
+
+/// A target host of an SRV lookup
+#[derive(Clone, Copy, Debug)]
+pub struct SrvTarget<'a> {
+    srv: &'a rdata::SRV,
+    lookup: &'a Lookup,
+}
+
+impl<'a> SrvTarget<'a> {
+    /// The SRV record of this target
+    pub fn srv(&self) -> &'a rdata::SRV {
+        self.srv
+    }
+
+    /// The name of the target host
+    pub fn name(&self) -> &'a Name {
+        self.srv.target()
+    }
+
+    /// The port of the service on the target host
+    pub fn port(&self) -> u16 {
+        self.srv.port()
+    }
+
+    /// The addresses of the target which were included in the SRV response
+    pub fn glue(&self) -> impl Iterator<Item = IpAddr> + 'a {
+        let target = self.srv.target();
+        self.lookup
+            .record_iter()
+            .filter(move |record| record.name() == target)
+            .filter_map(|record| record.data().ip_addr())
+    }
+
+    /// Resolves the addresses of the service on the target
+    ///
+    /// The addresses included in the SRV response are used when there are any, otherwise the
+    /// target is looked up with [`Resolver::lookup_ip`].
+    pub async fn socket_addrs<P: ConnectionProvider>(
+        &self,
+        resolver: &Resolver<P>,
+    ) -> Result<Vec<SocketAddr>, ProtoError> {
+        let port = self.port();
+        let glue = self
+            .glue()
+            .map(|ip| SocketAddr::new(ip, port))
+            .collect::<Vec<_>>();
+        if !glue.is_empty() {
+            return Ok(glue);
+        }
+
+        let lookup = resolver.lookup_ip(self.name().clone()).await?;
+        Ok(lookup.iter().map(|ip| SocketAddr::new(ip, port)).collect())
+    }
+}
+
 impl From<Lookup> for SrvLookup {
     fn from(lookup: Lookup) -> Self {
         Self(lookup)
@@ -298,14 +498,82 @@ impl<'i, T: RecordData + 'i> Iterator for TypedLookupIter<'i, T> {
     }
 }
 
+/// The names of an address which are confirmed by a forward lookup
+///
+/// See [`Resolver::verified_reverse_lookup`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct VerifiedReverseLookup {
+    ip: IpAddr,
+    names: Vec<VerifiedName>,
+}
+
+impl VerifiedReverseLookup {
+    pub(crate) fn new(ip: IpAddr, names: Vec<VerifiedName>) -> Self {
+        Self { ip, names }
+    }
+
+    /// The address which was looked up
+    pub fn ip(&self) -> IpAddr {
+        self.ip
+    }
+
+    /// Returns an iterator over the verified names, in the order of the PTR records
+    pub fn iter(&self) -> Iter<'_, VerifiedName> {
+        self.names.iter()
+    }
+
+    /// Returns true if none of the names of the address resolve to it
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+/// A name of an address, whose forward lookup contains the address
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct VerifiedName {
+    name: Name,
+    #[cfg(feature = "__dnssec")]
+    proof: Proof,
+}
+
+impl VerifiedName {
+    /// Confirms the target of the `ptr` record if `forward` contains `ip`
+    pub(crate) fn verify(ptr: &Record, forward: &Lookup, ip: IpAddr) -> Option<Self> {
+        let RData::PTR(name) = ptr.data() else {
+            return None;
+        };
+
+        #[cfg_attr(not(feature = "__dnssec"), allow(unused_variables))]
+        let record = forward
+            .record_iter()
+            .find(|record| record.data().ip_addr() == Some(ip))?;
+
+        Some(Self {
+            name: name.0.clone(),
+            #[cfg(feature = "__dnssec")]
+            proof: ptr.proof().min(record.proof()),
+        })
+    }
+
+    /// The verified name
+    pub fn name(&self) -> &Name {
+        &self.name
+    }
+
+    /// The DNSSEC proof of the name, the weakest of the proofs of the PTR record and of the
+    /// address record
+    #[cfg(feature = "__dnssec")]
+    pub fn proof(&self) -> Proof {
+        self.proof
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::str::FromStr;
 
-    #[cfg(feature = "__dnssec")]
-    use crate::proto::op::Query;
     use crate::proto::rr::rdata::A;
-    use crate::proto::rr::{Name, RData, Record};
+    use crate::proto::rr::{Name, RData, Record, RecordType};
 
     use super::*;
 
@@ -349,11 +617,11 @@ mod tests {
         );
         a2.set_proof(Proof::Insecure);
 
-        let lookup = Lookup {
-            query: Query::default(),
-            records: Arc::from([a1.clone(), a2.clone()]),
-            valid_until: Instant::now(),
-        };
+        let lookup = Lookup::new_with_deadline(
+            Query::default(),
+            Arc::from([a1.clone(), a2.clone()]),
+            Instant::now(),
+        );
 
         let mut lookup = lookup.dnssec_iter();
 
@@ -367,4 +635,125 @@ mod tests {
         );
         assert_eq!(lookup.next(), None);
     }
+
+    fn srv(priority: u16, weight: u16, target: &str) -> rdata::SRV {
+        rdata::SRV::new(priority, weight, 443, Name::from_str(target).unwrap())
+    }
+
+    #[test]
+    fn test_rfc2782_order() {
+        let srvs = [
+            srv(20, 0, "d.example.com."),
+            srv(10, 60, "a.example.com."),
+            srv(10, 0, "b.example.com."),
+            srv(10, 40, "c.example.com."),
+        ];
+        let targets = |order: Vec<&rdata::SRV>| {
+            order
+                .into_iter()
+                .map(|srv| srv.target().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        // the lowest number selects the first record, which has a weight of 0
+        let order = rfc2782_order(srvs.iter().collect(), |_| 0);
+        assert_eq!(
+            targets(order),
+            [
+                "b.example.com.",
+                "a.example.com.",
+                "c.example.com.",
+                "d.example.com."
+            ]
+        );
+
+        // the highest number selects the last record
+        let order = rfc2782_order(srvs.iter().collect(), |total| total);
+        assert_eq!(
+            targets(order),
+            [
+                "c.example.com.",
+                "a.example.com.",
+                "b.example.com.",
+                "d.example.com."
+            ]
+        );
+
+        // any other number skips the records with a weight of 0
+        let order = rfc2782_order(srvs.iter().collect(), |total| total.min(1));
+        assert_eq!(
+            targets(order),
+            [
+                "a.example.com.",
+                "c.example.com.",
+                "b.example.com.",
+                "d.example.com."
+            ]
+        );
+    }
+
+    #[test]
+    fn test_srv_targets() {
+        let name = Name::from_str("_https._tcp.example.com.").unwrap();
+        let target = Name::from_str("a.example.com.").unwrap();
+        let lookup = Lookup::new_with_max_ttl(
+            Query::query(name.clone(), RecordType::SRV),
+            Arc::from([
+                Record::from_rdata(name.clone(), 80, RData::SRV(srv(10, 1, "a.example.com."))),
+                Record::from_rdata(name, 80, RData::SRV(srv(20, 1, "b.example.com."))),
+                Record::from_rdata(target, 80, RData::A(A::new(127, 0, 0, 1))),
+            ]),
+        );
+
+        let lookup = SrvLookup::from(lookup);
+        let targets = lookup.targets().collect::<Vec<_>>();
+        assert_eq!(targets.len(), 2);
+        assert_eq!(targets[0].name().to_string(), "a.example.com.");
+        assert_eq!(targets[0].port(), 443);
+        assert_eq!(
+            targets[0].glue().collect::<Vec<_>>(),
+            [IpAddr::from([127, 0, 0, 1])]
+        );
+        assert_eq!(targets[1].name().to_string(), "b.example.com.");
+        assert_eq!(targets[1].glue().count(), 0);
+    }
+
+    #[test]
+    fn test_srv_targets_unavailable() {
+        let srvs = [srv(0, 0, ".")];
+        assert!(rfc2782_order(srvs.iter().collect(), |_| 0).is_empty());
+    }
+
+    #[test]
+    fn test_verified_name() {
+        use std::net::Ipv4Addr;
+
+        let ip = IpAddr::from([192, 0, 2, 1]);
+        let ptr = Record::from_rdata(
+            Name::from(ip),
+            80,
+            RData::PTR(rdata::PTR(Name::from_str("mail.example.com.").unwrap())),
+        );
+        let forward = |addrs: &[[u8; 4]]| {
+            let name = Name::from_str("mail.example.com.").unwrap();
+            let records = addrs
+                .iter()
+                .map(|addr| {
+                    Record::from_rdata(name.clone(), 80, RData::A(A::from(Ipv4Addr::from(*addr))))
+                })
+                .collect::<Vec<_>>();
+            Lookup::new_with_max_ttl(Query::query(name, RecordType::A), Arc::from(records))
+        };
+
+        let verified =
+            VerifiedName::verify(&ptr, &forward(&[[192, 0, 2, 2], [192, 0, 2, 1]]), ip).unwrap();
+        assert_eq!(verified.name().to_string(), "mail.example.com.");
+        #[cfg(feature = "__dnssec")]
+        assert_eq!(
+            verified.proof(),
+            hickory_proto::dnssec::Proof::Indeterminate
+        );
+
+        assert!(VerifiedName::verify(&ptr, &forward(&[[192, 0, 2, 2]]), ip).is_none());
+    }
 }
@@ -22,6 +22,7 @@ use futures_util::{
 };
 use tracing::debug;
 
+use crate::address_selection;
 use crate::cache::MAX_TTL;
 use crate::caching_client::CachingClient;
 use crate::config::LookupIpStrategy;
@@ -62,6 +63,23 @@ impl LookupIp {
     pub fn as_lookup(&self) -> &Lookup {
         &self.0
     }
+
+    /// Sorts the addresses in order of preference, per [RFC 6724](https://www.rfc-editor.org/rfc/rfc6724)
+    ///
+    /// This is applied by the [`Resolver`](crate::Resolver) when
+    /// [`ResolverOpts::sort_addresses`](crate::config::ResolverOpts::sort_addresses) is set. Other
+    /// records, such as CNAMEs, are kept before the addresses.
+    pub fn sort_addresses(&mut self) {
+        let mut addrs = self.iter().collect::<Vec<_>>();
+        address_selection::sort_destinations(&mut addrs);
+
+        let mut records = self.0.records().to_vec();
+        records.sort_by_key(|record| {
+            let ip = record.data().ip_addr()?;
+            addrs.iter().position(|addr| *addr == ip)
+        });
+        self.0 = self.0.with_records(Arc::from(records));
+    }
 }
 
 impl From<Lookup> for LookupIp {
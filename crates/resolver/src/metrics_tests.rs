@@ -0,0 +1,145 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+use metrics::{Key, Label, Unit, with_local_recorder};
+use metrics_util::{
+    CompositeKey, MetricKind,
+    debugging::{DebugValue, DebuggingRecorder},
+};
+use test_support::subscribe;
+use tokio::net::UdpSocket;
+use tokio::runtime::Builder;
+
+use crate::Resolver;
+use crate::config::{NameServerConfig, ResolveHosts, ResolverConfig};
+use crate::proto::op::Message;
+use crate::proto::rr::{Name, RData, Record, RecordType};
+use crate::proto::runtime::TokioRuntimeProvider;
+
+const A_RR_IP: Ipv4Addr = Ipv4Addr::new(192, 0, 2, 1);
+
+#[test]
+fn test_resolver_metrics() {
+    subscribe();
+    let recorder = DebuggingRecorder::new();
+    let snapshotter = recorder.snapshotter();
+
+    let query_name = Name::parse("hickory-dns.testing.", None).unwrap();
+
+    let server_addr = with_local_recorder(&recorder, || {
+        let runtime = Builder::new_current_thread().enable_all().build().unwrap();
+        runtime.block_on(async {
+            let server_addr = spawn_server().await;
+
+            let mut name_server = NameServerConfig::udp(server_addr.ip());
+            name_server.connections[0].port = server_addr.port();
+            let mut config = ResolverConfig::default();
+            config.add_name_server(name_server);
+
+            let mut builder =
+                Resolver::builder_with_config(config, TokioRuntimeProvider::default());
+            builder.options_mut().use_hosts_file = ResolveHosts::Never;
+            let resolver = builder.build().unwrap();
+
+            for _ in 0..3 {
+                let lookup = resolver
+                    .lookup(query_name.clone(), RecordType::A)
+                    .await
+                    .unwrap();
+                assert_eq!(lookup.records().len(), 1);
+            }
+
+            server_addr
+        })
+    });
+
+    #[allow(clippy::mutable_key_type)] // False positive, see the documentation for metrics::Key.
+    let map = snapshotter.snapshot().into_hashmap();
+
+    let (unit_opt, description_opt, value) = map
+        .get(&CompositeKey::new(
+            MetricKind::Counter,
+            Key::from_name("hickory_resolver_cache_hit_total"),
+        ))
+        .unwrap();
+    assert_eq!(unit_opt, &Some(Unit::Count));
+    assert!(description_opt.is_some());
+    assert_eq!(value, &DebugValue::Counter(2));
+
+    let (unit_opt, description_opt, value) = map
+        .get(&CompositeKey::new(
+            MetricKind::Counter,
+            Key::from_name("hickory_resolver_cache_miss_total"),
+        ))
+        .unwrap();
+    assert_eq!(unit_opt, &Some(Unit::Count));
+    assert!(description_opt.is_some());
+    assert_eq!(value, &DebugValue::Counter(1));
+
+    let labels = vec![
+        Label::new("server", server_addr.ip().to_string()),
+        Label::new("protocol", "udp"),
+    ];
+
+    let (unit_opt, description_opt, value) = map
+        .get(&CompositeKey::new(
+            MetricKind::Counter,
+            Key::from_parts("hickory_resolver_upstream_queries_total", labels.clone()),
+        ))
+        .unwrap();
+    assert_eq!(unit_opt, &Some(Unit::Count));
+    assert!(description_opt.is_some());
+    assert_eq!(value, &DebugValue::Counter(1));
+
+    let (_, _, value) = map
+        .get(&CompositeKey::new(
+            MetricKind::Counter,
+            Key::from_parts("hickory_resolver_connections_total", labels.clone()),
+        ))
+        .unwrap();
+    assert_eq!(value, &DebugValue::Counter(1));
+
+    let (unit_opt, description_opt, value) = map
+        .get(&CompositeKey::new(
+            MetricKind::Histogram,
+            Key::from_parts("hickory_resolver_upstream_response_time_seconds", labels),
+        ))
+        .unwrap();
+    assert_eq!(unit_opt, &Some(Unit::Seconds));
+    assert!(description_opt.is_some());
+    let DebugValue::Histogram(values) = value else {
+        panic!("unexpected value: {value:?}");
+    };
+    assert_eq!(values.len(), 1);
+}
+
+/// Spawns a name server answering every query with a single A record.
+async fn spawn_server() -> SocketAddr {
+    let server = UdpSocket::bind((IpAddr::from(Ipv4Addr::LOCALHOST), 0))
+        .await
+        .unwrap();
+    let server_addr = server.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let mut buffer = [0_u8; 512];
+        loop {
+            let (len, addr) = server.recv_from(&mut buffer).await.unwrap();
+            let request = Message::from_vec(&buffer[0..len]).unwrap();
+            let mut response = Message::response(request.id(), request.op_code());
+            response.set_recursion_available(true);
+            for query in request.queries() {
+                response.add_query(query.clone());
+                response.add_answer(Record::from_rdata(
+                    query.name().clone(),
+                    3600,
+                    RData::A(A_RR_IP.into()),
+                ));
+            }
+            server
+                .send_to(&response.to_vec().unwrap(), addr)
+                .await
+                .unwrap();
+        }
+    });
+
+    server_addr
+}
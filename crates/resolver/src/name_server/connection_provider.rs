@@ -38,7 +38,7 @@ use crate::proto::{
     xfer::{Connecting, DnsExchange, DnsHandle, DnsMultiplexer},
 };
 #[cfg(feature = "__tls")]
-use hickory_proto::rustls::client_config;
+use hickory_proto::rustls::{client_config, unauthenticated_client_config};
 
 /// Create `DnsHandle` with the help of `RuntimeProvider`.
 /// This trait is designed for customization.
@@ -58,6 +58,14 @@ pub trait ConnectionProvider: 'static + Clone + Send + Sync + Unpin {
         options: &ResolverOpts,
         tls: &TlsConfig,
     ) -> Result<Self::FutureConn, io::Error>;
+
+    /// Returns the provider used for spawning background tasks and opening TCP connections.
+    ///
+    /// Features which need background tasks, such as the probes of the circuit breaker, are
+    /// unavailable when this returns `None`, which is the default.
+    fn runtime_provider(&self) -> Option<Self::RuntimeProvider> {
+        None
+    }
 }
 
 /// Resolves to a new Connection
@@ -250,6 +258,10 @@ impl<P: RuntimeProvider> ConnectionProvider for P {
             spawner: self.create_handle(),
         })
     }
+
+    fn runtime_provider(&self) -> Option<Self::RuntimeProvider> {
+        Some(self.clone())
+    }
 }
 
 /// TLS configuration for the connection provider.
@@ -268,6 +280,16 @@ impl TlsConfig {
             config: client_config()?,
         })
     }
+
+    /// Create a `TlsConfig` which does not authenticate servers, for opportunistic encryption
+    ///
+    /// See [`unauthenticated_client_config`] for the protection this offers.
+    #[cfg(feature = "__tls")]
+    pub fn unauthenticated() -> Result<Self, ProtoError> {
+        Ok(Self {
+            config: unauthenticated_client_config()?,
+        })
+    }
 }
 
 #[cfg(all(
@@ -0,0 +1,300 @@
+//! Discovery of Designated Resolvers ([RFC 9462](https://www.rfc-editor.org/rfc/rfc9462))
+
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+#[cfg(not(test))]
+use std::time::{Duration, Instant};
+
+#[cfg(test)]
+use tokio::time::{Duration, Instant};
+
+use crate::config::{ConnectionConfig, ProtocolConfig};
+use crate::proto::op::{DnsRequest, DnsRequestOptions, Query};
+#[cfg(feature = "__https")]
+use crate::proto::rr::rdata::svcb::SvcParamKey;
+use crate::proto::rr::rdata::svcb::{SVCB, SvcParamValue};
+use crate::proto::rr::{Name, RData, Record, RecordType};
+
+/// Progress of the discovery of the designated resolver of a name server
+#[derive(Clone, Debug)]
+pub(super) enum DdrState {
+    /// Discovery has not been attempted yet
+    Pending,
+    /// Connections should use the designated resolver
+    Upgraded(ConnectionConfig),
+    /// The server does not designate a usable encrypted resolver
+    Unavailable,
+    /// Discovery could not reach the server, or the designated resolver failed, and is attempted
+    /// again after `retry_at`
+    Failed { retry_at: Instant, failures: u32 },
+}
+
+impl DdrState {
+    /// The state after `failures` consecutive failures, backing off exponentially
+    pub(super) fn failed(failures: u32) -> Self {
+        let backoff = INITIAL_RETRY_INTERVAL
+            .saturating_mul(2_u32.saturating_pow(failures.saturating_sub(1)))
+            .min(MAX_RETRY_INTERVAL);
+        Self::Failed {
+            retry_at: Instant::now() + backoff,
+            failures,
+        }
+    }
+}
+
+/// Request for the SVCB records of `_dns.resolver.arpa.`
+pub(super) fn request() -> DnsRequest {
+    let name = Name::from_ascii("_dns.resolver.arpa.").expect("valid name");
+    DnsRequest::from_query(
+        Query::query(name, RecordType::SVCB),
+        DnsRequestOptions::default(),
+    )
+}
+
+/// Builds the connection configs for the designated resolvers of the server at `ip`
+///
+/// Endpoints are returned in order of SVCB priority, then in ALPN order. Only designated resolvers
+/// that can be reached at `ip` are considered, so records with address hints not including `ip`
+/// are skipped. The server name of each connection is `ip`, so that connecting verifies that the
+/// certificate of the designated resolver covers the original IP address, as required for verified
+/// discovery.
+pub(super) fn designated_connections(
+    ip: IpAddr,
+    bind_addr: Option<SocketAddr>,
+    answers: &[Record],
+) -> Vec<ConnectionConfig> {
+    let mut records = answers
+        .iter()
+        .filter_map(|record| match record.data() {
+            RData::SVCB(svcb) if svcb.svc_priority() != 0 => Some(svcb),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    records.sort_by_key(|svcb| svcb.svc_priority());
+
+    let server_name = Arc::<str>::from(ip.to_string());
+    let mut connections = Vec::new();
+    for svcb in records {
+        if !reachable_at(svcb, ip) {
+            continue;
+        }
+
+        let port = svcb.svc_params().iter().find_map(|(_, value)| match value {
+            SvcParamValue::Port(port) => Some(*port),
+            _ => None,
+        });
+
+        for alpn in alpns(svcb) {
+            let Some(protocol) = protocol(alpn, &server_name, svcb) else {
+                continue;
+            };
+
+            let mut config = ConnectionConfig::new(protocol);
+            if let Some(port) = port {
+                config.port = port;
+            }
+            config.bind_addr = bind_addr;
+            connections.push(config);
+        }
+    }
+
+    connections
+}
+
+fn alpns(svcb: &SVCB) -> impl Iterator<Item = &str> + '_ {
+    svcb.svc_params()
+        .iter()
+        .filter_map(|(_, value)| match value {
+            SvcParamValue::Alpn(alpn) => Some(alpn.0.iter().map(String::as_str)),
+            _ => None,
+        })
+        .flatten()
+}
+
+/// Whether the address hints of the record, if any, include `ip`
+fn reachable_at(svcb: &SVCB, ip: IpAddr) -> bool {
+    let mut hints = svcb
+        .svc_params()
+        .iter()
+        .filter_map(|(_, value)| match value {
+            SvcParamValue::Ipv4Hint(hint) => {
+                Some(hint.0.iter().map(|a| IpAddr::from(a.0)).collect::<Vec<_>>())
+            }
+            SvcParamValue::Ipv6Hint(hint) => {
+                Some(hint.0.iter().map(|aaaa| IpAddr::from(aaaa.0)).collect())
+            }
+            _ => None,
+        })
+        .flatten()
+        .peekable();
+
+    hints.peek().is_none() || hints.any(|hint| hint == ip)
+}
+
+/// Maps an ALPN identifier to a protocol, per [RFC 9461](https://www.rfc-editor.org/rfc/rfc9461)
+#[cfg_attr(not(feature = "__https"), allow(unused_variables))]
+fn protocol(alpn: &str, server_name: &Arc<str>, svcb: &SVCB) -> Option<ProtocolConfig> {
+    Some(match alpn {
+        "dot" => ProtocolConfig::Tls {
+            server_name: server_name.clone(),
+        },
+        #[cfg(feature = "__https")]
+        "h2" => ProtocolConfig::Https {
+            server_name: server_name.clone(),
+            path: doh_path(svcb)?,
+        },
+        #[cfg(feature = "__quic")]
+        "doq" => ProtocolConfig::Quic {
+            server_name: server_name.clone(),
+        },
+        #[cfg(feature = "__h3")]
+        "h3" => ProtocolConfig::H3 {
+            server_name: server_name.clone(),
+            path: doh_path(svcb)?,
+            disable_grease: false,
+        },
+        _ => return None,
+    })
+}
+
+/// Returns the path of the `dohpath` URI template, which is required for DoH endpoints
+#[cfg(feature = "__https")]
+fn doh_path(svcb: &SVCB) -> Option<Arc<str>> {
+    let template = svcb
+        .svc_params()
+        .iter()
+        .find_map(|(key, value)| match (key, value) {
+            (SvcParamKey::Unknown(DOHPATH_KEY), SvcParamValue::Unknown(value)) => {
+                std::str::from_utf8(&value.0).ok()
+            }
+            _ => None,
+        })?;
+
+    // We only support the `{?dns}` variable, as in `/dns-query{?dns}`
+    let path = template.split('{').next()?;
+    path.starts_with('/').then(|| Arc::from(path))
+}
+
+/// Time until discovery is attempted again after a first failure
+const INITIAL_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+/// Upper bound of the time until discovery is attempted again
+const MAX_RETRY_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// SvcParamKey of `dohpath`, see [RFC 9461 section 5](https://www.rfc-editor.org/rfc/rfc9461#section-5)
+#[cfg(feature = "__https")]
+const DOHPATH_KEY: u16 = 7;
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::proto::rr::rdata::A;
+    #[cfg(feature = "__https")]
+    use crate::proto::rr::rdata::svcb::Unknown;
+    use crate::proto::rr::rdata::svcb::{Alpn, IpHint, SvcParamKey};
+
+    fn record(priority: u16, params: Vec<(SvcParamKey, SvcParamValue)>) -> Record {
+        Record::from_rdata(
+            Name::from_ascii("_dns.resolver.arpa.").unwrap(),
+            300,
+            RData::SVCB(SVCB::new(
+                priority,
+                Name::from_ascii("dns.example.").unwrap(),
+                params,
+            )),
+        )
+    }
+
+    fn alpn(ids: &[&str]) -> (SvcParamKey, SvcParamValue) {
+        (
+            SvcParamKey::Alpn,
+            SvcParamValue::Alpn(Alpn(ids.iter().map(|id| id.to_string()).collect())),
+        )
+    }
+
+    const IP: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+
+    #[test]
+    fn test_priority_and_port() {
+        let answers = [
+            record(
+                2,
+                vec![
+                    alpn(&["dot"]),
+                    (SvcParamKey::Port, SvcParamValue::Port(8853)),
+                ],
+            ),
+            record(0, vec![alpn(&["dot"])]),
+            record(1, vec![alpn(&["dot", "unknown"])]),
+        ];
+
+        let connections = designated_connections(IP, None, &answers);
+        assert_eq!(connections.len(), 2);
+        assert_eq!(connections[0].port, 853);
+        assert_eq!(connections[1].port, 8853);
+        for connection in connections {
+            assert_eq!(
+                connection.protocol,
+                ProtocolConfig::Tls {
+                    server_name: Arc::from("192.0.2.1")
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_address_hints() {
+        let hint = |ip: Ipv4Addr| {
+            (
+                SvcParamKey::Ipv4Hint,
+                SvcParamValue::Ipv4Hint(IpHint(vec![A(ip)])),
+            )
+        };
+        let answers = [
+            record(1, vec![alpn(&["dot"]), hint(Ipv4Addr::new(192, 0, 2, 2))]),
+            record(2, vec![alpn(&["dot"]), hint(Ipv4Addr::new(192, 0, 2, 1))]),
+        ];
+
+        let connections = designated_connections(IP, None, &answers);
+        assert_eq!(connections.len(), 1);
+    }
+
+    #[cfg(feature = "__https")]
+    #[test]
+    fn test_doh_path() {
+        let dohpath = |template: &str| {
+            (
+                SvcParamKey::Unknown(DOHPATH_KEY),
+                SvcParamValue::Unknown(Unknown(template.as_bytes().to_vec())),
+            )
+        };
+        let answers = [
+            record(1, vec![alpn(&["h2"])]),
+            record(2, vec![alpn(&["h2"]), dohpath("/dns-query{?dns}")]),
+        ];
+
+        let connections = designated_connections(IP, None, &answers);
+        assert_eq!(connections.len(), 1);
+        assert_eq!(connections[0].port, 443);
+        assert_eq!(
+            connections[0].protocol,
+            ProtocolConfig::Https {
+                server_name: Arc::from("192.0.2.1"),
+                path: Arc::from("/dns-query"),
+            }
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failed_backoff() {
+        let retry_interval = |failures| match DdrState::failed(failures) {
+            DdrState::Failed { retry_at, .. } => retry_at - Instant::now(),
+            _ => unreachable!(),
+        };
+
+        assert_eq!(retry_interval(1), INITIAL_RETRY_INTERVAL);
+        assert_eq!(retry_interval(2), INITIAL_RETRY_INTERVAL * 2);
+        assert_eq!(retry_interval(40), MAX_RETRY_INTERVAL);
+    }
+}
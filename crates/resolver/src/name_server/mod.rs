@@ -8,9 +8,13 @@
 //! A module with associated items for working with nameservers
 
 mod connection_provider;
+#[cfg(feature = "__tls")]
+mod ddr;
 pub use connection_provider::{ConnectionProvider, TlsConfig};
 #[allow(clippy::module_inception)]
 mod name_server;
-pub use name_server::NameServer;
+pub use name_server::{CircuitState, NameServer, NameServerHealth};
 mod name_server_pool;
-pub use name_server_pool::NameServerPool;
+pub use name_server_pool::{NameServerPool, PoolHooks};
+mod pool_router;
+pub use pool_router::PoolRouter;
@@ -7,22 +7,34 @@
 
 use std::cmp;
 use std::fmt::Debug;
-use std::sync::Arc;
-use std::sync::atomic::{AtomicU8, AtomicU32, Ordering};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicU32, Ordering};
+use std::sync::{Arc, Weak};
 #[cfg(not(test))]
 use std::time::{Duration, Instant};
 
 use futures_util::lock::Mutex as AsyncMutex;
+#[cfg(feature = "metrics")]
+use metrics::{
+    Counter, Gauge, Histogram, Unit, counter, describe_counter, describe_gauge, describe_histogram,
+    gauge, histogram,
+};
 use parking_lot::Mutex as SyncMutex;
 #[cfg(test)]
 use tokio::time::{Duration, Instant};
-use tracing::debug;
+use tracing::{debug, info, warn};
 
-use crate::config::{NameServerConfig, ProtocolConfig, ResolverOpts, ServerOrderingStrategy};
+#[cfg(feature = "__tls")]
+use crate::config::{ConnectionConfig, DdrPolicy, ProtocolConfig};
+use crate::config::{NameServerConfig, ResolverOpts, ServerOrderingStrategy};
 use crate::name_server::connection_provider::{ConnectionProvider, TlsConfig};
+#[cfg(feature = "__tls")]
+use crate::name_server::ddr::{self, DdrState};
 use crate::proto::{
     DnsError, NoRecords, ProtoError, ProtoErrorKind,
-    op::{DnsRequest, DnsResponse, ResponseCode},
+    op::{DnsRequest, DnsRequestOptions, DnsResponse, Query, ResponseCode},
+    rr::{Name, RecordType},
+    runtime::{RuntimeProvider, Spawn, Time},
     xfer::{DnsHandle, FirstAnswer, Protocol},
 };
 
@@ -36,7 +48,12 @@ pub struct NameServer<P: ConnectionProvider> {
     tls: Arc<TlsConfig>,
     connections: AsyncMutex<Vec<ConnectionState<P>>>,
     server_srtt: DecayingSrtt,
+    health: Health,
+    #[cfg(feature = "__tls")]
+    ddr: SyncMutex<DdrState>,
     connection_provider: P,
+    #[cfg(feature = "metrics")]
+    metrics: NameServerMetrics,
 }
 
 impl<P: ConnectionProvider> NameServer<P> {
@@ -52,7 +69,7 @@ impl<P: ConnectionProvider> NameServer<P> {
     ) -> Self {
         let mut connections = connections
             .into_iter()
-            .map(|(protocol, handle)| ConnectionState::new(handle, protocol))
+            .map(|(protocol, handle)| ConnectionState::new(handle, protocol, config.ip))
             .collect::<Vec<_>>();
 
         // Unless the user specified that we should follow the configured order,
@@ -62,11 +79,16 @@ impl<P: ConnectionProvider> NameServer<P> {
         }
 
         Self {
+            #[cfg(feature = "metrics")]
+            metrics: NameServerMetrics::new(&config),
             config,
             options,
             tls,
             connections: AsyncMutex::new(connections),
             server_srtt: DecayingSrtt::new(Duration::from_micros(rand::random_range(1..32))),
+            health: Health::default(),
+            #[cfg(feature = "__tls")]
+            ddr: SyncMutex::new(DdrState::Pending),
             connection_provider,
         }
     }
@@ -75,16 +97,29 @@ impl<P: ConnectionProvider> NameServer<P> {
     pub(super) async fn send(
         self: Arc<Self>,
         request: DnsRequest,
-        skip_udp: bool,
+        protocols: ProtocolFilter,
     ) -> Result<DnsResponse, ProtoError> {
-        let (handle, meta) = self.connected_mut_client(skip_udp).await?;
+        let (handle, meta) = match self.connected_mut_client(protocols).await {
+            Ok(client) => client,
+            Err(error) => {
+                if is_connection_failure(&error) {
+                    self.connection_failed();
+                }
+                return Err(error);
+            }
+        };
+        #[cfg(feature = "metrics")]
+        meta.metrics.queries.increment(1);
         let now = Instant::now();
         let response = handle.send(request).first_answer().await;
         let rtt = now.elapsed();
 
         match response {
             Ok(response) => {
+                #[cfg(feature = "metrics")]
+                meta.metrics.response_time.record(rtt);
                 meta.set_status(Status::Established);
+                self.connection_succeeded();
                 let result = DnsError::from_response(response);
                 let error = match result {
                     Ok(response) => {
@@ -124,20 +159,18 @@ impl<P: ConnectionProvider> NameServer<P> {
 
                 // this transitions the state to failure
                 meta.set_status(Status::Failed);
+                #[cfg(feature = "metrics")]
+                match error.kind() {
+                    ProtoErrorKind::Timeout => meta.metrics.timeouts.increment(1),
+                    _ => meta.metrics.errors.increment(1),
+                }
 
                 // record the failure
-                match error.kind() {
-                    ProtoErrorKind::Busy | ProtoErrorKind::Io(_) | ProtoErrorKind::Timeout => {
-                        meta.srtt.record_failure()
-                    }
-                    #[cfg(feature = "__quic")]
-                    ProtoErrorKind::QuinnConfigError(_)
-                    | ProtoErrorKind::QuinnConnect(_)
-                    | ProtoErrorKind::QuinnConnection(_)
-                    | ProtoErrorKind::QuinnTlsConfigError(_) => meta.srtt.record_failure(),
-                    #[cfg(feature = "__tls")]
-                    ProtoErrorKind::RustlsError(_) => meta.srtt.record_failure(),
-                    _ => {}
+                if matches!(error.kind(), ProtoErrorKind::Busy) || is_connection_failure(&error) {
+                    meta.srtt.record_failure();
+                }
+                if is_connection_failure(&error) {
+                    self.connection_failed();
                 }
 
                 // These are connection failures, not lookup failures, that is handled in the resolver layer
@@ -151,9 +184,12 @@ impl<P: ConnectionProvider> NameServer<P> {
     /// If the connection is in a failed state, then this will establish a new connection
     async fn connected_mut_client(
         &self,
-        skip_udp: bool,
+        protocols: ProtocolFilter,
     ) -> Result<(P::Conn, Arc<ConnectionMeta>), ProtoError> {
         let mut connections = self.connections.lock().await;
+        #[cfg(feature = "__tls")]
+        self.discover_designated_resolver(&mut connections).await?;
+
         connections.retain(|conn| matches!(conn.meta.status(), Status::Init | Status::Established));
         if !connections.is_empty() {
             connections.sort_by(|a, b| match (a.protocol, b.protocol) {
@@ -163,19 +199,33 @@ impl<P: ConnectionProvider> NameServer<P> {
                 (_, _) => a.meta.srtt.current().total_cmp(&b.meta.srtt.current()),
             });
 
-            let conn = connections.first().unwrap(); // safe to unwrap because we only get here if `connections` is not empty
-            if !(skip_udp && conn.protocol == Protocol::Udp) {
+            if let Some(conn) = connections
+                .iter()
+                .find(|conn| protocols.allows(conn.protocol))
+            {
                 return Ok((conn.handle.clone(), conn.meta.clone()));
             }
         }
 
         debug!(config = ?self.config, "connecting");
-        let config = self
-            .config
-            .connections
-            .iter()
-            .find(|conn| !(matches!(conn.protocol, ProtocolConfig::Udp) && skip_udp))
-            .ok_or_else(|| ProtoError::from(ProtoErrorKind::NoConnections))?;
+        #[cfg(feature = "__tls")]
+        let designated = self.designated_resolver();
+        #[cfg(not(feature = "__tls"))]
+        let designated = None;
+        let config = match &designated {
+            Some(config) => config,
+            None => self
+                .config
+                .connections
+                .iter()
+                .find(|conn| protocols.allows(conn.protocol.to_protocol()))
+                .ok_or_else(|| ProtoError::from(ProtoErrorKind::NoConnections))?,
+        };
+
+        // the designated resolver replaces the configured connections
+        if !protocols.allows(config.protocol.to_protocol()) {
+            return Err(ProtoErrorKind::NoConnections.into());
+        }
 
         let handle = Box::pin(self.connection_provider.new_connection(
             self.config.ip,
@@ -186,12 +236,272 @@ impl<P: ConnectionProvider> NameServer<P> {
         .await?;
 
         // establish a new connection
-        let state = ConnectionState::new(handle.clone(), config.protocol.to_protocol());
+        let state = ConnectionState::new(
+            handle.clone(),
+            config.protocol.to_protocol(),
+            self.config.ip,
+        );
         let meta = state.meta.clone();
         connections.push(state);
         Ok((handle, meta))
     }
 
+    /// Switches to the designated resolver of this server, if discovery is enabled.
+    ///
+    /// Discovery is attempted until the server answers the SVCB query, backing off after each
+    /// failure. Depending on the [`DdrPolicy`], a server without a usable designated resolver is
+    /// either used over plain text, or fails all requests. With [`DdrPolicy::Opportunistic`], plain
+    /// text is also used when the connection to the designated resolver fails, until discovery is
+    /// attempted again.
+    #[cfg(feature = "__tls")]
+    async fn discover_designated_resolver(
+        &self,
+        connections: &mut Vec<ConnectionState<P>>,
+    ) -> Result<(), ProtoError> {
+        let policy = self.options.ddr;
+        let plain_text = self
+            .config
+            .connections
+            .iter()
+            .all(|conn| matches!(conn.protocol, ProtocolConfig::Udp | ProtocolConfig::Tcp));
+        if policy == DdrPolicy::Disabled || !plain_text {
+            return Ok(());
+        }
+
+        let state = self.ddr.lock().clone();
+        let error = match state {
+            DdrState::Upgraded(_) => {
+                let failed = !connections
+                    .iter()
+                    .any(|conn| matches!(conn.meta.status(), Status::Init | Status::Established));
+                if failed && policy == DdrPolicy::Opportunistic {
+                    warn!(
+                        ip = %self.config.ip,
+                        "designated resolver failed, falling back to plain text"
+                    );
+                    connections.clear();
+                    *self.ddr.lock() = DdrState::failed(1);
+                }
+                return Ok(());
+            }
+            DdrState::Unavailable => ProtoError::from("no designated resolver available"),
+            DdrState::Failed { retry_at, .. } if Instant::now() < retry_at => {
+                ProtoError::from("discovery of the designated resolver failed")
+            }
+            DdrState::Pending | DdrState::Failed { .. } => match self.upgrade().await {
+                Ok(Some((config, handle))) => {
+                    info!(
+                        ip = %self.config.ip,
+                        protocol = %config.protocol.to_protocol(),
+                        port = config.port,
+                        "switching to designated resolver"
+                    );
+                    let state =
+                        ConnectionState::new(handle, config.protocol.to_protocol(), self.config.ip);
+                    state.meta.set_status(Status::Established);
+                    connections.clear();
+                    connections.push(state);
+                    *self.ddr.lock() = DdrState::Upgraded(config);
+                    return Ok(());
+                }
+                Ok(None) => {
+                    debug!(ip = %self.config.ip, "no designated resolver available");
+                    *self.ddr.lock() = DdrState::Unavailable;
+                    ProtoError::from("no designated resolver available")
+                }
+                Err(error) => {
+                    debug!(ip = %self.config.ip, %error, "failed to discover the designated resolver");
+                    let failures = match state {
+                        DdrState::Failed { failures, .. } => failures,
+                        _ => 0,
+                    };
+                    *self.ddr.lock() = DdrState::failed(failures + 1);
+                    error
+                }
+            },
+        };
+
+        match policy {
+            DdrPolicy::Required => Err(error),
+            _ => Ok(()),
+        }
+    }
+
+    /// Queries the designated resolvers of this server, and connects to the first one that works.
+    ///
+    /// Returns an error if the SVCB query could not be sent over plain text.
+    #[cfg(feature = "__tls")]
+    async fn upgrade(&self) -> Result<Option<(ConnectionConfig, P::Conn)>, ProtoError> {
+        let Some(plain) = self.config.connections.first() else {
+            return Ok(None);
+        };
+
+        let request = ddr::request();
+        let handle = Box::pin(self.connection_provider.new_connection(
+            self.config.ip,
+            plain,
+            &self.options,
+            &self.tls,
+        )?)
+        .await?;
+        let response = handle.send(request.clone()).first_answer().await?;
+
+        for config in
+            ddr::designated_connections(self.config.ip, plain.bind_addr, response.answers())
+        {
+            let connect = match self.connection_provider.new_connection(
+                self.config.ip,
+                &config,
+                &self.options,
+                &self.tls,
+            ) {
+                Ok(connect) => Box::pin(connect),
+                Err(error) => {
+                    debug!(?config, %error, "unsupported designated resolver");
+                    continue;
+                }
+            };
+
+            // The server name of the designated connection is the IP address of this server, so
+            // a response means that the certificate was valid for that address.
+            let result = match connect.await {
+                Ok(handle) => match handle.send(request.clone()).first_answer().await {
+                    Ok(_) => return Ok(Some((config, handle))),
+                    Err(error) => error,
+                },
+                Err(error) => error,
+            };
+            debug!(?config, %result, "failed to verify designated resolver");
+        }
+
+        Ok(None)
+    }
+
+    /// Returns the connection config of the designated resolver, if this server was upgraded.
+    #[cfg(feature = "__tls")]
+    pub fn designated_resolver(&self) -> Option<ConnectionConfig> {
+        match &*self.ddr.lock() {
+            DdrState::Upgraded(config) => Some(config.clone()),
+            _ => None,
+        }
+    }
+
+    /// Records a response from the server, putting it back into rotation if necessary.
+    fn connection_succeeded(&self) {
+        if !self.health.record_success() {
+            return;
+        }
+
+        info!(ip = %self.config.ip, "name server is responding again, returning it to rotation");
+        #[cfg(feature = "metrics")]
+        self.metrics.circuit_open.set(0.0);
+    }
+
+    /// Records a connection failure, and takes the server out of rotation if the circuit breaker
+    /// threshold is reached.
+    fn connection_failed(self: &Arc<Self>) {
+        let failures = self.health.record_failure();
+        let Some(config) = &self.options.circuit_breaker else {
+            return;
+        };
+
+        if failures < config.failure_threshold {
+            return;
+        }
+
+        // the server would never return to rotation without a probe
+        let Some(runtime) = self.connection_provider.runtime_provider() else {
+            if failures == config.failure_threshold {
+                warn!(
+                    ip = %self.config.ip,
+                    "name server is unresponsive, but the connection provider has no runtime provider to probe it, keeping it in rotation"
+                );
+            }
+            return;
+        };
+
+        if !self.health.open() {
+            return;
+        }
+
+        warn!(
+            ip = %self.config.ip,
+            failures, "name server is unresponsive, taking it out of rotation"
+        );
+        #[cfg(feature = "metrics")]
+        {
+            self.metrics.circuit_opened.increment(1);
+            self.metrics.circuit_open.set(1.0);
+        }
+
+        let probe = Self::probe(Arc::downgrade(self), config.probe_interval);
+        runtime.create_handle().spawn_bg(probe);
+    }
+
+    /// Periodically probes the server while its circuit is open, until it responds.
+    ///
+    /// Any response, including an error response code, closes the circuit. The probe stops if the
+    /// circuit is closed in the meantime, or if the server is dropped.
+    async fn probe(server: Weak<Self>, interval: Duration) -> Result<(), ProtoError> {
+        loop {
+            <P::RuntimeProvider as RuntimeProvider>::Timer::delay_for(interval).await;
+            let Some(server) = server.upgrade() else {
+                return Ok(());
+            };
+
+            if !server.health.is_open() {
+                return Ok(());
+            }
+
+            debug!(ip = %server.config.ip, "probing name server");
+            let request = DnsRequest::from_query(
+                Query::query(Name::root(), RecordType::NS),
+                DnsRequestOptions::default(),
+            );
+
+            let result = match server.connected_mut_client(ProtocolFilter::default()).await {
+                Ok((handle, meta)) => {
+                    let result = handle.send(request).first_answer().await;
+                    meta.set_status(match result {
+                        Ok(_) => Status::Established,
+                        Err(_) => Status::Failed,
+                    });
+                    result
+                }
+                Err(error) => Err(error),
+            };
+
+            match result {
+                Ok(_) => {
+                    server.connection_succeeded();
+                    return Ok(());
+                }
+                Err(error) => debug!(ip = %server.config.ip, %error, "name server probe failed"),
+            }
+        }
+    }
+
+    /// Returns the configuration of this name server.
+    pub fn config(&self) -> &NameServerConfig {
+        &self.config
+    }
+
+    /// Returns the current health of this name server.
+    pub fn health(&self) -> NameServerHealth {
+        NameServerHealth {
+            consecutive_failures: self.health.consecutive_failures.load(Ordering::Acquire),
+            circuit: match self.health.is_open() {
+                true => CircuitState::Open,
+                false => CircuitState::Closed,
+            },
+        }
+    }
+
+    /// Whether this server should be used for new requests
+    pub(super) fn is_available(&self) -> bool {
+        self.options.circuit_breaker.is_none() || !self.health.is_open()
+    }
+
     pub(super) fn protocols(&self) -> impl Iterator<Item = Protocol> + '_ {
         self.config
             .connections
@@ -199,7 +509,10 @@ impl<P: ConnectionProvider> NameServer<P> {
             .map(|conn| conn.protocol.to_protocol())
     }
 
-    pub(super) fn decayed_srtt(&self) -> f64 {
+    /// Returns the smoothed round-trip time of this name server, in microseconds
+    ///
+    /// Failures are accounted for as a penalty, which decays over time. Lower is better.
+    pub fn decayed_srtt(&self) -> f64 {
         self.server_srtt.current()
     }
 
@@ -222,6 +535,121 @@ impl<P: ConnectionProvider> NameServer<P> {
     }
 }
 
+/// Snapshot of the health of a [`NameServer`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct NameServerHealth {
+    /// Number of connection failures (timeouts, I/O errors) since the last response from the server
+    pub consecutive_failures: u32,
+    /// State of the circuit breaker of the server
+    pub circuit: CircuitState,
+}
+
+/// State of the circuit breaker of a [`NameServer`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// The server is in rotation and receives requests.
+    Closed,
+    /// The server has failed repeatedly, and is skipped until a probe gets a response from it.
+    Open,
+}
+
+#[derive(Default)]
+struct Health {
+    consecutive_failures: AtomicU32,
+    open: AtomicBool,
+}
+
+impl Health {
+    /// Resets the failure count, returns `true` if this closed the circuit.
+    fn record_success(&self) -> bool {
+        self.consecutive_failures.store(0, Ordering::Release);
+        self.open.swap(false, Ordering::AcqRel)
+    }
+
+    /// Increments the failure count, returning the new count.
+    fn record_failure(&self) -> u32 {
+        let previous = self
+            .consecutive_failures
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+                Some(count.saturating_add(1))
+            })
+            .unwrap_or_default();
+        previous.saturating_add(1)
+    }
+
+    /// Opens the circuit, returns `true` if it was previously closed.
+    fn open(&self) -> bool {
+        !self.open.swap(true, Ordering::AcqRel)
+    }
+
+    fn is_open(&self) -> bool {
+        self.open.load(Ordering::Acquire)
+    }
+}
+
+#[cfg(feature = "metrics")]
+struct NameServerMetrics {
+    circuit_opened: Counter,
+    circuit_open: Gauge,
+}
+
+#[cfg(feature = "metrics")]
+impl NameServerMetrics {
+    fn new(config: &NameServerConfig) -> Self {
+        let server = config.ip.to_string();
+        let circuit_opened =
+            counter!("hickory_resolver_circuit_breaker_opened_total", "server" => server.clone());
+        describe_counter!(
+            "hickory_resolver_circuit_breaker_opened_total",
+            Unit::Count,
+            "Number of times a name server was taken out of rotation after repeated failures."
+        );
+        let circuit_open = gauge!("hickory_resolver_circuit_breaker_open", "server" => server);
+        describe_gauge!(
+            "hickory_resolver_circuit_breaker_open",
+            Unit::Count,
+            "Whether a name server is currently out of rotation (1) or not (0)."
+        );
+        Self {
+            circuit_opened,
+            circuit_open,
+        }
+    }
+}
+
+/// Whether the error indicates that the server could not be reached, as opposed to an error
+/// response from the server.
+fn is_connection_failure(error: &ProtoError) -> bool {
+    match error.kind() {
+        ProtoErrorKind::Io(_) | ProtoErrorKind::Timeout => true,
+        #[cfg(feature = "__quic")]
+        ProtoErrorKind::QuinnConfigError(_)
+        | ProtoErrorKind::QuinnConnect(_)
+        | ProtoErrorKind::QuinnConnection(_)
+        | ProtoErrorKind::QuinnTlsConfigError(_) => true,
+        #[cfg(feature = "__tls")]
+        ProtoErrorKind::RustlsError(_) => true,
+        _ => false,
+    }
+}
+
+/// The protocols a request may be sent over
+#[derive(Clone, Copy, Debug, Default)]
+pub(super) struct ProtocolFilter {
+    /// Only use this protocol
+    pub(super) required: Option<Protocol>,
+    /// Avoid UDP, after a truncated or spoofed response
+    pub(super) skip_udp: bool,
+}
+
+impl ProtocolFilter {
+    pub(super) fn allows(self, protocol: Protocol) -> bool {
+        !(self.skip_udp && protocol == Protocol::Udp)
+            && self.required.map_or(true, |required| required == protocol)
+    }
+}
+
 struct ConnectionState<P: ConnectionProvider> {
     protocol: Protocol,
     handle: P::Conn,
@@ -229,11 +657,11 @@ struct ConnectionState<P: ConnectionProvider> {
 }
 
 impl<P: ConnectionProvider> ConnectionState<P> {
-    fn new(handle: P::Conn, protocol: Protocol) -> Self {
+    fn new(handle: P::Conn, protocol: Protocol, ip: IpAddr) -> Self {
         Self {
             protocol,
             handle,
-            meta: Arc::new(ConnectionMeta::default()),
+            meta: Arc::new(ConnectionMeta::new(ip, protocol)),
         }
     }
 }
@@ -241,9 +669,24 @@ impl<P: ConnectionProvider> ConnectionState<P> {
 struct ConnectionMeta {
     status: AtomicU8,
     srtt: DecayingSrtt,
+    #[cfg(feature = "metrics")]
+    metrics: ConnectionMetrics,
 }
 
 impl ConnectionMeta {
+    #[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+    fn new(ip: IpAddr, protocol: Protocol) -> Self {
+        // Initialize the SRTT to a randomly generated value that represents a
+        // very low RTT. Such a value helps ensure that each server is attempted
+        // early.
+        Self {
+            status: AtomicU8::new(Status::Init.into()),
+            srtt: DecayingSrtt::new(Duration::from_micros(rand::random_range(1..32))),
+            #[cfg(feature = "metrics")]
+            metrics: ConnectionMetrics::new(ip, protocol),
+        }
+    }
+
     fn set_status(&self, status: Status) {
         self.status.store(status.into(), Ordering::Release);
     }
@@ -253,14 +696,58 @@ impl ConnectionMeta {
     }
 }
 
-impl Default for ConnectionMeta {
-    fn default() -> Self {
-        // Initialize the SRTT to a randomly generated value that represents a
-        // very low RTT. Such a value helps ensure that each server is attempted
-        // early.
+#[cfg(feature = "metrics")]
+struct ConnectionMetrics {
+    queries: Counter,
+    response_time: Histogram,
+    timeouts: Counter,
+    errors: Counter,
+}
+
+#[cfg(feature = "metrics")]
+impl ConnectionMetrics {
+    fn new(ip: IpAddr, protocol: Protocol) -> Self {
+        let labels = [
+            ("server", ip.to_string()),
+            ("protocol", protocol.to_string()),
+        ];
+
+        counter!("hickory_resolver_connections_total", &labels).increment(1);
+        describe_counter!(
+            "hickory_resolver_connections_total",
+            Unit::Count,
+            "Number of connections established to upstream name servers."
+        );
+        let queries = counter!("hickory_resolver_upstream_queries_total", &labels);
+        describe_counter!(
+            "hickory_resolver_upstream_queries_total",
+            Unit::Count,
+            "Number of queries sent to upstream name servers."
+        );
+        let response_time = histogram!("hickory_resolver_upstream_response_time_seconds", &labels);
+        describe_histogram!(
+            "hickory_resolver_upstream_response_time_seconds",
+            Unit::Seconds,
+            "Time until a response was received from an upstream name server."
+        );
+        let timeouts = counter!("hickory_resolver_upstream_timeouts_total", &labels);
+        describe_counter!(
+            "hickory_resolver_upstream_timeouts_total",
+            Unit::Count,
+            "Number of queries to upstream name servers that timed out."
+        );
+        let errors = counter!("hickory_resolver_upstream_errors_total", &labels);
+        describe_counter!(
+            "hickory_resolver_upstream_errors_total",
+            Unit::Count,
+            "Number of queries to upstream name servers that failed without a response, other than timeouts."
+        );
+
         Self {
-            status: AtomicU8::new(Status::Init.into()),
-            srtt: DecayingSrtt::new(Duration::from_micros(rand::random_range(1..32))),
+            queries,
+            response_time,
+            timeouts,
+            errors,
         }
     }
 }
@@ -454,7 +941,7 @@ mod tests {
     use tokio::spawn;
 
     use super::*;
-    use crate::config::{ConnectionConfig, ProtocolConfig};
+    use crate::config::{CircuitBreakerConfig, ConnectionConfig, ProtocolConfig};
     use crate::proto::op::{DnsRequestOptions, Message, Query, ResponseCode};
     use crate::proto::rr::rdata::NULL;
     use crate::proto::rr::{Name, RData, Record, RecordType};
@@ -480,7 +967,7 @@ mod tests {
                     Query::query(name.clone(), RecordType::A),
                     DnsRequestOptions::default(),
                 ),
-                false,
+                ProtocolFilter::default(),
             )
             .await
             .expect("query failed");
@@ -513,7 +1000,7 @@ mod tests {
                         Query::query(name.clone(), RecordType::A),
                         DnsRequestOptions::default(),
                     ),
-                    false
+                    ProtocolFilter::default()
                 )
                 .await
                 .is_err()
@@ -579,7 +1066,7 @@ mod tests {
                     Query::query(name.clone(), RecordType::NULL),
                     request_options,
                 ),
-                false,
+                ProtocolFilter::default(),
             )
             .await
             .unwrap();
@@ -588,6 +1075,239 @@ mod tests {
         assert!(response_query_name.eq_case(&name));
     }
 
+    #[tokio::test]
+    async fn test_circuit_breaker() {
+        subscribe();
+
+        let server = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let responding = Arc::new(AtomicBool::new(false));
+
+        spawn({
+            let responding = responding.clone();
+            async move {
+                let mut buffer = [0_u8; 512];
+                loop {
+                    let (len, addr) = server.recv_from(&mut buffer).await.unwrap();
+                    if !responding.load(Ordering::Acquire) {
+                        continue;
+                    }
+
+                    let request = Message::from_vec(&buffer[0..len]).unwrap();
+                    let mut response = Message::response(request.id(), request.op_code());
+                    response.add_queries(request.queries().to_vec());
+                    server
+                        .send_to(&response.to_vec().unwrap(), addr)
+                        .await
+                        .unwrap();
+                }
+            }
+        });
+
+        let options = ResolverOpts {
+            timeout: Duration::from_millis(50),
+            circuit_breaker: Some(CircuitBreakerConfig {
+                failure_threshold: 2,
+                probe_interval: Duration::from_millis(50),
+                ..CircuitBreakerConfig::default()
+            }),
+            ..ResolverOpts::default()
+        };
+
+        let config = NameServerConfig {
+            ip: server_addr.ip(),
+            trust_negative_responses: true,
+            connections: vec![ConnectionConfig {
+                port: server_addr.port(),
+                protocol: ProtocolConfig::Udp,
+                bind_addr: None,
+            }],
+        };
+
+        let ns = Arc::new(NameServer::new(
+            [],
+            config,
+            Arc::new(options),
+            Arc::new(TlsConfig::new().unwrap()),
+            TokioRuntimeProvider::default(),
+        ));
+
+        let request = DnsRequest::from_query(
+            Query::query(Name::from_str("www.example.com.").unwrap(), RecordType::A),
+            DnsRequestOptions::default(),
+        );
+
+        assert!(
+            ns.clone()
+                .send(request.clone(), ProtocolFilter::default())
+                .await
+                .is_err()
+        );
+        assert_eq!(ns.health().circuit, CircuitState::Closed);
+        assert!(ns.is_available());
+
+        assert!(
+            ns.clone()
+                .send(request.clone(), ProtocolFilter::default())
+                .await
+                .is_err()
+        );
+        assert_eq!(
+            ns.health(),
+            NameServerHealth {
+                consecutive_failures: 2,
+                circuit: CircuitState::Open,
+            }
+        );
+        assert!(!ns.is_available());
+
+        // The background probe should return the server to rotation once it responds.
+        responding.store(true, Ordering::Release);
+        for _ in 0..100 {
+            if ns.is_available() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        assert_eq!(
+            ns.health(),
+            NameServerHealth {
+                consecutive_failures: 0,
+                circuit: CircuitState::Closed,
+            }
+        );
+    }
+
+    #[cfg(feature = "__tls")]
+    #[tokio::test]
+    async fn test_ddr_fallback() {
+        subscribe();
+
+        // A server without designated resolvers, answering all queries with an empty response
+        let server = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let server_addr = server.local_addr().unwrap();
+        spawn(async move {
+            let mut buffer = [0_u8; 512];
+            loop {
+                let (len, addr) = server.recv_from(&mut buffer).await.unwrap();
+                let request = Message::from_vec(&buffer[0..len]).unwrap();
+                let mut response = Message::response(request.id(), request.op_code());
+                response.add_queries(request.queries().to_vec());
+                server
+                    .send_to(&response.to_vec().unwrap(), addr)
+                    .await
+                    .unwrap();
+            }
+        });
+
+        let name_server = |ddr| {
+            let mut config = NameServerConfig::udp(server_addr.ip());
+            config.connections[0].port = server_addr.port();
+            Arc::new(NameServer::new(
+                [],
+                config,
+                Arc::new(ResolverOpts {
+                    ddr,
+                    ..ResolverOpts::default()
+                }),
+                Arc::new(TlsConfig::new().unwrap()),
+                TokioRuntimeProvider::default(),
+            ))
+        };
+        let request = DnsRequest::from_query(
+            Query::query(Name::from_str("www.example.com.").unwrap(), RecordType::A),
+            DnsRequestOptions::default(),
+        );
+
+        let opportunistic = name_server(DdrPolicy::Opportunistic);
+        let err = opportunistic
+            .clone()
+            .send(request.clone(), ProtocolFilter::default())
+            .await;
+        assert!(
+            matches!(err.unwrap_err().kind(), ProtoErrorKind::Dns(_)),
+            "should fall back to plain text"
+        );
+        assert!(opportunistic.designated_resolver().is_none());
+
+        let required = name_server(DdrPolicy::Required);
+        let err = required
+            .clone()
+            .send(request, ProtocolFilter::default())
+            .await
+            .unwrap_err();
+        assert!(!matches!(err.kind(), ProtoErrorKind::Dns(_)), "{err:?}");
+        assert!(required.designated_resolver().is_none());
+    }
+
+    #[cfg(feature = "__tls")]
+    #[tokio::test]
+    async fn test_ddr_backoff() {
+        subscribe();
+
+        // A server which never answers the SVCB query, and answers other queries with an empty
+        // response
+        let server = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let discovery_queries = Arc::new(AtomicU32::new(0));
+        let discovery_queries_clone = discovery_queries.clone();
+        spawn(async move {
+            let mut buffer = [0_u8; 512];
+            loop {
+                let (len, addr) = server.recv_from(&mut buffer).await.unwrap();
+                let request = Message::from_vec(&buffer[0..len]).unwrap();
+                if request.queries()[0].query_type() == RecordType::SVCB {
+                    discovery_queries_clone.fetch_add(1, Ordering::Relaxed);
+                    continue;
+                }
+
+                let mut response = Message::response(request.id(), request.op_code());
+                response.add_queries(request.queries().to_vec());
+                server
+                    .send_to(&response.to_vec().unwrap(), addr)
+                    .await
+                    .unwrap();
+            }
+        });
+
+        let mut config = NameServerConfig::udp(server_addr.ip());
+        config.connections[0].port = server_addr.port();
+        let name_server = Arc::new(NameServer::new(
+            [],
+            config,
+            Arc::new(ResolverOpts {
+                ddr: DdrPolicy::Opportunistic,
+                timeout: Duration::from_millis(100),
+                ..ResolverOpts::default()
+            }),
+            Arc::new(TlsConfig::new().unwrap()),
+            TokioRuntimeProvider::default(),
+        ));
+        let request = DnsRequest::from_query(
+            Query::query(Name::from_str("www.example.com.").unwrap(), RecordType::A),
+            DnsRequestOptions::default(),
+        );
+
+        for _ in 0..3 {
+            let err = name_server
+                .clone()
+                .send(request.clone(), ProtocolFilter::default())
+                .await;
+            assert!(
+                matches!(err.unwrap_err().kind(), ProtoErrorKind::Dns(_)),
+                "should fall back to plain text"
+            );
+        }
+
+        // discovery is not attempted again until the retry interval has elapsed
+        assert_eq!(discovery_queries.load(Ordering::Relaxed), 1);
+        assert!(matches!(
+            *name_server.ddr.lock(),
+            DdrState::Failed { failures: 1, .. }
+        ));
+    }
+
     #[allow(clippy::extra_unused_type_parameters)]
     fn is_send_sync<S: Sync + Send>() -> bool {
         true
@@ -12,15 +12,18 @@ use std::sync::{
     Arc,
     atomic::{AtomicUsize, Ordering as AtomicOrdering},
 };
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use futures_util::future::BoxFuture;
 use futures_util::stream::{FuturesUnordered, Stream, StreamExt, once};
+#[cfg(feature = "metrics")]
+use metrics::{Counter, Unit, counter, describe_counter};
 use smallvec::SmallVec;
 use tracing::debug;
 
-use crate::config::{NameServerConfig, ResolverOpts, ServerOrderingStrategy};
+use crate::config::{AllServersDown, NameServerConfig, ResolverOpts, ServerOrderingStrategy};
 use crate::name_server::connection_provider::{ConnectionProvider, TlsConfig};
-use crate::name_server::name_server::NameServer;
+use crate::name_server::name_server::{NameServer, ProtocolFilter};
 use crate::proto::op::{DnsRequest, DnsResponse, ResponseCode};
 use crate::proto::runtime::{RuntimeProvider, Time};
 use crate::proto::xfer::{DnsHandle, Protocol};
@@ -60,7 +63,20 @@ impl<P: ConnectionProvider> NameServerPool<P> {
     #[doc(hidden)]
     pub fn from_nameservers(servers: Vec<Arc<NameServer<P>>>, options: Arc<ResolverOpts>) -> Self {
         Self {
-            state: Arc::new(PoolState::new(servers, options)),
+            state: Arc::new(PoolState::new(servers, options, None)),
+        }
+    }
+
+    /// Returns a pool of the same name servers, which only sends requests over `protocol`
+    ///
+    /// The name servers, with their connections and statistics, are shared with this pool.
+    pub fn with_protocol(&self, protocol: Protocol) -> Self {
+        Self {
+            state: Arc::new(PoolState::new(
+                self.state.servers.clone(),
+                self.state.options.clone(),
+                Some(protocol),
+            )),
         }
     }
 
@@ -68,8 +84,72 @@ impl<P: ConnectionProvider> NameServerPool<P> {
     pub fn options(&self) -> &ResolverOpts {
         &self.state.options
     }
+
+    /// Returns the name servers in this pool, in configured order.
+    pub fn servers(&self) -> &[Arc<NameServer<P>>] {
+        &self.state.servers
+    }
+
+    /// Sends `request` like [`DnsHandle::send`], with `hooks` to choose the name servers it is sent
+    /// to and to see how each of them responded
+    pub async fn send_with_hooks(
+        &self,
+        request: DnsRequest,
+        hooks: &dyn PoolHooks<P>,
+    ) -> Result<DnsResponse, ProtoError> {
+        debug!("sending request: {:?}", request.queries());
+        self.state.try_send(request, hooks).await
+    }
+}
+
+/// Customizes how the requests sent with [`NameServerPool::send_with_hooks`] reach the name servers
+///
+/// The pool still handles concurrent requests, retries over TCP and the other error handling;
+/// the default implementations leave it unchanged.
+pub trait PoolHooks<P: ConnectionProvider>: Send + Sync {
+    /// Sorts `servers` in the order in which they are tried, after the configured
+    /// [`ServerOrderingStrategy`], and removes those which should not be tried
+    fn order(&self, servers: &mut Vec<Arc<NameServer<P>>>) {
+        let _ = servers;
+    }
+
+    /// Returns how long to wait for a response from `server` over UDP
+    ///
+    /// If `server` does not respond in time, the next server is tried. This does not apply to the
+    /// retry over TCP after a truncated response, which waits as long as the configured timeout.
+    fn udp_timeout(&self, server: &NameServer<P>) -> Option<Duration> {
+        let _ = server;
+        None
+    }
+
+    /// Returns a request to `server` over another transport, which is sent instead of `request`
+    ///
+    /// If the returned future resolves to `None`, `request` is sent to `server` as usual.
+    fn send(
+        &self,
+        server: &NameServer<P>,
+        request: &DnsRequest,
+    ) -> Option<BoxFuture<'static, Option<Result<DnsResponse, ProtoError>>>> {
+        let _ = (server, request);
+        None
+    }
+
+    /// Called with the result of each request sent to `server`, and the time it took
+    fn response(
+        &self,
+        server: &NameServer<P>,
+        rtt: Duration,
+        result: &Result<DnsResponse, ProtoError>,
+    ) {
+        let _ = (server, rtt, result);
+    }
 }
 
+/// The hooks of requests sent with [`DnsHandle::send`]
+struct NoHooks;
+
+impl<P: ConnectionProvider> PoolHooks<P> for NoHooks {}
+
 impl<P: ConnectionProvider> DnsHandle for NameServerPool<P> {
     type Response = Pin<Box<dyn Stream<Item = Result<DnsResponse, ProtoError>> + Send>>;
     type Runtime = P::RuntimeProvider;
@@ -78,7 +158,7 @@ impl<P: ConnectionProvider> DnsHandle for NameServerPool<P> {
         let state = self.state.clone();
         Box::pin(once(async move {
             debug!("sending request: {:?}", request.queries());
-            state.try_send(request).await
+            state.try_send(request, &NoHooks).await
         }))
     }
 }
@@ -86,19 +166,33 @@ impl<P: ConnectionProvider> DnsHandle for NameServerPool<P> {
 struct PoolState<P: ConnectionProvider> {
     servers: Vec<Arc<NameServer<P>>>,
     options: Arc<ResolverOpts>,
+    protocol: Option<Protocol>,
     next: AtomicUsize,
+    #[cfg(feature = "metrics")]
+    metrics: PoolMetrics,
 }
 
 impl<P: ConnectionProvider> PoolState<P> {
-    fn new(servers: Vec<Arc<NameServer<P>>>, options: Arc<ResolverOpts>) -> Self {
+    fn new(
+        servers: Vec<Arc<NameServer<P>>>,
+        options: Arc<ResolverOpts>,
+        protocol: Option<Protocol>,
+    ) -> Self {
         Self {
             servers,
             options,
+            protocol,
             next: AtomicUsize::new(0),
+            #[cfg(feature = "metrics")]
+            metrics: PoolMetrics::new(),
         }
     }
 
-    async fn try_send(&self, request: DnsRequest) -> Result<DnsResponse, ProtoError> {
+    async fn try_send(
+        &self,
+        request: DnsRequest,
+        hooks: &dyn PoolHooks<P>,
+    ) -> Result<DnsResponse, ProtoError> {
         let mut servers = self.servers.clone();
         match self.options.server_ordering_strategy {
             // select the highest priority connection
@@ -124,6 +218,19 @@ impl<P: ConnectionProvider> PoolState<P> {
             }
         }
 
+        // Skip servers that are out of rotation, unless there are no others left.
+        if let Some(circuit_breaker) = &self.options.circuit_breaker {
+            if servers.iter().any(|server| server.is_available()) {
+                servers.retain(|server| server.is_available());
+            } else if circuit_breaker.fallback == AllServersDown::Fail {
+                debug!("all name servers are out of rotation");
+                #[cfg(feature = "metrics")]
+                self.metrics.all_servers_down.increment(1);
+                return Err(ProtoError::from(ProtoErrorKind::NoConnections));
+            }
+        }
+        hooks.order(&mut servers);
+
         // If the name server we're trying is giving us backpressure by returning ProtoErrorKind::Busy,
         // we will first try the other name servers (as for other error types). However, if the other
         // servers are also busy, we're going to wait for a little while and then retry each server that
@@ -138,7 +245,10 @@ impl<P: ConnectionProvider> PoolState<P> {
         let mut backoff = Duration::from_millis(20);
         let mut busy = SmallVec::<[Arc<NameServer<P>>; 2]>::new();
         let mut err = ProtoError::from(ProtoErrorKind::NoConnections);
-        let mut skip_udp = false;
+        let mut protocols = ProtocolFilter {
+            required: self.protocol,
+            skip_udp: false,
+        };
 
         loop {
             // construct the parallel requests, 2 is the default
@@ -147,7 +257,7 @@ impl<P: ConnectionProvider> PoolState<P> {
                 && par_servers.len() < Ord::max(self.options.num_concurrent_reqs, 1)
             {
                 if let Some(server) = servers.pop_front() {
-                    if !(skip_udp && server.protocols().all(|p| p == Protocol::Udp)) {
+                    if server.protocols().any(|p| protocols.allows(p)) {
                         par_servers.push(server);
                     }
                 }
@@ -159,10 +269,10 @@ impl<P: ConnectionProvider> PoolState<P> {
                     backoff,
                 )
                 .await;
-                    servers
-                        .extend(busy.drain(..).filter(|ns| {
-                            !(skip_udp && ns.protocols().all(|p| p == Protocol::Udp))
-                        }));
+                    servers.extend(
+                        busy.drain(..)
+                            .filter(|ns| ns.protocols().any(|p| protocols.allows(p))),
+                    );
                     backoff *= 2;
                     continue;
                 }
@@ -172,7 +282,7 @@ impl<P: ConnectionProvider> PoolState<P> {
             let mut requests = par_servers
                 .into_iter()
                 .map(|server| {
-                    let future = server.clone().send(request.clone(), skip_udp);
+                    let future = Self::send_to(server.clone(), request.clone(), protocols, hooks);
                     async { (server, future.await) }
                 })
                 .collect::<FuturesUnordered<_>>();
@@ -181,7 +291,9 @@ impl<P: ConnectionProvider> PoolState<P> {
                 let e = match result {
                     Ok(response) if response.truncated() => {
                         debug!("truncated response received, retrying over TCP");
-                        skip_udp = true;
+                        #[cfg(feature = "metrics")]
+                        self.metrics.truncated_retries.increment(1);
+                        protocols.skip_udp = true;
                         err = ProtoError::from("received truncated response");
                         servers.push_front(server);
                         continue;
@@ -195,13 +307,15 @@ impl<P: ConnectionProvider> PoolState<P> {
                     // request to try and avoid further spoofing.
                     ProtoErrorKind::QueryCaseMismatch => {
                         servers.push_front(server);
-                        skip_udp = true;
+                        protocols.skip_udp = true;
                         continue;
                     }
                     // If the server is busy, try it again later if necessary.
                     ProtoErrorKind::Busy => busy.push(server),
                     // If the connection failed, try another one.
                     ProtoErrorKind::Io(_) | ProtoErrorKind::NoConnections => {}
+                    // If the server did not respond in the time given by the hooks, likewise.
+                    ProtoErrorKind::Timeout if hooks.udp_timeout(&server).is_some() => {}
                     // If we got an `NXDomain` response from a server whose negative responses we
                     // don't trust, we should try another server.
                     ProtoErrorKind::Dns(DnsError::NoRecordsFound(NoRecords {
@@ -217,19 +331,83 @@ impl<P: ConnectionProvider> PoolState<P> {
             }
         }
     }
+
+    /// Sends `request` to `server`, through the transport and with the UDP timeout given by `hooks`
+    async fn send_to(
+        server: Arc<NameServer<P>>,
+        request: DnsRequest,
+        protocols: ProtocolFilter,
+        hooks: &dyn PoolHooks<P>,
+    ) -> Result<DnsResponse, ProtoError> {
+        let start = Instant::now();
+        let other_transport = match hooks.send(&server, &request) {
+            Some(future) => future.await,
+            None => None,
+        };
+
+        let result = match other_transport {
+            Some(result) => result,
+            None => {
+                let udp = protocols.allows(Protocol::Udp)
+                    && server.protocols().any(|protocol| protocol == Protocol::Udp);
+                let future = server.clone().send(request, protocols);
+                match hooks.udp_timeout(&server).filter(|_| udp) {
+                    Some(timeout) => {
+                        <P::RuntimeProvider as RuntimeProvider>::Timer::timeout(timeout, future)
+                            .await
+                            .unwrap_or_else(|_| Err(ProtoError::from(ProtoErrorKind::Timeout)))
+                    }
+                    None => future.await,
+                }
+            }
+        };
+
+        hooks.response(&server, start.elapsed(), &result);
+        result
+    }
+}
+
+#[cfg(feature = "metrics")]
+struct PoolMetrics {
+    truncated_retries: Counter,
+    all_servers_down: Counter,
+}
+
+#[cfg(feature = "metrics")]
+impl PoolMetrics {
+    fn new() -> Self {
+        let truncated_retries = counter!("hickory_resolver_truncated_retries_total");
+        describe_counter!(
+            "hickory_resolver_truncated_retries_total",
+            Unit::Count,
+            "Number of requests retried over TCP after a truncated response."
+        );
+        let all_servers_down = counter!("hickory_resolver_all_servers_down_total");
+        describe_counter!(
+            "hickory_resolver_all_servers_down_total",
+            Unit::Count,
+            "Number of requests failed because all name servers were out of rotation."
+        );
+        Self {
+            truncated_retries,
+            all_servers_down,
+        }
+    }
 }
 
 #[cfg(test)]
 #[cfg(feature = "tokio")]
 mod tests {
-    use std::net::IpAddr;
+    use std::net::{IpAddr, Ipv4Addr};
     use std::str::FromStr;
 
     use test_support::subscribe;
+    use tokio::net::UdpSocket;
     use tokio::runtime::Runtime;
 
     use super::*;
-    use crate::config::{NameServerConfig, ResolverConfig};
+    use crate::config::{CircuitBreakerConfig, NameServerConfig, ResolverConfig};
+    use crate::name_server::CircuitState;
     use crate::proto::op::{DnsRequestOptions, Query};
     use crate::proto::rr::{Name, RecordType};
     use crate::proto::runtime::TokioRuntimeProvider;
@@ -351,4 +529,60 @@ mod tests {
             "if this is failing then the NameServers aren't being properly shared."
         );
     }
+
+    async fn open_circuit_lookup(fallback: AllServersDown) -> ProtoError {
+        subscribe();
+
+        // a socket that never responds
+        let server = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let server_addr = server.local_addr().unwrap();
+
+        let opts = Arc::new(ResolverOpts {
+            timeout: Duration::from_millis(50),
+            circuit_breaker: Some(CircuitBreakerConfig {
+                failure_threshold: 1,
+                probe_interval: Duration::from_secs(60),
+                fallback,
+            }),
+            ..ResolverOpts::default()
+        });
+
+        let mut config = NameServerConfig::udp(server_addr.ip());
+        config.connections[0].port = server_addr.port();
+        let pool = NameServerPool::from_config(
+            [config],
+            opts,
+            Arc::new(TlsConfig::new().unwrap()),
+            TokioRuntimeProvider::default(),
+        );
+
+        let query = Query::query(Name::from_str("www.example.com.").unwrap(), RecordType::A);
+        let err = pool
+            .lookup(query.clone(), DnsRequestOptions::default())
+            .first_answer()
+            .await
+            .unwrap_err();
+        assert!(matches!(err.kind(), ProtoErrorKind::Timeout), "{err:?}");
+        assert_eq!(pool.servers()[0].health().circuit, CircuitState::Open);
+
+        pool.lookup(query, DnsRequestOptions::default())
+            .first_answer()
+            .await
+            .unwrap_err()
+    }
+
+    #[tokio::test]
+    async fn test_all_servers_down_fail() {
+        let err = open_circuit_lookup(AllServersDown::Fail).await;
+        assert!(
+            matches!(err.kind(), ProtoErrorKind::NoConnections),
+            "{err:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_all_servers_down_try_all() {
+        let err = open_circuit_lookup(AllServersDown::TryAll).await;
+        assert!(matches!(err.kind(), ProtoErrorKind::Timeout), "{err:?}");
+    }
 }
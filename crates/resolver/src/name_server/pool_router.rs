@@ -0,0 +1,253 @@
+use std::sync::Arc;
+#[cfg(unix)]
+#[cfg(feature = "system-config")]
+use std::sync::Weak;
+
+use parking_lot::RwLock;
+
+use crate::config::{ResolverConfig, ResolverOpts, Upstream};
+use crate::name_server::connection_provider::{ConnectionProvider, TlsConfig};
+use crate::name_server::name_server_pool::NameServerPool;
+use crate::proto::ProtoError;
+use crate::proto::op::DnsRequest;
+use crate::proto::rr::Name;
+use crate::proto::xfer::{DnsHandle, Protocol};
+
+/// Selects a [`NameServerPool`] for each request, based on the query name
+///
+/// Each forwarding rule in the [`ResolverConfig`] gets its own pool. Requests are sent to the pool
+/// of the rule with the longest domain that is equal to or a parent of the query name, or to the
+/// default pool if no rule matches.
+///
+/// Clones of a router share the default pool, which is replaced when the system configuration
+/// is reloaded.
+#[derive(Clone)]
+pub struct PoolRouter<P: ConnectionProvider> {
+    default: Arc<RwLock<NameServerPool<P>>>,
+    /// Pools for forwarding rules, ordered from the most to the least specific domain
+    routes: Arc<[(Name, NameServerPool<P>)]>,
+}
+
+impl<P: ConnectionProvider> PoolRouter<P> {
+    /// Construct pools for the default name servers and each forwarding rule of `config`
+    pub fn from_config(
+        config: &ResolverConfig,
+        options: Arc<ResolverOpts>,
+        tls: Arc<TlsConfig>,
+        conn_provider: P,
+    ) -> Self {
+        let routes = config
+            .forwarding_rules()
+            .iter()
+            .map(|rule| {
+                let pool = NameServerPool::from_config(
+                    rule.name_servers.iter().cloned(),
+                    options.clone(),
+                    tls.clone(),
+                    conn_provider.clone(),
+                );
+                (rule.domain.clone(), pool)
+            })
+            .collect();
+
+        let default = NameServerPool::from_config(
+            config.name_servers().iter().cloned(),
+            options,
+            tls,
+            conn_provider,
+        );
+
+        Self::new(default, routes)
+    }
+
+    /// Construct a router from already built pools
+    ///
+    /// If the same domain appears more than once, the first pool for it is used.
+    pub fn new(default: NameServerPool<P>, mut routes: Vec<(Name, NameServerPool<P>)>) -> Self {
+        // stable sort, so that earlier rules win over later rules for the same domain
+        routes.sort_by_key(|(domain, _)| std::cmp::Reverse(domain.num_labels()));
+        Self {
+            default: Arc::new(RwLock::new(default)),
+            routes: routes.into(),
+        }
+    }
+
+    /// Returns the pool that queries for `name` are sent to
+    pub fn pool_for(&self, name: &Name) -> NameServerPool<P> {
+        match self.routes.iter().find(|(domain, _)| domain.zone_of(name)) {
+            Some((_, pool)) => pool.clone(),
+            None => self.default_pool(),
+        }
+    }
+
+    /// Returns the pool used for names not covered by any forwarding rule
+    pub fn default_pool(&self) -> NameServerPool<P> {
+        self.default.read().clone()
+    }
+
+    /// Returns a weak reference to the default pool, through which it can be replaced
+    ///
+    /// This applies to all clones of this router. Requests already sent to the previous pool are
+    /// not affected.
+    #[cfg(unix)]
+    #[cfg(feature = "system-config")]
+    pub(crate) fn default_pool_ref(&self) -> Weak<RwLock<NameServerPool<P>>> {
+        Arc::downgrade(&self.default)
+    }
+
+    /// Returns a router which sends all requests to the `upstream` pool, and only over `protocol`
+    ///
+    /// Requests are routed as by this router if `upstream` is not set, and over any protocol if
+    /// `protocol` is not set. Returns an error if there is no forwarding rule for the domain of
+    /// `upstream`.
+    pub fn restrict(
+        &self,
+        upstream: Option<&Upstream>,
+        protocol: Option<Protocol>,
+    ) -> Result<Self, ProtoError> {
+        let router = match upstream {
+            None => self.clone(),
+            Some(Upstream::Default) => Self::new(self.default_pool(), vec![]),
+            Some(Upstream::ForwardingRule(domain)) => {
+                let (_, pool) = self
+                    .routes
+                    .iter()
+                    .find(|(rule, _)| rule == domain)
+                    .ok_or_else(|| ProtoError::from(format!("no forwarding rule for {domain}")))?;
+                Self::new(pool.clone(), vec![])
+            }
+        };
+
+        let Some(protocol) = protocol else {
+            return Ok(router);
+        };
+
+        Ok(Self {
+            default: Arc::new(RwLock::new(router.default_pool().with_protocol(protocol))),
+            routes: router
+                .routes
+                .iter()
+                .map(|(domain, pool)| (domain.clone(), pool.with_protocol(protocol)))
+                .collect(),
+        })
+    }
+
+    /// Returns all pools, with the domain of their forwarding rule
+    ///
+    /// The default pool comes first, with no domain.
+    pub fn pools(&self) -> Vec<(Option<&Name>, NameServerPool<P>)> {
+        let routes = self
+            .routes
+            .iter()
+            .map(|(domain, pool)| (Some(domain), pool.clone()));
+        [(None, self.default_pool())]
+            .into_iter()
+            .chain(routes)
+            .collect()
+    }
+}
+
+impl<P: ConnectionProvider> DnsHandle for PoolRouter<P> {
+    type Response = <NameServerPool<P> as DnsHandle>::Response;
+    type Runtime = P::RuntimeProvider;
+
+    fn send(&self, request: DnsRequest) -> Self::Response {
+        match request.queries().first() {
+            Some(query) => self.pool_for(query.name()).send(request),
+            None => self.default_pool().send(request),
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "tokio")]
+mod tests {
+    use std::net::IpAddr;
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::config::NameServerConfig;
+    use crate::proto::runtime::TokioRuntimeProvider;
+
+    fn router(config: &ResolverConfig) -> PoolRouter<TokioRuntimeProvider> {
+        PoolRouter::from_config(
+            config,
+            Arc::new(ResolverOpts::default()),
+            Arc::new(TlsConfig::new().unwrap()),
+            TokioRuntimeProvider::default(),
+        )
+    }
+
+    fn servers(ip: [u8; 4]) -> Vec<NameServerConfig> {
+        vec![NameServerConfig::udp(IpAddr::from(ip))]
+    }
+
+    #[test]
+    fn test_longest_suffix_match() {
+        let mut config = ResolverConfig::default();
+        config.add_name_server(NameServerConfig::udp(IpAddr::from([192, 0, 2, 1])));
+        config.add_forwarding_rule(Name::from_str("example.").unwrap(), servers([192, 0, 2, 2]));
+        config.add_forwarding_rule(
+            Name::from_str("corp.example.").unwrap(),
+            servers([192, 0, 2, 3]),
+        );
+        config.add_forwarding_rule(Name::from_str("consul.").unwrap(), servers([192, 0, 2, 4]));
+
+        let router = router(&config);
+        let ip_for = |name: &str| {
+            router.pool_for(&Name::from_str(name).unwrap()).servers()[0]
+                .config()
+                .ip
+        };
+
+        assert_eq!(ip_for("www.corp.example."), IpAddr::from([192, 0, 2, 3]));
+        assert_eq!(ip_for("CORP.example."), IpAddr::from([192, 0, 2, 3]));
+        assert_eq!(ip_for("www.corp.example"), IpAddr::from([192, 0, 2, 3]));
+        assert_eq!(ip_for("www.example."), IpAddr::from([192, 0, 2, 2]));
+        assert_eq!(ip_for("notcorp.example."), IpAddr::from([192, 0, 2, 2]));
+        assert_eq!(ip_for("web.service.consul."), IpAddr::from([192, 0, 2, 4]));
+        assert_eq!(ip_for("www.example.com."), IpAddr::from([192, 0, 2, 1]));
+        assert_eq!(ip_for("."), IpAddr::from([192, 0, 2, 1]));
+    }
+
+    #[test]
+    fn test_first_rule_wins() {
+        let mut config = ResolverConfig::default();
+        config.add_forwarding_rule(Name::from_str("example.").unwrap(), servers([192, 0, 2, 2]));
+        config.add_forwarding_rule(Name::from_str("example.").unwrap(), servers([192, 0, 2, 3]));
+
+        let router = router(&config);
+        let pool = router.pool_for(&Name::from_str("www.example.").unwrap());
+        assert_eq!(pool.servers()[0].config().ip, IpAddr::from([192, 0, 2, 2]));
+    }
+
+    #[test]
+    fn test_restrict_upstream() {
+        let mut config = ResolverConfig::default();
+        config.add_name_server(NameServerConfig::udp(IpAddr::from([192, 0, 2, 1])));
+        config.add_forwarding_rule(Name::from_str("example.").unwrap(), servers([192, 0, 2, 2]));
+
+        let router = router(&config);
+        let ip_for = |router: &PoolRouter<_>, name: &str| {
+            router.pool_for(&Name::from_str(name).unwrap()).servers()[0]
+                .config()
+                .ip
+        };
+
+        let default = router.restrict(Some(&Upstream::Default), None).unwrap();
+        assert_eq!(
+            ip_for(&default, "www.example."),
+            IpAddr::from([192, 0, 2, 1])
+        );
+
+        let upstream = Upstream::ForwardingRule(Name::from_str("EXAMPLE.").unwrap());
+        let pinned = router.restrict(Some(&upstream), None).unwrap();
+        assert_eq!(
+            ip_for(&pinned, "www.example.com."),
+            IpAddr::from([192, 0, 2, 2])
+        );
+
+        let upstream = Upstream::ForwardingRule(Name::from_str("example.com.").unwrap());
+        assert!(router.restrict(Some(&upstream), None).is_err());
+    }
+}
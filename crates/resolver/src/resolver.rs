@@ -8,33 +8,56 @@
 //! Structs for creating and using a Resolver
 use std::fmt;
 use std::future::Future;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+#[cfg(unix)]
+#[cfg(feature = "system-config")]
+use std::path::Path;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::{Context, Poll};
+#[cfg(unix)]
+#[cfg(feature = "system-config")]
+use std::time::Duration;
+#[cfg(feature = "__dnssec")]
+use std::time::SystemTime;
 
 use futures_util::{
     FutureExt, Stream,
     future::{self, BoxFuture},
 };
+use parking_lot::RwLock;
 use tracing::debug;
 
 use crate::cache::{MAX_TTL, ResponseCache, TtlConfig};
 use crate::caching_client::CachingClient;
-use crate::config::{ResolveHosts, ResolverConfig, ResolverOpts};
+use crate::config::{LookupOptions, ResolveHosts, ResolverConfig, ResolverOpts};
+use crate::happy_eyeballs;
 use crate::hosts::Hosts;
-use crate::lookup::{Lookup, TypedLookup};
+#[cfg(unix)]
+#[cfg(feature = "system-config")]
+use crate::hosts::hosts_path;
+use crate::lookup::{Lookup, TypedLookup, VerifiedName, VerifiedReverseLookup};
 use crate::lookup_ip::{LookupIp, LookupIpFuture};
 use crate::name_server::TlsConfig;
-use crate::name_server::{ConnectionProvider, NameServerPool};
+use crate::name_server::{ConnectionProvider, PoolRouter};
 #[cfg(feature = "__dnssec")]
-use crate::proto::dnssec::{DnssecDnsHandle, TrustAnchors};
+use crate::proto::dnssec::{DnssecDnsHandle, NegativeTrustAnchors, TrustAnchors};
 use crate::proto::op::{DnsRequest, DnsRequestOptions, DnsResponse, Query};
 use crate::proto::rr::domain::usage::ONION;
 use crate::proto::rr::{IntoName, Name, RData, Record, RecordType, rdata};
 #[cfg(feature = "tokio")]
 use crate::proto::runtime::TokioRuntimeProvider;
+use crate::proto::runtime::{RuntimeProvider, Time};
 use crate::proto::xfer::{DnsHandle, RetryDnsHandle};
 use crate::proto::{ProtoError, ProtoErrorKind};
+use crate::service_endpoint::{self, ServiceEndpoint};
+#[cfg(unix)]
+#[cfg(feature = "system-config")]
+use crate::system_conf::Watcher;
+
+/// Maximum number of PTR names checked by [`Resolver::verified_reverse_lookup`]
+const MAX_VERIFIED_PTR_NAMES: usize = 10;
 
 macro_rules! lookup_fn {
     ($p:ident, $l:ty, $r:path) => {
@@ -86,7 +109,19 @@ pub struct Resolver<P: ConnectionProvider> {
     config: ResolverConfig,
     options: Arc<ResolverOpts>,
     client_cache: CachingClient<LookupEither<P>>,
-    hosts: Arc<Hosts>,
+    pools: PoolRouter<P>,
+    hosts: Arc<RwLock<Arc<Hosts>>>,
+    runtime: Option<P::RuntimeProvider>,
+    #[cfg(unix)]
+    #[cfg(feature = "system-config")]
+    tls: Arc<TlsConfig>,
+    #[cfg(unix)]
+    #[cfg(feature = "system-config")]
+    provider: P,
+    #[cfg(feature = "__dnssec")]
+    validator: Option<Validator>,
+    #[cfg(feature = "__dnssec")]
+    negative_trust_anchors: Arc<NegativeTrustAnchors>,
 }
 
 impl<R: ConnectionProvider> Resolver<R> {
@@ -122,8 +157,55 @@ impl<R: ConnectionProvider> Resolver<R> {
     }
 
     /// Customizes the static hosts used in this resolver.
+    ///
+    /// Other clones of this resolver keep their hosts, and the hosts of this resolver are no
+    /// longer reloaded by [`Self::watch_system_conf()`].
     pub fn set_hosts(&mut self, hosts: Arc<Hosts>) {
-        self.hosts = hosts;
+        self.hosts = Arc::new(RwLock::new(hosts));
+    }
+
+    /// Returns a future which reloads the name servers and hosts when the system configuration
+    /// changes
+    ///
+    /// Every `interval`, `/etc/resolv.conf` and `/etc/hosts` are read again. If `/etc/resolv.conf`
+    /// changed, the default name servers are replaced by the ones it lists; name servers with an
+    /// unchanged address keep their connections and statistics. If `/etc/hosts` changed, and
+    /// [`ResolverOpts::use_hosts_file`] is not [`ResolveHosts::Never`], the hosts are replaced.
+    /// Search domains, options and forwarding rules are not reloaded, and the cache is kept. A file
+    /// which can't be read or parsed leaves the current configuration in place.
+    ///
+    /// The changes apply to this resolver and all its clones. The future must be spawned by the
+    /// caller, and completes once the resolver and its clones are dropped.
+    #[cfg(unix)]
+    #[cfg(feature = "system-config")]
+    pub fn watch_system_conf(
+        &self,
+        interval: Duration,
+    ) -> impl Future<Output = ()> + Send + 'static {
+        let hosts = match self.options.use_hosts_file {
+            ResolveHosts::Always | ResolveHosts::Auto => Some(Path::new(hosts_path())),
+            ResolveHosts::Never => None,
+        };
+        self.watch_files(Path::new("/etc/resolv.conf"), hosts)
+            .run(interval)
+    }
+
+    #[cfg(unix)]
+    #[cfg(feature = "system-config")]
+    pub(crate) fn watch_files(&self, resolv_conf: &Path, hosts: Option<&Path>) -> Watcher<R> {
+        Watcher::new(
+            resolv_conf,
+            hosts,
+            self.pools.default_pool_ref(),
+            Arc::downgrade(&self.hosts),
+            self.options.clone(),
+            self.tls.clone(),
+            self.provider.clone(),
+        )
+    }
+
+    fn hosts(&self) -> Arc<Hosts> {
+        self.hosts.read().clone()
     }
 
     /// Generic lookup for any RecordType
@@ -147,6 +229,69 @@ impl<R: ConnectionProvider> Resolver<R> {
             .await
     }
 
+    /// Generic lookup for any RecordType, with options overriding the settings of the resolver
+    ///
+    /// This looks up `name` as [`Self::lookup()`] does, with the search domains, hosts file and
+    /// cache of the resolver, except as changed by `options`. See [`LookupOptions`] for the
+    /// settings that can be changed for a single lookup.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - name of the record to lookup, if name is not a valid domain name, an error will be returned
+    /// * `record_type` - type of record to lookup, all RecordData responses will be filtered to this type
+    /// * `options` - settings for this lookup
+    pub async fn lookup_with_options(
+        &self,
+        name: impl IntoName,
+        record_type: RecordType,
+        options: &LookupOptions,
+    ) -> Result<Lookup, ProtoError> {
+        let mut request_options = self.request_options();
+        request_options.checking_disabled = options.checking_disabled;
+        if options.dnssec_ok {
+            request_options.use_edns = true;
+            request_options.edns_set_dnssec_ok = true;
+        }
+        if let (Some(config), Some(address)) = (&self.options.client_subnet, options.client_address)
+        {
+            request_options.client_subnet = config.client_subnet(Some(address));
+        }
+
+        let (client_cache, shared) = match (&options.upstream, options.protocol) {
+            (None, None) => (self.client_cache.clone(), true),
+            (upstream, protocol) => {
+                let pools = self.pools.restrict(upstream.as_ref(), protocol)?;
+                (
+                    self.client_cache.with_client(self.lookup_handle(pools)),
+                    false,
+                )
+            }
+        };
+        // the cache is shared by all name servers, so lookups pinned to some of them bypass it, and
+        // unvalidated responses must not be served to other lookups
+        let client_cache = client_cache.with_cache_access(
+            shared && options.cache.reads(),
+            shared && options.cache.writes() && !options.checking_disabled,
+        );
+
+        let lookup = LookupFuture::lookup_with_hosts(
+            self.build_names(name.into_name()?),
+            record_type,
+            request_options,
+            client_cache,
+            self.hosts(),
+        );
+
+        match options.timeout {
+            Some(timeout) => {
+                <R::RuntimeProvider as RuntimeProvider>::Timer::timeout(timeout, lookup)
+                    .await
+                    .map_err(|_| ProtoError::from(ProtoErrorKind::Timeout))?
+            }
+            None => lookup.await,
+        }
+    }
+
     pub(crate) async fn inner_lookup<L>(
         &self,
         name: Name,
@@ -162,7 +307,7 @@ impl<R: ConnectionProvider> Resolver<R> {
             record_type,
             options,
             self.client_cache.clone(),
-            self.hosts.clone(),
+            self.hosts(),
         )
         .await
         .map(L::from)
@@ -209,9 +354,9 @@ impl<R: ConnectionProvider> Resolver<R> {
         };
 
         let names = self.build_names(name);
-        let hosts = self.hosts.clone();
+        let hosts = self.hosts();
 
-        LookupIpFuture::lookup(
+        let mut lookup = LookupIpFuture::lookup(
             names,
             self.options.ip_strategy,
             self.client_cache.clone(),
@@ -219,9 +364,91 @@ impl<R: ConnectionProvider> Resolver<R> {
             hosts,
             finally_ip_addr.map(Record::into_data),
         )
+        .await?;
+
+        if self.options.sort_addresses {
+            lookup.sort_addresses();
+        }
+
+        Ok(lookup)
+    }
+
+    /// Opens a TCP connection to `port` on `host`, with Happy Eyeballs
+    ///
+    /// This implements [RFC 8305](https://www.rfc-editor.org/rfc/rfc8305): A and AAAA records are
+    /// queried in parallel, connection attempts start as soon as AAAA records are received, or
+    /// 50ms after A records if AAAA records are still pending, and addresses of each family are
+    /// tried alternately, starting a new attempt every 250ms until one succeeds. The stream of the
+    /// first successful attempt is returned, the others are dropped.
+    ///
+    /// Addresses are sorted per RFC 6724 when
+    /// [`ResolverOpts::sort_addresses`](crate::config::ResolverOpts::sort_addresses) is set,
+    /// otherwise IPv6 addresses are tried first. Only one family is queried if
+    /// [`ResolverOpts::ip_strategy`] is `Ipv4Only` or `Ipv6Only`.
+    ///
+    /// # Arguments
+    /// * `host` - string hostname or IP address, if this is an invalid hostname, an error will be returned.
+    /// * `port` - the port to connect to
+    pub async fn connect_tcp(
+        &self,
+        host: impl IntoName,
+        port: u16,
+    ) -> io::Result<<R::RuntimeProvider as RuntimeProvider>::Tcp> {
+        let Some(runtime) = &self.runtime else {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the connection provider has no runtime provider",
+            ));
+        };
+
+        if let Some(ip) = host.to_ip() {
+            return runtime
+                .connect_tcp(SocketAddr::new(ip, port), None, None)
+                .await;
+        }
+
+        happy_eyeballs::connect_tcp(
+            self,
+            runtime,
+            host.into_name()?,
+            port,
+            self.options.ip_strategy,
+            self.options.sort_addresses,
+        )
         .await
     }
 
+    /// Resolves the endpoints of a service from its SVCB or HTTPS records, per [RFC 9460](https://www.rfc-editor.org/rfc/rfc9460)
+    ///
+    /// For the `https` and `http` schemes HTTPS records are queried, at `name` for port 443 (and
+    /// port 80 of `http`, whose endpoints are upgraded to port 443 when HTTPS records are found) or
+    /// at `_{port}._https.{name}` otherwise. For other schemes SVCB records are queried at
+    /// `_{port}._{scheme}.{name}`.
+    ///
+    /// AliasMode records are followed, and ServiceMode records are returned by ascending priority,
+    /// shuffled within a priority, with their `port`, `alpn`, `no-default-alpn` and `ech`
+    /// parameters applied. Records with unsupported mandatory parameters are skipped. The
+    /// addresses of each target are looked up with [`Self::lookup_ip`], falling back to the
+    /// `ipv4hint` and `ipv6hint` parameters, and targets without any address are skipped.
+    ///
+    /// If there are no usable ServiceMode records, the result is a single
+    /// [fallback](ServiceEndpoint::is_fallback) endpoint with the addresses of `name`, or of the
+    /// target of the last AliasMode record, at `port`.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - the host of the origin, if this is an invalid hostname, an error will be returned
+    /// * `port` - the port of the origin
+    /// * `scheme` - the scheme of the origin, e.g. `https`, or the protocol of the service
+    pub async fn lookup_service_endpoints(
+        &self,
+        name: impl IntoName,
+        port: u16,
+        scheme: &str,
+    ) -> Result<Vec<ServiceEndpoint>, ProtoError> {
+        service_endpoint::lookup(self, name.into_name()?, port, scheme).await
+    }
+
     fn build_names(&self, name: Name) -> Vec<Name> {
         // if it's fully qualified, we can short circuit the lookup logic
         if name.is_fqdn()
@@ -297,6 +524,64 @@ impl<R: ConnectionProvider> Resolver<R> {
         }
     }
 
+    /// Performs a forward-confirmed reverse lookup of `ip`
+    ///
+    /// The PTR records of the address are looked up, then the A or AAAA records of each of their
+    /// names, depending on the family of `ip`. Only the names which resolve to `ip` are returned.
+    /// At most 10 names are checked, any further PTR records are ignored.
+    ///
+    /// An error is returned if the PTR lookup fails, or if no name could be verified and one of
+    /// the forward lookups failed for another reason than the absence of records.
+    pub async fn verified_reverse_lookup(
+        &self,
+        ip: IpAddr,
+    ) -> Result<VerifiedReverseLookup, ProtoError> {
+        let ptrs = self.lookup(ip, RecordType::PTR).await?;
+        let ptrs = ptrs
+            .record_iter()
+            .filter(|record| record.record_type() == RecordType::PTR)
+            .collect::<Vec<_>>();
+        if ptrs.len() > MAX_VERIFIED_PTR_NAMES {
+            debug!(
+                "{ip} has {} PTR records, only verifying the first {MAX_VERIFIED_PTR_NAMES}",
+                ptrs.len()
+            );
+        }
+
+        let record_type = match ip {
+            IpAddr::V4(_) => RecordType::A,
+            IpAddr::V6(_) => RecordType::AAAA,
+        };
+        let forward = future::join_all(ptrs.iter().take(MAX_VERIFIED_PTR_NAMES).map(
+            |ptr| async move {
+                let res = match ptr.data() {
+                    RData::PTR(name) => self.lookup(name.0.clone(), record_type).await,
+                    _ => Err(ProtoError::from("not a PTR record")),
+                };
+                (*ptr, res)
+            },
+        ))
+        .await;
+
+        let mut names = Vec::with_capacity(forward.len());
+        let mut error = None;
+        for (ptr, res) in forward {
+            match res {
+                Ok(lookup) => names.extend(VerifiedName::verify(ptr, &lookup, ip)),
+                Err(e) if e.is_no_records_found() => {}
+                Err(e) => {
+                    debug!("failed to verify {}: {e}", ptr.data());
+                    error = Some(e);
+                }
+            }
+        }
+
+        match error {
+            Some(error) if names.is_empty() => Err(error),
+            _ => Ok(VerifiedReverseLookup::new(ip, names)),
+        }
+    }
+
     lookup_fn!(reverse_lookup, rdata::PTR, RecordType::PTR);
     lookup_fn!(ipv4_lookup, rdata::A, RecordType::A);
     lookup_fn!(ipv6_lookup, rdata::AAAA, RecordType::AAAA);
@@ -313,12 +598,47 @@ impl<R: ConnectionProvider> Resolver<R> {
         self.client_cache.clear_cache();
     }
 
+    /// Returns the response cache of this resolver.
+    ///
+    /// This can be used to list cached entries, remove entries for a single query or a whole
+    /// subtree of names, and read hit, miss and eviction counts. The cache is shared between all
+    /// clones of this resolver.
+    pub fn cache(&self) -> &ResponseCache {
+        self.client_cache.response_cache()
+    }
+
+    /// Returns the name server pools of this resolver.
+    ///
+    /// This can be used to inspect the health of each upstream name server.
+    pub fn name_servers(&self) -> &PoolRouter<R> {
+        &self.pools
+    }
+
+    /// Wraps `pools` in the retry and DNSSEC validation handles of this resolver
+    fn lookup_handle(&self, pools: PoolRouter<R>) -> LookupEither<R> {
+        LookupEither::new(
+            pools,
+            self.options.attempts,
+            #[cfg(feature = "__dnssec")]
+            self.validator.as_ref(),
+        )
+    }
+
     /// Per request options based on the ResolverOpts
     pub(crate) fn request_options(&self) -> DnsRequestOptions {
         let mut request_opts = DnsRequestOptions::default();
         request_opts.recursion_desired = self.options.recursion_desired;
         request_opts.use_edns = self.options.edns0;
         request_opts.case_randomization = self.options.case_randomization;
+        #[cfg(feature = "__dnssec")]
+        {
+            request_opts.validation_trace = self.options.validation_trace;
+        }
+        request_opts.client_subnet = self
+            .options
+            .client_subnet
+            .as_ref()
+            .and_then(|config| config.client_subnet(None));
 
         request_opts
     }
@@ -332,6 +652,16 @@ impl<R: ConnectionProvider> Resolver<R> {
     pub fn options(&self) -> &ResolverOpts {
         &self.options
     }
+
+    /// Returns the negative trust anchors of this resolver
+    ///
+    /// These are initialized from [`ResolverOpts::negative_trust_anchors`], and can be changed
+    /// while the resolver is running. Call [`Self::clear_cache()`] after adding an anchor, so that
+    /// previously failed lookups are retried.
+    #[cfg(feature = "__dnssec")]
+    pub fn negative_trust_anchors(&self) -> &Arc<NegativeTrustAnchors> {
+        &self.negative_trust_anchors
+    }
 }
 
 impl<P: ConnectionProvider> fmt::Debug for Resolver<P> {
@@ -343,9 +673,25 @@ impl<P: ConnectionProvider> fmt::Debug for Resolver<P> {
 /// Different lookup options for the lookup attempts and validation
 #[derive(Clone)]
 enum LookupEither<P: ConnectionProvider> {
-    Retry(RetryDnsHandle<NameServerPool<P>>),
+    Retry(RetryDnsHandle<PoolRouter<P>>),
     #[cfg(feature = "__dnssec")]
-    Secure(DnssecDnsHandle<RetryDnsHandle<NameServerPool<P>>>),
+    Secure(DnssecDnsHandle<RetryDnsHandle<PoolRouter<P>>>),
+}
+
+impl<P: ConnectionProvider> LookupEither<P> {
+    fn new(
+        pools: PoolRouter<P>,
+        attempts: usize,
+        #[cfg(feature = "__dnssec")] validator: Option<&Validator>,
+    ) -> Self {
+        let client = RetryDnsHandle::new(pools, attempts);
+        #[cfg(feature = "__dnssec")]
+        if let Some(validator) = validator {
+            return Self::Secure(validator.wrap(client));
+        }
+
+        Self::Retry(client)
+    }
 }
 
 impl<P: ConnectionProvider> DnsHandle for LookupEither<P> {
@@ -369,6 +715,28 @@ impl<P: ConnectionProvider> DnsHandle for LookupEither<P> {
     }
 }
 
+/// The DNSSEC validation settings of a [`Resolver`]
+#[cfg(feature = "__dnssec")]
+#[derive(Clone)]
+struct Validator {
+    trust_anchor: Arc<TrustAnchors>,
+    nsec3_soft_iteration_limit: Option<u16>,
+    nsec3_hard_iteration_limit: Option<u16>,
+    negative_trust_anchors: Arc<NegativeTrustAnchors>,
+}
+
+#[cfg(feature = "__dnssec")]
+impl Validator {
+    fn wrap<H: DnsHandle>(&self, handle: H) -> DnssecDnsHandle<H> {
+        DnssecDnsHandle::with_trust_anchor(handle, self.trust_anchor.clone())
+            .nsec3_iteration_limits(
+                self.nsec3_soft_iteration_limit,
+                self.nsec3_hard_iteration_limit,
+            )
+            .negative_trust_anchors(self.negative_trust_anchors.clone())
+    }
+}
+
 /// A builder to construct a [`Resolver`].
 ///
 /// Created by [`Resolver::builder`].
@@ -436,6 +804,9 @@ impl<P: ConnectionProvider> ResolverBuilder<P> {
     }
 
     /// Construct the resolver.
+    ///
+    /// Fails if [`ResolverOpts::circuit_breaker`] is set, but the connection provider has no
+    /// [`ConnectionProvider::runtime_provider()`] to probe unresponsive name servers.
     pub fn build(self) -> Result<Resolver<P>, ProtoError> {
         #[cfg_attr(not(feature = "__dnssec"), allow(unused_mut))]
         let Self {
@@ -456,45 +827,73 @@ impl<P: ConnectionProvider> ResolverBuilder<P> {
             options.validate = true;
         }
 
+        let runtime = provider.runtime_provider();
+        if options.circuit_breaker.is_some() && runtime.is_none() {
+            return Err(ProtoError::from(
+                "the circuit breaker needs a connection provider with a runtime provider to probe name servers",
+            ));
+        }
+
         let options = Arc::new(options);
-        let pool = NameServerPool::from_config(
-            config.name_servers().iter().cloned(),
-            options.clone(),
-            Arc::new(match tls {
-                Some(config) => config,
-                None => TlsConfig::new()?,
-            }),
-            provider,
-        );
-        let client = RetryDnsHandle::new(pool, options.attempts);
+        let tls = Arc::new(match tls {
+            Some(config) => config,
+            None => TlsConfig::new()?,
+        });
+        let pool = PoolRouter::from_config(&config, options.clone(), tls.clone(), provider.clone());
+        #[cfg(feature = "__dnssec")]
+        let negative_trust_anchors = {
+            let anchors = NegativeTrustAnchors::new();
+            let now = SystemTime::now();
+            for anchor in &options.negative_trust_anchors {
+                anchors.insert(anchor.name.clone(), now + anchor.lifetime);
+            }
+            Arc::new(anchors)
+        };
 
         #[cfg(feature = "__dnssec")]
-        let either = if options.validate {
-            let trust_anchor = trust_anchor.unwrap_or_else(|| Arc::new(TrustAnchors::default()));
+        let validator = options.validate.then(|| Validator {
+            trust_anchor: trust_anchor.unwrap_or_else(|| Arc::new(TrustAnchors::default())),
+            nsec3_soft_iteration_limit,
+            nsec3_hard_iteration_limit,
+            negative_trust_anchors: negative_trust_anchors.clone(),
+        });
 
-            LookupEither::Secure(
-                DnssecDnsHandle::with_trust_anchor(client, trust_anchor)
-                    .nsec3_iteration_limits(nsec3_soft_iteration_limit, nsec3_hard_iteration_limit),
-            )
-        } else {
-            LookupEither::Retry(client)
-        };
-        #[cfg(not(feature = "__dnssec"))]
-        let either = LookupEither::Retry(client);
+        let either = LookupEither::new(
+            pool.clone(),
+            options.attempts,
+            #[cfg(feature = "__dnssec")]
+            validator.as_ref(),
+        );
 
         let cache = ResponseCache::new(options.cache_size, TtlConfig::from_opts(&options));
-        let client_cache = CachingClient::with_cache(cache, either, options.preserve_intermediates);
+        let mut client_cache =
+            CachingClient::with_cache(cache, either, options.preserve_intermediates);
+        if let Some(client_subnet) = &options.client_subnet {
+            client_cache = client_cache.with_client_subnet_domains(&client_subnet.domains);
+        }
 
-        let hosts = Arc::new(match options.use_hosts_file {
+        let hosts = Arc::new(RwLock::new(Arc::new(match options.use_hosts_file {
             ResolveHosts::Always | ResolveHosts::Auto => Hosts::from_system().unwrap_or_default(),
             ResolveHosts::Never => Hosts::default(),
-        });
+        })));
 
         Ok(Resolver {
             config,
             options,
             client_cache,
+            pools: pool,
             hosts,
+            runtime,
+            #[cfg(unix)]
+            #[cfg(feature = "system-config")]
+            tls,
+            #[cfg(unix)]
+            #[cfg(feature = "system-config")]
+            provider,
+            #[cfg(feature = "__dnssec")]
+            validator,
+            #[cfg(feature = "__dnssec")]
+            negative_trust_anchors,
         })
     }
 }
@@ -1119,7 +1518,10 @@ pub(crate) mod testing {
 #[allow(clippy::extra_unused_type_parameters)]
 mod tests {
     use std::net::{IpAddr, Ipv4Addr};
+    use std::str::FromStr;
     use std::sync::Mutex;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
 
     use futures_util::stream::once;
     use futures_util::{Stream, future};
@@ -1139,10 +1541,14 @@ mod tests {
     #[cfg(feature = "__dnssec")]
     use super::testing::{sec_lookup_fails_test, sec_lookup_test};
     use super::*;
-    use crate::config::{CLOUDFLARE, GOOGLE, ResolverConfig, ResolverOpts};
-    use crate::proto::op::{DnsRequest, DnsResponse, Message};
+    use crate::config::{
+        CLOUDFLARE, CircuitBreakerConfig, ClientSubnetConfig, ConnectionConfig, GOOGLE,
+        NameServerConfig, ResolverConfig, ResolverOpts, Upstream,
+    };
+    use crate::proto::op::{DnsRequest, DnsResponse, Edns, Message, OpCode};
     use crate::proto::rr::rdata::A;
-    use crate::proto::xfer::DnsExchange;
+    use crate::proto::rr::rdata::opt::{ClientSubnet, EdnsCode, EdnsOption};
+    use crate::proto::xfer::{DnsExchange, Protocol};
     use crate::proto::{DnsError, NoRecords, ProtoError, ProtoErrorKind};
 
     fn is_send_t<T: Send>() -> bool {
@@ -1290,6 +1696,237 @@ mod tests {
         localhost_ipv6_test(handle).await;
     }
 
+    #[tokio::test]
+    async fn test_connect_tcp_localhost() {
+        subscribe();
+        let listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let resolver = Resolver::builder_with_config(
+            ResolverConfig::udp_and_tcp(&GOOGLE),
+            TokioRuntimeProvider::default(),
+        )
+        .build()
+        .unwrap();
+
+        // ::1 is tried first, and refused
+        let stream = resolver.connect_tcp("localhost", port).await.unwrap();
+        assert_eq!(
+            stream.0.peer_addr().unwrap(),
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_lookup_with_options() {
+        subscribe();
+        // a name server which never answers
+        let socket = tokio::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let mut server = NameServerConfig::udp(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        server.connections[0].port = socket.local_addr().unwrap().port();
+
+        let mut config = ResolverConfig::default();
+        config.add_name_server(server);
+        let resolver = Resolver::builder_with_config(config, TokioRuntimeProvider::default())
+            .build()
+            .unwrap();
+        let lookup = |options| {
+            let resolver = resolver.clone();
+            async move {
+                resolver
+                    .lookup_with_options("www.example.com.", RecordType::A, &options)
+                    .await
+                    .unwrap_err()
+            }
+        };
+
+        let mut options = LookupOptions {
+            timeout: Some(Duration::from_millis(100)),
+            ..LookupOptions::default()
+        };
+        assert!(matches!(
+            lookup(options.clone()).await.kind(),
+            ProtoErrorKind::Timeout
+        ));
+
+        options.protocol = Some(Protocol::Tcp);
+        assert!(matches!(
+            lookup(options.clone()).await.kind(),
+            ProtoErrorKind::NoConnections
+        ));
+
+        options.upstream = Some(Upstream::ForwardingRule(
+            Name::from_str("example.com.").unwrap(),
+        ));
+        assert!(matches!(
+            lookup(options).await.kind(),
+            ProtoErrorKind::Msg(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_lookup_pinned_upstream() {
+        subscribe();
+        let (default_server, default_queries) = answering_server(Ipv4Addr::new(192, 0, 2, 1)).await;
+        let (rule_server, rule_queries) = answering_server(Ipv4Addr::new(192, 0, 2, 2)).await;
+
+        let mut config = ResolverConfig::default();
+        config.add_name_server(default_server);
+        config.add_forwarding_rule(Name::from_str("example.com.").unwrap(), vec![rule_server]);
+        let resolver = Resolver::builder_with_config(config, TokioRuntimeProvider::default())
+            .build()
+            .unwrap();
+        let lookup = |upstream| {
+            let resolver = resolver.clone();
+            let options = LookupOptions {
+                upstream,
+                ..LookupOptions::default()
+            };
+            async move {
+                let lookup = resolver
+                    .lookup_with_options("www.example.com.", RecordType::A, &options)
+                    .await
+                    .unwrap();
+                lookup.iter().next().unwrap().ip_addr().unwrap()
+            }
+        };
+
+        assert_eq!(lookup(None).await, Ipv4Addr::new(192, 0, 2, 2));
+        assert_eq!(rule_queries.load(Ordering::Relaxed), 1);
+
+        // not answered from the cache, nor stored in it
+        assert_eq!(
+            lookup(Some(Upstream::Default)).await,
+            Ipv4Addr::new(192, 0, 2, 1)
+        );
+        assert_eq!(default_queries.load(Ordering::Relaxed), 1);
+        assert_eq!(lookup(None).await, Ipv4Addr::new(192, 0, 2, 2));
+        assert_eq!(rule_queries.load(Ordering::Relaxed), 1);
+    }
+
+    /// Starts a name server which answers every query with `ip`, and counts the queries
+    async fn answering_server(ip: Ipv4Addr) -> (NameServerConfig, Arc<AtomicUsize>) {
+        let socket = tokio::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let mut server = NameServerConfig::udp(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        server.connections[0].port = socket.local_addr().unwrap().port();
+        let queries = Arc::new(AtomicUsize::new(0));
+        let server_queries = queries.clone();
+        tokio::spawn(async move {
+            let mut buf = [0; 512];
+            loop {
+                let (len, src) = socket.recv_from(&mut buf).await.unwrap();
+                let request = Message::from_vec(&buf[..len]).unwrap();
+                server_queries.fetch_add(1, Ordering::Relaxed);
+
+                let query = request.queries()[0].clone();
+                let mut response = Message::response(request.id(), OpCode::Query);
+                response.add_answer(Record::from_rdata(
+                    query.name().clone(),
+                    300,
+                    RData::A(A(ip)),
+                ));
+                response.add_query(query);
+                socket
+                    .send_to(&response.to_vec().unwrap(), src)
+                    .await
+                    .unwrap();
+            }
+        });
+
+        (server, queries)
+    }
+
+    #[tokio::test]
+    async fn test_client_subnet() {
+        subscribe();
+        // a name server which answers with a scope of /24 to queries with a client subnet
+        let socket = tokio::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let mut server = NameServerConfig::udp(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        server.connections[0].port = socket.local_addr().unwrap().port();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let server_received = received.clone();
+        tokio::spawn(async move {
+            let mut buf = [0; 512];
+            loop {
+                let (len, src) = socket.recv_from(&mut buf).await.unwrap();
+                let request = Message::from_vec(&buf[..len]).unwrap();
+                let subnet = request
+                    .extensions()
+                    .as_ref()
+                    .and_then(|edns| edns.option(EdnsCode::Subnet))
+                    .cloned();
+                server_received.lock().unwrap().push(subnet.clone());
+
+                let query = request.queries()[0].clone();
+                let mut response = Message::response(request.id(), OpCode::Query);
+                response.add_answer(Record::from_rdata(
+                    query.name().clone(),
+                    300,
+                    RData::A(A::new(192, 0, 2, 1)),
+                ));
+                response.add_query(query);
+                if let Some(EdnsOption::Subnet(mut subnet)) = subnet {
+                    subnet.set_scope_prefix(24);
+                    let mut edns = Edns::new();
+                    edns.options_mut().insert(EdnsOption::Subnet(subnet));
+                    response.set_edns(edns);
+                }
+                socket
+                    .send_to(&response.to_vec().unwrap(), src)
+                    .await
+                    .unwrap();
+            }
+        });
+
+        let mut config = ResolverConfig::default();
+        config.add_name_server(server);
+        let mut builder = Resolver::builder_with_config(config, TokioRuntimeProvider::default());
+        builder.options_mut().client_subnet = Some(ClientSubnetConfig::new(vec![
+            Name::from_str("example.com.").unwrap(),
+        ]));
+        let resolver = builder.build().unwrap();
+        let lookup = |name: &'static str, client: [u8; 4]| {
+            let resolver = resolver.clone();
+            let options = LookupOptions {
+                client_address: Some(IpAddr::from(client)),
+                ..LookupOptions::default()
+            };
+            async move {
+                resolver
+                    .lookup_with_options(name, RecordType::A, &options)
+                    .await
+                    .unwrap();
+            }
+        };
+        let subnet = |s: &str| Some(EdnsOption::Subnet(ClientSubnet::from_str(s).unwrap()));
+
+        lookup("www.example.com.", [198, 51, 100, 7]).await;
+        assert_eq!(*received.lock().unwrap(), [subnet("198.51.100.0/24")]);
+
+        // answered from the cache for the same /24
+        lookup("www.example.com.", [198, 51, 100, 99]).await;
+        assert_eq!(received.lock().unwrap().len(), 1);
+
+        lookup("www.example.com.", [203, 0, 113, 5]).await;
+        assert_eq!(received.lock().unwrap()[1], subnet("203.0.113.0/24"));
+
+        // not in the allowed domains
+        lookup("www.example.net.", [198, 51, 100, 7]).await;
+        assert_eq!(received.lock().unwrap()[2], None);
+
+        // private addresses are not sent
+        lookup("www.example.com.", [10, 0, 0, 1]).await;
+        assert_eq!(received.lock().unwrap()[3], None);
+    }
+
     #[tokio::test]
     async fn test_search_ipv4_large_ndots() {
         subscribe();
@@ -1457,6 +2094,41 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_circuit_breaker_needs_runtime() {
+        /// Connections from Tokio, but no runtime provider for background tasks
+        #[derive(Clone)]
+        struct NoRuntime(TokioRuntimeProvider);
+
+        impl ConnectionProvider for NoRuntime {
+            type Conn = <TokioRuntimeProvider as ConnectionProvider>::Conn;
+            type FutureConn = <TokioRuntimeProvider as ConnectionProvider>::FutureConn;
+            type RuntimeProvider = TokioRuntimeProvider;
+
+            fn new_connection(
+                &self,
+                ip: IpAddr,
+                config: &ConnectionConfig,
+                options: &ResolverOpts,
+                tls: &TlsConfig,
+            ) -> Result<Self::FutureConn, io::Error> {
+                self.0.new_connection(ip, config, options, tls)
+            }
+        }
+
+        let provider = NoRuntime(TokioRuntimeProvider::default());
+        let config = ResolverConfig::udp_and_tcp(&GOOGLE);
+        let mut builder = Resolver::builder_with_config(config.clone(), provider.clone());
+        builder.options_mut().circuit_breaker = Some(CircuitBreakerConfig::default());
+        assert!(builder.build().is_err());
+
+        assert!(
+            Resolver::builder_with_config(config, provider)
+                .build()
+                .is_ok()
+        );
+    }
+
     #[derive(Clone)]
     struct MockDnsHandle {
         messages: Arc<Mutex<Vec<Result<DnsResponse, ProtoError>>>>,
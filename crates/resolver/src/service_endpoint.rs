@@ -0,0 +1,554 @@
+//! Connection endpoints of a service, resolved from its SVCB or HTTPS records
+//!
+//! See [`Resolver::lookup_service_endpoints`].
+
+use std::net::{IpAddr, SocketAddr};
+
+use tracing::debug;
+
+use crate::{
+    Resolver,
+    name_server::ConnectionProvider,
+    proto::{
+        ProtoError,
+        rr::{
+            Name, RData, RecordType,
+            rdata::svcb::{SVCB, SvcParamKey, SvcParamValue},
+        },
+    },
+};
+
+/// Maximum number of AliasMode records followed before giving up
+///
+/// RFC 9460 leaves the limit to the client, this matches the usual limit on CNAME chains.
+const MAX_ALIAS_CHAIN: usize = 8;
+
+/// An endpoint of a service, ready to connect to
+///
+/// Endpoints are returned by [`Resolver::lookup_service_endpoints`], in the order in which they
+/// should be tried.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ServiceEndpoint {
+    target_name: Name,
+    port: u16,
+    addrs: Vec<IpAddr>,
+    alpn: Vec<String>,
+    ech_config: Option<Vec<u8>>,
+    priority: Option<u16>,
+}
+
+impl ServiceEndpoint {
+    /// The name of the host serving this endpoint
+    pub fn target_name(&self) -> &Name {
+        &self.target_name
+    }
+
+    /// The port to connect to
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// The addresses of the target host
+    pub fn addrs(&self) -> &[IpAddr] {
+        &self.addrs
+    }
+
+    /// The addresses of the target host, with the port of the endpoint
+    pub fn socket_addrs(&self) -> impl Iterator<Item = SocketAddr> + '_ {
+        self.addrs
+            .iter()
+            .map(move |addr| SocketAddr::new(*addr, self.port))
+    }
+
+    /// The protocols supported by the endpoint, as ALPN identifiers, in order of preference
+    ///
+    /// This includes the default protocol of the scheme unless the record has the
+    /// `no-default-alpn` parameter. It is empty for the fallback endpoint, and for schemes without a
+    /// known default protocol when the record has no `alpn` parameter.
+    pub fn alpn(&self) -> &[String] {
+        &self.alpn
+    }
+
+    /// The `ECHConfigList` to use for Encrypted Client Hello, if the record has an `ech` parameter
+    pub fn ech_config(&self) -> Option<&[u8]> {
+        self.ech_config.as_deref()
+    }
+
+    /// The SvcPriority of the record of this endpoint, or `None` for the fallback endpoint
+    pub fn priority(&self) -> Option<u16> {
+        self.priority
+    }
+
+    /// Whether this endpoint was not derived from an SVCB or HTTPS record
+    ///
+    /// When no usable ServiceMode record is found, the only endpoint is the origin (or the target
+    /// of the last AliasMode record) at the requested port, as if there were no SVCB records.
+    pub fn is_fallback(&self) -> bool {
+        self.priority.is_none()
+    }
+}
+
+/// Resolves the endpoints of a service, see [`Resolver::lookup_service_endpoints`]
+pub(crate) async fn lookup<P: ConnectionProvider>(
+    resolver: &Resolver<P>,
+    name: Name,
+    port: u16,
+    scheme: &str,
+) -> Result<Vec<ServiceEndpoint>, ProtoError> {
+    let scheme = scheme.to_ascii_lowercase();
+    let (mut qname, record_type, service_port) = svcb_query(name.clone(), port, &scheme)?;
+    // the name of the fallback endpoint, the origin or the target of the last AliasMode record
+    let mut fallback_name = name;
+    let default_alpn = match record_type {
+        RecordType::HTTPS => Some("http/1.1"),
+        _ => None,
+    };
+
+    for _ in 0..MAX_ALIAS_CHAIN {
+        let lookup = match resolver.lookup(qname.clone(), record_type).await {
+            Ok(lookup) => lookup,
+            Err(e) if e.is_no_records_found() => {
+                debug!("no {record_type} records for {qname}, falling back to {fallback_name}");
+                return fallback(resolver, fallback_name, port).await;
+            }
+            Err(e) => return Err(e),
+        };
+
+        let records = lookup
+            .record_iter()
+            .filter_map(|record| match record.data() {
+                RData::SVCB(svcb) => Some((record.name(), svcb)),
+                RData::HTTPS(https) => Some((record.name(), &https.0)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        // "If an RRSet contains a record in AliasMode, the recipient MUST ignore any ServiceMode
+        // records in the set."
+        if let Some((_, alias)) = records.iter().find(|(_, svcb)| svcb.svc_priority() == 0) {
+            // "a TargetName of "." indicates that the service is not available or does not exist.
+            // This indication is advisory: clients encountering this indication MAY ignore it and
+            // attempt to connect without the use of SVCB."
+            if alias.target_name().is_root() {
+                return fallback(resolver, fallback_name, port).await;
+            }
+
+            qname = alias.target_name().clone();
+            fallback_name = qname.clone();
+            continue;
+        }
+
+        let services = service_mode_order(records, |_| rand::random())
+            .into_iter()
+            .filter_map(|(owner, svcb)| endpoint(owner, svcb, service_port, default_alpn))
+            .collect::<Vec<_>>();
+        if services.is_empty() {
+            return fallback(resolver, fallback_name, port).await;
+        }
+
+        let mut endpoints = Vec::with_capacity(services.len());
+        let mut error = None;
+        for (mut endpoint, hints) in services {
+            match resolver.lookup_ip(endpoint.target_name.clone()).await {
+                Ok(lookup) => endpoint.addrs.extend(lookup.iter()),
+                Err(e) => {
+                    debug!("failed to resolve {}: {e}", endpoint.target_name);
+                    error = Some(e);
+                }
+            }
+
+            // "If A and AAAA records for TargetName are locally available, the client SHOULD
+            // ignore these hints."
+            if endpoint.addrs.is_empty() {
+                endpoint.addrs = hints;
+            }
+
+            if !endpoint.addrs.is_empty() {
+                endpoints.push(endpoint);
+            }
+        }
+
+        return match error {
+            Some(error) if endpoints.is_empty() => Err(error),
+            _ => Ok(endpoints),
+        };
+    }
+
+    Err(format!("too many {record_type} AliasMode records following {qname}").into())
+}
+
+/// Returns the name and type of the records to query, and the port of the service endpoints
+///
+/// The port of the endpoints only differs from `port` for port 80 of `http`: "When an HTTPS RR
+/// is present for an origin, all "http" scheme requests to that origin SHOULD logically be
+/// redirected to "https"", so the records are those of port 443 of `https`. Without records, the
+/// fallback endpoint keeps port 80.
+fn svcb_query(name: Name, port: u16, scheme: &str) -> Result<(Name, RecordType, u16), ProtoError> {
+    match scheme {
+        "https" | "http" => {
+            let port = match (scheme, port) {
+                ("http", 80) => 443,
+                _ => port,
+            };
+
+            // "The HTTPS RR uses Port Prefix Naming, with one modification: if the scheme is
+            // "https" and the port is 443, then the client's original QNAME is equal to the
+            // service name"
+            let qname = match port {
+                443 => name,
+                _ => name
+                    .prepend_label("_https")?
+                    .prepend_label(format!("_{port}"))?,
+            };
+
+            Ok((qname, RecordType::HTTPS, port))
+        }
+        _ => {
+            let qname = name
+                .prepend_label(format!("_{scheme}"))?
+                .prepend_label(format!("_{port}"))?;
+            Ok((qname, RecordType::SVCB, port))
+        }
+    }
+}
+
+/// Orders ServiceMode records by ascending priority, records with the same priority are ordered
+/// by `random`
+fn service_mode_order<'a>(
+    mut records: Vec<(&'a Name, &'a SVCB)>,
+    mut random: impl FnMut(&SVCB) -> u32,
+) -> Vec<(&'a Name, &'a SVCB)> {
+    // "When receiving an RRSet containing multiple SVCB records with the same SvcPriority value,
+    // clients SHOULD apply a random shuffle within a priority level"
+    records.sort_by_cached_key(|(_, svcb)| (svcb.svc_priority(), random(svcb)));
+    records
+}
+
+/// Builds the endpoint of a ServiceMode record, along with its address hints
+///
+/// Returns `None` if the record is not usable by this client.
+fn endpoint(
+    owner: &Name,
+    svcb: &SVCB,
+    port: u16,
+    default_alpn: Option<&str>,
+) -> Option<(ServiceEndpoint, Vec<IpAddr>)> {
+    // "If TargetName has the value ".", then the owner name of this record MUST be used as the
+    // effective TargetName."
+    let target_name = match svcb.target_name().is_root() {
+        true => owner.clone(),
+        false => svcb.target_name().clone(),
+    };
+
+    let mut endpoint = ServiceEndpoint {
+        target_name,
+        port,
+        addrs: Vec::new(),
+        alpn: Vec::new(),
+        ech_config: None,
+        priority: Some(svcb.svc_priority()),
+    };
+
+    let mut hints = Vec::new();
+    let mut no_default_alpn = false;
+    for (key, value) in svcb.svc_params() {
+        match value {
+            SvcParamValue::Mandatory(mandatory) => {
+                // "In a ServiceMode RR, if the client does not recognize all the keys in the
+                // "mandatory" SvcParam, it MUST ignore the RR"
+                if !mandatory.0.iter().all(is_supported) {
+                    debug!("ignoring {svcb}, unsupported mandatory keys");
+                    return None;
+                }
+            }
+            SvcParamValue::Alpn(alpn) => endpoint.alpn.extend(alpn.0.iter().cloned()),
+            SvcParamValue::NoDefaultAlpn => no_default_alpn = true,
+            SvcParamValue::Port(value) => endpoint.port = *value,
+            SvcParamValue::Ipv4Hint(hint) => {
+                hints.extend(hint.0.iter().map(|a| IpAddr::V4(a.0)));
+            }
+            SvcParamValue::Ipv6Hint(hint) => {
+                hints.extend(hint.0.iter().map(|aaaa| IpAddr::V6(aaaa.0)));
+            }
+            SvcParamValue::EchConfigList(ech) => endpoint.ech_config = Some(ech.0.clone()),
+            SvcParamValue::Unknown(_) => debug!("ignoring unknown SvcParamKey {key}"),
+        }
+    }
+
+    match (default_alpn, no_default_alpn) {
+        // "When "no-default-alpn" is specified in an RR, "alpn" must also be specified in order
+        // for the RR to be "self-consistent""
+        (_, true) if endpoint.alpn.is_empty() => {
+            debug!("ignoring {svcb}, no-default-alpn without alpn");
+            return None;
+        }
+        (Some(default), false) if !endpoint.alpn.iter().any(|alpn| alpn == default) => {
+            endpoint.alpn.push(default.to_owned());
+        }
+        _ => {}
+    }
+
+    Some((endpoint, hints))
+}
+
+/// Whether the key is used to build the endpoints
+fn is_supported(key: &SvcParamKey) -> bool {
+    matches!(
+        key,
+        SvcParamKey::Alpn
+            | SvcParamKey::NoDefaultAlpn
+            | SvcParamKey::Port
+            | SvcParamKey::Ipv4Hint
+            | SvcParamKey::EchConfigList
+            | SvcParamKey::Ipv6Hint
+    )
+}
+
+/// Returns the endpoint used when there are no usable SVCB records
+async fn fallback<P: ConnectionProvider>(
+    resolver: &Resolver<P>,
+    name: Name,
+    port: u16,
+) -> Result<Vec<ServiceEndpoint>, ProtoError> {
+    let lookup = resolver.lookup_ip(name.clone()).await?;
+    Ok(vec![ServiceEndpoint {
+        target_name: name,
+        port,
+        addrs: lookup.iter().collect(),
+        alpn: Vec::new(),
+        ech_config: None,
+        priority: None,
+    }])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use test_support::subscribe;
+
+    use super::*;
+    use crate::proto::rr::rdata::{
+        A,
+        svcb::{Alpn, EchConfigList, IpHint, Mandatory},
+    };
+
+    fn svcb(priority: u16, target: &str, params: Vec<(SvcParamKey, SvcParamValue)>) -> SVCB {
+        SVCB::new(priority, Name::from_str(target).unwrap(), params)
+    }
+
+    #[test]
+    fn test_svcb_query() {
+        let name = Name::from_str("example.com.").unwrap();
+        let query = |port, scheme| {
+            let (qname, record_type, port) = svcb_query(name.clone(), port, scheme).unwrap();
+            (qname.to_string(), record_type, port)
+        };
+
+        assert_eq!(
+            query(443, "https"),
+            ("example.com.".to_owned(), RecordType::HTTPS, 443)
+        );
+        assert_eq!(
+            query(80, "http"),
+            ("example.com.".to_owned(), RecordType::HTTPS, 443)
+        );
+        assert_eq!(
+            query(8443, "https"),
+            (
+                "_8443._https.example.com.".to_owned(),
+                RecordType::HTTPS,
+                8443
+            )
+        );
+        assert_eq!(
+            query(853, "dot"),
+            ("_853._dot.example.com.".to_owned(), RecordType::SVCB, 853)
+        );
+    }
+
+    #[test]
+    fn test_service_mode_order() {
+        let owner = Name::from_str("example.com.").unwrap();
+        let records = [
+            svcb(2, "c.example.com.", vec![]),
+            svcb(1, "a.example.com.", vec![]),
+            svcb(2, "b.example.com.", vec![]),
+        ];
+
+        // later records get lower random keys
+        let mut key = u32::MAX;
+        let order = service_mode_order(records.iter().map(|svcb| (&owner, svcb)).collect(), |_| {
+            key -= 1;
+            key
+        });
+        let targets = order
+            .iter()
+            .map(|(_, svcb)| svcb.target_name().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            targets,
+            ["a.example.com.", "b.example.com.", "c.example.com."]
+        );
+    }
+
+    #[test]
+    fn test_endpoint_params() {
+        let owner = Name::from_str("example.com.").unwrap();
+        let record = svcb(
+            1,
+            ".",
+            vec![
+                (
+                    SvcParamKey::Alpn,
+                    SvcParamValue::Alpn(Alpn(vec!["h3".to_owned(), "h2".to_owned()])),
+                ),
+                (SvcParamKey::Port, SvcParamValue::Port(8443)),
+                (
+                    SvcParamKey::Ipv4Hint,
+                    SvcParamValue::Ipv4Hint(IpHint(vec![A::new(192, 0, 2, 1)])),
+                ),
+                (
+                    SvcParamKey::EchConfigList,
+                    SvcParamValue::EchConfigList(EchConfigList(vec![1, 2, 3])),
+                ),
+            ],
+        );
+
+        let (endpoint, hints) = endpoint(&owner, &record, 443, Some("http/1.1")).unwrap();
+        assert_eq!(endpoint.target_name(), &owner);
+        assert_eq!(endpoint.port(), 8443);
+        assert_eq!(endpoint.alpn(), ["h3", "h2", "http/1.1"]);
+        assert_eq!(endpoint.ech_config(), Some(&[1, 2, 3][..]));
+        assert_eq!(endpoint.priority(), Some(1));
+        assert!(!endpoint.is_fallback());
+        assert_eq!(hints, [IpAddr::from([192, 0, 2, 1])]);
+    }
+
+    #[test]
+    fn test_endpoint_unusable() {
+        let owner = Name::from_str("example.com.").unwrap();
+        let alpn = (
+            SvcParamKey::Alpn,
+            SvcParamValue::Alpn(Alpn(vec!["h2".to_owned()])),
+        );
+
+        let record = svcb(
+            1,
+            "a.example.com.",
+            vec![
+                (
+                    SvcParamKey::Mandatory,
+                    SvcParamValue::Mandatory(Mandatory(vec![SvcParamKey::Key(42)])),
+                ),
+                alpn.clone(),
+            ],
+        );
+        assert!(endpoint(&owner, &record, 443, Some("http/1.1")).is_none());
+
+        let record = svcb(
+            1,
+            "a.example.com.",
+            vec![(SvcParamKey::NoDefaultAlpn, SvcParamValue::NoDefaultAlpn)],
+        );
+        assert!(endpoint(&owner, &record, 443, Some("http/1.1")).is_none());
+
+        let record = svcb(
+            1,
+            "a.example.com.",
+            vec![
+                alpn,
+                (SvcParamKey::NoDefaultAlpn, SvcParamValue::NoDefaultAlpn),
+            ],
+        );
+        let (endpoint, _) = endpoint(&owner, &record, 443, Some("http/1.1")).unwrap();
+        assert_eq!(endpoint.target_name().to_string(), "a.example.com.");
+        assert_eq!(endpoint.alpn(), ["h2"]);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_fallback() {
+        use std::net::Ipv4Addr;
+        use std::sync::{Arc, Mutex};
+
+        use tokio::net::UdpSocket;
+
+        use crate::config::{NameServerConfig, ResolveHosts, ResolverConfig};
+        use crate::proto::op::Message;
+        use crate::proto::rr::Record;
+        use crate::proto::runtime::TokioRuntimeProvider;
+
+        subscribe();
+
+        // answers A queries, and every other query without records
+        let server = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let queries = Arc::new(Mutex::new(Vec::new()));
+        let server_queries = queries.clone();
+        tokio::spawn(async move {
+            let mut buffer = [0_u8; 512];
+            loop {
+                let (len, addr) = server.recv_from(&mut buffer).await.unwrap();
+                let request = Message::from_vec(&buffer[0..len]).unwrap();
+                let mut response = Message::response(request.id(), request.op_code());
+                response.set_recursion_available(true);
+                for query in request.queries() {
+                    server_queries.lock().unwrap().push(query.clone());
+                    response.add_query(query.clone());
+                    if query.query_type() == RecordType::A {
+                        response.add_answer(Record::from_rdata(
+                            query.name().clone(),
+                            3600,
+                            RData::A(A::new(192, 0, 2, 1)),
+                        ));
+                    }
+                }
+                let response = response.to_vec().unwrap();
+                server.send_to(&response, addr).await.unwrap();
+            }
+        });
+
+        let mut name_server = NameServerConfig::udp(server_addr.ip());
+        name_server.connections[0].port = server_addr.port();
+        let mut config = ResolverConfig::default();
+        config.add_name_server(name_server);
+        let mut builder = Resolver::builder_with_config(config, TokioRuntimeProvider::default());
+        builder.options_mut().use_hosts_file = ResolveHosts::Never;
+        let resolver = builder.build().unwrap();
+
+        let endpoints = resolver
+            .lookup_service_endpoints("example.com.", 8443, "https")
+            .await
+            .unwrap();
+        assert_eq!(endpoints.len(), 1);
+        assert!(endpoints[0].is_fallback());
+        assert_eq!(endpoints[0].target_name().to_string(), "example.com.");
+        assert_eq!(endpoints[0].port(), 8443);
+        assert_eq!(endpoints[0].addrs(), [IpAddr::from([192, 0, 2, 1])]);
+
+        // without an HTTPS record, http is not upgraded to port 443
+        let endpoints = resolver
+            .lookup_service_endpoints("example.net.", 80, "http")
+            .await
+            .unwrap();
+        assert_eq!(endpoints.len(), 1);
+        assert!(endpoints[0].is_fallback());
+        assert_eq!(endpoints[0].target_name().to_string(), "example.net.");
+        assert_eq!(endpoints[0].port(), 80);
+
+        let queries = queries.lock().unwrap();
+        let https_queries = queries
+            .iter()
+            .filter(|query| query.query_type() == RecordType::HTTPS)
+            .map(|query| query.name().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(https_queries, ["_8443._https.example.com.", "example.net."]);
+        let a_queries = queries
+            .iter()
+            .filter(|query| query.query_type() == RecordType::A)
+            .map(|query| query.name().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(a_queries, ["example.com.", "example.net."]);
+    }
+}
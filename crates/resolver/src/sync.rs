@@ -0,0 +1,303 @@
+//! A blocking resolver, for callers which do not run an async runtime
+//!
+//! ```rust,no_run
+//! # fn main() -> Result<(), hickory_resolver::proto::ProtoError> {
+//! use hickory_resolver::config::{GOOGLE, ResolverConfig, ResolverOpts};
+//! use hickory_resolver::sync::Resolver;
+//!
+//! let resolver = Resolver::new(ResolverConfig::udp_and_tcp(&GOOGLE), ResolverOpts::default())?;
+//! let response = resolver.lookup_ip("www.example.com.")?;
+//! for ip in response.iter() {
+//!     println!("{ip}");
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::future::Future;
+use std::net::IpAddr;
+use std::sync::{Arc, mpsc};
+use std::time::Duration;
+
+use tokio::runtime::{self, Runtime};
+
+use crate::config::{LookupOptions, ResolverConfig, ResolverOpts};
+use crate::lookup::{Lookup, TypedLookup, VerifiedReverseLookup};
+use crate::lookup_ip::LookupIp;
+use crate::proto::rr::{IntoName, RecordType, rdata};
+use crate::proto::runtime::TokioRuntimeProvider;
+use crate::proto::{ProtoError, ProtoErrorKind};
+use crate::service_endpoint::ServiceEndpoint;
+use crate::{ResolverBuilder, TokioResolver};
+
+macro_rules! lookup_fn {
+    ($p:ident, $l:ty) => {
+        /// Performs a lookup for the associated type, blocking until it completes.
+        ///
+        /// See the method of the same name on [`crate::Resolver`].
+        pub fn $p(&self, query: impl IntoName) -> Result<$l, ProtoError> {
+            let query = query.into_name()?;
+            let resolver = self.resolver.clone();
+            self.block_on(async move { resolver.$p(query).await })
+        }
+    };
+}
+
+/// A blocking DNS resolver
+///
+/// This wraps a [`TokioResolver`], which runs on a runtime owned by this resolver, with a single
+/// background thread. Each method blocks the calling thread until the lookup completes, or until
+/// the [timeout](Self::with_timeout) expires. As the lookups do not run on the calling thread,
+/// the methods can be called from any thread, including from within another Tokio runtime,
+/// although blocking an async task should be avoided.
+///
+/// The resolver can be cloned cheaply, clones share the runtime and the cache. The runtime is
+/// shut down when the last clone is dropped.
+#[derive(Clone)]
+pub struct Resolver {
+    resolver: TokioResolver,
+    runtime: Arc<Background>,
+    timeout: Duration,
+}
+
+impl Resolver {
+    /// Constructs a new resolver with the system configuration
+    ///
+    /// This will use `/etc/resolv.conf` on Unix OSes and the registry on Windows.
+    #[cfg(any(unix, target_os = "windows"))]
+    #[cfg(feature = "system-config")]
+    pub fn from_system_conf() -> Result<Self, ProtoError> {
+        Self::from_builder(TokioResolver::builder_tokio()?)
+    }
+
+    /// Constructs a new resolver with the given configuration and options
+    pub fn new(config: ResolverConfig, options: ResolverOpts) -> Result<Self, ProtoError> {
+        Self::from_builder(
+            TokioResolver::builder_with_config(config, TokioRuntimeProvider::default())
+                .with_options(options),
+        )
+    }
+
+    /// Constructs a new resolver from a configured builder
+    ///
+    /// The default timeout is the time the resolver may spend on all the attempts of a request,
+    /// `ResolverOpts::timeout` multiplied by `ResolverOpts::attempts + 1`.
+    pub fn from_builder(
+        builder: ResolverBuilder<TokioRuntimeProvider>,
+    ) -> Result<Self, ProtoError> {
+        let runtime = runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("hickory-resolver-sync")
+            .enable_all()
+            .build()?;
+
+        // the connections of the resolver are bound to the runtime they are created in
+        let resolver = {
+            let _guard = runtime.enter();
+            builder.build()?
+        };
+
+        let options = resolver.options();
+        let timeout = options.timeout * (options.attempts as u32 + 1);
+        Ok(Self {
+            resolver,
+            runtime: Arc::new(Background(Some(runtime))),
+            timeout,
+        })
+    }
+
+    /// Sets the maximum time a method blocks for, after which it returns a timeout error
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// The maximum time a method blocks for
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Returns the async resolver used for the lookups
+    ///
+    /// Its futures are not bound to the runtime of this resolver, they can be run on any Tokio
+    /// runtime.
+    pub fn as_async(&self) -> &TokioResolver {
+        &self.resolver
+    }
+
+    /// Performs a lookup of `name` for `record_type`, blocking until it completes
+    ///
+    /// See [`crate::Resolver::lookup`].
+    pub fn lookup(
+        &self,
+        name: impl IntoName,
+        record_type: RecordType,
+    ) -> Result<Lookup, ProtoError> {
+        let name = name.into_name()?;
+        let resolver = self.resolver.clone();
+        self.block_on(async move { resolver.lookup(name, record_type).await })
+    }
+
+    /// Performs a lookup of `name` for `record_type` with per-lookup options, blocking until it
+    /// completes
+    ///
+    /// See [`crate::Resolver::lookup_with_options`].
+    pub fn lookup_with_options(
+        &self,
+        name: impl IntoName,
+        record_type: RecordType,
+        options: &LookupOptions,
+    ) -> Result<Lookup, ProtoError> {
+        let name = name.into_name()?;
+        let options = options.clone();
+        let resolver = self.resolver.clone();
+        self.block_on(async move {
+            resolver
+                .lookup_with_options(name, record_type, &options)
+                .await
+        })
+    }
+
+    /// Performs a dual-stack lookup of the addresses of `host`, blocking until it completes
+    ///
+    /// See [`crate::Resolver::lookup_ip`].
+    pub fn lookup_ip(&self, host: impl IntoName) -> Result<LookupIp, ProtoError> {
+        let resolver = self.resolver.clone();
+        match host.to_ip() {
+            // keep the address as a string, so that lookup_ip recognizes it
+            Some(ip) => {
+                let host = ip.to_string();
+                self.block_on(async move { resolver.lookup_ip(host).await })
+            }
+            None => {
+                let host = host.into_name()?;
+                self.block_on(async move { resolver.lookup_ip(host).await })
+            }
+        }
+    }
+
+    /// Performs a forward-confirmed reverse lookup of `ip`, blocking until it completes
+    ///
+    /// See [`crate::Resolver::verified_reverse_lookup`].
+    pub fn verified_reverse_lookup(&self, ip: IpAddr) -> Result<VerifiedReverseLookup, ProtoError> {
+        let resolver = self.resolver.clone();
+        self.block_on(async move { resolver.verified_reverse_lookup(ip).await })
+    }
+
+    /// Resolves the endpoints of a service, blocking until it completes
+    ///
+    /// See [`crate::Resolver::lookup_service_endpoints`].
+    pub fn lookup_service_endpoints(
+        &self,
+        name: impl IntoName,
+        port: u16,
+        scheme: &str,
+    ) -> Result<Vec<ServiceEndpoint>, ProtoError> {
+        let name = name.into_name()?;
+        let scheme = scheme.to_owned();
+        let resolver = self.resolver.clone();
+        self.block_on(async move { resolver.lookup_service_endpoints(name, port, &scheme).await })
+    }
+
+    lookup_fn!(reverse_lookup, TypedLookup<rdata::PTR>);
+    lookup_fn!(ipv4_lookup, TypedLookup<rdata::A>);
+    lookup_fn!(ipv6_lookup, TypedLookup<rdata::AAAA>);
+    lookup_fn!(mx_lookup, TypedLookup<rdata::MX>);
+    lookup_fn!(ns_lookup, TypedLookup<rdata::NS>);
+    lookup_fn!(soa_lookup, TypedLookup<rdata::SOA>);
+    lookup_fn!(srv_lookup, TypedLookup<rdata::SRV>);
+    lookup_fn!(tlsa_lookup, TypedLookup<rdata::TLSA>);
+    lookup_fn!(txt_lookup, TypedLookup<rdata::TXT>);
+    lookup_fn!(cert_lookup, TypedLookup<rdata::CERT>);
+
+    /// Flushes/Removes all entries from the cache
+    pub fn clear_cache(&self) {
+        self.resolver.clear_cache();
+    }
+
+    /// Runs `future` on the background runtime, and waits for its output
+    fn block_on<T: Send + 'static>(
+        &self,
+        future: impl Future<Output = Result<T, ProtoError>> + Send + 'static,
+    ) -> Result<T, ProtoError> {
+        let (tx, rx) = mpsc::sync_channel(1);
+        let task = self.runtime.handle().spawn(async move {
+            // the receiver is gone if the caller timed out
+            let _ = tx.send(future.await);
+        });
+
+        match rx.recv_timeout(self.timeout) {
+            Ok(result) => result,
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                task.abort();
+                Err(ProtoErrorKind::Timeout.into())
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => {
+                Err("the lookup was cancelled by the runtime shutting down".into())
+            }
+        }
+    }
+}
+
+/// Shuts down the runtime without blocking, which is allowed from within an async context
+struct Background(Option<Runtime>);
+
+impl Background {
+    fn handle(&self) -> &runtime::Handle {
+        // the runtime is only taken on drop
+        self.0.as_ref().unwrap().handle()
+    }
+}
+
+impl Drop for Background {
+    fn drop(&mut self) {
+        if let Some(runtime) = self.0.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+    use crate::config::GOOGLE;
+
+    fn resolver() -> Resolver {
+        Resolver::new(
+            ResolverConfig::udp_and_tcp(&GOOGLE),
+            ResolverOpts::default(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_send_sync() {
+        fn is_send_sync<T: Send + Sync>() {}
+        is_send_sync::<Resolver>();
+    }
+
+    #[test]
+    fn test_localhost() {
+        let resolver = resolver();
+        let response = resolver.lookup_ip("localhost").unwrap();
+        assert!(
+            response
+                .iter()
+                .any(|ip| ip == IpAddr::V4(Ipv4Addr::LOCALHOST))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_within_runtime() {
+        // constructing, using and dropping the resolver must not panic in an async context
+        let resolver = resolver();
+        let response = resolver.lookup_ip("127.0.0.1").unwrap();
+        assert_eq!(
+            response.iter().collect::<Vec<_>>(),
+            [IpAddr::V4(Ipv4Addr::LOCALHOST)]
+        );
+        drop(resolver);
+    }
+}
@@ -20,6 +20,14 @@ mod unix;
 #[cfg(feature = "system-config")]
 pub use self::unix::{parse_resolv_conf, read_system_conf};
 
+#[cfg(unix)]
+#[cfg(feature = "system-config")]
+mod watch;
+
+#[cfg(unix)]
+#[cfg(feature = "system-config")]
+pub(crate) use self::watch::Watcher;
+
 #[cfg(windows)]
 #[cfg(feature = "system-config")]
 mod windows;
@@ -0,0 +1,238 @@
+//! Reloading of the system configuration while the resolver is running
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use parking_lot::RwLock;
+use tracing::{debug, info, warn};
+
+use super::parse_resolv_conf;
+use crate::config::ResolverOpts;
+use crate::hosts::Hosts;
+use crate::name_server::{ConnectionProvider, NameServer, NameServerPool, TlsConfig};
+use crate::proto::runtime::{RuntimeProvider, Time};
+
+/// Polls `resolv.conf` and the hosts file, and applies their changes to a resolver
+///
+/// Only weak references to the resolver's state are kept, so that the watcher stops once the
+/// resolver is dropped.
+pub(crate) struct Watcher<P: ConnectionProvider> {
+    resolv_conf: WatchedFile,
+    hosts_file: Option<WatchedFile>,
+    pool: Weak<RwLock<NameServerPool<P>>>,
+    hosts: Weak<RwLock<Arc<Hosts>>>,
+    options: Arc<ResolverOpts>,
+    tls: Arc<TlsConfig>,
+    provider: P,
+}
+
+impl<P: ConnectionProvider> Watcher<P> {
+    /// Start watching the files, with their current contents as the baseline
+    pub(crate) fn new(
+        resolv_conf: &Path,
+        hosts_file: Option<&Path>,
+        pool: Weak<RwLock<NameServerPool<P>>>,
+        hosts: Weak<RwLock<Arc<Hosts>>>,
+        options: Arc<ResolverOpts>,
+        tls: Arc<TlsConfig>,
+        provider: P,
+    ) -> Self {
+        Self {
+            resolv_conf: WatchedFile::new(resolv_conf),
+            hosts_file: hosts_file.map(WatchedFile::new),
+            pool,
+            hosts,
+            options,
+            tls,
+            provider,
+        }
+    }
+
+    /// Reload the files every `interval`, until the resolver is dropped
+    pub(crate) async fn run(mut self, interval: Duration) {
+        loop {
+            <P::RuntimeProvider as RuntimeProvider>::Timer::delay_for(interval).await;
+            if !self.reload() {
+                debug!("resolver dropped, no longer watching the system configuration");
+                return;
+            }
+        }
+    }
+
+    /// Apply the changes of the files since the last reload
+    ///
+    /// Returns `false` if the resolver was dropped.
+    fn reload(&mut self) -> bool {
+        let Some(pool) = self.pool.upgrade() else {
+            return false;
+        };
+
+        if let Some(data) = self.resolv_conf.changed() {
+            match parse_resolv_conf(&data) {
+                Ok((config, _)) => {
+                    let current = pool.read().clone();
+                    let servers = config
+                        .name_servers()
+                        .iter()
+                        .map(|config| {
+                            match current
+                                .servers()
+                                .iter()
+                                .find(|ns| ns.config().ip == config.ip)
+                            {
+                                Some(server) => server.clone(),
+                                None => Arc::new(NameServer::new(
+                                    [],
+                                    config.clone(),
+                                    self.options.clone(),
+                                    self.tls.clone(),
+                                    self.provider.clone(),
+                                )),
+                            }
+                        })
+                        .collect();
+
+                    info!(path = %self.resolv_conf.path.display(), "reloaded name servers");
+                    *pool.write() = NameServerPool::from_nameservers(servers, self.options.clone());
+                }
+                Err(error) => {
+                    warn!(path = %self.resolv_conf.path.display(), %error, "keeping current name servers");
+                }
+            }
+        }
+
+        if let Some(file) = &mut self.hosts_file {
+            if let Some(data) = file.changed() {
+                let mut hosts = Hosts::default();
+                match hosts.read_hosts_conf(&data[..]) {
+                    Ok(()) => {
+                        if let Some(cell) = self.hosts.upgrade() {
+                            info!(path = %file.path.display(), "reloaded hosts");
+                            *cell.write() = Arc::new(hosts);
+                        }
+                    }
+                    Err(error) => {
+                        warn!(path = %file.path.display(), %error, "keeping current hosts");
+                    }
+                }
+            }
+        }
+
+        true
+    }
+}
+
+/// A file and its contents when it was last read
+struct WatchedFile {
+    path: PathBuf,
+    contents: Option<Vec<u8>>,
+}
+
+impl WatchedFile {
+    fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_owned(),
+            contents: fs::read(path).ok(),
+        }
+    }
+
+    /// Returns the contents of the file if they changed since the last call
+    ///
+    /// A file which can't be read is not considered changed.
+    fn changed(&mut self) -> Option<Vec<u8>> {
+        let contents = match fs::read(&self.path) {
+            Ok(contents) => contents,
+            Err(error) => {
+                debug!(path = %self.path.display(), %error, "failed to read watched file");
+                return None;
+            }
+        };
+
+        if self.contents.as_ref() == Some(&contents) {
+            return None;
+        }
+
+        self.contents = Some(contents.clone());
+        Some(contents)
+    }
+}
+
+#[cfg(feature = "tokio")]
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::process;
+
+    use test_support::subscribe;
+
+    use super::*;
+    use crate::Resolver;
+    use crate::config::{LookupIpStrategy, NameServerConfig, ResolverConfig};
+    use crate::proto::runtime::TokioRuntimeProvider;
+
+    #[tokio::test]
+    async fn test_reload() {
+        subscribe();
+        let dir = std::env::temp_dir();
+        let resolv_conf = dir.join(format!("hickory-watch-{}-resolv.conf", process::id()));
+        let hosts_file = dir.join(format!("hickory-watch-{}-hosts", process::id()));
+        fs::write(&resolv_conf, "nameserver 192.0.2.1\nnameserver 192.0.2.2\n").unwrap();
+        fs::write(&hosts_file, "192.0.2.10 example.test\n").unwrap();
+
+        let mut config = ResolverConfig::default();
+        for ip in [[192, 0, 2, 1], [192, 0, 2, 2]] {
+            config.add_name_server(NameServerConfig::udp_and_tcp(IpAddr::from(ip)));
+        }
+        let mut builder = Resolver::builder_with_config(config, TokioRuntimeProvider::default());
+        builder.options_mut().ip_strategy = LookupIpStrategy::Ipv4Only;
+        let mut resolver = builder.build().unwrap();
+        let mut hosts = Hosts::default();
+        hosts
+            .read_hosts_conf(&b"192.0.2.10 example.test\n"[..])
+            .unwrap();
+        resolver.set_hosts(Arc::new(hosts));
+
+        let mut watcher = resolver.watch_files(&resolv_conf, Some(&hosts_file));
+        let before = resolver.name_servers().default_pool();
+        assert!(watcher.reload());
+        assert!(Arc::ptr_eq(
+            &before.servers()[0],
+            &resolver.name_servers().default_pool().servers()[0]
+        ));
+
+        fs::write(&resolv_conf, "nameserver 192.0.2.2\nnameserver 192.0.2.3\n").unwrap();
+        fs::write(&hosts_file, "192.0.2.11 example.test\n").unwrap();
+        assert!(watcher.reload());
+
+        let after = resolver.name_servers().default_pool();
+        let ips = after
+            .servers()
+            .iter()
+            .map(|ns| ns.config().ip)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ips,
+            [IpAddr::from([192, 0, 2, 2]), IpAddr::from([192, 0, 2, 3])]
+        );
+        assert!(Arc::ptr_eq(&before.servers()[1], &after.servers()[0]));
+
+        let lookup = resolver.lookup_ip("example.test.").await.unwrap();
+        assert_eq!(
+            lookup.iter().collect::<Vec<_>>(),
+            [IpAddr::V4(Ipv4Addr::new(192, 0, 2, 11))]
+        );
+
+        // invalid contents keep the current configuration
+        fs::write(&resolv_conf, "nameserver not-an-address\n").unwrap();
+        assert!(watcher.reload());
+        assert_eq!(resolver.name_servers().default_pool().servers().len(), 2);
+
+        drop(resolver);
+        assert!(!watcher.reload());
+
+        fs::remove_file(resolv_conf).unwrap();
+        fs::remove_file(hosts_file).unwrap();
+    }
+}
@@ -25,8 +25,8 @@ use crate::{
         runtime::TokioRuntimeProvider,
     },
     resolver::{
-        Resolver,
-        config::{NameServerConfig, ResolveHosts, ResolverConfig, ResolverOpts},
+        self, Resolver,
+        config::{ForwardingRule, NameServerConfig, ResolveHosts, ResolverConfig, ResolverOpts},
         name_server::ConnectionProvider,
     },
     server::{Request, RequestInfo},
@@ -99,8 +99,12 @@ impl<P: ConnectionProvider> ForwardZoneHandlerBuilder<P> {
         } = self;
         info!(%origin, "loading forwarder config");
 
-        let name_servers = config.name_servers;
-        let mut options = config.options.unwrap_or_default();
+        let ForwardConfig {
+            name_servers,
+            forwarding_rules,
+            options,
+        } = config;
+        let mut options = options.unwrap_or_default();
 
         // See RFC 1034, Section 4.3.2:
         // "If the data at the node is a CNAME, and QTYPE doesn't match
@@ -127,7 +131,8 @@ impl<P: ConnectionProvider> ForwardZoneHandlerBuilder<P> {
             options.use_hosts_file = ResolveHosts::Never;
         }
 
-        let config = ResolverConfig::from_parts(domain, search, name_servers);
+        let mut config = ResolverConfig::from_parts(domain, search, name_servers);
+        config.forwarding_rules = forwarding_rules;
 
         let mut resolver_builder = Resolver::builder_with_config(config, runtime);
 
@@ -171,6 +176,7 @@ impl<P: ConnectionProvider> ForwardZoneHandler<P> {
             .map_err(|e| format!("error reading system configuration: {e}"))?;
         let forward_config = ForwardConfig {
             name_servers: resolver_config.name_servers().to_owned(),
+            forwarding_rules: resolver_config.forwarding_rules().to_owned(),
             options: Some(options),
         };
         let mut builder = Self::builder_with_config(forward_config, runtime);
@@ -240,7 +246,7 @@ impl<P: ConnectionProvider> ZoneHandler for ForwardZoneHandler<P> {
         &self,
         name: &LowerName,
         rtype: RecordType,
-        _request_info: Option<&RequestInfo<'_>>,
+        request_info: Option<&RequestInfo<'_>>,
         _lookup_options: LookupOptions,
     ) -> LookupControlFlow<AuthLookup> {
         // TODO: make this an error?
@@ -253,8 +259,16 @@ impl<P: ConnectionProvider> ZoneHandler for ForwardZoneHandler<P> {
         let mut name: Name = name.clone().into();
         name.set_fqdn(false);
 
+        // the prefix of the client is sent upstream if client subnets are configured
+        let mut options = resolver::config::LookupOptions::default();
+        options.client_address = request_info.map(|info| info.src.ip());
+
         use LookupControlFlow::*;
-        match self.resolver.lookup(name, rtype).await {
+        match self
+            .resolver
+            .lookup_with_options(name, rtype, &options)
+            .await
+        {
             Ok(lookup) => Continue(Ok(AuthLookup::from(lookup))),
             Err(e) => Continue(Err(LookupError::from(e))),
         }
@@ -322,6 +336,10 @@ impl<P: ConnectionProvider> ZoneHandler for ForwardZoneHandler<P> {
 pub struct ForwardConfig {
     /// upstream name_server configurations
     pub name_servers: Vec<NameServerConfig>,
+    /// Per-domain upstream name_server configurations, overriding `name_servers` for queries
+    /// within those domains
+    #[serde(default)]
+    pub forwarding_rules: Vec<ForwardingRule>,
     /// Resolver options
     pub options: Option<ResolverOpts>,
 }
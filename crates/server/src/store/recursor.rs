@@ -9,8 +9,6 @@
 
 //! Recursive resolver related types
 
-#[cfg(feature = "__dnssec")]
-use std::sync::Arc;
 use std::{
     borrow::Cow,
     collections::HashSet,
@@ -20,13 +18,21 @@ use std::{
     path::{Path, PathBuf},
     time::Instant,
 };
+#[cfg(feature = "__dnssec")]
+use std::{sync::Arc, time::SystemTime};
 
 use ipnet::IpNet;
 use serde::Deserialize;
 use tracing::{debug, info};
 
 #[cfg(feature = "__dnssec")]
-use crate::{dnssec::NxProofKind, proto::dnssec::TrustAnchors, zone_handler::Nsec3QueryInfo};
+use crate::{
+    dnssec::NxProofKind,
+    proto::dnssec::{NegativeTrustAnchors, TrustAnchorManager, TrustAnchors},
+    recursor::RootZoneSource,
+    resolver::config::NegativeTrustAnchorConfig,
+    zone_handler::Nsec3QueryInfo,
+};
 use crate::{
     error::ConfigError,
     proto::{
@@ -36,8 +42,8 @@ use crate::{
         runtime::RuntimeProvider,
         serialize::txt::{ParseError, Parser},
     },
-    recursor::{DnssecPolicy, Recursor},
-    resolver::TtlConfig,
+    recursor::{DnssecPolicy, QnameMinimization, Recursor, ResourceLimits, ZoneServers},
+    resolver::{TtlConfig, config::ClientSubnetConfig},
     server::{Request, RequestInfo},
     zone_handler::{
         AuthLookup, AxfrPolicy, LookupControlFlow, LookupError, LookupOptions, ZoneHandler,
@@ -51,6 +57,9 @@ use crate::{
 pub struct RecursiveZoneHandler<P: RuntimeProvider> {
     origin: LowerName,
     recursor: Recursor<P>,
+    /// Domains for which validation is disabled, if the recursor is validating
+    #[cfg(feature = "__dnssec")]
+    negative_trust_anchors: Option<Arc<NegativeTrustAnchors>>,
 }
 
 impl<P: RuntimeProvider> RecursiveZoneHandler<P> {
@@ -76,9 +85,45 @@ impl<P: RuntimeProvider> RecursiveZoneHandler<P> {
         if let Some(response_cache_size) = config.response_cache_size {
             builder = builder.response_cache_size(response_cache_size);
         }
+        if let Some(client_subnet) = &config.client_subnet {
+            builder = builder.client_subnet(client_subnet.clone());
+        }
+        for zone in &config.stub_zones {
+            builder = builder.stub_zone(zone.clone());
+        }
+        for zone in &config.forward_zones {
+            builder = builder.forward_zone(zone.clone());
+        }
+        #[cfg(feature = "__tls")]
+        if config.opportunistic_encryption {
+            builder = builder.opportunistic_encryption(true);
+        }
+        #[cfg(feature = "__dnssec")]
+        if let Some(root_zone) = &config.root_zone {
+            builder = builder.root_zone(match (root_zone, root_dir) {
+                (RootZoneSource::File(path), Some(root_dir)) => {
+                    RootZoneSource::File(root_dir.join(path))
+                }
+                (source, _) => source.clone(),
+            });
+        }
+
+        let dnssec_policy = config.dnssec_policy.load().map_err(|e| e.to_string())?;
+        #[cfg(feature = "__dnssec")]
+        let negative_trust_anchors = match &dnssec_policy {
+            DnssecPolicy::ValidateWithStaticKey {
+                negative_trust_anchors,
+                ..
+            }
+            | DnssecPolicy::ValidateWithInitialKey {
+                negative_trust_anchors,
+                ..
+            } => negative_trust_anchors.clone(),
+            _ => None,
+        };
 
         let recursor = builder
-            .dnssec_policy(config.dnssec_policy.load().map_err(|e| e.to_string())?)
+            .dnssec_policy(dnssec_policy)
             .deny_servers(config.deny_server.iter())
             .allow_servers(config.allow_server.iter())
             .recursion_limit(match config.recursion_limit {
@@ -92,12 +137,18 @@ impl<P: RuntimeProvider> RecursiveZoneHandler<P> {
             .avoid_local_udp_ports(config.avoid_local_udp_ports.clone())
             .ttl_config(config.cache_policy.clone())
             .case_randomization(config.case_randomization)
+            .qname_minimization(config.qname_minimization)
+            .max_minimization_steps(config.max_minimization_steps)
+            .resource_limits(config.resource_limits)
+            .root_priming(config.root_priming)
             .build(&root_addrs)
             .map_err(|e| format!("failed to initialize recursor: {e}"))?;
 
         Ok(Self {
             origin: origin.into(),
             recursor,
+            #[cfg(feature = "__dnssec")]
+            negative_trust_anchors,
         })
     }
 }
@@ -132,7 +183,7 @@ impl<P: RuntimeProvider> ZoneHandler for RecursiveZoneHandler<P> {
         &self,
         name: &LowerName,
         rtype: RecordType,
-        _request_info: Option<&RequestInfo<'_>>,
+        request_info: Option<&RequestInfo<'_>>,
         lookup_options: LookupOptions,
     ) -> LookupControlFlow<AuthLookup> {
         debug!("recursive lookup: {} {}", name, rtype);
@@ -140,10 +191,18 @@ impl<P: RuntimeProvider> ZoneHandler for RecursiveZoneHandler<P> {
         let query = Query::query(name.into(), rtype);
         let now = Instant::now();
 
-        let result = self
-            .recursor
-            .resolve(query.clone(), now, lookup_options.dnssec_ok)
-            .await;
+        let result = match request_info {
+            Some(info) => {
+                self.recursor
+                    .resolve_for_client(query, now, lookup_options.dnssec_ok, info.src.ip())
+                    .await
+            }
+            None => {
+                self.recursor
+                    .resolve(query, now, lookup_options.dnssec_ok)
+                    .await
+            }
+        };
 
         let response = match result {
             Ok(response) => response,
@@ -202,6 +261,11 @@ impl<P: RuntimeProvider> ZoneHandler for RecursiveZoneHandler<P> {
         None
     }
 
+    #[cfg(feature = "__dnssec")]
+    fn negative_trust_anchors(&self) -> Option<&Arc<NegativeTrustAnchors>> {
+        self.negative_trust_anchors.as_ref()
+    }
+
     #[cfg(feature = "metrics")]
     fn metrics_label(&self) -> &'static str {
         "recursive"
@@ -259,6 +323,50 @@ pub struct RecursiveConfig {
     /// [draft-vixie-dnsext-dns0x20-00](https://datatracker.ietf.org/doc/html/draft-vixie-dnsext-dns0x20-00).
     #[serde(default)]
     pub case_randomization: bool,
+
+    /// Send the subnet of the client to authoritative name servers (EDNS Client Subnet)
+    #[serde(default)]
+    pub client_subnet: Option<ClientSubnetConfig>,
+
+    /// How query names are minimised when looking for the name servers of a zone (RFC 9156),
+    /// `Off` disables minimisation
+    #[serde(default)]
+    pub qname_minimization: QnameMinimization,
+
+    /// Maximum number of queries made to find the name servers of a zone, unused when
+    /// `qname_minimization` is `Off`
+    #[serde(default = "max_minimization_steps_default")]
+    pub max_minimization_steps: u8,
+
+    /// Limits on the work done for a single client query, against resource exhaustion attacks
+    ///
+    /// Queries exceeding a limit are answered with SERVFAIL, and an Extended DNS Error giving the
+    /// limit if the client uses EDNS.
+    #[serde(default)]
+    pub resource_limits: ResourceLimits,
+
+    /// Query the roots for the root name servers, which then replace the roots (RFC 8109)
+    #[serde(default = "root_priming_default")]
+    pub root_priming: bool,
+
+    /// Zones resolved starting from the listed name servers, instead of the roots
+    #[serde(default)]
+    pub stub_zones: Vec<ZoneServers>,
+
+    /// Zones for which queries are forwarded to the listed recursive resolvers
+    #[serde(default)]
+    pub forward_zones: Vec<ZoneServers>,
+
+    /// Send queries to authoritative name servers over DNS over QUIC or DNS over TLS when they
+    /// support it, without authenticating them (RFC 9539)
+    #[cfg(feature = "__tls")]
+    #[serde(default)]
+    pub opportunistic_encryption: bool,
+
+    /// Local copy of the root zone, used instead of the root name servers (RFC 8806)
+    #[cfg(feature = "__dnssec")]
+    #[serde(default)]
+    pub root_zone: Option<RootZoneSource>,
 }
 
 impl RecursiveConfig {
@@ -294,6 +402,14 @@ fn ns_recursion_limit_default() -> u8 {
     24
 }
 
+fn max_minimization_steps_default() -> u8 {
+    10
+}
+
+fn root_priming_default() -> bool {
+    true
+}
+
 /// DNSSEC policy configuration
 #[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
 #[serde(deny_unknown_fields)]
@@ -320,10 +436,48 @@ pub enum DnssecPolicyConfig {
         /// returned having an iteration count above this limit will be considered Bogus and will
         /// result in a SERVFAIL response being returned to the requester.
         nsec3_hard_iteration_limit: Option<u16>,
+        /// domains for which validation is disabled, see RFC 7646; `hickory-dns` reloads them from
+        /// its configuration file when it receives SIGHUP
+        #[serde(default)]
+        negative_trust_anchors: Vec<NegativeTrustAnchorConfig>,
+    },
+
+    /// DNSSEC validation is enabled, starting from the chosen `trust_anchor` set of keys, which
+    /// is then kept up to date with the root key rollovers as described in RFC 5011
+    #[cfg(feature = "__dnssec")]
+    ValidateWithInitialKey {
+        /// set to `None` to start from the built-in trust anchor
+        path: Option<PathBuf>,
+        /// file in which the state of the trust anchors is kept across restarts; when it exists,
+        /// it takes precedence over `path`
+        state_file: Option<PathBuf>,
+        /// set to control the 'soft' NSEC3 iteration limit, see `ValidateWithStaticKey`
+        nsec3_soft_iteration_limit: Option<u16>,
+        /// set to control the 'hard' NSEC3 iteration limit, see `ValidateWithStaticKey`
+        nsec3_hard_iteration_limit: Option<u16>,
+        /// domains for which validation is disabled, see `ValidateWithStaticKey`
+        #[serde(default)]
+        negative_trust_anchors: Vec<NegativeTrustAnchorConfig>,
     },
 }
 
 impl DnssecPolicyConfig {
+    /// Returns the configured negative trust anchors
+    #[cfg(feature = "__dnssec")]
+    pub fn negative_trust_anchors(&self) -> &[NegativeTrustAnchorConfig] {
+        match self {
+            Self::ValidateWithStaticKey {
+                negative_trust_anchors,
+                ..
+            }
+            | Self::ValidateWithInitialKey {
+                negative_trust_anchors,
+                ..
+            } => negative_trust_anchors,
+            _ => &[],
+        }
+    }
+
     pub(crate) fn load(&self) -> Result<DnssecPolicy, ParseError> {
         Ok(match self {
             Self::SecurityUnaware => DnssecPolicy::SecurityUnaware,
@@ -334,6 +488,7 @@ impl DnssecPolicyConfig {
                 path,
                 nsec3_soft_iteration_limit,
                 nsec3_hard_iteration_limit,
+                negative_trust_anchors,
             } => DnssecPolicy::ValidateWithStaticKey {
                 trust_anchor: path
                     .as_ref()
@@ -342,14 +497,68 @@ impl DnssecPolicyConfig {
                     .map(Arc::new),
                 nsec3_soft_iteration_limit: *nsec3_soft_iteration_limit,
                 nsec3_hard_iteration_limit: *nsec3_hard_iteration_limit,
+                negative_trust_anchors: load_negative_trust_anchors(negative_trust_anchors),
             },
+            #[cfg(feature = "__dnssec")]
+            Self::ValidateWithInitialKey {
+                path,
+                state_file,
+                nsec3_soft_iteration_limit,
+                nsec3_hard_iteration_limit,
+                negative_trust_anchors,
+            } => {
+                let initial = match path {
+                    Some(path) => TrustAnchors::from_file(path)?,
+                    None => TrustAnchors::default(),
+                };
+
+                let trust_anchor_manager = match state_file {
+                    Some(state_file) => TrustAnchorManager::with_state_file(&initial, state_file)?,
+                    None => TrustAnchorManager::new(&initial),
+                };
+
+                DnssecPolicy::ValidateWithInitialKey {
+                    trust_anchor_manager: Arc::new(trust_anchor_manager),
+                    nsec3_soft_iteration_limit: *nsec3_soft_iteration_limit,
+                    nsec3_hard_iteration_limit: *nsec3_hard_iteration_limit,
+                    negative_trust_anchors: load_negative_trust_anchors(negative_trust_anchors),
+                }
+            }
         })
     }
 }
 
+/// Loads the configured negative trust anchors
+///
+/// The set is created even if no anchors are configured, so that anchors can be added to it while
+/// the recursor is running.
+#[cfg(feature = "__dnssec")]
+fn load_negative_trust_anchors(
+    config: &[NegativeTrustAnchorConfig],
+) -> Option<Arc<NegativeTrustAnchors>> {
+    let anchors = NegativeTrustAnchors::new();
+    reload_negative_trust_anchors(&anchors, config);
+    Some(Arc::new(anchors))
+}
+
+/// Replaces the anchors of `anchors` with those of `config`, which expire after their lifetime
+/// from now
+#[cfg(feature = "__dnssec")]
+pub fn reload_negative_trust_anchors(
+    anchors: &NegativeTrustAnchors,
+    config: &[NegativeTrustAnchorConfig],
+) {
+    let now = SystemTime::now();
+    anchors.replace(
+        config
+            .iter()
+            .map(|anchor| (anchor.name.clone(), now + anchor.lifetime)),
+    );
+}
+
 #[cfg(test)]
 mod tests {
-    #[cfg(all(feature = "__dnssec", feature = "toml"))]
+    #[cfg(feature = "toml")]
     use super::*;
 
     #[cfg(all(feature = "__dnssec", feature = "toml"))]
@@ -367,6 +576,96 @@ dnssec_policy.ValidateWithStaticKey.path = "/etc/trusted-key.key""#;
         }
     }
 
+    #[cfg(all(feature = "__dnssec", feature = "toml"))]
+    #[test]
+    fn can_parse_rfc5011_config() {
+        let input = r#"roots = "/etc/root.hints"
+dnssec_policy.ValidateWithInitialKey.state_file = "/var/lib/hickory/root.state""#;
+
+        let config: RecursiveConfig = toml::from_str(input).unwrap();
+
+        if let DnssecPolicyConfig::ValidateWithInitialKey {
+            path, state_file, ..
+        } = config.dnssec_policy
+        {
+            assert_eq!(None, path);
+            assert_eq!(
+                Some(Path::new("/var/lib/hickory/root.state")),
+                state_file.as_deref()
+            );
+        } else {
+            unreachable!()
+        }
+    }
+
+    #[cfg(all(feature = "__dnssec", feature = "toml"))]
+    #[test]
+    fn can_parse_root_zone_config() {
+        let input = r#"roots = "/etc/root.hints"
+root_zone.Transfer = "192.0.47.132:53""#;
+
+        let config: RecursiveConfig = toml::from_str(input).unwrap();
+        assert_eq!(
+            config.root_zone,
+            Some(RootZoneSource::Transfer("192.0.47.132:53".parse().unwrap()))
+        );
+    }
+
+    #[cfg(feature = "toml")]
+    #[test]
+    fn can_parse_stub_and_forward_zones() {
+        use std::net::Ipv4Addr;
+
+        use crate::recursor::ZoneValidation;
+
+        let input = r#"roots = "/etc/root.hints"
+
+[[stub_zones]]
+zone = "corp.internal."
+servers = ["10.0.0.53", "10.0.1.53"]
+
+[[forward_zones]]
+zone = "10.in-addr.arpa."
+servers = ["10.0.0.1"]
+validation = "Insecure""#;
+
+        let config: RecursiveConfig = toml::from_str(input).unwrap();
+
+        let [stub] = &config.stub_zones[..] else {
+            panic!("expected one stub zone: {:?}", config.stub_zones);
+        };
+        assert_eq!(stub.zone, Name::from_ascii("corp.internal.").unwrap());
+        assert_eq!(stub.servers.len(), 2);
+        assert_eq!(stub.validation, ZoneValidation::Validate);
+
+        let [forward] = &config.forward_zones[..] else {
+            panic!("expected one forward zone: {:?}", config.forward_zones);
+        };
+        assert_eq!(forward.servers, [IpAddr::from(Ipv4Addr::new(10, 0, 0, 1))]);
+        assert_eq!(forward.validation, ZoneValidation::Insecure);
+    }
+
+    #[cfg(all(feature = "recursor", feature = "toml"))]
+    #[test]
+    fn can_parse_resource_limits() {
+        let config: RecursiveConfig = toml::from_str(r#"roots = "/etc/root.hints""#).unwrap();
+        assert_eq!(config.resource_limits, ResourceLimits::default());
+
+        let input = r#"roots = "/etc/root.hints"
+
+[resource_limits]
+max_upstream_queries = 32
+max_key_tag_collisions = 4"#;
+
+        let config: RecursiveConfig = toml::from_str(input).unwrap();
+        assert_eq!(config.resource_limits.max_upstream_queries, 32);
+        assert_eq!(config.resource_limits.max_key_tag_collisions, 4);
+        assert_eq!(
+            config.resource_limits.max_signature_validations,
+            ResourceLimits::default().max_signature_validations
+        );
+    }
+
     #[cfg(all(feature = "recursor", feature = "toml"))]
     #[test]
     fn can_parse_recursor_cache_policy() {
@@ -400,4 +699,108 @@ positive_max_ttl = 3600"#;
             Duration::from_secs(3600)
         )
     }
+
+    #[cfg(feature = "recursor")]
+    #[tokio::test]
+    async fn resource_limit_is_extended_dns_error() {
+        use std::{
+            io,
+            net::IpAddr,
+            sync::{Arc, Mutex},
+        };
+
+        use test_support::subscribe;
+
+        use crate::{
+            proto::{
+                op::{Edns, Message, Query, ResponseCode},
+                rr::{
+                    LowerName, Name, Record, RecordType,
+                    rdata::opt::{EdnsCode, EdnsOption},
+                },
+                runtime::TokioTime,
+                serialize::binary::{BinEncodable, BinEncoder},
+                xfer::Protocol,
+            },
+            recursor::{Recursor, ResourceLimits},
+            server::{Request, RequestHandler, ResponseHandler, ResponseInfo},
+            zone_handler::{Catalog, MessageResponse},
+        };
+
+        use super::RecursiveZoneHandler;
+
+        #[derive(Clone, Default)]
+        struct TestResponseHandler(Arc<Mutex<Vec<u8>>>);
+
+        #[async_trait::async_trait]
+        impl ResponseHandler for TestResponseHandler {
+            async fn send_response<'a>(
+                &mut self,
+                response: MessageResponse<
+                    '_,
+                    'a,
+                    impl Iterator<Item = &'a Record> + Send + 'a,
+                    impl Iterator<Item = &'a Record> + Send + 'a,
+                    impl Iterator<Item = &'a Record> + Send + 'a,
+                    impl Iterator<Item = &'a Record> + Send + 'a,
+                >,
+            ) -> io::Result<ResponseInfo> {
+                let mut buf = self.0.lock().unwrap();
+                Ok(response
+                    .destructive_emit(&mut BinEncoder::new(&mut buf))
+                    .expect("could not encode"))
+            }
+        }
+
+        subscribe();
+
+        // no queries may be sent to the name servers, so every resolution exceeds the limit
+        let recursor = Recursor::builder()
+            .resource_limits(ResourceLimits {
+                max_upstream_queries: 0,
+                ..ResourceLimits::default()
+            })
+            .root_priming(false)
+            .build(&[IpAddr::from([127, 0, 0, 1])])
+            .unwrap();
+        let handler = RecursiveZoneHandler {
+            origin: LowerName::from(Name::root()),
+            recursor,
+            #[cfg(feature = "__dnssec")]
+            negative_trust_anchors: None,
+        };
+        let mut catalog = Catalog::new();
+        catalog.upsert(LowerName::from(Name::root()), vec![Arc::new(handler)]);
+
+        let mut message = Message::query();
+        message.add_query(Query::query(
+            Name::from_ascii("www.example.com.").unwrap(),
+            RecordType::A,
+        ));
+        message.set_recursion_desired(true);
+        message.set_edns(Edns::new());
+        let request = Request::from_bytes(
+            message.to_bytes().unwrap(),
+            ([127, 0, 0, 1], 5553).into(),
+            Protocol::Udp,
+        )
+        .unwrap();
+
+        let response_handler = TestResponseHandler::default();
+        catalog
+            .handle_request::<_, TokioTime>(&request, response_handler.clone())
+            .await;
+        let response = Message::from_vec(&response_handler.0.lock().unwrap()).unwrap();
+        assert_eq!(response.response_code(), ResponseCode::ServFail);
+
+        let edns = response
+            .extensions()
+            .as_ref()
+            .expect("response without EDNS");
+        let Some(EdnsOption::Unknown(15, payload)) = edns.option(EdnsCode::Unknown(15)) else {
+            panic!("response without an Extended DNS Error: {edns:?}");
+        };
+        assert_eq!(payload[..2], [0, 0]);
+        assert_eq!(&payload[2..], b"too many queries to name servers");
+    }
 }
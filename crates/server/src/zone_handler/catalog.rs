@@ -541,6 +541,17 @@ async fn lookup<R: ResponseHandler + Unpin>(
             .await;
         };
 
+        // explain failures with an Extended DNS Error, if the client understands EDNS
+        let error_edns = match (&result, response_edns) {
+            (Err(e), Some(edns)) => e.extended_dns_error().map(|option| {
+                let mut edns = edns.clone();
+                edns.options_mut().insert(option);
+                edns
+            }),
+            _ => None,
+        };
+        let response_edns = error_edns.as_ref().or(response_edns);
+
         let (response_header, sections) = build_response(
             result,
             &**handler,
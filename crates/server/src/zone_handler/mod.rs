@@ -21,10 +21,11 @@ use crate::proto::dnssec::crypto::Digest;
 #[cfg(feature = "__dnssec")]
 use crate::proto::dnssec::rdata::KEY;
 #[cfg(feature = "__dnssec")]
-use crate::proto::dnssec::{DnsSecResult, Nsec3HashAlgorithm, SigSigner};
+use crate::proto::dnssec::{DnsSecResult, NegativeTrustAnchors, Nsec3HashAlgorithm, SigSigner};
 use crate::proto::op::{Edns, ResponseCode, ResponseSigner};
 #[cfg(feature = "__dnssec")]
 use crate::proto::rr::Name;
+use crate::proto::rr::rdata::opt::EdnsOption;
 use crate::proto::rr::{LowerName, Record, RecordSet, RecordType, RrsetRecords, rdata::SOA};
 use crate::proto::{DnsError, NoRecords, ProtoError, ProtoErrorKind};
 #[cfg(feature = "recursor")]
@@ -202,6 +203,13 @@ pub trait ZoneHandler: Send + Sync {
     #[cfg(feature = "__dnssec")]
     fn nx_proof_kind(&self) -> Option<&NxProofKind>;
 
+    /// Returns the negative trust anchors of a validating zone handler, which can be changed while
+    /// it is running.
+    #[cfg(feature = "__dnssec")]
+    fn negative_trust_anchors(&self) -> Option<&Arc<NegativeTrustAnchors>> {
+        None
+    }
+
     /// Returns the zone handler metrics label.
     #[cfg(feature = "metrics")]
     fn metrics_label(&self) -> &'static str;
@@ -399,6 +407,14 @@ pub enum LookupError {
     Io(io::Error),
 }
 
+/// EDNS option code of an Extended DNS Error, from RFC 8914
+#[cfg(feature = "recursor")]
+const EXTENDED_DNS_ERROR: u16 = 15;
+
+/// Extended DNS Error INFO-CODE for errors not covered by a more specific code
+#[cfg(feature = "recursor")]
+const EDE_OTHER: u16 = 0;
+
 impl LookupError {
     /// Create a lookup error, specifying that a name exists at the location, but no matching RecordType
     pub fn for_name_exists() -> Self {
@@ -426,6 +442,23 @@ impl LookupError {
         }
     }
 
+    /// Returns an [RFC 8914](https://www.rfc-editor.org/rfc/rfc8914) Extended DNS Error option
+    /// explaining the failure, if the error has one
+    ///
+    /// Queries which exceeded a resource limit of the recursor are given INFO-CODE 0 (Other),
+    /// with the description of the limit as EXTRA-TEXT.
+    pub fn extended_dns_error(&self) -> Option<EdnsOption> {
+        #[cfg(feature = "recursor")]
+        if let Self::RecursiveError(e) = self {
+            let limit = e.resource_limit()?;
+            let mut payload = EDE_OTHER.to_be_bytes().to_vec();
+            payload.extend_from_slice(limit.to_string().as_bytes());
+            return Some(EdnsOption::Unknown(EXTENDED_DNS_ERROR, payload));
+        }
+
+        None
+    }
+
     /// Returns the SOA record, if the error contains one
     pub fn into_soa(self) -> Option<Box<Record<SOA>>> {
         match self {
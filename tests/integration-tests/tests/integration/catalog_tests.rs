@@ -907,6 +907,7 @@ async fn test_update_forwarder() {
 
     let handler = ForwardZoneHandler::builder_tokio(ForwardConfig {
         name_servers: Vec::new(),
+        forwarding_rules: Vec::new(),
         options: None,
     })
     .build()
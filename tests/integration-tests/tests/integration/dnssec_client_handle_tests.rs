@@ -7,13 +7,14 @@ use std::sync::{Arc, Mutex as StdMutex};
 use futures::executor::block_on;
 
 use hickory_client::client::{Client, ClientHandle, MemoizeClientHandle};
-use hickory_proto::dnssec::{DnssecDnsHandle, TrustAnchors};
-use hickory_proto::op::ResponseCode;
+use hickory_proto::dnssec::{DenialKind, DnssecDnsHandle, Proof, TrustAnchors, ValidationStep};
+use hickory_proto::op::{DnsRequestOptions, DnsResponse, Query, ResponseCode};
 use hickory_proto::rr::Name;
 use hickory_proto::rr::{DNSClass, RecordType};
 use hickory_proto::runtime::TokioRuntimeProvider;
 use hickory_proto::tcp::TcpClientStream;
 use hickory_proto::udp::UdpClientStream;
+use hickory_proto::xfer::{DnsHandle, FirstAnswer};
 use hickory_server::zone_handler::{Catalog, ZoneHandler};
 
 use hickory_integration::example_zone::create_secure_example;
@@ -130,6 +131,85 @@ where
     assert!(response.answers().is_empty());
 }
 
+#[tokio::test]
+async fn test_secure_query_validation_trace_nonet() {
+    subscribe();
+    with_nonet(test_secure_query_validation_trace).await;
+}
+
+async fn test_secure_query_validation_trace<H>(mut client: DnssecDnsHandle<H>)
+where
+    H: ClientHandle + Sync + 'static,
+{
+    let name = Name::from_str("www.example.com.").unwrap();
+    let response = client
+        .query(name.clone(), DNSClass::IN, RecordType::A)
+        .await
+        .expect("query failed");
+    // traces are only recorded on request
+    assert!(response.validation_trace().is_none());
+
+    let response = traced_query(&client, name.clone()).await;
+    let trace = response.validation_trace().expect("no validation trace");
+    println!("validation trace:\n{trace}");
+    assert_eq!(trace.failures().count(), 0);
+
+    let zone = Name::from_str("example.com.").unwrap();
+    let position = |matches: &dyn Fn(&ValidationStep) -> bool| {
+        trace
+            .steps()
+            .iter()
+            .position(matches)
+            .expect("step missing")
+    };
+    let anchor =
+        position(&|step| matches!(step, ValidationStep::TrustAnchor { name, .. } if *name == zone));
+    let rrsig = position(
+        &|step| matches!(step, ValidationStep::Rrsig { name: owner, record_type: RecordType::A, result: Ok(()), .. } if *owner == name),
+    );
+    let rrset = position(
+        &|step| matches!(step, ValidationStep::Rrset { name: owner, record_type: RecordType::A, proof: Proof::Secure, .. } if *owner == name),
+    );
+    assert!(anchor < rrsig && rrsig < rrset);
+}
+
+#[tokio::test]
+async fn test_nsec_query_validation_trace_nonet() {
+    subscribe();
+    with_nonet(test_nsec_query_validation_trace).await;
+}
+
+async fn test_nsec_query_validation_trace<H>(client: DnssecDnsHandle<H>)
+where
+    H: ClientHandle + Sync + 'static,
+{
+    let name = Name::from_str("none.example.com.").unwrap();
+    let response = traced_query(&client, name.clone()).await;
+    let trace = response.validation_trace().expect("no validation trace");
+    println!("validation trace:\n{trace}");
+    assert!(trace.steps().contains(&ValidationStep::Denial {
+        name,
+        record_type: RecordType::A,
+        kind: DenialKind::Nsec,
+        proof: Proof::Secure,
+    }));
+}
+
+async fn traced_query<H>(client: &DnssecDnsHandle<H>, name: Name) -> DnsResponse
+where
+    H: ClientHandle + Sync + 'static,
+{
+    let mut options = DnsRequestOptions::default();
+    options.use_edns = true;
+    options.edns_set_dnssec_ok = true;
+    options.validation_trace = true;
+    client
+        .lookup(Query::query(name, RecordType::A), options)
+        .first_answer()
+        .await
+        .expect("query failed")
+}
+
 // // TODO: this test is flaky
 // #[test]
 // #[ignore]
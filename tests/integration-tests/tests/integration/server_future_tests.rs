@@ -29,7 +29,9 @@ use hickory_proto::rr::rdata::{A, OPT};
 use hickory_proto::rr::{DNSClass, Name, RData, Record, RecordType};
 use hickory_proto::runtime::TokioRuntimeProvider;
 #[cfg(feature = "__tls")]
-use hickory_proto::rustls::{default_provider, tls_client_connect_with_bind_addr};
+use hickory_proto::rustls::{
+    default_provider, tls_client_connect_with_bind_addr, unauthenticated_client_config,
+};
 use hickory_proto::tcp::TcpClientStream;
 use hickory_proto::udp::UdpClientStream;
 use hickory_proto::xfer::{DnsHandle, DnsMultiplexer};
@@ -247,6 +249,45 @@ async fn test_server_www_tls() {
     server.await.unwrap();
 }
 
+/// The certificate of the server is not checked, as for opportunistic encryption (RFC 9539)
+#[cfg(feature = "__tls")]
+#[tokio::test]
+async fn test_server_www_tls_unauthenticated() {
+    use std::env;
+
+    subscribe();
+
+    let server_path = env::var("TDNS_WORKSPACE_ROOT").unwrap_or_else(|_| "../..".to_owned());
+    let cert_chain = read_certs(format!("{server_path}/tests/test-data/cert.pem")).unwrap();
+    let key =
+        PrivateKeyDer::from_pem_file(format!("{server_path}/tests/test-data/cert.key")).unwrap();
+    let certified_key = CertifiedKey::from_der(cert_chain, key, &default_provider()).unwrap();
+
+    let addr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0));
+    let tcp_listener = TcpListener::bind(&addr).await.unwrap();
+    let ipaddr = tcp_listener.local_addr().unwrap();
+    let server_continue = Arc::new(AtomicBool::new(true));
+
+    let server = tokio::spawn(server_thread_tls(
+        tcp_listener,
+        server_continue.clone(),
+        Arc::new(SingleCertAndKey::from(certified_key)),
+    ));
+
+    // neither the issuer nor the name of the certificate match
+    let client = tokio::spawn(client_thread_www(tls_client(
+        ipaddr,
+        "127.0.0.1",
+        unauthenticated_client_config().unwrap(),
+    )));
+
+    let client_result = client.await;
+
+    assert!(client_result.is_ok(), "client failed: {client_result:?}");
+    server_continue.store(false, Ordering::Relaxed);
+    server.await.unwrap();
+}
+
 async fn lazy_udp_client(addr: SocketAddr) -> Client<TokioRuntimeProvider> {
     let conn = UdpClientStream::builder(addr, TokioRuntimeProvider::default()).build();
     let (client, driver) = Client::connect(conn).await.expect("failed to connect");
@@ -285,6 +326,15 @@ async fn lazy_tls_client(
         .with_root_certificates(root_store)
         .with_no_client_auth();
 
+    tls_client(ipaddr, server_name, config).await
+}
+
+#[cfg(feature = "__tls")]
+async fn tls_client(
+    ipaddr: SocketAddr,
+    server_name: &str,
+    config: ClientConfig,
+) -> Client<TokioRuntimeProvider> {
     let server_name = ServerName::try_from(server_name)
         .expect("failed to create server name")
         .to_owned();
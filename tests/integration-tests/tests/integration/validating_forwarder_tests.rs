@@ -28,7 +28,7 @@ use hickory_proto::{
 };
 use hickory_resolver::{
     Name,
-    config::{NameServerConfig, ResolverOpts},
+    config::{NameServerConfig, NegativeTrustAnchorConfig, ResolverOpts},
 };
 use hickory_server::{
     Server,
@@ -115,6 +115,32 @@ async fn query_validate_true_unsigned_zone_no_soa() {
     assert!(response.answers().is_empty());
 }
 
+#[tokio::test]
+async fn query_validate_true_unsigned_zone_negative_trust_anchor() {
+    subscribe();
+
+    let (name_server_addr, _name_server_future, public_key) =
+        setup_authoritative_server(false, true).await;
+    let mut options = ResolverOpts::default();
+    options.negative_trust_anchors = vec![NegativeTrustAnchorConfig::new(
+        Name::root(),
+        Duration::from_secs(3600),
+    )];
+    let (mut client, _forwarder_future) =
+        setup_client_forwarder_with_options(name_server_addr, Some(&public_key), options).await;
+    let response = client
+        .query(Name::root(), DNSClass::IN, RecordType::A)
+        .await
+        .unwrap();
+    assert_eq!(response.response_code(), ResponseCode::NoError);
+    assert!(response.answers().iter().any(|record| {
+        record
+            .data()
+            .as_a()
+            .is_some_and(|a| a.0 == Ipv4Addr::new(1, 2, 3, 4))
+    }));
+}
+
 #[tokio::test]
 async fn query_validate_false_signed_zone_with_soa() {
     subscribe();
@@ -252,13 +278,22 @@ async fn setup_authoritative_server(
 async fn setup_client_forwarder(
     name_server_addr: SocketAddr,
     public_key: Option<&PublicKeyBuf>,
+) -> (Client<TokioRuntimeProvider>, Server<Catalog>) {
+    setup_client_forwarder_with_options(name_server_addr, public_key, ResolverOpts::default()).await
+}
+
+async fn setup_client_forwarder_with_options(
+    name_server_addr: SocketAddr,
+    public_key: Option<&PublicKeyBuf>,
+    options: ResolverOpts,
 ) -> (Client<TokioRuntimeProvider>, Server<Catalog>) {
     // Server setup
     let mut config = NameServerConfig::udp(name_server_addr.ip());
     config.connections[0].port = name_server_addr.port();
     let mut builder = ForwardZoneHandler::builder_tokio(ForwardConfig {
         name_servers: vec![config],
-        options: Some(ResolverOpts::default()),
+        forwarding_rules: Vec::new(),
+        options: Some(options),
     });
 
     if let Some(public_key) = public_key {
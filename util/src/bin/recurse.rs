@@ -30,7 +30,9 @@ use hickory_proto::{
     op::Query,
     rr::{Name, RecordType},
 };
-use hickory_recursor::Recursor;
+#[cfg(feature = "__dnssec")]
+use hickory_recursor::DnssecPolicy;
+use hickory_recursor::{Recursor, ResolutionTrace};
 
 /// A CLI interface for the hickory-dns-recursor.
 ///
@@ -52,6 +54,20 @@ struct Opts {
     #[clap(short = 'n', long, use_value_delimiter = true, value_delimiter(','))]
     nameservers: Vec<IpAddr>,
 
+    /// Validate the responses with DNSSEC
+    #[cfg(feature = "__dnssec")]
+    #[clap(long)]
+    validate: bool,
+
+    /// Print the steps taken to validate the response, implies --validate
+    #[cfg(feature = "__dnssec")]
+    #[clap(long, conflicts_with = "trace")]
+    validation_trace: bool,
+
+    /// Print the zone cuts visited, and the queries sent to their name servers
+    #[clap(long)]
+    trace: bool,
+
     /// Configure log verbosity.
     #[clap(flatten)]
     log_config: hickory_util::LogConfig,
@@ -74,7 +90,18 @@ pub async fn main() -> Result<(), Box<dyn std::error::Error>> {
         opts.nameservers = ROOTS.to_vec();
     }
 
-    let recursor = Recursor::builder().build(&opts.nameservers)?;
+    #[cfg_attr(not(feature = "__dnssec"), allow(unused_mut))]
+    let mut builder = Recursor::builder();
+    #[cfg(feature = "__dnssec")]
+    if opts.validate || opts.validation_trace {
+        builder = builder.dnssec_policy(DnssecPolicy::ValidateWithStaticKey {
+            trust_anchor: None,
+            nsec3_soft_iteration_limit: None,
+            nsec3_hard_iteration_limit: None,
+            negative_trust_anchors: None,
+        });
+    }
+    let recursor = builder.build(&opts.nameservers)?;
 
     // execute query
     println!(
@@ -85,7 +112,30 @@ pub async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let now = Instant::now();
     let query = Query::query(name, ty);
-    let response = recursor.resolve(query, now, false).await?;
+
+    #[cfg(feature = "__dnssec")]
+    let response = if opts.trace {
+        let (response, trace) = recursor.resolve_with_trace(query, now, false).await;
+        print_resolution_trace(&trace);
+        response?
+    } else {
+        let (response, trace) = recursor
+            .resolve_with_validation_trace(query, now, false)
+            .await;
+
+        if opts.validation_trace {
+            hickory_util::print_validation_trace(trace.as_deref());
+        }
+        response?
+    };
+    #[cfg(not(feature = "__dnssec"))]
+    let response = if opts.trace {
+        let (response, trace) = recursor.resolve_with_trace(query, now, false).await;
+        print_resolution_trace(&trace);
+        response?
+    } else {
+        recursor.resolve(query, now, false).await?
+    };
 
     // report response, TODO: better display of errors
     println!(
@@ -107,3 +157,13 @@ pub async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+fn print_resolution_trace(trace: &ResolutionTrace) {
+    println!("{}", style("Resolution trace").yellow());
+    for step in trace.steps() {
+        match step.is_failure() {
+            true => println!("\t{}", style(step).red()),
+            false => println!("\t{step}"),
+        }
+    }
+}
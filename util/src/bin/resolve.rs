@@ -128,6 +128,16 @@ struct Opts {
     #[clap(long)]
     tcp: bool,
 
+    /// Validate the responses with DNSSEC
+    #[cfg(feature = "__dnssec")]
+    #[clap(long)]
+    validate: bool,
+
+    /// Print the steps taken to validate the responses, implies --validate
+    #[cfg(feature = "__dnssec")]
+    #[clap(long)]
+    validation_trace: bool,
+
     /// Configure log verbosity.
     #[clap(flatten)]
     log_config: hickory_util::LogConfig,
@@ -148,7 +158,7 @@ fn print_record<D: RecordData, R: Deref<Target = Record<D>>>(r: &R) {
     );
 }
 
-fn print_ok(lookup: Lookup) {
+fn print_ok(lookup: &Lookup) {
     println!(
         "{} for query {}",
         style("Success").green(),
@@ -160,7 +170,7 @@ fn print_ok(lookup: Lookup) {
     }
 }
 
-fn print_error(error: ProtoError) {
+fn print_error(error: &ProtoError) {
     let no_records = match error.kind() {
         ProtoErrorKind::Dns(DnsError::NoRecordsFound(no_records)) => no_records,
         _ => {
@@ -180,7 +190,20 @@ fn print_error(error: ProtoError) {
     }
 }
 
-fn print_result(result: Result<Lookup, ProtoError>) {
+#[cfg(feature = "__dnssec")]
+fn print_validation_trace(result: &Result<Lookup, ProtoError>) {
+    let trace = match result {
+        Ok(lookup) => lookup.validation_trace(),
+        Err(error) => match error.kind() {
+            ProtoErrorKind::Dns(DnsError::Nsec { response, .. }) => response.validation_trace(),
+            _ => None,
+        },
+    };
+
+    hickory_util::print_validation_trace(trace.map(|trace| &**trace));
+}
+
+fn print_result(result: &Result<Lookup, ProtoError>) {
     match result {
         Ok(lookup) => print_ok(lookup),
         Err(re) => print_error(re),
@@ -306,6 +329,11 @@ pub async fn main() -> Result<(), Box<dyn std::error::Error>> {
     if opts.happy {
         options.ip_strategy = hickory_resolver::config::LookupIpStrategy::Ipv4AndIpv6;
     }
+    #[cfg(feature = "__dnssec")]
+    if opts.validate || opts.validation_trace {
+        options.validate = true;
+        options.validation_trace = opts.validation_trace;
+    }
 
     let mut resolver_builder =
         TokioResolver::builder_with_config(config, TokioRuntimeProvider::default());
@@ -322,7 +350,12 @@ pub async fn main() -> Result<(), Box<dyn std::error::Error>> {
             opts.ty,
         )
         .await;
-        print_result(lookup);
+        print_result(&lookup);
+
+        #[cfg(feature = "__dnssec")]
+        if opts.validation_trace {
+            print_validation_trace(&lookup);
+        }
     } else {
         let duration = Duration::from_secs_f32(opts.interval);
         let fd = File::open(opts.inputfile.as_ref().unwrap())?;
@@ -340,7 +373,13 @@ pub async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     _ = timer.tick() => break,
                     lookup_opt = taskset.join_next() => match lookup_opt {
                         Some(lookup_rr) => {
-                            print_result(lookup_rr?);
+                            let lookup = lookup_rr?;
+                            print_result(&lookup);
+
+                            #[cfg(feature = "__dnssec")]
+                            if opts.validation_trace {
+                                print_validation_trace(&lookup);
+                            }
                         },
                         None => { timer.tick().await; break; }
                     }
@@ -8,6 +8,10 @@
 use std::env;
 
 use clap::Parser;
+#[cfg(feature = "__dnssec")]
+use console::style;
+#[cfg(feature = "__dnssec")]
+use hickory_proto::dnssec::ValidationTrace;
 use tracing::Level;
 use tracing::metadata::LevelFilter;
 use tracing_subscriber::{EnvFilter, layer::SubscriberExt, util::SubscriberInitExt};
@@ -89,3 +93,20 @@ impl LogConfig {
         })
     }
 }
+
+/// Prints the steps of a DNSSEC validation, highlighting the failed ones
+#[cfg(feature = "__dnssec")]
+pub fn print_validation_trace(trace: Option<&ValidationTrace>) {
+    let Some(trace) = trace else {
+        println!("{}", style("No validation trace").yellow());
+        return;
+    };
+
+    println!("{}", style("Validation trace").yellow());
+    for step in trace.steps() {
+        match step.is_failure() {
+            true => println!("\t{}", style(step).red()),
+            false => println!("\t{step}"),
+        }
+    }
+}